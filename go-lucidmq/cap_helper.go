@@ -41,7 +41,7 @@ func topic_request_describe(topicName string) ([]byte, error) {
 	return framedMessageBytes, nil
 }
 
-func topic_request_create(topicName string) ([]byte, error) {
+func topic_request_create(topicName string, config map[string]string) ([]byte, error) {
 	msg, seg, err := capnp.NewMessage(capnp.SingleSegment(nil))
 	if err != nil {
 		return []byte{}, err
@@ -59,6 +59,23 @@ func topic_request_create(topicName string) ([]byte, error) {
 	if err != nil {
 		return []byte{}, err
 	}
+	configEntries, err := topicRequest.NewConfig(int32(len(config)))
+	if err != nil {
+		return []byte{}, err
+	}
+	i := 0
+	for key, value := range config {
+		entry := configEntries.At(i)
+		err = entry.SetKey(key)
+		if err != nil {
+			return []byte{}, err
+		}
+		err = entry.SetValue(value)
+		if err != nil {
+			return []byte{}, err
+		}
+		i++
+	}
 	err = envelope.SetTopicRequest(topicRequest)
 	if err != nil {
 		return []byte{}, err
@@ -178,7 +195,10 @@ func produce_request(topicName string, key []byte, value []byte) ([]byte, error)
 	return framedMessageBytes, nil
 }
 
-func consume_request(topicName string, consumerGroup string, timeout uint64) ([]byte, error) {
+// consume_request builds a consume request, if fromTimestamp is set the consumer group is moved to the first message
+// at or after it before consuming. With a member id only the partitions assigned to the member are consumed. With
+// manual commit the messages are consumed again until they're committed.
+func consume_request(topicName string, consumerGroup string, timeout uint64, fromTimestamp *uint64, memberId string, manualCommit bool) ([]byte, error) {
 	msg, seg, err := capnp.NewMessage(capnp.SingleSegment(nil))
 	if err != nil {
 		return []byte{}, err
@@ -200,6 +220,14 @@ func consume_request(topicName string, consumerGroup string, timeout uint64) ([]
 		return []byte{}, err
 	}
 	consumeRequest.SetTimout(timeout)
+	if fromTimestamp != nil {
+		consumeRequest.SetFromTimestamp(*fromTimestamp)
+	}
+	err = consumeRequest.SetMemberId(memberId)
+	if err != nil {
+		return []byte{}, err
+	}
+	consumeRequest.SetManualCommit(manualCommit)
 
 	err = envelope.SetConsumeRequest(consumeRequest)
 	if err != nil {
@@ -213,6 +241,222 @@ func consume_request(topicName string, consumerGroup string, timeout uint64) ([]
 	return framedMessageBytes, nil
 }
 
+func commit_offset_request(topicName string, consumerGroup string, partition uint32, offset uint64, memberId string) ([]byte, error) {
+	msg, seg, err := capnp.NewMessage(capnp.SingleSegment(nil))
+	if err != nil {
+		return []byte{}, err
+	}
+	envelope, err := protocol.NewRootMessageEnvelope(seg)
+	if err != nil {
+		return []byte{}, err
+	}
+	commitOffsetRequest, err := protocol.NewCommitOffsetRequest(seg)
+	if err != nil {
+		return []byte{}, err
+	}
+	err = commitOffsetRequest.SetTopicName(topicName)
+	if err != nil {
+		return []byte{}, err
+	}
+	err = commitOffsetRequest.SetConsumerGroup(consumerGroup)
+	if err != nil {
+		return []byte{}, err
+	}
+	commitOffsetRequest.SetPartition(partition)
+	commitOffsetRequest.SetOffset(offset)
+	err = commitOffsetRequest.SetMemberId(memberId)
+	if err != nil {
+		return []byte{}, err
+	}
+
+	err = envelope.SetCommitOffsetRequest(commitOffsetRequest)
+	if err != nil {
+		return []byte{}, err
+	}
+	b, err := msg.MarshalPacked()
+	if err != nil {
+		return []byte{}, err
+	}
+	framedMessageBytes := createMessageFrame(b)
+	return framedMessageBytes, nil
+}
+
+// SeekTarget is where a seek moves a consumer group to, built with SeekToEarliest, SeekToLatest, SeekToOffset,
+// SeekToTimestamp or ShiftBy
+type SeekTarget struct {
+	to    protocol.SeekRequest_to_Which
+	value uint64
+	shift int64
+}
+
+// SeekToEarliest moves the consumer group to the oldest offset still retained
+func SeekToEarliest() SeekTarget {
+	return SeekTarget{to: protocol.SeekRequest_to_Which_earliest}
+}
+
+// SeekToLatest moves the consumer group to the latest offset, skipping every message produced so far
+func SeekToLatest() SeekTarget {
+	return SeekTarget{to: protocol.SeekRequest_to_Which_latest}
+}
+
+// SeekToOffset moves the consumer group to an offset
+func SeekToOffset(offset uint64) SeekTarget {
+	return SeekTarget{to: protocol.SeekRequest_to_Which_offset, value: offset}
+}
+
+// SeekToTimestamp moves the consumer group to the first message produced at or after the timestamp in milliseconds
+func SeekToTimestamp(timestamp uint64) SeekTarget {
+	return SeekTarget{to: protocol.SeekRequest_to_Which_timestamp, value: timestamp}
+}
+
+// ShiftBy moves the committed offset of the consumer group by a number of messages, negative to move back
+func ShiftBy(messages int64) SeekTarget {
+	return SeekTarget{to: protocol.SeekRequest_to_Which_shift, shift: messages}
+}
+
+// seek_request builds a request moving the consumer group in one partition, or in every partition when the partition
+// is nil
+func seek_request(topicName string, consumerGroup string, partition *uint32, target SeekTarget) ([]byte, error) {
+	msg, seg, err := capnp.NewMessage(capnp.SingleSegment(nil))
+	if err != nil {
+		return []byte{}, err
+	}
+	envelope, err := protocol.NewRootMessageEnvelope(seg)
+	if err != nil {
+		return []byte{}, err
+	}
+	seekRequest, err := protocol.NewSeekRequest(seg)
+	if err != nil {
+		return []byte{}, err
+	}
+	err = seekRequest.SetTopicName(topicName)
+	if err != nil {
+		return []byte{}, err
+	}
+	err = seekRequest.SetConsumerGroup(consumerGroup)
+	if err != nil {
+		return []byte{}, err
+	}
+	if partition == nil {
+		seekRequest.Partition().SetAll()
+	} else {
+		seekRequest.Partition().SetId(*partition)
+	}
+	switch target.to {
+	case protocol.SeekRequest_to_Which_earliest:
+		seekRequest.To().SetEarliest()
+	case protocol.SeekRequest_to_Which_latest:
+		seekRequest.To().SetLatest()
+	case protocol.SeekRequest_to_Which_offset:
+		seekRequest.To().SetOffset(target.value)
+	case protocol.SeekRequest_to_Which_timestamp:
+		seekRequest.To().SetTimestamp(target.value)
+	case protocol.SeekRequest_to_Which_shift:
+		seekRequest.To().SetShift(target.shift)
+	}
+
+	err = envelope.SetSeekRequest(seekRequest)
+	if err != nil {
+		return []byte{}, err
+	}
+	b, err := msg.MarshalPacked()
+	if err != nil {
+		return []byte{}, err
+	}
+	framedMessageBytes := createMessageFrame(b)
+	return framedMessageBytes, nil
+}
+
+// describe_group_request builds a request for the position of a consumer group, or of every consumer group of the
+// topic when the consumer group is empty. Only the consumer groups named after startAfter are described.
+func describe_group_request(topicName string, consumerGroup string, startAfter string) ([]byte, error) {
+	msg, seg, err := capnp.NewMessage(capnp.SingleSegment(nil))
+	if err != nil {
+		return []byte{}, err
+	}
+	envelope, err := protocol.NewRootMessageEnvelope(seg)
+	if err != nil {
+		return []byte{}, err
+	}
+	describeGroupRequest, err := protocol.NewDescribeGroupRequest(seg)
+	if err != nil {
+		return []byte{}, err
+	}
+	err = describeGroupRequest.SetTopicName(topicName)
+	if err != nil {
+		return []byte{}, err
+	}
+	err = describeGroupRequest.SetConsumerGroup(consumerGroup)
+	if err != nil {
+		return []byte{}, err
+	}
+	err = describeGroupRequest.SetStartAfter(startAfter)
+	if err != nil {
+		return []byte{}, err
+	}
+
+	err = envelope.SetDescribeGroupRequest(describeGroupRequest)
+	if err != nil {
+		return []byte{}, err
+	}
+	b, err := msg.MarshalPacked()
+	if err != nil {
+		return []byte{}, err
+	}
+	framedMessageBytes := createMessageFrame(b)
+	return framedMessageBytes, nil
+}
+
+// group_request builds a join, heartbeat or leave request for a member of a consumer group, the session timeout and
+// strategy are only used when joining
+func group_request(topicName string, consumerGroup string, memberId string, requestType protocol.GroupRequest_Which, sessionTimeout uint64, strategy protocol.AssignmentStrategy) ([]byte, error) {
+	msg, seg, err := capnp.NewMessage(capnp.SingleSegment(nil))
+	if err != nil {
+		return []byte{}, err
+	}
+	envelope, err := protocol.NewRootMessageEnvelope(seg)
+	if err != nil {
+		return []byte{}, err
+	}
+	groupRequest, err := protocol.NewGroupRequest(seg)
+	if err != nil {
+		return []byte{}, err
+	}
+	err = groupRequest.SetTopicName(topicName)
+	if err != nil {
+		return []byte{}, err
+	}
+	err = groupRequest.SetConsumerGroup(consumerGroup)
+	if err != nil {
+		return []byte{}, err
+	}
+	err = groupRequest.SetMemberId(memberId)
+	if err != nil {
+		return []byte{}, err
+	}
+	switch requestType {
+	case protocol.GroupRequest_Which_join:
+		groupRequest.SetJoin()
+		groupRequest.Join().SetSessionTimeout(sessionTimeout)
+		groupRequest.Join().SetStrategy(strategy)
+	case protocol.GroupRequest_Which_heartbeat:
+		groupRequest.SetHeartbeat()
+	case protocol.GroupRequest_Which_leave:
+		groupRequest.SetLeave()
+	}
+
+	err = envelope.SetGroupRequest(groupRequest)
+	if err != nil {
+		return []byte{}, err
+	}
+	b, err := msg.MarshalPacked()
+	if err != nil {
+		return []byte{}, err
+	}
+	framedMessageBytes := createMessageFrame(b)
+	return framedMessageBytes, nil
+}
+
 func createMessageFrame(msg []byte) []byte {
 	fullMsg := make([]byte, 2)
 	binary.LittleEndian.PutUint16(fullMsg, uint16(len(msg)))
@@ -248,10 +492,20 @@ func ResponseParser(msg []byte) (interface{}, error) {
 			return nil, err
 		}
 		offset := produceResponse.Offset()
+		partitionOffsetList, err := produceResponse.PartitionOffsets()
+		if err != nil {
+			return nil, err
+		}
+		partitionOffsets := make(map[uint32]uint64)
+		for i := 0; i < partitionOffsetList.Len(); i++ {
+			partitionOffset := partitionOffsetList.At(i)
+			partitionOffsets[partitionOffset.Partition()] = partitionOffset.Offset()
+		}
 		produceResponseType := ProduceResponse{
-			Success:   success,
-			TopicName: topicName,
-			Offset:    offset,
+			Success:          success,
+			TopicName:        topicName,
+			Offset:           offset,
+			PartitionOffsets: partitionOffsets,
 		}
 		return produceResponseType, nil
 	case protocol.MessageEnvelope_Which_consumeResponse:
@@ -260,6 +514,66 @@ func ResponseParser(msg []byte) (interface{}, error) {
 			return nil, err
 		}
 		return parseConsumeResponse(consumeResponse)
+	case protocol.MessageEnvelope_Which_groupResponse:
+		groupResponse, err := envelope.GroupResponse()
+		if err != nil {
+			return nil, err
+		}
+		return parseGroupResponse(groupResponse)
+	case protocol.MessageEnvelope_Which_commitOffsetResponse:
+		commitOffsetResponse, err := envelope.CommitOffsetResponse()
+		if err != nil {
+			return nil, err
+		}
+		topicName, err := commitOffsetResponse.TopicName()
+		if err != nil {
+			return nil, err
+		}
+		consumerGroup, err := commitOffsetResponse.ConsumerGroup()
+		if err != nil {
+			return nil, err
+		}
+		return CommitOffsetResponse{
+			Success:       commitOffsetResponse.Success(),
+			TopicName:     topicName,
+			ConsumerGroup: consumerGroup,
+			Partition:     commitOffsetResponse.Partition(),
+			Offset:        commitOffsetResponse.Offset(),
+		}, nil
+	case protocol.MessageEnvelope_Which_seekResponse:
+		seekResponse, err := envelope.SeekResponse()
+		if err != nil {
+			return nil, err
+		}
+		topicName, err := seekResponse.TopicName()
+		if err != nil {
+			return nil, err
+		}
+		consumerGroup, err := seekResponse.ConsumerGroup()
+		if err != nil {
+			return nil, err
+		}
+		partitionOffsetList, err := seekResponse.PartitionOffsets()
+		if err != nil {
+			return nil, err
+		}
+		partitionOffsets := make(map[uint32]uint64)
+		for i := 0; i < partitionOffsetList.Len(); i++ {
+			partitionOffset := partitionOffsetList.At(i)
+			partitionOffsets[partitionOffset.Partition()] = partitionOffset.Offset()
+		}
+		return SeekResponse{
+			Success:          seekResponse.Success(),
+			TopicName:        topicName,
+			ConsumerGroup:    consumerGroup,
+			PartitionOffsets: partitionOffsets,
+		}, nil
+	case protocol.MessageEnvelope_Which_describeGroupResponse:
+		describeGroupResponse, err := envelope.DescribeGroupResponse()
+		if err != nil {
+			return nil, err
+		}
+		return parseDescribeGroupResponse(describeGroupResponse)
 	case protocol.MessageEnvelope_Which_invalidResponse:
 		invalidResponse, err := envelope.InvalidResponse()
 		if err != nil {
@@ -279,6 +593,53 @@ func ResponseParser(msg []byte) (interface{}, error) {
 	}
 }
 
+func parseDescribeGroupResponse(describeGroupResponse protocol.DescribeGroupResponse) (DescribeGroupResponse, error) {
+	var response DescribeGroupResponse
+	topicName, err := describeGroupResponse.TopicName()
+	if err != nil {
+		return response, err
+	}
+	groupList, err := describeGroupResponse.ConsumerGroups()
+	if err != nil {
+		return response, err
+	}
+	consumerGroups := make([]ConsumerGroupDescription, 0, groupList.Len())
+	for i := 0; i < groupList.Len(); i++ {
+		group := groupList.At(i)
+		name, err := group.Name()
+		if err != nil {
+			return response, err
+		}
+		partitionList, err := group.Partitions()
+		if err != nil {
+			return response, err
+		}
+		partitions := make([]ConsumerGroupPartition, 0, partitionList.Len())
+		for j := 0; j < partitionList.Len(); j++ {
+			partition := partitionList.At(j)
+			partitions = append(partitions, ConsumerGroupPartition{
+				Partition:       partition.Partition(),
+				CommittedOffset: partition.CommittedOffset(),
+				LogEndOffset:    partition.LogEndOffset(),
+				OldestOffset:    partition.OldestOffset(),
+				Lag:             partition.Lag(),
+			})
+		}
+		consumerGroups = append(consumerGroups, ConsumerGroupDescription{
+			Name:       name,
+			Lag:        group.Lag(),
+			Partitions: partitions,
+		})
+	}
+	response = DescribeGroupResponse{
+		Success:        describeGroupResponse.Success(),
+		TopicName:      topicName,
+		ConsumerGroups: consumerGroups,
+		More:           describeGroupResponse.More(),
+	}
+	return response, nil
+}
+
 func topicResponseParser(topicResponse protocol.TopicResponse) (interface{}, error) {
 	switch topicResponsetype := topicResponse.Which(); topicResponsetype {
 	case protocol.TopicResponse_Which_all:
@@ -358,12 +719,30 @@ func topicResponseParser(topicResponse protocol.TopicResponse) (interface{}, err
 			}
 			cgs = append(cgs, cg)
 		}
+		configEntries, err := topicResponseDescribe.Config()
+		if err != nil {
+			return nil, err
+		}
+		config := make(map[string]string)
+		for i := 0; i < configEntries.Len(); i++ {
+			entry := configEntries.At(i)
+			key, err := entry.Key()
+			if err != nil {
+				return nil, err
+			}
+			value, err := entry.Value()
+			if err != nil {
+				return nil, err
+			}
+			config[key] = value
+		}
 		topicDescribeResponse := TopicDescribeResponse{
 			Success:           success,
 			TopicName:         topicName,
 			MaxSegmentBytes:   maxSegmentBytes,
 			MaxRetentionBytes: maxRetentionBytes,
 			ConsumerGroups:    cgs,
+			Config:            config,
 		}
 		return topicDescribeResponse, nil
 	default:
@@ -402,8 +781,42 @@ func parseConsumeResponse(consumeResponse protocol.ConsumeResponse) (interface{}
 	}
 
 	return ConsumeResponse{
-		Success:   success,
-		TopicName: topicName,
-		Messages:  msgs,
+		Success:    success,
+		TopicName:  topicName,
+		Partition:  consumeResponse.Partition(),
+		NextOffset: consumeResponse.NextOffset(),
+		Messages:   msgs,
+	}, nil
+}
+
+func parseGroupResponse(groupResponse protocol.GroupResponse) (interface{}, error) {
+	topicName, err := groupResponse.TopicName()
+	if err != nil {
+		return nil, err
+	}
+	consumerGroup, err := groupResponse.ConsumerGroup()
+	if err != nil {
+		return nil, err
+	}
+	memberId, err := groupResponse.MemberId()
+	if err != nil {
+		return nil, err
+	}
+	partitionList, err := groupResponse.Partitions()
+	if err != nil {
+		return nil, err
+	}
+	partitions := make([]uint32, partitionList.Len())
+	for i := 0; i < partitionList.Len(); i++ {
+		partitions[i] = partitionList.At(i)
+	}
+
+	return GroupResponse{
+		Success:       groupResponse.Success(),
+		TopicName:     topicName,
+		ConsumerGroup: consumerGroup,
+		MemberId:      memberId,
+		Generation:    groupResponse.Generation(),
+		Partitions:    partitions,
 	}, nil
 }
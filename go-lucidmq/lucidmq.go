@@ -4,6 +4,7 @@ import (
 	"encoding/binary"
 	"fmt"
 	"net"
+	"protocol"
 )
 
 type LucidmqClient struct {
@@ -71,8 +72,13 @@ func NewTopicManger(host string, port int) (*TopicManager, error) {
 }
 
 func (topicManager *TopicManager) CreateTopic(topicName string) (TopicCreateResponse, error) {
+	return topicManager.CreateTopicWithConfig(topicName, nil)
+}
+
+// CreateTopicWithConfig creates a topic with the given settings, settings that aren't given use the brokers defaults.
+func (topicManager *TopicManager) CreateTopicWithConfig(topicName string, config map[string]string) (TopicCreateResponse, error) {
 	var topicResponse TopicCreateResponse
-	bytes, err := topic_request_create(topicName)
+	bytes, err := topic_request_create(topicName, config)
 	if err != nil {
 		return topicResponse, err
 	}
@@ -174,6 +180,46 @@ func (topicManager *TopicManager) AllTopics() (TopicAllResponse, error) {
 	return topicResponse, nil
 }
 
+// DescribeGroup returns the committed offset and lag of a consumer group in every partition of the topic, or of every
+// consumer group of the topic when the consumer group is empty. The broker describes the consumer groups in pages
+// ordered by name, which are requested until the last one.
+func (topicManager *TopicManager) DescribeGroup(topicName string, consumerGroup string) (DescribeGroupResponse, error) {
+	var describeGroupResponse DescribeGroupResponse
+	var consumerGroups []ConsumerGroupDescription
+	startAfter := ""
+	for {
+		bytes, err := describe_group_request(topicName, consumerGroup, startAfter)
+		if err != nil {
+			return describeGroupResponse, err
+		}
+		err = topicManager.LucidmqClient.SendMessageBytes(bytes)
+		if err != nil {
+			return describeGroupResponse, err
+		}
+
+		responseBytes, err := topicManager.LucidmqClient.RecieveResponse()
+		if err != nil {
+			return describeGroupResponse, err
+		}
+
+		responseMessage, err := ResponseParser(responseBytes)
+		if err != nil {
+			return describeGroupResponse, err
+		}
+
+		describeGroupResponse = responseMessage.(DescribeGroupResponse)
+		consumerGroups = append(consumerGroups, describeGroupResponse.ConsumerGroups...)
+		if !describeGroupResponse.Success || !describeGroupResponse.More || len(describeGroupResponse.ConsumerGroups) == 0 {
+			break
+		}
+		startAfter = describeGroupResponse.ConsumerGroups[len(describeGroupResponse.ConsumerGroups)-1].Name
+	}
+	describeGroupResponse.ConsumerGroups = consumerGroups
+	describeGroupResponse.More = false
+
+	return describeGroupResponse, nil
+}
+
 type Producer struct {
 	LucidmqClient
 }
@@ -234,8 +280,30 @@ func NewConsumer(host string, port int, timeout uint64) (*Consumer, error) {
 }
 
 func (consumer *Consumer) Consume(topicName string, consumerGroup string) (ConsumeResponse, error) {
+	return consumer.consume(topicName, consumerGroup, nil, "", false)
+}
+
+// ConsumeWithoutCommit consumes without committing the messages, they're consumed again until they're committed with
+// CommitOffset so messages aren't lost when the consumer fails before processing them
+func (consumer *Consumer) ConsumeWithoutCommit(topicName string, consumerGroup string, memberId string) (ConsumeResponse, error) {
+	return consumer.consume(topicName, consumerGroup, nil, memberId, true)
+}
+
+// ConsumeAsMember consumes from the partitions assigned to a member of the consumer group, consuming also counts as a
+// heartbeat for the member
+func (consumer *Consumer) ConsumeAsMember(topicName string, consumerGroup string, memberId string) (ConsumeResponse, error) {
+	return consumer.consume(topicName, consumerGroup, nil, memberId, false)
+}
+
+// ConsumeFromTimestamp moves the consumer group to the first message produced at or after the timestamp(milliseconds
+// since the unix epoch) and consumes from there, to replay a topic from a point in time
+func (consumer *Consumer) ConsumeFromTimestamp(topicName string, consumerGroup string, timestamp uint64) (ConsumeResponse, error) {
+	return consumer.consume(topicName, consumerGroup, &timestamp, "", false)
+}
+
+func (consumer *Consumer) consume(topicName string, consumerGroup string, fromTimestamp *uint64, memberId string, manualCommit bool) (ConsumeResponse, error) {
 	var consumeResponse ConsumeResponse
-	bytes, err := consume_request(topicName, consumerGroup, consumer.timeout)
+	bytes, err := consume_request(topicName, consumerGroup, consumer.timeout, fromTimestamp, memberId, manualCommit)
 	if err != nil {
 		return consumeResponse, err
 	}
@@ -256,3 +324,105 @@ func (consumer *Consumer) Consume(topicName string, consumerGroup string) (Consu
 	consumeResponse = responseMessage.(ConsumeResponse)
 	return consumeResponse, nil
 }
+
+// CommitOffset commits every message of the partition before the offset as processed by the consumer group, usually
+// with the NextOffset of a consume response. Members can only commit the partitions assigned to them.
+func (consumer *Consumer) CommitOffset(topicName string, consumerGroup string, partition uint32, offset uint64, memberId string) (CommitOffsetResponse, error) {
+	var commitOffsetResponse CommitOffsetResponse
+	bytes, err := commit_offset_request(topicName, consumerGroup, partition, offset, memberId)
+	if err != nil {
+		return commitOffsetResponse, err
+	}
+	err = consumer.LucidmqClient.SendMessageBytes(bytes)
+	if err != nil {
+		return commitOffsetResponse, err
+	}
+	responseBytes, err := consumer.LucidmqClient.RecieveResponse()
+	if err != nil {
+		return commitOffsetResponse, err
+	}
+
+	responseMessage, err := ResponseParser(responseBytes)
+	if err != nil {
+		return commitOffsetResponse, err
+	}
+
+	commitOffsetResponse = responseMessage.(CommitOffsetResponse)
+	return commitOffsetResponse, nil
+}
+
+// Seek moves the consumer group in every partition of the topic. Consumer groups with active members can't be moved,
+// the members have to leave first.
+func (consumer *Consumer) Seek(topicName string, consumerGroup string, target SeekTarget) (SeekResponse, error) {
+	return consumer.seek(topicName, consumerGroup, nil, target)
+}
+
+// SeekPartition moves the consumer group in a single partition of the topic
+func (consumer *Consumer) SeekPartition(topicName string, consumerGroup string, partition uint32, target SeekTarget) (SeekResponse, error) {
+	return consumer.seek(topicName, consumerGroup, &partition, target)
+}
+
+func (consumer *Consumer) seek(topicName string, consumerGroup string, partition *uint32, target SeekTarget) (SeekResponse, error) {
+	var seekResponse SeekResponse
+	bytes, err := seek_request(topicName, consumerGroup, partition, target)
+	if err != nil {
+		return seekResponse, err
+	}
+	err = consumer.LucidmqClient.SendMessageBytes(bytes)
+	if err != nil {
+		return seekResponse, err
+	}
+	responseBytes, err := consumer.LucidmqClient.RecieveResponse()
+	if err != nil {
+		return seekResponse, err
+	}
+
+	responseMessage, err := ResponseParser(responseBytes)
+	if err != nil {
+		return seekResponse, err
+	}
+
+	seekResponse = responseMessage.(SeekResponse)
+	return seekResponse, nil
+}
+
+// JoinGroup adds the consumer as a member of the consumer group and returns the partitions assigned to it. An empty
+// member id joins as a new member, the member id from the response is used for heartbeats, consuming and leaving.
+func (consumer *Consumer) JoinGroup(topicName string, consumerGroup string, memberId string, sessionTimeout uint64, strategy protocol.AssignmentStrategy) (GroupResponse, error) {
+	return consumer.group(topicName, consumerGroup, memberId, protocol.GroupRequest_Which_join, sessionTimeout, strategy)
+}
+
+// Heartbeat keeps the member in the consumer group, members that don't heartbeat within their session timeout lose
+// their partitions. The generation of the response goes up when the partitions were reassigned.
+func (consumer *Consumer) Heartbeat(topicName string, consumerGroup string, memberId string) (GroupResponse, error) {
+	return consumer.group(topicName, consumerGroup, memberId, protocol.GroupRequest_Which_heartbeat, 0, protocol.AssignmentStrategy_range)
+}
+
+// LeaveGroup removes the member from the consumer group so its partitions go to the other members right away
+func (consumer *Consumer) LeaveGroup(topicName string, consumerGroup string, memberId string) (GroupResponse, error) {
+	return consumer.group(topicName, consumerGroup, memberId, protocol.GroupRequest_Which_leave, 0, protocol.AssignmentStrategy_range)
+}
+
+func (consumer *Consumer) group(topicName string, consumerGroup string, memberId string, requestType protocol.GroupRequest_Which, sessionTimeout uint64, strategy protocol.AssignmentStrategy) (GroupResponse, error) {
+	var groupResponse GroupResponse
+	bytes, err := group_request(topicName, consumerGroup, memberId, requestType, sessionTimeout, strategy)
+	if err != nil {
+		return groupResponse, err
+	}
+	err = consumer.LucidmqClient.SendMessageBytes(bytes)
+	if err != nil {
+		return groupResponse, err
+	}
+	responseBytes, err := consumer.LucidmqClient.RecieveResponse()
+	if err != nil {
+		return groupResponse, err
+	}
+
+	responseMessage, err := ResponseParser(responseBytes)
+	if err != nil {
+		return groupResponse, err
+	}
+
+	groupResponse = responseMessage.(GroupResponse)
+	return groupResponse, nil
+}
@@ -16,6 +16,7 @@ type TopicDescribeResponse struct {
 	MaxSegmentBytes   uint64
 	MaxRetentionBytes uint64
 	ConsumerGroups    []string
+	Config            map[string]string
 }
 
 type TopicAllResponse struct {
@@ -29,15 +30,72 @@ type TopicList struct {
 }
 
 type ProduceResponse struct {
-	Success   bool
-	TopicName string
-	Offset    uint64
+	Success          bool
+	TopicName        string
+	Offset           uint64
+	PartitionOffsets map[uint32]uint64
 }
 
 type ConsumeResponse struct {
-	Success   bool
-	TopicName string
-	Messages  []Message
+	Success    bool
+	TopicName  string
+	Partition  uint32
+	NextOffset uint64
+	Messages   []Message
+}
+
+type CommitOffsetResponse struct {
+	Success       bool
+	TopicName     string
+	ConsumerGroup string
+	Partition     uint32
+	Offset        uint64
+}
+
+// SeekResponse holds the offsets a consumer group was moved to in every partition
+type SeekResponse struct {
+	Success          bool
+	TopicName        string
+	ConsumerGroup    string
+	PartitionOffsets map[uint32]uint64
+}
+
+// DescribeGroupResponse holds the position of every consumer group of a topic, or of the one consumer group asked for.
+// More is set when the broker has consumer groups left to describe after the last one of ConsumerGroups.
+type DescribeGroupResponse struct {
+	Success        bool
+	TopicName      string
+	ConsumerGroups []ConsumerGroupDescription
+	More           bool
+}
+
+// ConsumerGroupDescription holds the position of a consumer group in every partition, Lag is summed over the
+// partitions
+type ConsumerGroupDescription struct {
+	Name       string
+	Lag        uint64
+	Partitions []ConsumerGroupPartition
+}
+
+// ConsumerGroupPartition holds the position of a consumer group in a partition. Lag counts the retained messages after
+// the committed offset.
+type ConsumerGroupPartition struct {
+	Partition       uint32
+	CommittedOffset uint64
+	LogEndOffset    uint64
+	OldestOffset    uint64
+	Lag             uint64
+}
+
+// GroupResponse holds the partitions assigned to a member of a consumer group. The generation goes up every time the
+// partitions of the consumer group are reassigned.
+type GroupResponse struct {
+	Success       bool
+	TopicName     string
+	ConsumerGroup string
+	MemberId      string
+	Generation    uint64
+	Partitions    []uint32
 }
 
 type Message struct {
@@ -11,34 +11,40 @@ pub fn parse_response(data: Vec<u8>) -> String {
             match topic_response.which() {
                 Ok(topic_response::Which::Create(_create)) => {
                     let mut s = "Topic Create Response ------------\n".to_string();
-                    write!(s, "Topic Name: {}\n", topic_response.get_topic_name().unwrap()).unwrap();
-                    write!(s, "Status: {}\n", topic_response.get_success()).unwrap();
-                    return s;
+                    writeln!(s, "Topic Name: {}", topic_response.get_topic_name().unwrap()).unwrap();
+                    writeln!(s, "Status: {}", topic_response.get_success()).unwrap();
+                    s
                 },
                 Ok(topic_response::Which::Describe(describe)) => {
                     let mut s = "Topic Describe Response ------------\n".to_string();
-                    write!(s, "Topic Name: {}\n", topic_response.get_topic_name().unwrap()).unwrap();
-                    write!(s, "Status: {}\n", topic_response.get_success()).unwrap();
+                    writeln!(s, "Topic Name: {}", topic_response.get_topic_name().unwrap()).unwrap();
+                    writeln!(s, "Status: {}", topic_response.get_success()).unwrap();
                     // Parse out the consumer groups to a vector to write them pretty
                     let cgs = describe.get_consumer_groups().unwrap();
                     let mut cgs_vec = Vec::new();
                     for msg in cgs {
                         cgs_vec.push(msg.unwrap().to_string())
                     }
-                    write!(s, "Topic max retention bytes: {}, max segments bytes: {}, consumer groups: {:?}\n", describe.get_max_retention_bytes(), describe.get_max_segment_bytes(), cgs_vec).unwrap();
-                    return s;
+                    writeln!(s, "Topic max retention bytes: {}, max segments bytes: {}, consumer groups: {:?}", describe.get_max_retention_bytes(), describe.get_max_segment_bytes(), cgs_vec).unwrap();
+                    for entry in describe.get_config().unwrap() {
+                        writeln!(s, "{}: {}", entry.get_key().unwrap(), entry.get_value().unwrap()).unwrap();
+                    }
+                    for partition in describe.get_partitions().unwrap() {
+                        writeln!(s, "Partition {}, oldest offset: {}, latest offset: {}", partition.get_partition(), partition.get_oldest_offset(), partition.get_latest_offset()).unwrap();
+                    }
+                    s
                 },
                 Ok(topic_response::Which::Delete(_deletes)) => {
                     let mut s = "Topic Delete Response ------------\n".to_string();
-                    write!(s, "Topic Name: {}\n", topic_response.get_topic_name().unwrap()).unwrap();
-                    write!(s, "Status: {}\n", topic_response.get_success()).unwrap();
-                    return s;
+                    writeln!(s, "Topic Name: {}", topic_response.get_topic_name().unwrap()).unwrap();
+                    writeln!(s, "Status: {}", topic_response.get_success()).unwrap();
+                    s
                 },
                 Ok(topic_response::Which::All(all)) => {
                     let unwraped_all = all.unwrap();
                     // this thing is fucked
                     let mut s = "Topic All Response ------------\n".to_string();
-                    write!(s, "Status: {}\n", topic_response.get_success()).unwrap();
+                    writeln!(s, "Status: {}", topic_response.get_success()).unwrap();
                     for i in 0..unwraped_all.len() {
                         let topic = unwraped_all.get(i);
                         let cgs = topic.get_consumer_groups().unwrap();
@@ -46,9 +52,9 @@ pub fn parse_response(data: Vec<u8>) -> String {
                         for msg in cgs {
                             cgs_vec.push(msg.unwrap().to_string())
                         }
-                        write!(s, "Topic Name: {}, consumer groups: {:?}\n", topic.get_topic_name().unwrap(), cgs_vec).unwrap();
+                        writeln!(s, "Topic Name: {}, consumer groups: {:?}", topic.get_topic_name().unwrap(), cgs_vec).unwrap();
                     }
-                    return s;
+                    s
                 },
                 Err(_) => unimplemented!(),
             }
@@ -56,16 +62,21 @@ pub fn parse_response(data: Vec<u8>) -> String {
         Ok(message_envelope::ProduceResponse(envelope_produce_response)) => {
             let produce_response = envelope_produce_response.expect("Unable to get produce request from envelope");
             let mut s = "Produce Response ------------\n".to_string();
-            write!(s, "Topic Name: {}\n", produce_response.get_topic_name().unwrap()).unwrap();
-            write!(s, "Status: {}\n", produce_response.get_success()).unwrap();
-            write!(s,"Last offset: {}\n", produce_response.get_offset()).unwrap();
-            return s;
+            writeln!(s, "Topic Name: {}", produce_response.get_topic_name().unwrap()).unwrap();
+            writeln!(s, "Status: {}", produce_response.get_success()).unwrap();
+            writeln!(s,"Last offset: {}", produce_response.get_offset()).unwrap();
+            for partition_offset in produce_response.get_partition_offsets().unwrap() {
+                writeln!(s, "Partition {} last offset: {}", partition_offset.get_partition(), partition_offset.get_offset()).unwrap();
+            }
+            s
         },
         Ok(message_envelope::ConsumeResponse(envelope_consume_response)) => {
             let consume_response = envelope_consume_response.unwrap();
             let mut s = "Consume Response ------------\n".to_string();
-            write!(s, "Topic Name: {}\n", consume_response.get_topic_name().unwrap()).unwrap();
-            write!(s, "Status: {}\n", consume_response.get_success()).unwrap(); 
+            writeln!(s, "Topic Name: {}", consume_response.get_topic_name().unwrap()).unwrap();
+            writeln!(s, "Status: {}", consume_response.get_success()).unwrap(); 
+            writeln!(s, "Partition: {}", consume_response.get_partition()).unwrap();
+            writeln!(s, "Next offset: {}", consume_response.get_next_offset()).unwrap();
 
             let messages = consume_response.get_messages().unwrap();
             let mut message_vec = Vec::new();
@@ -73,25 +84,91 @@ pub fn parse_response(data: Vec<u8>) -> String {
                 let message_string = format!("Key: {:?},Value: {:?}, Timestamp: {}", msg.get_key().unwrap(), msg.get_value().unwrap(), msg.get_timestamp());
                 message_vec.push(message_string)
             }
-            write!(s, "Messages: {:?}\n", message_vec).unwrap();
-            return s;
+            writeln!(s, "Messages: {:?}", message_vec).unwrap();
+            s
+        },
+        Ok(message_envelope::GroupResponse(envelope_group_response)) => {
+            let group_response = envelope_group_response.unwrap();
+            let mut s = "Group Response ------------\n".to_string();
+            writeln!(s, "Topic Name: {}", group_response.get_topic_name().unwrap()).unwrap();
+            writeln!(s, "Consumer Group: {}", group_response.get_consumer_group().unwrap()).unwrap();
+            writeln!(s, "Status: {}", group_response.get_success()).unwrap();
+            writeln!(s, "Member ID: {}", group_response.get_member_id().unwrap()).unwrap();
+            writeln!(s, "Generation: {}", group_response.get_generation()).unwrap();
+            let partitions: Vec<u32> = group_response.get_partitions().unwrap().iter().collect();
+            writeln!(s, "Partitions: {:?}", partitions).unwrap();
+            s
+        },
+        Ok(message_envelope::CommitOffsetResponse(envelope_commit_offset_response)) => {
+            let commit_offset_response = envelope_commit_offset_response.unwrap();
+            let mut s = "Commit Offset Response ------------\n".to_string();
+            writeln!(s, "Topic Name: {}", commit_offset_response.get_topic_name().unwrap()).unwrap();
+            writeln!(s, "Consumer Group: {}", commit_offset_response.get_consumer_group().unwrap()).unwrap();
+            writeln!(s, "Status: {}", commit_offset_response.get_success()).unwrap();
+            writeln!(s, "Partition: {}, offset: {}", commit_offset_response.get_partition(), commit_offset_response.get_offset()).unwrap();
+            s
+        },
+        Ok(message_envelope::SeekResponse(envelope_seek_response)) => {
+            let seek_response = envelope_seek_response.unwrap();
+            let mut s = "Seek Response ------------\n".to_string();
+            writeln!(s, "Topic Name: {}", seek_response.get_topic_name().unwrap()).unwrap();
+            writeln!(s, "Consumer Group: {}", seek_response.get_consumer_group().unwrap()).unwrap();
+            writeln!(s, "Status: {}", seek_response.get_success()).unwrap();
+            for partition_offset in seek_response.get_partition_offsets().unwrap() {
+                writeln!(s, "Partition {} offset: {}", partition_offset.get_partition(), partition_offset.get_offset()).unwrap();
+            }
+            s
+        },
+        Ok(message_envelope::DescribeGroupResponse(envelope_describe_group_response)) => {
+            let describe_group_response = envelope_describe_group_response.unwrap();
+            let mut s = "Describe Group Response ------------\n".to_string();
+            writeln!(s, "Topic Name: {}", describe_group_response.get_topic_name().unwrap()).unwrap();
+            writeln!(s, "Status: {}", describe_group_response.get_success()).unwrap();
+            for consumer_group in describe_group_response.get_consumer_groups().unwrap() {
+                writeln!(s, "Consumer Group: {}, lag: {}", consumer_group.get_name().unwrap(), consumer_group.get_lag()).unwrap();
+                for partition in consumer_group.get_partitions().unwrap() {
+                    writeln!(
+                        s,
+                        "Partition {} committed offset: {}, log end offset: {}, oldest offset: {}, lag: {}",
+                        partition.get_partition(),
+                        partition.get_committed_offset(),
+                        partition.get_log_end_offset(),
+                        partition.get_oldest_offset(),
+                        partition.get_lag()
+                    ).unwrap();
+                }
+            }
+            writeln!(s, "More: {}", describe_group_response.get_more()).unwrap();
+            s
         },
         Ok(message_envelope::Which::InvalidResponse(envelope_invalid_request)) => {
             let invalid_response = envelope_invalid_request.unwrap();
             let invalid_response_text = invalid_response.get_error_message().unwrap();
-            return invalid_response_text.to_string();
+            invalid_response_text.to_string()
         }
         Ok(message_envelope::TopicRequest(_envelope_topic_request)) => {
-            return "Topic Request is an invalid response type\n".to_string();
+            "Topic Request is an invalid response type\n".to_string()
         },
         Ok(message_envelope::ConsumeRequest(_envelope_produce_request)) => {
-            return "Consume request is an invalid request type\n".to_string();
+            "Consume request is an invalid request type\n".to_string()
         },
         Ok(message_envelope::ProduceRequest(_envelope_consume_request)) => {
-            return "Produce request is an invalid request type\n".to_string();
+            "Produce request is an invalid request type\n".to_string()
+        },
+        Ok(message_envelope::GroupRequest(_envelope_group_request)) => {
+            "Group request is an invalid request type\n".to_string()
+        },
+        Ok(message_envelope::CommitOffsetRequest(_envelope_commit_offset_request)) => {
+            "Commit offset request is an invalid request type\n".to_string()
+        },
+        Ok(message_envelope::SeekRequest(_envelope_seek_request)) => {
+            "Seek request is an invalid request type\n".to_string()
+        },
+        Ok(message_envelope::DescribeGroupRequest(_envelope_describe_group_request)) => {
+            "Describe group request is an invalid request type\n".to_string()
         },
         Err(::capnp::NotInSchema(_)) => {
-            return "Unable to parse cap n p message\n".to_string();
+            "Unable to parse cap n p message\n".to_string()
         }
     }
 }
\ No newline at end of file
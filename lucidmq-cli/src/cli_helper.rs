@@ -1,5 +1,8 @@
-use clap::{arg, Command};
-use crate::utils::{CONNECT, PRODUCER, CONSUMER, PRODUCE, CONSUME, TOPIC, QUIT};
+use clap::{arg, ArgGroup, Command};
+use crate::utils::{
+    CONNECT, PRODUCER, CONSUMER, PRODUCE, CONSUME, TOPIC, GROUP, COMMIT, SEEK, DESCRIBE_GROUP, QUIT, GROUP_JOIN, GROUP_HEARTBEAT, GROUP_LEAVE,
+    STRATEGY_RANGE, STRATEGY_ROUND_ROBIN,
+};
 
 pub fn base_cli() -> Command<'static> {
     Command::new("LucidMQ")
@@ -56,6 +59,11 @@ pub fn interactive_cli() -> Command<'static> {
             Command::new(PRODUCE)
                 .about("Get a response")
                 .arg(arg!(<TOPIC_NAME> "The topic to produce to"))
+                .arg(
+                    arg!(-p --partition <PARTITION> "The partition to produce to, otherwise it's picked by the message key")
+                        .required(false)
+                        .value_parser(clap::value_parser!(u32)),
+                )
                 .arg_required_else_help(true)
                 .help_template(APPLET_TEMPLATE),
         )
@@ -63,6 +71,16 @@ pub fn interactive_cli() -> Command<'static> {
             Command::new(CONSUME)
                 .arg(arg!(<TOPIC_NAME> "The topic to consume from"))
                 .arg(arg!(<CONSUMER_GROUP> "The consumer group to use"))
+                .arg(
+                    arg!(-p --partition <PARTITION> "The partition to consume from, otherwise every partition")
+                        .required(false)
+                        .value_parser(clap::value_parser!(u32)),
+                )
+                .arg(
+                    arg!(-m --member <MEMBER_ID> "The member of the consumer group to consume as, only its partitions are read")
+                        .required(false),
+                )
+                .arg(arg!(--"manual-commit" "Don't commit the messages, they're consumed again until they're committed"))
                 .arg_required_else_help(true)
                 .help_template(APPLET_TEMPLATE),
         )
@@ -70,6 +88,97 @@ pub fn interactive_cli() -> Command<'static> {
             Command::new(TOPIC)
                 .arg(arg!(<TYPE> "The topic request message tye"))
                 .arg(arg!(<TOPIC_NAME> "The topic to consume from"))
+                .arg(
+                    arg!(-c --config <SETTING> "A setting to create the topic with as KEY=VALUE")
+                        .required(false)
+                        .multiple_occurrences(true),
+                )
+                .arg_required_else_help(true)
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new(GROUP)
+                .arg(arg!(<TYPE> "The group request message type").value_parser([GROUP_JOIN, GROUP_HEARTBEAT, GROUP_LEAVE]))
+                .arg(arg!(<TOPIC_NAME> "The topic of the consumer group"))
+                .arg(arg!(<CONSUMER_GROUP> "The consumer group to join, heartbeat or leave"))
+                .arg(
+                    arg!(-m --member <MEMBER_ID> "The member id the broker gave when joining")
+                        .required(false),
+                )
+                .arg(
+                    arg!(-s --strategy <STRATEGY> "How partitions are assigned when the first member joins")
+                        .required(false)
+                        .value_parser([STRATEGY_RANGE, STRATEGY_ROUND_ROBIN])
+                        .default_value(STRATEGY_RANGE),
+                )
+                .arg(
+                    arg!(-t --"session-timeout" <MILLISECONDS> "How long the member can go without a heartbeat")
+                        .required(false)
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("10000"),
+                )
+                .arg_required_else_help(true)
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new(COMMIT)
+                .about("Commit every message of a partition before the offset as processed by the consumer group")
+                .arg(arg!(<TOPIC_NAME> "The topic of the consumer group"))
+                .arg(arg!(<CONSUMER_GROUP> "The consumer group to commit for"))
+                .arg(arg!(<PARTITION> "The partition to commit").value_parser(clap::value_parser!(u32)))
+                .arg(arg!(<OFFSET> "The next offset of the consume response").value_parser(clap::value_parser!(u64)))
+                .arg(
+                    arg!(-m --member <MEMBER_ID> "The member of the consumer group the partition is assigned to")
+                        .required(false),
+                )
+                .arg_required_else_help(true)
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new(SEEK)
+                .about("Move a consumer group without members to another offset")
+                .arg(arg!(<TOPIC_NAME> "The topic of the consumer group"))
+                .arg(arg!(<CONSUMER_GROUP> "The consumer group to move"))
+                .arg(
+                    arg!(-p --partition <PARTITION> "The partition to move the consumer group in, otherwise every partition")
+                        .required(false)
+                        .value_parser(clap::value_parser!(u32)),
+                )
+                .arg(arg!(--"to-earliest" "Move to the oldest offset still retained"))
+                .arg(arg!(--"to-latest" "Move to the latest offset, skipping every message produced so far"))
+                .arg(
+                    arg!(--"to-offset" <OFFSET> "Move to an offset")
+                        .required(false)
+                        .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(
+                    arg!(--"to-timestamp" <MILLISECONDS> "Move to the first message produced at or after the timestamp")
+                        .required(false)
+                        .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(
+                    arg!(--"shift-by" <MESSAGES> "Move the committed offset by a number of messages, negative to move back")
+                        .required(false)
+                        .allow_hyphen_values(true)
+                        .value_parser(clap::value_parser!(i64)),
+                )
+                .group(
+                    ArgGroup::new("target")
+                        .args(&["to-earliest", "to-latest", "to-offset", "to-timestamp", "shift-by"])
+                        .required(true),
+                )
+                .arg_required_else_help(true)
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new(DESCRIBE_GROUP)
+                .about("Show the committed offset and lag of the consumer groups of a topic in every partition")
+                .arg(arg!(<TOPIC_NAME> "The topic of the consumer groups"))
+                .arg(arg!([CONSUMER_GROUP] "The consumer group to describe, otherwise every consumer group"))
+                .arg(
+                    arg!(--"start-after" <CONSUMER_GROUP> "Only describe the consumer groups named after this one")
+                        .required(false),
+                )
                 .arg_required_else_help(true)
                 .help_template(APPLET_TEMPLATE),
         )
@@ -1,7 +1,7 @@
 extern crate capnpc;
 
 fn main () {
-    let files = vec!["lucid_schema.capnp"];
+    let files = ["lucid_schema.capnp"];
     let file_path_prefix = "../protocol/schemas/";
 
     for file in files.iter() {
@@ -13,7 +13,7 @@ mod cli_helper;
 pub mod utils;
 use std::io::{self, BufRead};
 
-use crate::utils::{CONNECT, PRODUCER, CONSUMER, PRODUCE, CONSUME, TOPIC, QUIT};
+use crate::utils::{CONNECT, PRODUCER, CONSUMER, PRODUCE, CONSUME, TOPIC, GROUP, COMMIT, SEEK, DESCRIBE_GROUP, QUIT};
 
 fn respond(line: &str) -> Result<Vec<u8>, String> {
     let args = shlex::split(line).ok_or("error: Invalid quoting")?;
@@ -23,25 +23,75 @@ fn respond(line: &str) -> Result<Vec<u8>, String> {
     match matches.subcommand() {
         Some((PRODUCE, sub_matches)) => {
             let topic_name = sub_matches.get_one::<String>("TOPIC_NAME").expect("required");
+            let partition = sub_matches.get_one::<u32>("partition").copied();
             let msg = "value".as_bytes().to_vec();
-            let mut messages_to_produce: Vec<Vec<u8>> = Vec::new();
-            messages_to_produce.push(msg);
-            return Ok(request_builder::new_produce_request(topic_name, messages_to_produce));
+            let messages_to_produce: Vec<Vec<u8>> = vec![msg];
+            Ok(request_builder::new_produce_request(topic_name, messages_to_produce, partition))
         }
         Some((CONSUME, sub_matches)) => {
             let topic_name = sub_matches.get_one::<String>("TOPIC_NAME").expect("required");
             let consumer_group = sub_matches.get_one::<String>("CONSUMER_GROUP").expect("required");
-            return Ok(request_builder::new_consume_message(topic_name, consumer_group, 1));
+            let partition = sub_matches.get_one::<u32>("partition").copied();
+            let member_id = sub_matches.get_one::<String>("member").map(String::as_str);
+            let manual_commit = sub_matches.contains_id("manual-commit");
+            Ok(request_builder::new_consume_message(topic_name, consumer_group, 1, partition, member_id, manual_commit))
         }
         Some((TOPIC, sub_matches)) => {
             let topic_name = sub_matches.get_one::<String>("TOPIC_NAME").expect("required");
             let operation_type = sub_matches.get_one::<String>("TYPE").expect("required");
-            return Ok(request_builder::new_topic_request(topic_name, operation_type));
+            let mut config = Vec::new();
+            for setting in sub_matches.get_many::<String>("config").unwrap_or_default() {
+                let (key, value) = setting
+                    .split_once('=')
+                    .ok_or(format!("error: Topic setting {} isn't KEY=VALUE\n", setting))?;
+                config.push((key.to_string(), value.to_string()));
+            }
+            Ok(request_builder::new_topic_request(topic_name, operation_type, &config))
+        }
+        Some((GROUP, sub_matches)) => {
+            let topic_name = sub_matches.get_one::<String>("TOPIC_NAME").expect("required");
+            let consumer_group = sub_matches.get_one::<String>("CONSUMER_GROUP").expect("required");
+            let operation_type = sub_matches.get_one::<String>("TYPE").expect("required");
+            let member_id = sub_matches.get_one::<String>("member").map_or("", String::as_str);
+            let strategy = sub_matches.get_one::<String>("strategy").expect("defaulted");
+            let session_timeout = *sub_matches.get_one::<u64>("session-timeout").expect("defaulted");
+            Ok(request_builder::new_group_request(topic_name, consumer_group, operation_type, member_id, strategy, session_timeout))
+        }
+        Some((COMMIT, sub_matches)) => {
+            let topic_name = sub_matches.get_one::<String>("TOPIC_NAME").expect("required");
+            let consumer_group = sub_matches.get_one::<String>("CONSUMER_GROUP").expect("required");
+            let partition = *sub_matches.get_one::<u32>("PARTITION").expect("required");
+            let offset = *sub_matches.get_one::<u64>("OFFSET").expect("required");
+            let member_id = sub_matches.get_one::<String>("member").map_or("", String::as_str);
+            Ok(request_builder::new_commit_offset_request(topic_name, consumer_group, partition, offset, member_id))
+        }
+        Some((SEEK, sub_matches)) => {
+            let topic_name = sub_matches.get_one::<String>("TOPIC_NAME").expect("required");
+            let consumer_group = sub_matches.get_one::<String>("CONSUMER_GROUP").expect("required");
+            let partition = sub_matches.get_one::<u32>("partition").copied();
+            let target = if sub_matches.contains_id("to-earliest") {
+                request_builder::SeekTarget::Earliest
+            } else if sub_matches.contains_id("to-latest") {
+                request_builder::SeekTarget::Latest
+            } else if let Some(offset) = sub_matches.get_one::<u64>("to-offset") {
+                request_builder::SeekTarget::Offset(*offset)
+            } else if let Some(timestamp) = sub_matches.get_one::<u64>("to-timestamp") {
+                request_builder::SeekTarget::Timestamp(*timestamp)
+            } else {
+                request_builder::SeekTarget::Shift(*sub_matches.get_one::<i64>("shift-by").expect("required by group"))
+            };
+            Ok(request_builder::new_seek_request(topic_name, consumer_group, partition, target))
+        }
+        Some((DESCRIBE_GROUP, sub_matches)) => {
+            let topic_name = sub_matches.get_one::<String>("TOPIC_NAME").expect("required");
+            let consumer_group = sub_matches.get_one::<String>("CONSUMER_GROUP").map_or("", String::as_str);
+            let start_after = sub_matches.get_one::<String>("start-after").map_or("", String::as_str);
+            Ok(request_builder::new_describe_group_request(topic_name, consumer_group, start_after))
         }
         Some((QUIT, _matches)) => {
             write!(std::io::stdout(), "Exiting ...").map_err(|e| e.to_string())?;
             std::io::stdout().flush().map_err(|e| e.to_string())?;
-            return Ok("0".as_bytes().to_vec());
+            Ok("0".as_bytes().to_vec())
         }
         Some((name, _matches)) => unimplemented!("{}", name),
         None => unreachable!("subcommand required"),
@@ -101,7 +151,7 @@ async fn stdin_processor(topic_name: &str, stdin_tx: UnboundedSender<Vec<u8>>, m
         let stdin = io::stdin();
         let mut handle = stdin.lock();
         handle.read_line(&mut buffer)?;
-        if buffer.len() == 0 {
+        if buffer.is_empty() {
             continue;
         }
         messages_to_produce.push(buffer.as_bytes().to_vec());
@@ -110,7 +160,7 @@ async fn stdin_processor(topic_name: &str, stdin_tx: UnboundedSender<Vec<u8>>, m
             elapsed_duration = start_time.elapsed();
             continue;
         }
-        let msg = request_builder::new_produce_request(topic_name, messages_to_produce);
+        let msg = request_builder::new_produce_request(topic_name, messages_to_produce, None);
         stdin_tx.send(msg).expect("Unable to send message");
         // Reset our vector to clear out our buffer
         messages_to_produce = Vec::new();
@@ -125,7 +175,7 @@ async fn stdin_processor(topic_name: &str, stdin_tx: UnboundedSender<Vec<u8>>, m
 
 async fn stdout_processor(topic_name: &str, consumer_group: &str, stdin_tx: UnboundedSender<Vec<u8>>, mut stdin_rx: UnboundedReceiver<String>) -> io::Result<()> {
     loop {
-        let msg = request_builder::new_consume_message(topic_name, consumer_group, 500);
+        let msg = request_builder::new_consume_message(topic_name, consumer_group, 500, None, None, false);
         stdin_tx.send(msg).expect("Unable to send message");
         let response = stdin_rx.recv().await.expect("Unable to recieve message");
         write!(std::io::stdout(), "{}", response).expect("Unable to write message");
@@ -175,7 +225,7 @@ async fn main() -> Result<(), String> {
 
             stdin_processor(topic_name, request_channel_sender, response_channel_reciever).await.expect("Unable to process messages");
             info!("Exiting...");
-            return Ok(());
+            Ok(())
         },
         Some((CONSUMER, sub_matches)) => {
             let address = sub_matches.get_one::<String>("ADDRESS").expect("required");
@@ -192,7 +242,7 @@ async fn main() -> Result<(), String> {
 
             stdout_processor(topic_name, consumer_group, request_channel_sender, response_channel_reciever).await.expect("Unable to process consumer message");
             info!("Exiting...");
-            return Ok(());
+            Ok(())
         }
         _ => unreachable!(), // If all subcommands are defined above, anything else is unreachabe!()
     } 
@@ -1,12 +1,15 @@
 use capnp::message::Builder;
 use capnp::serialize_packed;
 use std::time::{SystemTime, UNIX_EPOCH};
-use crate::lucid_schema_capnp::{topic_request, produce_request, consume_request, message_envelope};
-use crate::utils::{TOPIC_CREATE, TOPIC_DESCRIBE, TOPIC_DELETE, TOPIC_ALL};
+use crate::lucid_schema_capnp::{topic_request, produce_request, consume_request, group_request, commit_offset_request, seek_request, describe_group_request,
+    message_envelope, AssignmentStrategy};
+use crate::utils::{
+    TOPIC_CREATE, TOPIC_DESCRIBE, TOPIC_DELETE, TOPIC_ALL, GROUP_JOIN, GROUP_HEARTBEAT, GROUP_LEAVE, STRATEGY_ROUND_ROBIN,
+};
 
-pub fn new_topic_request(topic_name: &str, topic_request_type: &str) -> Vec<u8> {
+pub fn new_topic_request(topic_name: &str, topic_request_type: &str, config: &[(String, String)]) -> Vec<u8> {
     match topic_request_type {
-        TOPIC_CREATE => new_topic_request_create(topic_name),
+        TOPIC_CREATE => new_topic_request_create(topic_name, config),
         TOPIC_DESCRIBE => new_topic_request_describe(topic_name),
         TOPIC_DELETE => new_topic_request_delete(topic_name),
         TOPIC_ALL => new_topic_request_all(),
@@ -14,7 +17,7 @@ pub fn new_topic_request(topic_name: &str, topic_request_type: &str) -> Vec<u8>
     }
 }
 
-fn new_topic_request_create(topic_name: &str) -> Vec<u8> {
+fn new_topic_request_create(topic_name: &str, config: &[(String, String)]) -> Vec<u8> {
     let mut request_message_envelope = Builder::new_default();
     let mut message_envelope = request_message_envelope.init_root::<message_envelope::Builder>();
 
@@ -23,14 +26,18 @@ fn new_topic_request_create(topic_name: &str) -> Vec<u8> {
 
     topic_request.set_topic_name(topic_name);
     topic_request.set_create(());
+    let mut entries = topic_request.reborrow().init_config(u32::try_from(config.len()).unwrap());
+    for (i, (key, value)) in config.iter().enumerate() {
+        let mut entry = entries.reborrow().get(i.try_into().unwrap());
+        entry.set_key(key);
+        entry.set_value(value);
+    }
 
     message_envelope.set_topic_request(topic_request.reborrow_as_reader()).expect("Unable to set message sent");
 
     let mut buffer = vec![];
     serialize_packed::write_message(&mut buffer, &request_message_envelope).expect("Unable to serialize packed message");
-    let framed_message = create_message_frame(buffer);
-
-    framed_message
+    create_message_frame(buffer)
 }
 
 fn new_topic_request_all() -> Vec<u8> {
@@ -47,9 +54,7 @@ fn new_topic_request_all() -> Vec<u8> {
 
     let mut buffer = vec![];
     serialize_packed::write_message(&mut buffer, &request_message_envelope).expect("Unable to serialize packed message");
-    let framed_message = create_message_frame(buffer);
-
-    framed_message
+    create_message_frame(buffer)
 }
 
 fn new_topic_request_describe(topic_name: &str) -> Vec<u8> {
@@ -66,9 +71,7 @@ fn new_topic_request_describe(topic_name: &str) -> Vec<u8> {
 
     let mut buffer = vec![];
     serialize_packed::write_message(&mut buffer, &request_message_envelope).expect("Unable to serialize packed message");
-    let framed_message = create_message_frame(buffer);
-
-    framed_message
+    create_message_frame(buffer)
 }
 
 fn new_topic_request_delete(topic_name: &str) -> Vec<u8> {
@@ -85,12 +88,10 @@ fn new_topic_request_delete(topic_name: &str) -> Vec<u8> {
 
     let mut buffer = vec![];
     serialize_packed::write_message(&mut buffer, &request_message_envelope).expect("Unable to serialize packed message");
-    let framed_message = create_message_frame(buffer);
-
-    framed_message
+    create_message_frame(buffer)
 }
 
-pub fn new_produce_request(topic_name: &str, values: Vec<Vec<u8>>) -> Vec<u8> {
+pub fn new_produce_request(topic_name: &str, values: Vec<Vec<u8>>, partition: Option<u32>) -> Vec<u8> {
     let mut request_message_envelope = Builder::new_default();
     let mut message_envelope = request_message_envelope.init_root::<message_envelope::Builder>();
     let mut request_message = Builder::new_default();
@@ -98,6 +99,9 @@ pub fn new_produce_request(topic_name: &str, values: Vec<Vec<u8>>) -> Vec<u8> {
         let mut produce_request = request_message.init_root::<produce_request::Builder>();
     
         produce_request.set_topic_name(topic_name);
+        if let Some(partition) = partition {
+            produce_request.reborrow().init_partition().set_id(partition);
+        }
         
         let size = u32::try_from(values.len()).unwrap();
 
@@ -119,12 +123,10 @@ pub fn new_produce_request(topic_name: &str, values: Vec<Vec<u8>>) -> Vec<u8> {
 
     let mut buffer = vec![];
     serialize_packed::write_message(&mut buffer, &request_message_envelope).expect("Unable to serialize packed message");
-    let framed_message = create_message_frame(buffer);
-
-    framed_message
+    create_message_frame(buffer)
 }
 
-pub fn new_consume_message(topic_name: &str, consumer_group: &str, timeout: u64) -> Vec<u8> {
+pub fn new_consume_message(topic_name: &str, consumer_group: &str, timeout: u64, partition: Option<u32>, member_id: Option<&str>, manual_commit: bool) -> Vec<u8> {
     let mut request_message_envelope = Builder::new_default();
     let mut message_envelope = request_message_envelope.init_root::<message_envelope::Builder>();
 
@@ -134,14 +136,135 @@ pub fn new_consume_message(topic_name: &str, consumer_group: &str, timeout: u64)
     consume_request.set_topic_name(topic_name);
     consume_request.set_consumer_group(consumer_group);
     consume_request.set_timout(timeout);
+    if let Some(partition) = partition {
+        consume_request.reborrow().init_partition().set_id(partition);
+    }
+    if let Some(member_id) = member_id {
+        consume_request.set_member_id(member_id);
+    }
+    consume_request.set_manual_commit(manual_commit);
 
     message_envelope.set_consume_request(consume_request.reborrow_as_reader()).expect("Unable to set message sent");
 
     let mut buffer = vec![];
     serialize_packed::write_message(&mut buffer, &request_message_envelope).expect("Unable to serialize packed message");
-    let framed_message = create_message_frame(buffer);
     
-    framed_message
+    create_message_frame(buffer)
+}
+
+pub fn new_group_request(
+    topic_name: &str,
+    consumer_group: &str,
+    group_request_type: &str,
+    member_id: &str,
+    strategy: &str,
+    session_timeout: u64,
+) -> Vec<u8> {
+    let mut request_message_envelope = Builder::new_default();
+    let mut message_envelope = request_message_envelope.init_root::<message_envelope::Builder>();
+
+    let mut request_message = Builder::new_default();
+    let mut group_request = request_message.init_root::<group_request::Builder>();
+
+    group_request.set_topic_name(topic_name);
+    group_request.set_consumer_group(consumer_group);
+    group_request.set_member_id(member_id);
+    match group_request_type {
+        GROUP_JOIN => {
+            let mut join = group_request.reborrow().init_join();
+            join.set_session_timeout(session_timeout);
+            if strategy == STRATEGY_ROUND_ROBIN {
+                join.set_strategy(AssignmentStrategy::RoundRobin);
+            } else {
+                join.set_strategy(AssignmentStrategy::Range);
+            }
+        }
+        GROUP_HEARTBEAT => group_request.set_heartbeat(()),
+        GROUP_LEAVE => group_request.set_leave(()),
+        _ => panic!("Invalid group command..."),
+    }
+
+    message_envelope.set_group_request(group_request.reborrow_as_reader()).expect("Unable to set message sent");
+
+    let mut buffer = vec![];
+    serialize_packed::write_message(&mut buffer, &request_message_envelope).expect("Unable to serialize packed message");
+    create_message_frame(buffer)
+}
+
+pub fn new_commit_offset_request(topic_name: &str, consumer_group: &str, partition: u32, offset: u64, member_id: &str) -> Vec<u8> {
+    let mut request_message_envelope = Builder::new_default();
+    let mut message_envelope = request_message_envelope.init_root::<message_envelope::Builder>();
+
+    let mut request_message = Builder::new_default();
+    let mut commit_offset_request = request_message.init_root::<commit_offset_request::Builder>();
+
+    commit_offset_request.set_topic_name(topic_name);
+    commit_offset_request.set_consumer_group(consumer_group);
+    commit_offset_request.set_partition(partition);
+    commit_offset_request.set_offset(offset);
+    commit_offset_request.set_member_id(member_id);
+
+    message_envelope.set_commit_offset_request(commit_offset_request.reborrow_as_reader()).expect("Unable to set message sent");
+
+    let mut buffer = vec![];
+    serialize_packed::write_message(&mut buffer, &request_message_envelope).expect("Unable to serialize packed message");
+    create_message_frame(buffer)
+}
+
+/// Where a seek request moves the consumer group to.
+pub enum SeekTarget {
+    Earliest,
+    Latest,
+    Offset(u64),
+    Timestamp(u64),
+    Shift(i64),
+}
+
+pub fn new_seek_request(topic_name: &str, consumer_group: &str, partition: Option<u32>, target: SeekTarget) -> Vec<u8> {
+    let mut request_message_envelope = Builder::new_default();
+    let mut message_envelope = request_message_envelope.init_root::<message_envelope::Builder>();
+
+    let mut request_message = Builder::new_default();
+    let mut seek_request = request_message.init_root::<seek_request::Builder>();
+
+    seek_request.set_topic_name(topic_name);
+    seek_request.set_consumer_group(consumer_group);
+    match partition {
+        Some(partition) => seek_request.reborrow().init_partition().set_id(partition),
+        None => seek_request.reborrow().init_partition().set_all(()),
+    }
+    let mut to = seek_request.reborrow().init_to();
+    match target {
+        SeekTarget::Earliest => to.set_earliest(()),
+        SeekTarget::Latest => to.set_latest(()),
+        SeekTarget::Offset(offset) => to.set_offset(offset),
+        SeekTarget::Timestamp(timestamp) => to.set_timestamp(timestamp),
+        SeekTarget::Shift(shift) => to.set_shift(shift),
+    }
+
+    message_envelope.set_seek_request(seek_request.reborrow_as_reader()).expect("Unable to set message sent");
+
+    let mut buffer = vec![];
+    serialize_packed::write_message(&mut buffer, &request_message_envelope).expect("Unable to serialize packed message");
+    create_message_frame(buffer)
+}
+
+pub fn new_describe_group_request(topic_name: &str, consumer_group: &str, start_after: &str) -> Vec<u8> {
+    let mut request_message_envelope = Builder::new_default();
+    let mut message_envelope = request_message_envelope.init_root::<message_envelope::Builder>();
+
+    let mut request_message = Builder::new_default();
+    let mut describe_group_request = request_message.init_root::<describe_group_request::Builder>();
+
+    describe_group_request.set_topic_name(topic_name);
+    describe_group_request.set_consumer_group(consumer_group);
+    describe_group_request.set_start_after(start_after);
+
+    message_envelope.set_describe_group_request(describe_group_request.reborrow_as_reader()).expect("Unable to set message sent");
+
+    let mut buffer = vec![];
+    serialize_packed::write_message(&mut buffer, &request_message_envelope).expect("Unable to serialize packed message");
+    create_message_frame(buffer)
 }
 
 fn create_message_frame(mut original_message: Vec<u8>) -> Vec<u8> {
@@ -149,5 +272,5 @@ fn create_message_frame(mut original_message: Vec<u8>) -> Vec<u8> {
     let size_in_bytes = size_u16.to_le_bytes();
     // Append the size in bytes to the begining of the vector
     original_message.splice(0..0, size_in_bytes.iter().cloned());
-    return original_message;
+    original_message
 }
\ No newline at end of file
@@ -27,7 +27,7 @@ async fn write_to_stream(mut stream: TcpStream, mut rx: UnboundedReceiver<Vec<u8
             break;
         }
         debug!("{:?}", message);
-        stream.write(&message).expect("Unable to send message");
+        stream.write_all(&message).expect("Unable to send message");
     }
     stream.shutdown(Shutdown::Both).expect("unable to shutdown stream");
 }
@@ -5,6 +5,10 @@ pub const CONSUMER: &str = "consumer";
 pub const PRODUCE: &str = "produce";
 pub const CONSUME: &str = "consume";
 pub const TOPIC: &str = "topic";
+pub const GROUP: &str = "group";
+pub const COMMIT: &str = "commit";
+pub const SEEK: &str = "seek";
+pub const DESCRIBE_GROUP: &str = "describe-group";
 pub const QUIT: &str = "quit";
 
 pub const TOPIC_CREATE: &str = "create";
@@ -12,3 +16,9 @@ pub const TOPIC_DESCRIBE: &str = "describe";
 pub const TOPIC_DELETE: &str = "delete";
 pub const TOPIC_ALL: &str = "all";
 
+pub const GROUP_JOIN: &str = "join";
+pub const GROUP_HEARTBEAT: &str = "heartbeat";
+pub const GROUP_LEAVE: &str = "leave";
+
+pub const STRATEGY_RANGE: &str = "range";
+pub const STRATEGY_ROUND_ROBIN: &str = "round-robin";
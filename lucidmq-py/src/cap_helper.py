@@ -33,10 +33,15 @@ def topic_request_describe(topic_name: str) -> bytes:
     message_envelope.topicRequest = topic_request
     return create_message_frame(message_envelope.to_bytes_packed())
 
-def topic_request_create(topic_name: str) -> bytes:
+def topic_request_create(topic_name: str, config: dict = None) -> bytes:
     topic_request = lucid_schema_capnp.TopicRequest.new_message()
     topic_request.topicName = topic_name
     topic_request.create = None
+    if config:
+        entries = topic_request.init('config', len(config))
+        for i, (key, value) in enumerate(config.items()):
+            entries[i].key = key
+            entries[i].value = str(value)
 
     message_envelope = lucid_schema_capnp.MessageEnvelope.new_message()
     message_envelope.topicRequest = topic_request
@@ -60,9 +65,12 @@ def topic_request_all() -> bytes:
     message_envelope.topicRequest = topic_request
     return create_message_frame(message_envelope.to_bytes_packed())
 
-def produce_request(topic_name: str, key: bytes, value: bytes) -> bytes:
+def produce_request(topic_name: str, key: bytes, value: bytes, partition: int = None) -> bytes:
     produce_request = lucid_schema_capnp.ProduceRequest.new_message()
     produce_request.topicName = topic_name
+    if partition is not None:
+        # Otherwise the message goes to the partition of its key
+        produce_request.partition.id = partition
     messages = produce_request.init('messages', 1)
 
     message = messages[0]
@@ -76,16 +84,104 @@ def produce_request(topic_name: str, key: bytes, value: bytes) -> bytes:
     message_envelope.produceRequest = produce_request
     return create_message_frame(message_envelope.to_bytes_packed())
 
-def consume_request(topic_name: str, consumer_group: str, timeout: int) -> bytes:
+def consume_request(topic_name: str, consumer_group: str, timeout: int, from_timestamp: int = None, partition: int = None, member_id: str = None, manual_commit: bool = False) -> bytes:
     consume_request = lucid_schema_capnp.ConsumeRequest.new_message()
     consume_request.topicName = topic_name
     consume_request.consumerGroup = consumer_group
     consume_request.timout = timeout
+    if from_timestamp is not None:
+        # Move the consumer group to the first message at or after the timestamp(ms since the epoch)
+        consume_request.fromTimestamp = from_timestamp
+    if partition is not None:
+        # Otherwise messages are consumed from every partition
+        consume_request.partition.id = partition
+    if member_id is not None:
+        # Only the partitions assigned to the member are read
+        consume_request.memberId = member_id
+    # Without auto commit the messages are consumed again until they're committed
+    consume_request.manualCommit = manual_commit
 
     message_envelope = lucid_schema_capnp.MessageEnvelope.new_message()
     message_envelope.consumeRequest = consume_request
     return create_message_frame(message_envelope.to_bytes_packed())
 
+def commit_offset_request(topic_name: str, consumer_group: str, partition: int, offset: int, member_id: str = None) -> bytes:
+    commit_offset_request = lucid_schema_capnp.CommitOffsetRequest.new_message()
+    commit_offset_request.topicName = topic_name
+    commit_offset_request.consumerGroup = consumer_group
+    commit_offset_request.partition = partition
+    commit_offset_request.offset = offset
+    if member_id is not None:
+        commit_offset_request.memberId = member_id
+
+    message_envelope = lucid_schema_capnp.MessageEnvelope.new_message()
+    message_envelope.commitOffsetRequest = commit_offset_request
+    return create_message_frame(message_envelope.to_bytes_packed())
+
+def seek_request(topic_name: str, consumer_group: str, to: str, value: int = None, partition: int = None) -> bytes:
+    seek_request = lucid_schema_capnp.SeekRequest.new_message()
+    seek_request.topicName = topic_name
+    seek_request.consumerGroup = consumer_group
+    if partition is None:
+        seek_request.partition.all = None
+    else:
+        seek_request.partition.id = partition
+    # Either 'earliest', 'latest', 'offset', 'timestamp' or 'shift', only the last three take a value
+    setattr(seek_request.to, to, value)
+
+    message_envelope = lucid_schema_capnp.MessageEnvelope.new_message()
+    message_envelope.seekRequest = seek_request
+    return create_message_frame(message_envelope.to_bytes_packed())
+
+def describe_group_request(topic_name: str, consumer_group: str = "", start_after: str = "") -> bytes:
+    describe_group_request = lucid_schema_capnp.DescribeGroupRequest.new_message()
+    describe_group_request.topicName = topic_name
+    # Every consumer group of the topic is described when it's empty
+    describe_group_request.consumerGroup = consumer_group
+    # Only the consumer groups named after it are described, to get the next page of consumer groups
+    describe_group_request.startAfter = start_after
+
+    message_envelope = lucid_schema_capnp.MessageEnvelope.new_message()
+    message_envelope.describeGroupRequest = describe_group_request
+    return create_message_frame(message_envelope.to_bytes_packed())
+
+#### All group requests
+def group_request_join(topic_name: str, consumer_group: str, member_id: str, session_timeout: int, strategy: str) -> bytes:
+    group_request = lucid_schema_capnp.GroupRequest.new_message()
+    group_request.topicName = topic_name
+    group_request.consumerGroup = consumer_group
+    group_request.memberId = member_id
+    join = group_request.init('join')
+    join.sessionTimeout = session_timeout
+    # Either 'range' or 'roundRobin'
+    join.strategy = strategy
+
+    message_envelope = lucid_schema_capnp.MessageEnvelope.new_message()
+    message_envelope.groupRequest = group_request
+    return create_message_frame(message_envelope.to_bytes_packed())
+
+def group_request_heartbeat(topic_name: str, consumer_group: str, member_id: str) -> bytes:
+    group_request = lucid_schema_capnp.GroupRequest.new_message()
+    group_request.topicName = topic_name
+    group_request.consumerGroup = consumer_group
+    group_request.memberId = member_id
+    group_request.heartbeat = None
+
+    message_envelope = lucid_schema_capnp.MessageEnvelope.new_message()
+    message_envelope.groupRequest = group_request
+    return create_message_frame(message_envelope.to_bytes_packed())
+
+def group_request_leave(topic_name: str, consumer_group: str, member_id: str) -> bytes:
+    group_request = lucid_schema_capnp.GroupRequest.new_message()
+    group_request.topicName = topic_name
+    group_request.consumerGroup = consumer_group
+    group_request.memberId = member_id
+    group_request.leave = None
+
+    message_envelope = lucid_schema_capnp.MessageEnvelope.new_message()
+    message_envelope.groupRequest = group_request
+    return create_message_frame(message_envelope.to_bytes_packed())
+
 
 def create_message_frame(orginal_data: bytes) -> bytes:
     num_bytes = len(orginal_data)
@@ -102,6 +198,14 @@ def response_parser(data: bytes):
             return message_envelope.produceResponse
         case 'consumeResponse':
             return message_envelope.consumeResponse
+        case 'groupResponse':
+            return message_envelope.groupResponse
+        case 'commitOffsetResponse':
+            return message_envelope.commitOffsetResponse
+        case 'seekResponse':
+            return message_envelope.seekResponse
+        case 'describeGroupResponse':
+            return message_envelope.describeGroupResponse
         case 'invalidResponse':
             return message_envelope.invalidResponse
         case _:
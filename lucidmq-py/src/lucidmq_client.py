@@ -25,8 +25,8 @@ class Producer(LucidmqClient):
     def __init__(self, host: str, port: int):
         super().__init__(host, port)
 
-    def produce(self, topic_name: str, key: bytes, value: bytes) -> dict:
-        msg = cap_helper.produce_request(topic_name, key, value)
+    def produce(self, topic_name: str, key: bytes, value: bytes, partition: int = None) -> dict:
+        msg = cap_helper.produce_request(topic_name, key, value, partition)
         self.send_message_bytes(msg)
         data = self.recieve_response()
         produce_response_obj = cap_helper.response_parser(data)
@@ -40,12 +40,47 @@ class Consumer(LucidmqClient):
         self.timeout = timeout
         super().__init__(host, port)
 
-    def consume(self, topic_name: str, consumer_group: str) -> dict:
-        msg = cap_helper.consume_request(topic_name, consumer_group, self.timeout)
+    def consume(self, topic_name: str, consumer_group: str, from_timestamp: int = None, partition: int = None, member_id: str = None, manual_commit: bool = False) -> dict:
+        msg = cap_helper.consume_request(topic_name, consumer_group, self.timeout, from_timestamp, partition, member_id, manual_commit)
         self.send_message_bytes(msg)
         data = self.recieve_response()
         cosumer_response_object = cap_helper.response_parser(data)
         return cosumer_response_object.to_dict()
+
+    def commit_offset(self, topic_name: str, consumer_group: str, partition: int, offset: int, member_id: str = None) -> dict:
+        msg = cap_helper.commit_offset_request(topic_name, consumer_group, partition, offset, member_id)
+        self.send_message_bytes(msg)
+        data = self.recieve_response()
+        commit_offset_response_object = cap_helper.response_parser(data)
+        return commit_offset_response_object.to_dict()
+
+    def seek(self, topic_name: str, consumer_group: str, to: str, value: int = None, partition: int = None) -> dict:
+        msg = cap_helper.seek_request(topic_name, consumer_group, to, value, partition)
+        self.send_message_bytes(msg)
+        data = self.recieve_response()
+        seek_response_object = cap_helper.response_parser(data)
+        return seek_response_object.to_dict()
+
+    def join_group(self, topic_name: str, consumer_group: str, member_id: str = "", session_timeout: int = 10000, strategy: str = "range") -> dict:
+        msg = cap_helper.group_request_join(topic_name, consumer_group, member_id, session_timeout, strategy)
+        self.send_message_bytes(msg)
+        data = self.recieve_response()
+        group_response_object = cap_helper.response_parser(data)
+        return group_response_object.to_dict()
+
+    def heartbeat(self, topic_name: str, consumer_group: str, member_id: str) -> dict:
+        msg = cap_helper.group_request_heartbeat(topic_name, consumer_group, member_id)
+        self.send_message_bytes(msg)
+        data = self.recieve_response()
+        group_response_object = cap_helper.response_parser(data)
+        return group_response_object.to_dict()
+
+    def leave_group(self, topic_name: str, consumer_group: str, member_id: str) -> dict:
+        msg = cap_helper.group_request_leave(topic_name, consumer_group, member_id)
+        self.send_message_bytes(msg)
+        data = self.recieve_response()
+        group_response_object = cap_helper.response_parser(data)
+        return group_response_object.to_dict()
     
     def close(self) -> None:
         self.close_client()
@@ -54,8 +89,8 @@ class TopicManager(LucidmqClient):
     def __init__(self, host: str, port: int):
         super().__init__(host, port)
     
-    def create_topic(self, topic_name: str) -> dict:
-        msg = cap_helper.topic_request_create(topic_name)
+    def create_topic(self, topic_name: str, config: dict = None) -> dict:
+        msg = cap_helper.topic_request_create(topic_name, config)
         self.send_message_bytes(msg)
         data = self.recieve_response()
         topic_response_object = cap_helper.response_parser(data)
@@ -81,6 +116,24 @@ class TopicManager(LucidmqClient):
         data = self.recieve_response()
         topic_all_object = cap_helper.response_parser(data)
         return topic_all_object.to_dict()
+
+    def describe_group(self, topic_name: str, consumer_group: str = "") -> dict:
+        # The broker describes the consumer groups in pages ordered by name, so every page is requested
+        consumer_groups = []
+        start_after = ""
+        while True:
+            msg = cap_helper.describe_group_request(topic_name, consumer_group, start_after)
+            self.send_message_bytes(msg)
+            data = self.recieve_response()
+            describe_group_object = cap_helper.response_parser(data).to_dict()
+            page = describe_group_object.get("consumerGroups", [])
+            consumer_groups.extend(page)
+            if not describe_group_object.get("success") or not describe_group_object.get("more") or not page:
+                break
+            start_after = page[-1]["name"]
+        describe_group_object["consumerGroups"] = consumer_groups
+        describe_group_object["more"] = False
+        return describe_group_object
         
     def close(self) -> None:
         self.close_client()
\ No newline at end of file
@@ -1,11 +1,17 @@
 use crate::cap_n_proto_helper::{
     new_consume_response, new_produce_response, new_topic_response_create,
-    new_topic_response_delete, new_topic_response_describe, new_topic_response_all, new_invalid_response
+    new_topic_response_delete, new_topic_response_describe, new_topic_response_all, new_invalid_response,
+    new_group_response, GroupResponseType, new_commit_offset_response, new_seek_response,
+    new_describe_group_response, describe_group_page_len,
+};
+use crate::coordinator::{Assignment, AssignmentStrategy, GroupCoordinator};
+use crate::lucid_schema_capnp::{
+    self, commit_offset_request, consume_request, describe_group_request, group_request, produce_request, seek_request, topic_request,
 };
-use crate::lucid_schema_capnp::{consume_request, produce_request, topic_request};
 use crate::{
-    consumer::Consumer, producer::Producer, topic::Topic, types::Command, types::SenderType,
-    types::RecieverType, topic::SimpleTopic
+    consumer::Consumer, consumer::SeekTarget, producer::Producer, topic::Topic, types::Command, types::SenderType,
+    types::RecieverType, topic::SimpleTopic, topic::LegacyTopic, topic::TopicConfig,
+    topic::CleanupPolicy
 };
 use capnp::{
     message::{Builder, HeapAllocator, TypedReader},
@@ -13,12 +19,21 @@ use capnp::{
 };
 use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs::{self, OpenOptions};
 use std::io::Write;
 use std::path::Path;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use crate::lucidmq_errors::{BrokerError, ProtocolError};
 
-use crate::lucidmq_errors::BrokerError;
+/// Magic bytes written at the start of lucidmq.meta, followed by a byte for the version of the metadata format.
+/// Files written before the header was introduced have no header and use 32 bit consumer group offsets.
+const META_FILE_MAGIC: &[u8; 7] = b"LMQMETA";
+/// Current version of the metadata format. The metadata is written as JSON, so settings added later are filled in
+/// with their defaults when an older file is loaded instead of needing a new version.
+const META_FILE_VERSION: u8 = 1;
 
 /// The brain of the operation. It is responsible for data about topics and how to run correspoding commands on them.
 #[derive(Serialize, Deserialize, Clone)]
@@ -26,6 +41,12 @@ use crate::lucidmq_errors::BrokerError;
 pub struct Broker {
     pub base_directory: String,
     topics: Arc<RwLock<Vec<Arc<RwLock<Topic>>>>>,
+    /// Key file new topics are encrypted at rest with, it's configured when the broker starts and isn't persisted
+    #[serde(skip)]
+    encryption_key_file: Option<String>,
+    /// Members of the consumer groups and the partitions assigned to them, members join again after a restart
+    #[serde(skip)]
+    coordinator: Arc<Mutex<GroupCoordinator>>,
 }
 
 #[derive(Deserialize)]
@@ -39,23 +60,68 @@ impl From<DeserBroker> for Broker {
         Self {
             base_directory: tmp.base_directory,
             topics: tmp.topics,
+            encryption_key_file: None,
+            coordinator: Arc::default(),
+        }
+    }
+}
+
+/// Deserialize a broker from a lucidmq.meta file that was written before the metadata header existed
+#[derive(Deserialize)]
+struct LegacyBroker {
+    base_directory: String,
+    topics: Vec<LegacyTopic>,
+}
+
+impl From<LegacyBroker> for Broker {
+    fn from(tmp: LegacyBroker) -> Self {
+        let topics = tmp
+            .topics
+            .into_iter()
+            .map(|topic| Arc::new(RwLock::new(Topic::from(topic))))
+            .collect();
+        Self {
+            base_directory: tmp.base_directory,
+            topics: Arc::new(RwLock::new(topics)),
+            encryption_key_file: None,
+            coordinator: Arc::default(),
         }
     }
 }
 
 impl Broker {
-    /// Create a new instance of a broker
-    pub fn new(directory: String) -> Result<Broker, BrokerError> {
+    /// Create a new instance of a broker, new topics are encrypted at rest with the keys in the key file if one is given
+    pub fn new(directory: String, encryption_key_file: Option<String>) -> Result<Broker, BrokerError> {
         debug!("Creating new instance of lucidmq in {}", directory);
         //Try to load from file
         let lucidmq_file_path = Path::new(&directory).join("lucidmq.meta");
         let file_bytes = fs::read(lucidmq_file_path);
         match file_bytes {
             Ok(bytes) => {
-                let decoded_lucidmq: Broker = bincode::deserialize(&bytes).map_err(|e| {
-                    error!("{}", e);
-                    BrokerError::new("Unable to deserialize lucidmq.meta file")
-                })?;
+                let versioned_bytes = bytes
+                    .strip_prefix(META_FILE_MAGIC)
+                    .and_then(|meta_bytes| meta_bytes.split_first());
+                let mut decoded_lucidmq: Broker = match versioned_bytes {
+                    Some((&META_FILE_VERSION, meta_bytes)) => serde_json::from_slice(meta_bytes).map_err(|e| {
+                        error!("{}", e);
+                        BrokerError::new("Unable to deserialize lucidmq.meta file")
+                    })?,
+                    Some((version, _)) => {
+                        error!("lucidmq.meta file has unknown version {}", version);
+                        return Err(BrokerError::new("Unsupported lucidmq.meta file version"));
+                    }
+                    None => {
+                        info!("Migrating lucidmq.meta file from the legacy format");
+                        let legacy_lucidmq: LegacyBroker = bincode::deserialize(&bytes).map_err(|e| {
+                            error!("{}", e);
+                            BrokerError::new("Unable to deserialize legacy lucidmq.meta file")
+                        })?;
+                        let migrated_lucidmq = Broker::from(legacy_lucidmq);
+                        migrated_lucidmq.flush()?;
+                        migrated_lucidmq
+                    }
+                };
+                decoded_lucidmq.encryption_key_file = encryption_key_file;
                 Ok(decoded_lucidmq)
             }
             Err(_err) => {
@@ -67,6 +133,8 @@ impl Broker {
                 let lucidmq = Broker {
                     base_directory: directory.clone(),
                     topics: Arc::new(RwLock::new(lucidmq_vec)),
+                    encryption_key_file,
+                    coordinator: Arc::default(),
                 };
                 fs::create_dir_all(directory).map_err(|e| {
                     error!("{}", e);
@@ -92,7 +160,7 @@ impl Broker {
                     match result_data {
                         Ok(data) => {
                             Command::Response {
-                                conn_id: conn_id,
+                                conn_id,
                                 capmessagedata: data,
                             }
                         }
@@ -100,7 +168,7 @@ impl Broker {
                             let error_string = err.to_string();
                             let data = self.handle_invalid_message(&error_string).await?;
                             Command::Invalid {
-                                conn_id: conn_id,
+                                conn_id,
                                 error_message: error_string,
                                 capmessage_data: data
                             }
@@ -115,7 +183,7 @@ impl Broker {
                     match result_data {
                         Ok(data) => {
                             Command::Response {
-                                conn_id: conn_id,
+                                conn_id,
                                 capmessagedata: data,
                             }
                         },
@@ -123,7 +191,7 @@ impl Broker {
                             let error_string = err.to_string();
                             let data = self.handle_invalid_message(&error_string).await?;
                             Command::Invalid {
-                                conn_id: conn_id,
+                                conn_id,
                                 error_message: error_string,
                                 capmessage_data: data
                             }
@@ -139,7 +207,7 @@ impl Broker {
                     match result_data {
                         Ok(data) =>{
                             Command::Response {
-                                conn_id: conn_id,
+                                conn_id,
                                 capmessagedata: data,
                             }
                         }                     
@@ -147,7 +215,7 @@ impl Broker {
                             let error_string = err.to_string();
                             let data = self.handle_invalid_message(&error_string).await?;
                             Command::Invalid {
-                                conn_id: conn_id,
+                                conn_id,
                                 error_message: error_string,
                                 capmessage_data: data
                             }
@@ -155,11 +223,103 @@ impl Broker {
                     }
 
                 }
+                Command::GroupRequest {
+                    conn_id,
+                    capmessage,
+                } => {
+                    let result_data = self.handle_group(capmessage);
+                    match result_data {
+                        Ok(data) => {
+                            Command::Response {
+                                conn_id,
+                                capmessagedata: data,
+                            }
+                        }
+                        Err(err) => {
+                            let error_string = err.to_string();
+                            let data = self.handle_invalid_message(&error_string).await?;
+                            Command::Invalid {
+                                conn_id,
+                                error_message: error_string,
+                                capmessage_data: data
+                            }
+                        },
+                    }
+                }
+                Command::CommitOffsetRequest {
+                    conn_id,
+                    capmessage,
+                } => {
+                    let result_data = self.handle_commit_offset(capmessage);
+                    match result_data {
+                        Ok(data) => {
+                            Command::Response {
+                                conn_id,
+                                capmessagedata: data,
+                            }
+                        }
+                        Err(err) => {
+                            let error_string = err.to_string();
+                            let data = self.handle_invalid_message(&error_string).await?;
+                            Command::Invalid {
+                                conn_id,
+                                error_message: error_string,
+                                capmessage_data: data
+                            }
+                        },
+                    }
+                }
+                Command::SeekRequest {
+                    conn_id,
+                    capmessage,
+                } => {
+                    let result_data = self.handle_seek(capmessage);
+                    match result_data {
+                        Ok(data) => {
+                            Command::Response {
+                                conn_id,
+                                capmessagedata: data,
+                            }
+                        }
+                        Err(err) => {
+                            let error_string = err.to_string();
+                            let data = self.handle_invalid_message(&error_string).await?;
+                            Command::Invalid {
+                                conn_id,
+                                error_message: error_string,
+                                capmessage_data: data
+                            }
+                        },
+                    }
+                }
+                Command::DescribeGroupRequest {
+                    conn_id,
+                    capmessage,
+                } => {
+                    let result_data = self.handle_describe_group(capmessage);
+                    match result_data {
+                        Ok(data) => {
+                            Command::Response {
+                                conn_id,
+                                capmessagedata: data,
+                            }
+                        }
+                        Err(err) => {
+                            let error_string = err.to_string();
+                            let data = self.handle_invalid_message(&error_string).await?;
+                            Command::Invalid {
+                                conn_id,
+                                error_message: error_string,
+                                capmessage_data: data
+                            }
+                        },
+                    }
+                }
                 Command::Invalid { conn_id, error_message,  capmessage_data:_} => {
                     let data = self.handle_invalid_message(&error_message).await?;
                     Command::Invalid {
-                        conn_id: conn_id,
-                        error_message: error_message,
+                        conn_id,
+                        error_message,
                         capmessage_data: data
                     }
                 }
@@ -167,7 +327,7 @@ impl Broker {
                     warn!("Response type unexected command");
                     let data = self.handle_invalid_message("Response message is invalid").await?;
                     Command::Invalid {
-                        conn_id: conn_id,
+                        conn_id,
                         error_message: "Response message is invalid".to_string(),
                         capmessage_data: data
                     }
@@ -175,13 +335,77 @@ impl Broker {
                 },
             };
             let res = sender.send(response_command).await;
-            match res {
+            if let Err(e) = res {
+                error!("{}", e);
+                return Err(BrokerError::new("Unable to send message"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Periodically syncs records that are due, rolls segments past their segment time and runs the cleaner of every
+    /// topic, so fsync and retention policies are applied to topics that aren't being produced to.
+    pub async fn run_cleaner(self, clean_interval: Duration) {
+        info!("Topic cleaner is running every {:?}", clean_interval);
+        let mut interval = tokio::time::interval(clean_interval);
+        loop {
+            interval.tick().await;
+            // The topics are copied out, so topics can be created and deleted while they're cleaned
+            let topics = match self.topics.read() {
+                Ok(topics) => topics.clone(),
                 Err(e) => {
                     error!("{}", e);
-                    return Err(BrokerError::new("Unable to send message"));
+                    continue;
+                }
+            };
+            // Cleaning rolls, deletes and rewrites segment files, so it's kept off the async worker threads
+            if let Err(e) = tokio::task::spawn_blocking(move || Broker::clean_topics(&topics)).await {
+                error!("{}", e);
+            }
+        }
+    }
+
+    /// Clean up the commitlog of every topic based on its retention settings, compacted topics are compacted instead.
+    /// A topic that fails to clean is logged and skipped, so it doesn't hold back the rest.
+    fn clean_topics(topics: &[Arc<RwLock<Topic>>]) {
+        for topic in topics {
+            if let Err(err) = Broker::clean_topic(topic) {
+                error!("{}", err);
+            }
+        }
+    }
+
+    /// Sync records that are due and roll the segments of the topic past their segment time, then clean or compact its
+    /// partitions.
+    fn clean_topic(topic: &RwLock<Topic>) -> Result<(), BrokerError> {
+        // The commitlog cleans up behind its own locks, so consumers keep reading while it does
+        let topic = topic.read().map_err(|e| {
+            error!("{}", e);
+            BrokerError::new("Unable to get read lock on topic")
+        })?;
+        for commitlog in &topic.partitions {
+            commitlog.sync_if_due().map_err(|e| {
+                error!("{}", e);
+                BrokerError::new("Unable to sync topic segment")
+            })?;
+            commitlog.roll().map_err(|e| {
+                error!("{}", e);
+                BrokerError::new("Unable to roll topic segment")
+            })?;
+        }
+        match topic.cleanup_policy {
+            CleanupPolicy::Delete => {
+                for commitlog in &topic.partitions {
+                    commitlog.clean().map_err(|e| {
+                        error!("{}", e);
+                        BrokerError::new("Unable to clean topic")
+                    })?;
                 }
-                Ok(_) => {}
             }
+            CleanupPolicy::Compact => topic.compact().map_err(|e| {
+                error!("{}", e);
+                BrokerError::new("Unable to compact topic")
+            })?,
         }
         Ok(())
     }
@@ -201,7 +425,23 @@ impl Broker {
         })?;
         match topic_request.which() {
             Ok(topic_request::Which::Create(_create_request)) => {
-                Ok(self.handle_create_topic(topic_name))?
+                let config_entries = topic_request.get_config().map_err(|e| {
+                    error!("{}", e);
+                    BrokerError::new("Unable to unpack topic config from topic request")
+                })?;
+                let mut entries = Vec::with_capacity(config_entries.len() as usize);
+                for entry in config_entries {
+                    let key = entry.get_key().map_err(|e| {
+                        error!("{}", e);
+                        BrokerError::new("Unable to unpack topic config key from topic request")
+                    })?;
+                    let value = entry.get_value().map_err(|e| {
+                        error!("{}", e);
+                        BrokerError::new("Unable to unpack topic config value from topic request")
+                    })?;
+                    entries.push((key.to_string(), value.to_string()));
+                }
+                Ok(self.handle_create_topic(topic_name, &entries))?
             }
             Ok(topic_request::Which::Delete(_delete_request)) => {
                 Ok(self.handle_delete_topic(topic_name))?
@@ -216,21 +456,28 @@ impl Broker {
         }
     }
 
-    /// Given a topic name, create a new topic
-    /// TODO: we need segment size and topic size to be configurable instead of hard coded.
-    fn handle_create_topic(&mut self, topic_name: &str) -> Result<Vec<u8>, BrokerError> {
+    /// Given a topic name and the settings from the request, create a new topic. Settings that aren't given use the
+    /// defaults, invalid settings are returned as an error so the client gets to see why the topic wasn't created.
+    fn handle_create_topic(&mut self, topic_name: &str, config_entries: &[(String, String)]) -> Result<Vec<u8>, BrokerError> {
         let found_index = self.check_topics(topic_name);
         match found_index {
             Some(_) => {
                 warn!("topic already exisits");
-                Ok(new_topic_response_create(topic_name, false))
+                new_topic_response_create(topic_name, false).map_err(Broker::map_response_error)
             }
             None => {
+                let defaults = TopicConfig {
+                    encryption_key_file: self.encryption_key_file.clone(),
+                    ..TopicConfig::default()
+                };
+                let config = TopicConfig::from_entries(config_entries, defaults).map_err(|err| {
+                    error!("{}", err);
+                    BrokerError::new(&format!("Invalid topic config: {}", err))
+                })?;
                 let topic = Topic::new(
                     topic_name.to_string(),
                     self.base_directory.clone(),
-                    100000, //100kb
-                    1000000, //1mb
+                    config,
                 ).map_err(|err| {
                     error!("{}", err);
                     BrokerError::new("Unable to create topic directory")
@@ -249,7 +496,7 @@ impl Broker {
                         .push(Arc::new(RwLock::new(topic)));
                 }
                 self.flush()?;
-                Ok(new_topic_response_create(topic_name, true))
+                new_topic_response_create(topic_name, true).map_err(Broker::map_response_error)
             }
         }
     }
@@ -270,20 +517,22 @@ impl Broker {
                 let cgs = topic.get_consumer_groups();
                 let max_segment_size = topic.get_max_segment_size();
                 info!("{}, {:?}, {}", topic_name, cgs, max_segment_size);
-                Ok(new_topic_response_describe(
+                new_topic_response_describe(
                     topic_name,
                     true,
                     topic.max_topic_size,
                     topic.max_segment_size,
                     cgs,
-                ))
+                    topic.config().entries(),
+                    topic.get_partitions(),
+                ).map_err(Broker::map_response_error)
             }
             None => {
                 warn!("topic does not exist");
                 let dummy_vec = Vec::new();
-                Ok(new_topic_response_describe(
-                    topic_name, false, 0, 0, dummy_vec,
-                ))
+                new_topic_response_describe(
+                    topic_name, false, 0, 0, dummy_vec, Vec::new(), Vec::new(),
+                ).map_err(Broker::map_response_error)
             }
         }
     }
@@ -318,12 +567,19 @@ impl Broker {
                     error!("{}", e);
                     BrokerError::new("Unable to delete diretory of topic")
                 })?;
+                self.coordinator
+                    .lock()
+                    .map_err(|e| {
+                        error!("{}", e);
+                        BrokerError::new("Unable to get lock on group coordinator")
+                    })?
+                    .remove_topic(topic_name);
                 self.flush()?;
-                Ok(new_topic_response_delete(topic_name, true))
+                new_topic_response_delete(topic_name, true).map_err(Broker::map_response_error)
             }
             None => {
                 warn!("topic does not exist");
-                Ok(new_topic_response_delete(topic_name, false))
+                new_topic_response_delete(topic_name, false).map_err(Broker::map_response_error)
             }
         }
     }
@@ -342,12 +598,105 @@ impl Broker {
             let consumer_groups = topic.read().unwrap().get_consumer_groups();
             let st = SimpleTopic {
                 topic_name: topic_name.to_string(),
-                consumer_groups: consumer_groups
+                consumer_groups
             };
             simple_topics.push(st)
         }
-        let response_data = new_topic_response_all(true, simple_topics);
-        Ok(response_data)
+        new_topic_response_all(true, simple_topics).map_err(Broker::map_response_error)
+    }
+
+    /// Given a group request, add a member to a consumer group, keep it in the consumer group or remove it. Responds
+    /// with the partitions assigned to the member.
+    fn handle_group(
+        &mut self,
+        group_request_message: TypedReader<Builder<HeapAllocator>, group_request::Owned>,
+    ) -> Result<Vec<u8>, BrokerError> {
+        let group_request = group_request_message.get().map_err(|e| {
+            error!("{}", e);
+            BrokerError::new("Unable to unpack group request message")
+        })?;
+        let topic_name = group_request.get_topic_name().map_err(|e| {
+            error!("{}", e);
+            BrokerError::new("Unable to get topic name from group request")
+        })?;
+        let consumer_group = group_request.get_consumer_group().map_err(|e| {
+            error!("{}", e);
+            BrokerError::new("Unable to get consumer group from group request")
+        })?;
+        let member_id = group_request.get_member_id().map_err(|e| {
+            error!("{}", e);
+            BrokerError::new("Unable to get member id from group request")
+        })?;
+        let request_type = group_request.which().map_err(|e| {
+            error!("{}", e);
+            BrokerError::new("Unknown group request type")
+        })?;
+        let response_type = match request_type {
+            group_request::Join(_) => GroupResponseType::Join,
+            group_request::Heartbeat(()) => GroupResponseType::Heartbeat,
+            group_request::Leave(()) => GroupResponseType::Leave,
+        };
+        let found_topic = match self.check_topics(topic_name) {
+            Some(ind) => self.topics.read().map_err(|e| {
+                error!("{}", e);
+                BrokerError::new("Unable to get read lock on topics")
+            })?[ind].clone(),
+            None => {
+                warn!("topic does not exist");
+                let no_assignment = Assignment {
+                    member_id: member_id.to_string(),
+                    generation: 0,
+                    partitions: Vec::new(),
+                };
+                return new_group_response(topic_name, consumer_group, false, response_type, &no_assignment)
+                    .map_err(Broker::map_response_error);
+            }
+        };
+        let mut coordinator = self.coordinator.lock().map_err(|e| {
+            error!("{}", e);
+            BrokerError::new("Unable to get lock on group coordinator")
+        })?;
+        let assignment = match request_type {
+            group_request::Join(join) => {
+                let strategy = match join.get_strategy() {
+                    Ok(lucid_schema_capnp::AssignmentStrategy::Range) => AssignmentStrategy::Range,
+                    Ok(lucid_schema_capnp::AssignmentStrategy::RoundRobin) => AssignmentStrategy::RoundRobin,
+                    Err(e) => {
+                        error!("{}", e);
+                        return Err(BrokerError::new("Unknown assignment strategy"));
+                    }
+                };
+                let partition_count = {
+                    let mut topic = found_topic.write().map_err(|e| {
+                        error!("{}", e);
+                        BrokerError::new("Unable to get write lock on topic")
+                    })?;
+                    topic.load_consumer_group(consumer_group);
+                    topic.partition_count()
+                };
+                coordinator.join(
+                    topic_name,
+                    consumer_group,
+                    member_id,
+                    partition_count,
+                    strategy,
+                    join.get_session_timeout(),
+                )
+            }
+            group_request::Heartbeat(()) => coordinator.heartbeat(topic_name, consumer_group, member_id),
+            group_request::Leave(()) => coordinator
+                .leave(topic_name, consumer_group, member_id)
+                .map(|generation| Assignment {
+                    member_id: member_id.to_string(),
+                    generation,
+                    partitions: Vec::new(),
+                }),
+        }
+        .map_err(|err| {
+            error!("{}", err);
+            BrokerError::new(&err.to_string())
+        })?;
+        new_group_response(topic_name, consumer_group, true, response_type, &assignment).map_err(Broker::map_response_error)
     }
 
     async fn handle_consumer(
@@ -376,35 +725,315 @@ impl Broker {
                     error!("{}", e);
                     BrokerError::new("Unable to get read lock on topic")
                 })?[x];
-                let consumer_group = found_topic
-                    .write()
-                    .map_err(|e| {
+                let (consumer_group, partition_count) = {
+                    let mut topic = found_topic.write().map_err(|e| {
                         error!("{}", e);
                         BrokerError::new("Unable to get wrote lock on topic")
-                    })?
-                    .load_consumer_group(consumer_group);
+                    })?;
+                    (topic.load_consumer_group(consumer_group), topic.partition_count())
+                };
+                let member_id = consume_request_reader.get_member_id().map_err(|e| {
+                    error!("{}", e);
+                    BrokerError::new("Unable to get member id from consume request")
+                })?;
+                // Members of a consumer group only read from the partitions assigned to them, consuming counts as a
+                // heartbeat. Reading without a member would read the partitions the members were assigned as well.
+                let readable_partitions: Vec<u32> = if member_id.is_empty() {
+                    self.check_no_members(topic_name, consumer_group.name.as_str())?;
+                    (0..partition_count).collect()
+                } else {
+                    self.coordinator
+                        .lock()
+                        .map_err(|e| {
+                            error!("{}", e);
+                            BrokerError::new("Unable to get lock on group coordinator")
+                        })?
+                        .heartbeat(topic_name, consumer_group.name.as_str(), member_id)
+                        .map_err(|err| {
+                            error!("{}", err);
+                            BrokerError::new(&err.to_string())
+                        })?
+                        .partitions
+                };
+                let partitions = match consume_request_reader.get_partition().which() {
+                    Ok(consume_request::partition::All(())) => readable_partitions,
+                    Ok(consume_request::partition::Id(partition)) => {
+                        if partition < partition_count && !readable_partitions.contains(&partition) {
+                            return Err(BrokerError::new("Partition is not assigned to the member"));
+                        }
+                        vec![partition]
+                    }
+                    Err(e) => {
+                        error!("{}", e);
+                        return Err(BrokerError::new("Unable to get partition from consume request"));
+                    }
+                };
+                if partitions.is_empty() {
+                    info!("member {} has no partitions assigned", member_id);
+                    return new_consume_response(topic_name, false, 0, 0, &[]).map_err(Broker::map_response_error);
+                }
                 let mut consumer = Consumer::new(
                     found_topic.clone(),
                     consumer_group,
+                    partitions,
                     Box::new(move || broker.flush()),
                 ).map_err(|e| {
                     error!("{}", e);
                     BrokerError::new("Unable to create new consumer")
                 })?;
-                let messages = consumer.poll(timeout).map_err(|e| {
+                consumer.set_auto_commit(!consume_request_reader.get_manual_commit());
+                match consume_request_reader.which() {
+                    Ok(consume_request::GroupOffset(())) => {}
+                    Ok(consume_request::FromTimestamp(timestamp)) => {
+                        consumer.seek_to_timestamp(timestamp).map_err(|e| {
+                            error!("{}", e);
+                            BrokerError::new("Unable to seek consumer group to timestamp")
+                        })?;
+                    }
+                    Err(e) => {
+                        error!("{}", e);
+                        return Err(BrokerError::new("Unable to get starting point from consume request"));
+                    }
+                }
+                // The response is built while the messages are borrowed from the commitlog
+                let data = consumer
+                    .poll(timeout, |partition, next_offset, messages| {
+                        new_consume_response(topic_name, true, partition, next_offset, messages)
+                    })
+                    .map_err(|e| {
+                        error!("{}", e);
+                        BrokerError::new("Unable to poll consumers commitlog")
+                    })?;
+                data.map_err(Broker::map_response_error)
+            }
+            None => {
+                warn!("topic does not exist");
+                new_consume_response(topic_name, false, 0, 0, &[]).map_err(Broker::map_response_error)
+            }
+        }
+    }
+
+    /// Given a commit offset request, commit every message of a partition before the offset as processed by the
+    /// consumer group. Members can only commit the partitions assigned to them, and commits without a member are
+    /// rejected while the consumer group has members.
+    fn handle_commit_offset(
+        &mut self,
+        commit_offset_request: TypedReader<Builder<HeapAllocator>, commit_offset_request::Owned>,
+    ) -> Result<Vec<u8>, BrokerError> {
+        let commit_offset_reader = commit_offset_request.get().map_err(|e| {
+            error!("{}", e);
+            BrokerError::new("Unable to get commit offset request from bytes")
+        })?;
+        let topic_name = commit_offset_reader.get_topic_name().map_err(|e| {
+            error!("{}", e);
+            BrokerError::new("Unable to get topic name from commit offset request")
+        })?;
+        let consumer_group_name = commit_offset_reader.get_consumer_group().map_err(|e| {
+            error!("{}", e);
+            BrokerError::new("Unable to get consumer group from commit offset request")
+        })?;
+        let member_id = commit_offset_reader.get_member_id().map_err(|e| {
+            error!("{}", e);
+            BrokerError::new("Unable to get member id from commit offset request")
+        })?;
+        let partition = commit_offset_reader.get_partition();
+        let offset = commit_offset_reader.get_offset();
+        let found_topic = match self.check_topics(topic_name) {
+            Some(ind) => self.topics.read().map_err(|e| {
+                error!("{}", e);
+                BrokerError::new("Unable to get read lock on topics")
+            })?[ind].clone(),
+            None => {
+                warn!("topic does not exist");
+                return new_commit_offset_response(topic_name, consumer_group_name, false, partition, offset)
+                    .map_err(Broker::map_response_error);
+            }
+        };
+        if member_id.is_empty() {
+            self.check_no_members(topic_name, consumer_group_name)?;
+        } else {
+            let assignment = self
+                .coordinator
+                .lock()
+                .map_err(|e| {
                     error!("{}", e);
-                    BrokerError::new("Unable to poll consumers commitlog")
+                    BrokerError::new("Unable to get lock on group coordinator")
+                })?
+                .heartbeat(topic_name, consumer_group_name, member_id)
+                .map_err(|err| {
+                    error!("{}", err);
+                    BrokerError::new(&err.to_string())
                 })?;
-                let data = new_consume_response(topic_name, true, messages);
-                Ok(data)
+            if !assignment.partitions.contains(&partition) {
+                return Err(BrokerError::new("Partition is not assigned to the member"));
+            }
+        }
+        let consumer_group = found_topic
+            .write()
+            .map_err(|e| {
+                error!("{}", e);
+                BrokerError::new("Unable to get write lock on topic")
+            })?
+            .load_consumer_group(consumer_group_name);
+        let broker = self.clone();
+        let mut consumer = Consumer::new(
+            found_topic,
+            consumer_group,
+            vec![partition],
+            Box::new(move || broker.flush()),
+        ).map_err(|err| {
+            error!("{}", err);
+            BrokerError::new(&err.to_string())
+        })?;
+        consumer.commit_offset(partition, offset).map_err(|err| {
+            error!("{}", err);
+            BrokerError::new(&err.to_string())
+        })?;
+        new_commit_offset_response(topic_name, consumer_group_name, true, partition, offset)
+            .map_err(Broker::map_response_error)
+    }
+
+    /// Returns an error if the consumer group of the topic has active members, for requests that would act on the
+    /// consumer group behind the backs of its members.
+    fn check_no_members(&self, topic_name: &str, consumer_group_name: &str) -> Result<(), BrokerError> {
+        let has_members = self
+            .coordinator
+            .lock()
+            .map_err(|e| {
+                error!("{}", e);
+                BrokerError::new("Unable to get lock on group coordinator")
+            })?
+            .has_members(topic_name, consumer_group_name);
+        if has_members {
+            warn!("consumer group {} of topic {} has active members", consumer_group_name, topic_name);
+            return Err(BrokerError::new("Consumer group has active members"));
+        }
+        Ok(())
+    }
+
+    /**
+    Moves a consumer group of a topic in one or every partition. Consumer groups with active members are rejected
+    since the members would carry on from where they were, the members have to leave or time out first.
+     */
+    fn handle_seek(
+        &mut self,
+        seek_request: TypedReader<Builder<HeapAllocator>, seek_request::Owned>,
+    ) -> Result<Vec<u8>, BrokerError> {
+        let seek_reader = seek_request.get().map_err(|e| {
+            error!("{}", e);
+            BrokerError::new("Unable to get seek request from bytes")
+        })?;
+        let topic_name = seek_reader.get_topic_name().map_err(|e| {
+            error!("{}", e);
+            BrokerError::new("Unable to get topic name from seek request")
+        })?;
+        let consumer_group_name = seek_reader.get_consumer_group().map_err(|e| {
+            error!("{}", e);
+            BrokerError::new("Unable to get consumer group from seek request")
+        })?;
+        let target = match seek_reader.get_to().which() {
+            Ok(seek_request::to::Earliest(())) => SeekTarget::Earliest,
+            Ok(seek_request::to::Latest(())) => SeekTarget::Latest,
+            Ok(seek_request::to::Offset(offset)) => SeekTarget::Offset(offset),
+            Ok(seek_request::to::Timestamp(timestamp)) => SeekTarget::Timestamp(timestamp),
+            Ok(seek_request::to::Shift(shift)) => SeekTarget::Shift(shift),
+            Err(e) => {
+                error!("{}", e);
+                return Err(BrokerError::new("Unable to get seek target from seek request"));
+            }
+        };
+        let found_topic = match self.check_topics(topic_name) {
+            Some(ind) => self.topics.read().map_err(|e| {
+                error!("{}", e);
+                BrokerError::new("Unable to get read lock on topics")
+            })?[ind].clone(),
+            None => {
+                warn!("topic does not exist");
+                return new_seek_response(topic_name, consumer_group_name, false, &[]).map_err(Broker::map_response_error);
+            }
+        };
+        self.check_no_members(topic_name, consumer_group_name)?;
+        let (consumer_group, partition_count) = {
+            let mut topic = found_topic.write().map_err(|e| {
+                error!("{}", e);
+                BrokerError::new("Unable to get write lock on topic")
+            })?;
+            (topic.load_consumer_group(consumer_group_name), topic.partition_count())
+        };
+        let partitions: Vec<u32> = match seek_reader.get_partition().which() {
+            Ok(seek_request::partition::All(())) => (0..partition_count).collect(),
+            Ok(seek_request::partition::Id(partition)) => vec![partition],
+            Err(e) => {
+                error!("{}", e);
+                return Err(BrokerError::new("Unable to get partition from seek request"));
             }
+        };
+        let broker = self.clone();
+        let mut consumer = Consumer::new(
+            found_topic,
+            consumer_group,
+            partitions.clone(),
+            Box::new(move || broker.flush()),
+        ).map_err(|err| {
+            error!("{}", err);
+            BrokerError::new(&err.to_string())
+        })?;
+        let offsets = consumer.seek(target).map_err(|err| {
+            error!("{}", err);
+            BrokerError::new(&err.to_string())
+        })?;
+        let partition_offsets: Vec<(u32, u64)> = partitions.into_iter().zip(offsets).collect();
+        new_seek_response(topic_name, consumer_group_name, true, &partition_offsets).map_err(Broker::map_response_error)
+    }
+
+    /// Reports how far behind the log end offset the consumer groups of a topic are in every partition.
+    fn handle_describe_group(
+        &mut self,
+        describe_group_request: TypedReader<Builder<HeapAllocator>, describe_group_request::Owned>,
+    ) -> Result<Vec<u8>, BrokerError> {
+        let describe_group_reader = describe_group_request.get().map_err(|e| {
+            error!("{}", e);
+            BrokerError::new("Unable to get describe group request from bytes")
+        })?;
+        let topic_name = describe_group_reader.get_topic_name().map_err(|e| {
+            error!("{}", e);
+            BrokerError::new("Unable to get topic name from describe group request")
+        })?;
+        let consumer_group_name = describe_group_reader.get_consumer_group().map_err(|e| {
+            error!("{}", e);
+            BrokerError::new("Unable to get consumer group from describe group request")
+        })?;
+        let start_after = describe_group_reader.get_start_after().map_err(|e| {
+            error!("{}", e);
+            BrokerError::new("Unable to get start after from describe group request")
+        })?;
+        let found_topic = match self.check_topics(topic_name) {
+            Some(ind) => self.topics.read().map_err(|e| {
+                error!("{}", e);
+                BrokerError::new("Unable to get read lock on topics")
+            })?[ind].clone(),
             None => {
                 warn!("topic does not exist");
-                let message_data = Vec::new();
-                let data = new_consume_response(topic_name, false, message_data);
-                Ok(data)
+                return new_describe_group_response(topic_name, false, &[], false).map_err(Broker::map_response_error);
             }
+        };
+        let topic = found_topic.read().map_err(|e| {
+            error!("{}", e);
+            BrokerError::new("Unable to get read lock on topic")
+        })?;
+        let filter = (!consumer_group_name.is_empty()).then_some(consumer_group_name);
+        let mut consumer_groups = topic.describe_consumer_groups(filter);
+        if filter.is_some() && consumer_groups.is_empty() {
+            warn!("consumer group {} does not exist", consumer_group_name);
+            return new_describe_group_response(topic_name, false, &[], false).map_err(Broker::map_response_error);
         }
+        // Every partition of every consumer group doesn't always fit in one response, the consumer groups are paged
+        // through by name
+        consumer_groups.sort_by(|a, b| a.name.cmp(&b.name));
+        consumer_groups.retain(|consumer_group| consumer_group.name.as_str() > start_after);
+        let page_len = describe_group_page_len(&consumer_groups);
+        new_describe_group_response(topic_name, true, &consumer_groups[..page_len], page_len < consumer_groups.len())
+            .map_err(Broker::map_response_error)
     }
 
     async fn handle_producer(
@@ -428,40 +1057,80 @@ impl Broker {
                     BrokerError::new("Unable to get reader for topics")
                 })?[x];
                 let mut producer = Producer::new(found_topic.clone());
-                // Parse out cap n proto produce messages and submit them to the commitlog
+                let explicit_partition = match produce_request_reader.get_partition().which() {
+                    Ok(produce_request::partition::ByKey(())) => None,
+                    Ok(produce_request::partition::Id(partition)) => Some(partition),
+                    Err(e) => {
+                        error!("{}", e);
+                        return Err(BrokerError::new("Unable to get partition from produce request"));
+                    }
+                };
+                // Parse out cap n proto produce messages and group them by the partition they're produced to
                 let cap_msgs = produce_request_reader.get_messages().map_err(|e| {
                     error!("{}", e);
                     BrokerError::new("Unable to get produce request messages")
                 })?;
-                // Let's insert multiple messages here, instead of iterating through each one.
-                // let mut builder_message = Builder::new_default();
-                // builder_message.set_root(cap_msgs).unwrap();
-                // serialize::write_message_to_words(&builder_message);
-                let mut last_offset = 0;
-                for msg in cap_msgs {
-                    let mut builder_message = Builder::new_default();
-                    builder_message.set_root(msg).map_err(|e| {
+                let mut partition_batches: BTreeMap<u32, Vec<Vec<u8>>> = BTreeMap::new();
+                let mut last_partition = 0;
+                {
+                    let topic = found_topic.read().map_err(|e| {
                         error!("{}", e);
-                        BrokerError::new("Unable to set root for our produce request builder")
+                        BrokerError::new("Unable to get read lock on topic")
                     })?;
-                    let bytes = serialize::write_message_to_words(&builder_message);
-                    last_offset = producer.produce_bytes(&bytes).map_err(|e| {
+                    for msg in cap_msgs {
+                        let partition = match explicit_partition {
+                            Some(partition) => partition,
+                            None => topic.partition_for_key(msg.get_key().map_err(|e| {
+                                error!("{}", e);
+                                BrokerError::new("Unable to get key of produce request message")
+                            })?),
+                        };
+                        let mut builder_message = Builder::new_default();
+                        builder_message.set_root(msg).map_err(|e| {
+                            error!("{}", e);
+                            BrokerError::new("Unable to set root for our produce request builder")
+                        })?;
+                        partition_batches
+                            .entry(partition)
+                            .or_default()
+                            .push(serialize::write_message_to_words(&builder_message));
+                        last_partition = partition;
+                    }
+                }
+                // Every batch is checked before any is appended, so a produce that's rejected writes nothing. The
+                // messages for a partition are appended as one batch, but a storage failure part way through can
+                // leave the batches of the partitions before it produced.
+                let partition_offsets: Vec<(u32, u64)> = producer
+                    .produce_batches(&partition_batches)
+                    .map_err(|e| {
                         error!("{}", e);
                         BrokerError::new("Unable to produce message to commitlog")
-                    })?;
-                }
-                Ok(new_produce_response(topic_name, last_offset.into(), true))
+                    })?
+                    .into_iter()
+                    .map(|(partition, written_offsets)| (partition, written_offsets.end.saturating_sub(1)))
+                    .collect();
+                let last_offset = partition_offsets
+                    .iter()
+                    .find(|(partition, _)| *partition == last_partition)
+                    .map_or(0, |(_, offset)| *offset);
+                new_produce_response(topic_name, last_offset, true, &partition_offsets).map_err(Broker::map_response_error)
             }
             None => {
                 warn!("Topic {} does not exist", topic_name);
-                Ok(new_produce_response(topic_name, 0, false))
+                new_produce_response(topic_name, 0, false, &[]).map_err(Broker::map_response_error)
             }
         }
     }
 
     async fn handle_invalid_message(&self, message_text: &str) -> Result<Vec<u8>, BrokerError> {
-        let data = new_invalid_response(message_text);
-        Ok(data)
+        new_invalid_response(message_text).map_err(Broker::map_response_error)
+    }
+
+    /// Convert an error from building a response message, like a response too large for its message frame, into the
+    /// error the client is sent back instead.
+    fn map_response_error(err: ProtocolError) -> BrokerError {
+        error!("{}", err);
+        BrokerError::new("Unable to create response message")
     }
 
     fn check_topics(&mut self, topic_to_find: &str) -> Option<usize> {
@@ -485,10 +1154,7 @@ impl Broker {
                     .name
                     == *topic_to_find
             });
-        match indexed_value {
-            None => None,
-            Some(index) => Some(*index),
-        }
+        indexed_value.as_ref().map(|index| *index)
     }
 
     fn flush(&self) -> Result<(), BrokerError>{
@@ -497,16 +1163,18 @@ impl Broker {
             "Saving lucidmq state to file {}",
             lucidmq_file_path.to_string_lossy()
         );
-        let encoded_data: Vec<u8> =
-            bincode::serialize(&self).map_err(|err| {
-                error!("{}", err);
-                BrokerError::new("Unable to encode lucidmq metadata")
-            })?;
+        let mut encoded_data: Vec<u8> = META_FILE_MAGIC.to_vec();
+        encoded_data.push(META_FILE_VERSION);
+        serde_json::to_writer(&mut encoded_data, &self).map_err(|err| {
+            error!("{}", err);
+            BrokerError::new("Unable to encode lucidmq metadata")
+        })?;
         let mut file = OpenOptions::new()
             .create(true)
             .read(false)
             .write(true)
             .append(false)
+            .truncate(true)
             .open(lucidmq_file_path)
             .map_err(|err| {
                 error!("{}", err);
@@ -524,6 +1192,18 @@ impl Broker {
 #[cfg(test)]
 mod broker_tests {
     use crate::broker::Broker;
+    use crate::lucid_schema_capnp::{
+        commit_offset_request, consume_request, describe_group_request, group_request, group_response,
+        message_envelope, seek_request, seek_response, topic_response, AssignmentStrategy,
+    };
+    use crate::lucidmq_errors::BrokerError;
+    use crate::topic::{CleanupPolicy, Topic, TopicConfig, MAX_PARTITIONS};
+    use capnp::message::{ReaderOptions, TypedBuilder, TypedReader};
+    use capnp::serialize_packed;
+    use nolan::Compression;
+    use serde::Serialize;
+    use std::fs;
+    use std::sync::atomic::Ordering;
     use tempdir::TempDir;
     use std::path::Path;
 
@@ -534,9 +1214,437 @@ mod broker_tests {
             .path()
             .to_str()
             .expect("Unable to conver path to string");
-        let broker = Broker::new(String::from(tmp_dir_string)).expect("unable to create new broker");
+        let broker = Broker::new(String::from(tmp_dir_string), None).expect("unable to create new broker");
         assert!(Path::new(&broker.base_directory).is_dir());
     }
+
+    #[test]
+    fn test_load_legacy_meta() {
+        #[derive(Serialize)]
+        struct LegacyConsumerGroup {
+            name: String,
+            offset: u32,
+        }
+        #[derive(Serialize)]
+        struct LegacyTopic {
+            name: String,
+            directory: String,
+            consumer_groups: Vec<LegacyConsumerGroup>,
+            max_segment_size: u64,
+            max_topic_size: u64,
+        }
+        #[derive(Serialize)]
+        struct LegacyBroker {
+            base_directory: String,
+            topics: Vec<LegacyTopic>,
+        }
+
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_string = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let topic_directory = tmp_dir.path().join("topic").to_str().unwrap().to_string();
+        let legacy_broker = LegacyBroker {
+            base_directory: tmp_dir_string.to_string(),
+            topics: vec![LegacyTopic {
+                name: "legacy_topic".to_string(),
+                directory: topic_directory,
+                consumer_groups: vec![LegacyConsumerGroup {
+                    name: "legacy_cg".to_string(),
+                    offset: 42,
+                }],
+                max_segment_size: 100,
+                max_topic_size: 1000,
+            }],
+        };
+        let meta_path = tmp_dir.path().join("lucidmq.meta");
+        fs::write(&meta_path, bincode::serialize(&legacy_broker).unwrap()).unwrap();
+
+        let broker = Broker::new(String::from(tmp_dir_string), None).expect("unable to load legacy broker");
+        {
+            let topics = broker.topics.read().unwrap();
+            let topic: &Topic = &topics[0].read().unwrap();
+            assert_eq!("legacy_topic", topic.name);
+            assert_eq!(42, topic.consumer_groups[0].offsets[0].load(Ordering::SeqCst));
+        }
+
+        // The migrated metadata should have been written back in the current format
+        let reloaded = Broker::new(String::from(tmp_dir_string), None).expect("unable to reload broker");
+        assert_eq!(1, reloaded.topics.read().unwrap().len());
+    }
+
+    #[test]
+    fn test_meta_round_trip() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_string = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let mut broker = Broker::new(String::from(tmp_dir_string), None).expect("unable to create new broker");
+        let config = vec![
+            ("compression".to_string(), "lz4".to_string()),
+            ("retention_ms".to_string(), "60000".to_string()),
+            ("partitions".to_string(), "2".to_string()),
+        ];
+        broker.handle_create_topic("round_trip", &config).expect("unable to create topic");
+        {
+            let topics = broker.topics.read().unwrap();
+            let consumer_group = topics[0].write().unwrap().load_consumer_group("cg");
+            consumer_group.offsets[1].store(42, Ordering::SeqCst);
+            consumer_group.committed_offsets[1].store(7, Ordering::SeqCst);
+        }
+        broker.flush().expect("unable to flush broker");
+        let meta_bytes = fs::read(tmp_dir.path().join("lucidmq.meta")).unwrap();
+        assert!(meta_bytes.starts_with(b"LMQMETA\x01"));
+
+        let reloaded = Broker::new(String::from(tmp_dir_string), None).expect("unable to reload broker");
+        {
+            let topics = reloaded.topics.read().unwrap();
+            let topic: &Topic = &topics[0].read().unwrap();
+            assert_eq!("round_trip", topic.name);
+            assert_eq!(Compression::Lz4, topic.compression);
+            assert_eq!(Some(60000), topic.retention_ms);
+            assert_eq!(2, topic.partition_count());
+            let consumer_group = &topic.consumer_groups[0];
+            assert_eq!(42, consumer_group.offsets[1].load(Ordering::SeqCst));
+            assert_eq!(7, consumer_group.committed_offsets[1].load(Ordering::SeqCst));
+        }
+
+        // Fields missing from the metadata keep their defaults
+        let topic_directory = tmp_dir.path().join("minimal").to_str().unwrap().to_string();
+        let minimal_meta = format!(
+            r#"{{"base_directory":{:?},"topics":[{{"name":"minimal","directory":{:?},"max_segment_size":100,"max_topic_size":1000,"consumer_groups":[{{"name":"cg","offset":42}}]}}]}}"#,
+            tmp_dir_string, topic_directory
+        );
+        let mut meta_bytes = b"LMQMETA\x01".to_vec();
+        meta_bytes.extend_from_slice(minimal_meta.as_bytes());
+        fs::write(tmp_dir.path().join("lucidmq.meta"), meta_bytes).unwrap();
+        let broker = Broker::new(String::from(tmp_dir_string), None).expect("unable to load minimal broker");
+        let topics = broker.topics.read().unwrap();
+        let topic: &Topic = &topics[0].read().unwrap();
+        assert_eq!(None, topic.retention_ms);
+        assert_eq!(CleanupPolicy::Delete, topic.cleanup_policy);
+        assert_eq!(Compression::None, topic.compression);
+        assert_eq!(None, topic.encryption_key_file);
+        assert_eq!(TopicConfig::default().segment_ms, topic.segment_ms);
+        assert_eq!(None, topic.max_message_size);
+        // The commitlog of a topic written before partitions is its only partition
+        assert_eq!(vec![topic_directory], topic.partition_directories);
+        assert_eq!(42, topic.consumer_groups[0].offsets[0].load(Ordering::SeqCst));
+        // What the consumer group read so far counts as committed
+        assert_eq!(42, topic.consumer_groups[0].committed_offsets[0].load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_create_topic_with_config() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_string = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let mut broker = Broker::new(String::from(tmp_dir_string), None).expect("unable to create new broker");
+        let config = vec![
+            ("max_segment_bytes".to_string(), "1000".to_string()),
+            ("cleanup_policy".to_string(), "compact".to_string()),
+            ("max_message_bytes".to_string(), "500".to_string()),
+            ("partitions".to_string(), "3".to_string()),
+        ];
+        broker.handle_create_topic("configured", &config).expect("unable to create topic");
+
+        let invalid_config = vec![("max_message_bytes".to_string(), "200000".to_string())];
+        let err = broker.handle_create_topic("invalid", &invalid_config).unwrap_err();
+        assert_eq!(
+            BrokerError::new("Invalid topic config: Max message bytes has to be between 1 and the max segment bytes"),
+            err
+        );
+
+        // The settings are persisted with the topic
+        let reloaded = Broker::new(String::from(tmp_dir_string), None).expect("unable to reload broker");
+        let topics = reloaded.topics.read().unwrap();
+        assert_eq!(1, topics.len());
+        let topic: &Topic = &topics[0].read().unwrap();
+        assert_eq!("configured", topic.name);
+        assert_eq!(1000, topic.max_segment_size);
+        assert_eq!(TopicConfig::default().max_topic_size, topic.max_topic_size);
+        assert_eq!(CleanupPolicy::Compact, topic.cleanup_policy);
+        assert_eq!(Some(500), topic.max_message_size);
+        assert_eq!(3, topic.partition_count());
+        assert!(topic.partition_directories.iter().all(|directory| Path::new(directory).is_dir()));
+    }
+
+    #[test]
+    fn test_describe_topic_with_max_partitions() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_string = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let mut broker = Broker::new(String::from(tmp_dir_string), None).expect("unable to create new broker");
+        let config = vec![("partitions".to_string(), MAX_PARTITIONS.to_string())];
+        broker.handle_create_topic("wide", &config).expect("unable to create topic");
+
+        let data = broker.handle_describe_topic("wide").expect("unable to describe topic");
+        // The frame holds the size of the rest of the message
+        assert_eq!(data.len() - 2, u16::from_le_bytes([data[0], data[1]]) as usize);
+        let reader = serialize_packed::read_message(&data[2..], ReaderOptions::new()).unwrap();
+        let envelope = reader.get_root::<message_envelope::Reader>().unwrap();
+        let response: topic_response::Reader = match envelope.which().unwrap() {
+            message_envelope::TopicResponse(response) => response.unwrap(),
+            _ => panic!("Expected a topic response"),
+        };
+        assert!(response.get_success());
+        let describe = match response.which().unwrap() {
+            topic_response::Describe(describe) => describe,
+            _ => panic!("Expected a describe topic response"),
+        };
+        let partitions = describe.get_partitions().unwrap();
+        assert_eq!(MAX_PARTITIONS, partitions.len());
+        assert_eq!(MAX_PARTITIONS - 1, partitions.get(MAX_PARTITIONS - 1).get_partition());
+    }
+
+    /// Send a group request to the broker and return the member id, generation and partitions from the response.
+    fn send_group_request(
+        broker: &mut Broker,
+        member_id: &str,
+        set_type: impl FnOnce(group_request::Builder),
+    ) -> Result<(String, u64, Vec<u32>), BrokerError> {
+        let mut message = TypedBuilder::<group_request::Owned>::new_default();
+        let mut request = message.init_root();
+        request.set_topic_name("grouped");
+        request.set_consumer_group("cg");
+        request.set_member_id(member_id);
+        set_type(request);
+        let data = broker.handle_group(TypedReader::from(message))?;
+        // Skip the two bytes of the message frame
+        let reader = serialize_packed::read_message(&data[2..], ReaderOptions::new()).unwrap();
+        let envelope = reader.get_root::<message_envelope::Reader>().unwrap();
+        let response: group_response::Reader = match envelope.which().unwrap() {
+            message_envelope::GroupResponse(response) => response.unwrap(),
+            _ => panic!("Expected a group response"),
+        };
+        assert!(response.get_success());
+        Ok((
+            response.get_member_id().unwrap().to_string(),
+            response.get_generation(),
+            response.get_partitions().unwrap().iter().collect(),
+        ))
+    }
+
+    fn join(request: group_request::Builder) {
+        let mut join = request.init_join();
+        join.set_session_timeout(10000);
+        join.set_strategy(AssignmentStrategy::RoundRobin);
+    }
+
+    #[test]
+    fn test_group_membership() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_string = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let mut broker = Broker::new(String::from(tmp_dir_string), None).expect("unable to create new broker");
+        let config = vec![("partitions".to_string(), "3".to_string())];
+        broker.handle_create_topic("grouped", &config).expect("unable to create topic");
+
+        let (first, generation, partitions) = send_group_request(&mut broker, "", join).expect("unable to join");
+        assert_eq!(1, generation);
+        assert_eq!(vec![0, 1, 2], partitions);
+        let (second, generation, partitions) = send_group_request(&mut broker, "", join).expect("unable to join");
+        assert_eq!(2, generation);
+        assert_eq!(vec![1], partitions);
+        let (_, generation, partitions) = send_group_request(&mut broker, &first, |mut request| request.set_heartbeat(()))
+            .expect("unable to heartbeat");
+        assert_eq!(2, generation);
+        assert_eq!(vec![0, 2], partitions);
+        {
+            // Joining creates the consumer group on the topic
+            let topics = broker.topics.read().unwrap();
+            let topic: &Topic = &topics[0].read().unwrap();
+            assert_eq!(vec!["cg".to_string()], topic.get_consumer_groups());
+        }
+
+        send_group_request(&mut broker, &second, |mut request| request.set_leave(())).expect("unable to leave");
+        let (_, generation, partitions) = send_group_request(&mut broker, &first, |mut request| request.set_heartbeat(()))
+            .expect("unable to heartbeat");
+        assert_eq!(3, generation);
+        assert_eq!(vec![0, 1, 2], partitions);
+        assert_eq!(
+            Err(BrokerError::new("Member is not part of the consumer group")),
+            send_group_request(&mut broker, &second, |mut request| request.set_heartbeat(()))
+        );
+
+        // Members of a deleted topic are forgotten
+        broker.handle_delete_topic("grouped").expect("unable to delete topic");
+        broker.handle_create_topic("grouped", &config).expect("unable to create topic");
+        assert_eq!(
+            Err(BrokerError::new("Member is not part of the consumer group")),
+            send_group_request(&mut broker, &first, |mut request| request.set_heartbeat(()))
+        );
+    }
+
+    #[test]
+    fn test_memberless_requests_with_members() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_string = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let mut broker = Broker::new(String::from(tmp_dir_string), None).expect("unable to create new broker");
+        let config = vec![("partitions".to_string(), "3".to_string())];
+        broker.handle_create_topic("grouped", &config).expect("unable to create topic");
+        let (member, _, _) = send_group_request(&mut broker, "", join).expect("unable to join");
+
+        // Consuming or committing without a member would read and move the partitions assigned to the member
+        let mut message = TypedBuilder::<consume_request::Owned>::new_default();
+        let mut request = message.init_root();
+        request.set_topic_name("grouped");
+        request.set_consumer_group("cg");
+        request.reborrow().init_partition().set_all(());
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        assert_eq!(
+            Err(BrokerError::new("Consumer group has active members")),
+            runtime.block_on(broker.handle_consumer(TypedReader::from(message)))
+        );
+        let send_commit_request = |broker: &mut Broker| {
+            let mut message = TypedBuilder::<commit_offset_request::Owned>::new_default();
+            let mut request = message.init_root();
+            request.set_topic_name("grouped");
+            request.set_consumer_group("cg");
+            request.set_partition(0);
+            request.set_offset(0);
+            broker.handle_commit_offset(TypedReader::from(message)).map(|_| ())
+        };
+        assert_eq!(Err(BrokerError::new("Consumer group has active members")), send_commit_request(&mut broker));
+
+        send_group_request(&mut broker, &member, |mut request| request.set_leave(())).expect("unable to leave");
+        send_commit_request(&mut broker).expect("unable to commit without members");
+    }
+
+    fn send_seek_request(
+        broker: &mut Broker,
+        set_partition: impl FnOnce(seek_request::partition::Builder),
+        set_to: impl FnOnce(seek_request::to::Builder),
+    ) -> Result<Vec<(u32, u64)>, BrokerError> {
+        let mut message = TypedBuilder::<seek_request::Owned>::new_default();
+        let mut request = message.init_root();
+        request.set_topic_name("grouped");
+        request.set_consumer_group("cg");
+        set_partition(request.reborrow().init_partition());
+        set_to(request.init_to());
+        let data = broker.handle_seek(TypedReader::from(message))?;
+        // Skip the two bytes of the message frame
+        let reader = serialize_packed::read_message(&data[2..], ReaderOptions::new()).unwrap();
+        let envelope = reader.get_root::<message_envelope::Reader>().unwrap();
+        let response: seek_response::Reader = match envelope.which().unwrap() {
+            message_envelope::SeekResponse(response) => response.unwrap(),
+            _ => panic!("Expected a seek response"),
+        };
+        assert!(response.get_success());
+        Ok(response
+            .get_partition_offsets()
+            .unwrap()
+            .iter()
+            .map(|partition_offset| (partition_offset.get_partition(), partition_offset.get_offset()))
+            .collect())
+    }
+
+    #[test]
+    fn test_seek_consumer_group() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_string = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let mut broker = Broker::new(String::from(tmp_dir_string), None).expect("unable to create new broker");
+        let config = vec![("partitions".to_string(), "2".to_string())];
+        broker.handle_create_topic("grouped", &config).expect("unable to create topic");
+        {
+            let topics = broker.topics.read().unwrap();
+            let topic: &Topic = &topics[0].read().unwrap();
+            for i in 0..5 {
+                topic.partitions[1].append(format!("hello{}", i).as_bytes()).expect("unable to append to commitlog");
+            }
+        }
+
+        assert_eq!(
+            vec![(0, 0), (1, 5)],
+            send_seek_request(&mut broker, |mut partition| partition.set_all(()), |mut to| to.set_latest(())).unwrap()
+        );
+        assert_eq!(
+            vec![(1, 2)],
+            send_seek_request(&mut broker, |mut partition| partition.set_id(1), |mut to| to.set_shift(-3)).unwrap()
+        );
+        assert_eq!(
+            Err(BrokerError::new("Offset is outside the oldest and latest offset of the partition")),
+            send_seek_request(&mut broker, |mut partition| partition.set_id(1), |mut to| to.set_offset(6))
+        );
+
+        // Members would carry on from where they were so the consumer group can't be moved under them
+        let (member, _, _) = send_group_request(&mut broker, "", join).expect("unable to join");
+        assert_eq!(
+            Err(BrokerError::new("Consumer group has active members")),
+            send_seek_request(&mut broker, |mut partition| partition.set_all(()), |mut to| to.set_earliest(()))
+        );
+        send_group_request(&mut broker, &member, |mut request| request.set_leave(())).expect("unable to leave");
+        assert_eq!(
+            vec![(0, 0), (1, 0)],
+            send_seek_request(&mut broker, |mut partition| partition.set_all(()), |mut to| to.set_earliest(())).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_describe_group_pages() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_string = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let mut broker = Broker::new(String::from(tmp_dir_string), None).expect("unable to create new broker");
+        let config = vec![("partitions".to_string(), MAX_PARTITIONS.to_string())];
+        broker.handle_create_topic("wide", &config).expect("unable to create topic");
+        let mut expected_groups = Vec::new();
+        {
+            let topics = broker.topics.read().unwrap();
+            let topic: &mut Topic = &mut topics[0].write().unwrap();
+            for i in (0..12).rev() {
+                let consumer_group_name = format!("cg{:02}", i);
+                topic.load_consumer_group(&consumer_group_name);
+                expected_groups.insert(0, consumer_group_name);
+            }
+        }
+
+        let mut groups = Vec::new();
+        let mut pages = 0;
+        loop {
+            let mut message = TypedBuilder::<describe_group_request::Owned>::new_default();
+            let mut request = message.init_root();
+            request.set_topic_name("wide");
+            request.set_start_after(groups.last().map_or("", |name: &String| name.as_str()));
+            let data = broker.handle_describe_group(TypedReader::from(message)).expect("unable to describe groups");
+            // The frame holds the size of the rest of the message
+            assert_eq!(data.len() - 2, u16::from_le_bytes([data[0], data[1]]) as usize);
+            let reader = serialize_packed::read_message(&data[2..], ReaderOptions::new()).unwrap();
+            let envelope = reader.get_root::<message_envelope::Reader>().unwrap();
+            let response = match envelope.which().unwrap() {
+                message_envelope::DescribeGroupResponse(response) => response.unwrap(),
+                _ => panic!("Expected a describe group response"),
+            };
+            assert!(response.get_success());
+            for consumer_group in response.get_consumer_groups().unwrap() {
+                assert_eq!(MAX_PARTITIONS, consumer_group.get_partitions().unwrap().len());
+                groups.push(consumer_group.get_name().unwrap().to_string());
+            }
+            pages += 1;
+            if !response.get_more() {
+                break;
+            }
+        }
+        assert_eq!(expected_groups, groups);
+        assert!(pages > 1);
+    }
+
     // Tests to write:
     // - happy path broker, directory and lucidmq meta are created
     // - handle run, send message of each kind, verify the response including invalid
@@ -1,15 +1,26 @@
+use crate::coordinator::Assignment;
 use crate::lucid_schema_capnp::{
     consume_request, consume_response, message, message_envelope, produce_request,
-    produce_response, topic_request, topic_response, invalid_response,
+    produce_response, topic_request, topic_response, topic_config_entry, invalid_response,
+    group_request, group_response, commit_offset_request, commit_offset_response, seek_request,
+    seek_response, describe_group_request, describe_group_response,
 };
 use crate::lucidmq_errors::ProtocolError;
-use crate::topic::SimpleTopic;
+use crate::topic::{SimpleConsumerGroup, SimplePartition, SimpleTopic};
 use crate::types::Command;
 use capnp::message::{Builder, ReaderOptions, TypedBuilder, TypedReader};
 use capnp::{serialize, serialize_packed};
 use log::{error, info, warn};
 
-pub fn new_topic_response_create(topic_name: &str, is_success: bool) -> Vec<u8> {
+/// Worst case size of a partition of a consumer group in a packed describe group response, five words with a tag byte each.
+const DESCRIBED_PARTITION_BYTES: usize = 45;
+/// Worst case size of a consumer group in a packed describe group response, not counting its name and partitions.
+const DESCRIBED_GROUP_BYTES: usize = 64;
+/// Most bytes of consumer groups in a describe group response, the rest of the 64KB message frame is left for the topic
+/// name and the message envelope.
+const MAX_DESCRIBED_GROUPS_BYTES: usize = 60 * 1024;
+
+pub fn new_topic_response_create(topic_name: &str, is_success: bool) -> Result<Vec<u8>, ProtocolError> {
     let mut response_message_envelope = Builder::new_default();
     let mut message_envelope = response_message_envelope.init_root::<message_envelope::Builder>();
 
@@ -27,9 +38,7 @@ pub fn new_topic_response_create(topic_name: &str, is_success: bool) -> Vec<u8>
     let mut buffer = vec![];
     serialize_packed::write_message(&mut buffer, &response_message_envelope)
         .expect("Unable to serialize packed message");
-    let framed_message = create_message_frame(buffer).unwrap();
-
-    return framed_message;
+    create_message_frame(buffer)
 }
 
 pub fn new_topic_response_describe(
@@ -38,7 +47,9 @@ pub fn new_topic_response_describe(
     max_retention: u64,
     max_segment: u64,
     consumer_groups: Vec<String>,
-) -> Vec<u8> {
+    config: Vec<(String, String)>,
+    partitions: Vec<SimplePartition>,
+) -> Result<Vec<u8>, ProtocolError> {
     let mut response_message_envelope = Builder::new_default();
     let mut message_envelope = response_message_envelope.init_root::<message_envelope::Builder>();
 
@@ -47,14 +58,14 @@ pub fn new_topic_response_describe(
 
     topic_response.set_topic_name(topic_name);
 
-    if is_success && consumer_groups.len() > 0 {
-        topic_response.set_success(is_success);
-        let mut describe = topic_response.init_describe();
-        describe.set_max_retention_bytes(max_retention);
-        describe.set_max_segment_bytes(max_segment);
+    topic_response.set_success(is_success);
+    let mut describe = topic_response.init_describe();
+    describe.set_max_retention_bytes(max_retention);
+    describe.set_max_segment_bytes(max_segment);
+    if is_success && !consumer_groups.is_empty() {
         //Build our consumer group response
         let size = u32::try_from(consumer_groups.len()).unwrap();
-        let mut cgs: capnp::text_list::Builder<'_> = describe.init_consumer_groups(size);
+        let mut cgs: capnp::text_list::Builder<'_> = describe.reborrow().init_consumer_groups(size);
         for (i, consumer_group) in consumer_groups.iter().enumerate() {
             let ind_u32 = u32::try_from(i).unwrap();
             {
@@ -62,11 +73,15 @@ pub fn new_topic_response_describe(
             }
         }
     } else {
-        topic_response.set_success(is_success);
-        let mut describe = topic_response.init_describe();
-        describe.set_max_retention_bytes(max_retention);
-        describe.set_max_segment_bytes(max_segment);
-        describe.init_consumer_groups(0);
+        describe.reborrow().init_consumer_groups(0);
+    }
+    set_topic_config(describe.reborrow().init_config(u32::try_from(config.len()).unwrap()), &config);
+    let mut partition_list = describe.init_partitions(u32::try_from(partitions.len()).unwrap());
+    for (i, partition) in partitions.iter().enumerate() {
+        let mut partition_description = partition_list.reborrow().get(u32::try_from(i).unwrap());
+        partition_description.set_partition(partition.partition);
+        partition_description.set_oldest_offset(partition.oldest_offset);
+        partition_description.set_latest_offset(partition.latest_offset);
     }
     message_envelope
         .set_topic_response(request_message.get_root_as_reader().unwrap())
@@ -75,12 +90,19 @@ pub fn new_topic_response_describe(
     let mut buffer = vec![];
     serialize_packed::write_message(&mut buffer, &response_message_envelope)
         .expect("Unable to serialize packed message");
-    let framed_message = create_message_frame(buffer).unwrap();
+    create_message_frame(buffer)
+}
 
-    return framed_message;
+/// Write the settings of a topic to a list of config entries.
+fn set_topic_config(mut entries: capnp::struct_list::Builder<'_, topic_config_entry::Owned>, config: &[(String, String)]) {
+    for (i, (key, value)) in config.iter().enumerate() {
+        let mut entry = entries.reborrow().get(u32::try_from(i).unwrap());
+        entry.set_key(key);
+        entry.set_value(value);
+    }
 }
 
-pub fn new_topic_response_all(is_success: bool, topics_datas: Vec<SimpleTopic>) -> Vec<u8> {
+pub fn new_topic_response_all(is_success: bool, topics_datas: Vec<SimpleTopic>) -> Result<Vec<u8>, ProtocolError> {
     let mut response_message_envelope = Builder::new_default();
     let mut message_envelope = response_message_envelope.init_root::<message_envelope::Builder>();
 
@@ -114,12 +136,10 @@ pub fn new_topic_response_all(is_success: bool, topics_datas: Vec<SimpleTopic>)
     let mut buffer = vec![];
     serialize_packed::write_message(&mut buffer, &response_message_envelope)
         .expect("Unable to serialize packed message");
-    let framed_message = create_message_frame(buffer).unwrap();
-
-    return framed_message;
+    create_message_frame(buffer)
 }
 
-pub fn new_topic_response_delete(topic_name: &str, is_success: bool) -> Vec<u8> {
+pub fn new_topic_response_delete(topic_name: &str, is_success: bool) -> Result<Vec<u8>, ProtocolError> {
     let mut response_message_envelope = Builder::new_default();
     let mut message_envelope = response_message_envelope.init_root::<message_envelope::Builder>();
 
@@ -137,12 +157,15 @@ pub fn new_topic_response_delete(topic_name: &str, is_success: bool) -> Vec<u8>
     let mut buffer = vec![];
     serialize_packed::write_message(&mut buffer, &response_message_envelope)
         .expect("Unable to serialize packed message");
-    let framed_message = create_message_frame(buffer).unwrap();
-
-    return framed_message;
+    create_message_frame(buffer)
 }
 
-pub fn new_produce_response(topic_name: &str, last_offset: u64, is_success: bool) -> Vec<u8> {
+pub fn new_produce_response(
+    topic_name: &str,
+    last_offset: u64,
+    is_success: bool,
+    partition_offsets: &[(u32, u64)],
+) -> Result<Vec<u8>, ProtocolError> {
     let mut response_message_envelope = Builder::new_default();
     let mut message_envelope = response_message_envelope.init_root::<message_envelope::Builder>();
 
@@ -152,6 +175,12 @@ pub fn new_produce_response(topic_name: &str, last_offset: u64, is_success: bool
     produce_response.set_topic_name(topic_name);
     produce_response.set_offset(last_offset);
     produce_response.set_success(is_success);
+    let mut offsets = produce_response.reborrow().init_partition_offsets(u32::try_from(partition_offsets.len()).unwrap());
+    for (i, (partition, offset)) in partition_offsets.iter().enumerate() {
+        let mut partition_offset = offsets.reborrow().get(u32::try_from(i).unwrap());
+        partition_offset.set_partition(*partition);
+        partition_offset.set_offset(*offset);
+    }
 
     message_envelope
         .set_produce_response(produce_response.reborrow_as_reader())
@@ -160,16 +189,16 @@ pub fn new_produce_response(topic_name: &str, last_offset: u64, is_success: bool
     let mut buffer = vec![];
     serialize_packed::write_message(&mut buffer, &response_message_envelope)
         .expect("Unable to serialize packed message");
-    let framed_message = create_message_frame(buffer).unwrap();
-
-    return framed_message;
+    create_message_frame(buffer)
 }
 
 pub fn new_consume_response(
     topic_name: &str,
     is_success: bool,
-    message_data: Vec<Vec<u8>>,
-) -> Vec<u8> {
+    partition: u32,
+    next_offset: u64,
+    message_data: &[&[u8]],
+) -> Result<Vec<u8>, ProtocolError> {
     let mut response_message_envelope = Builder::new_default();
     let mut message_envelope = response_message_envelope.init_root::<message_envelope::Builder>();
 
@@ -177,15 +206,17 @@ pub fn new_consume_response(
     let mut consume_reponse = request_message.init_root::<consume_response::Builder>();
 
     consume_reponse.set_topic_name(topic_name);
+    consume_reponse.set_partition(partition);
+    consume_reponse.set_next_offset(next_offset);
 
-    if is_success && message_data.len() > 0 {
+    if is_success && !message_data.is_empty() {
         consume_reponse.set_success(is_success);
         let size = u32::try_from(message_data.len()).unwrap();
         let mut messages = consume_reponse.init_messages(size);
 
         for (i, msg) in message_data.iter().enumerate() {
             let message_reader =
-                serialize::read_message(msg.as_slice(), ReaderOptions::new()).unwrap();
+                serialize::read_message(*msg, ReaderOptions::new()).unwrap();
             let reader = message_reader.get_root::<message::Reader>().unwrap();
             let message_index = u32::try_from(i).unwrap();
             {
@@ -206,12 +237,172 @@ pub fn new_consume_response(
     let mut buffer = vec![];
     serialize_packed::write_message(&mut buffer, &response_message_envelope)
         .expect("Unable to serialize packed message");
-    let framed_message = create_message_frame(buffer).unwrap();
+    create_message_frame(buffer)
+}
+
+pub fn new_commit_offset_response(
+    topic_name: &str,
+    consumer_group: &str,
+    is_success: bool,
+    partition: u32,
+    offset: u64,
+) -> Result<Vec<u8>, ProtocolError> {
+    let mut response_message_envelope = Builder::new_default();
+    let mut message_envelope = response_message_envelope.init_root::<message_envelope::Builder>();
+
+    let mut request_message = Builder::new_default();
+    let mut commit_offset_response = request_message.init_root::<commit_offset_response::Builder>();
+
+    commit_offset_response.set_success(is_success);
+    commit_offset_response.set_topic_name(topic_name);
+    commit_offset_response.set_consumer_group(consumer_group);
+    commit_offset_response.set_partition(partition);
+    commit_offset_response.set_offset(offset);
+    message_envelope
+        .set_commit_offset_response(commit_offset_response.reborrow_as_reader())
+        .expect("Unable to set message");
+
+    let mut buffer = vec![];
+    serialize_packed::write_message(&mut buffer, &response_message_envelope)
+        .expect("Unable to serialize packed message");
+    create_message_frame(buffer)
+}
+
+pub fn new_seek_response(
+    topic_name: &str,
+    consumer_group: &str,
+    is_success: bool,
+    partition_offsets: &[(u32, u64)],
+) -> Result<Vec<u8>, ProtocolError> {
+    let mut response_message_envelope = Builder::new_default();
+    let mut message_envelope = response_message_envelope.init_root::<message_envelope::Builder>();
+
+    let mut request_message = Builder::new_default();
+    let mut seek_response = request_message.init_root::<seek_response::Builder>();
+
+    seek_response.set_success(is_success);
+    seek_response.set_topic_name(topic_name);
+    seek_response.set_consumer_group(consumer_group);
+    let mut offsets = seek_response.reborrow().init_partition_offsets(u32::try_from(partition_offsets.len()).unwrap());
+    for (i, (partition, offset)) in partition_offsets.iter().enumerate() {
+        let mut partition_offset = offsets.reborrow().get(u32::try_from(i).unwrap());
+        partition_offset.set_partition(*partition);
+        partition_offset.set_offset(*offset);
+    }
+    message_envelope
+        .set_seek_response(seek_response.reborrow_as_reader())
+        .expect("Unable to set message");
+
+    let mut buffer = vec![];
+    serialize_packed::write_message(&mut buffer, &response_message_envelope)
+        .expect("Unable to serialize packed message");
+    create_message_frame(buffer)
+}
+
+/// Returns how many of the consumer groups fit in one describe group response, going by the worst case size of every
+/// consumer group once packed. The first consumer group is always included, so the consumer groups can be paged through.
+pub fn describe_group_page_len(consumer_groups: &[SimpleConsumerGroup]) -> usize {
+    let mut page_bytes = 0;
+    let mut page_len = 0;
+    for consumer_group in consumer_groups {
+        // Every word takes up at most its eight bytes and a tag byte once packed
+        let name_bytes = (consumer_group.name.len() / 8 + 1) * 9;
+        page_bytes += DESCRIBED_GROUP_BYTES + name_bytes + consumer_group.partitions.len() * DESCRIBED_PARTITION_BYTES;
+        if page_bytes > MAX_DESCRIBED_GROUPS_BYTES && page_len > 0 {
+            break;
+        }
+        page_len += 1;
+    }
+    page_len
+}
+
+pub fn new_describe_group_response(
+    topic_name: &str,
+    is_success: bool,
+    consumer_groups: &[SimpleConsumerGroup],
+    more: bool,
+) -> Result<Vec<u8>, ProtocolError> {
+    let mut response_message_envelope = Builder::new_default();
+    let mut message_envelope = response_message_envelope.init_root::<message_envelope::Builder>();
+
+    let mut request_message = Builder::new_default();
+    let mut describe_group_response = request_message.init_root::<describe_group_response::Builder>();
+
+    describe_group_response.set_success(is_success);
+    describe_group_response.set_topic_name(topic_name);
+    describe_group_response.set_more(more);
+    let mut group_list = describe_group_response
+        .reborrow()
+        .init_consumer_groups(u32::try_from(consumer_groups.len()).unwrap());
+    for (i, consumer_group) in consumer_groups.iter().enumerate() {
+        let mut group_description = group_list.reborrow().get(u32::try_from(i).unwrap());
+        group_description.set_name(&consumer_group.name);
+        group_description.set_lag(consumer_group.lag);
+        let mut partition_list = group_description.init_partitions(u32::try_from(consumer_group.partitions.len()).unwrap());
+        for (j, partition) in consumer_group.partitions.iter().enumerate() {
+            let mut group_partition = partition_list.reborrow().get(u32::try_from(j).unwrap());
+            group_partition.set_partition(partition.partition);
+            group_partition.set_committed_offset(partition.committed_offset);
+            group_partition.set_log_end_offset(partition.latest_offset);
+            group_partition.set_oldest_offset(partition.oldest_offset);
+            group_partition.set_lag(partition.lag);
+        }
+    }
+    message_envelope
+        .set_describe_group_response(describe_group_response.reborrow_as_reader())
+        .expect("Unable to set message");
+
+    let mut buffer = vec![];
+    serialize_packed::write_message(&mut buffer, &response_message_envelope)
+        .expect("Unable to serialize packed message");
+    create_message_frame(buffer)
+}
+
+/// Type of group request a group response answers.
+pub enum GroupResponseType {
+    Join,
+    Heartbeat,
+    Leave,
+}
+
+pub fn new_group_response(
+    topic_name: &str,
+    consumer_group: &str,
+    is_success: bool,
+    response_type: GroupResponseType,
+    assignment: &Assignment,
+) -> Result<Vec<u8>, ProtocolError> {
+    let mut response_message_envelope = Builder::new_default();
+    let mut message_envelope = response_message_envelope.init_root::<message_envelope::Builder>();
 
-    return framed_message;
+    let mut request_message = Builder::new_default();
+    let mut group_response = request_message.init_root::<group_response::Builder>();
+
+    group_response.set_success(is_success);
+    group_response.set_topic_name(topic_name);
+    group_response.set_consumer_group(consumer_group);
+    group_response.set_member_id(&assignment.member_id);
+    group_response.set_generation(assignment.generation);
+    let mut partitions = group_response.reborrow().init_partitions(u32::try_from(assignment.partitions.len()).unwrap());
+    for (i, partition) in assignment.partitions.iter().enumerate() {
+        partitions.set(u32::try_from(i).unwrap(), *partition);
+    }
+    match response_type {
+        GroupResponseType::Join => group_response.set_join(()),
+        GroupResponseType::Heartbeat => group_response.set_heartbeat(()),
+        GroupResponseType::Leave => group_response.set_leave(()),
+    }
+    message_envelope
+        .set_group_response(group_response.reborrow_as_reader())
+        .expect("Unable to set message");
+
+    let mut buffer = vec![];
+    serialize_packed::write_message(&mut buffer, &response_message_envelope)
+        .expect("Unable to serialize packed message");
+    create_message_frame(buffer)
 }
 
-pub fn new_invalid_response(message_text: &str) -> Vec<u8>{
+pub fn new_invalid_response(message_text: &str) -> Result<Vec<u8>, ProtocolError> {
     let mut response_message_envelope = Builder::new_default();
     let mut message_envelope = response_message_envelope.init_root::<message_envelope::Builder>();
 
@@ -225,9 +416,7 @@ pub fn new_invalid_response(message_text: &str) -> Vec<u8>{
     let mut buffer = vec![];
     serialize_packed::write_message(&mut buffer, &response_message_envelope)
         .expect("Unable to serialize packed message");
-    let framed_message = create_message_frame(buffer).unwrap();
-
-    return framed_message;
+    create_message_frame(buffer)
 
 }
 
@@ -251,7 +440,7 @@ pub fn parse_request(conn_id: String, data: Vec<u8>) -> Result<Command, capnp::E
             warn!("Error when attempting to read message from stream[capnperror {}]", err);
             return Ok(
                 Command::Invalid { 
-                    conn_id: conn_id,
+                    conn_id,
                     error_message: "invalid message sent".to_string(),
                     capmessage_data: Vec::new()
                 })
@@ -267,7 +456,7 @@ pub fn parse_request(conn_id: String, data: Vec<u8>) -> Result<Command, capnp::E
             message.set_root(topic_request)?;
             let typed_reader = TypedReader::from(message);
             Ok(Command::TopicRequest {
-                conn_id: conn_id,
+                conn_id,
                 capmessage: typed_reader,
             })
         }
@@ -286,7 +475,7 @@ pub fn parse_request(conn_id: String, data: Vec<u8>) -> Result<Command, capnp::E
             message.set_root(produce_request)?;
             let typed_reader = TypedReader::from(message);
             Ok(Command::ProduceRequest {
-                conn_id: conn_id,
+                conn_id,
                 capmessage: typed_reader,
             })
         }
@@ -296,14 +485,54 @@ pub fn parse_request(conn_id: String, data: Vec<u8>) -> Result<Command, capnp::E
             message.set_root(consume_request)?;
             let typed_reader = TypedReader::from(message);
             Ok(Command::ConsumeRequest {
-                conn_id: conn_id,
+                conn_id,
+                capmessage: typed_reader,
+            })
+        }
+        Ok(message_envelope::GroupRequest(envelope_group_request)) => {
+            let group_request = envelope_group_request?;
+            let mut message = TypedBuilder::<group_request::Owned>::new_default();
+            message.set_root(group_request)?;
+            let typed_reader = TypedReader::from(message);
+            Ok(Command::GroupRequest {
+                conn_id,
+                capmessage: typed_reader,
+            })
+        }
+        Ok(message_envelope::CommitOffsetRequest(envelope_commit_offset_request)) => {
+            let commit_offset_request = envelope_commit_offset_request?;
+            let mut message = TypedBuilder::<commit_offset_request::Owned>::new_default();
+            message.set_root(commit_offset_request)?;
+            let typed_reader = TypedReader::from(message);
+            Ok(Command::CommitOffsetRequest {
+                conn_id,
+                capmessage: typed_reader,
+            })
+        }
+        Ok(message_envelope::SeekRequest(envelope_seek_request)) => {
+            let seek_request = envelope_seek_request?;
+            let mut message = TypedBuilder::<seek_request::Owned>::new_default();
+            message.set_root(seek_request)?;
+            let typed_reader = TypedReader::from(message);
+            Ok(Command::SeekRequest {
+                conn_id,
+                capmessage: typed_reader,
+            })
+        }
+        Ok(message_envelope::DescribeGroupRequest(envelope_describe_group_request)) => {
+            let describe_group_request = envelope_describe_group_request?;
+            let mut message = TypedBuilder::<describe_group_request::Owned>::new_default();
+            message.set_root(describe_group_request)?;
+            let typed_reader = TypedReader::from(message);
+            Ok(Command::DescribeGroupRequest {
+                conn_id,
                 capmessage: typed_reader,
             })
         }
         Ok(message_envelope::TopicResponse(envelope_topic_response)) => {
             info!("{}", envelope_topic_response?.get_topic_name()?);
             Ok(Command::Invalid { 
-                conn_id: conn_id,
+                conn_id,
                 error_message: "Topic Response is an invalid request".to_string(),
                 capmessage_data: Vec::new()
             })
@@ -311,7 +540,7 @@ pub fn parse_request(conn_id: String, data: Vec<u8>) -> Result<Command, capnp::E
         Ok(message_envelope::ConsumeResponse(envelope_consume_response)) => {
             info!("{}",envelope_consume_response?.get_topic_name()?);
             Ok(Command::Invalid { 
-                conn_id: conn_id,
+                conn_id,
                 error_message: "Consume response is an invalid request".to_string(),
                 capmessage_data: Vec::new()
             })
@@ -319,15 +548,47 @@ pub fn parse_request(conn_id: String, data: Vec<u8>) -> Result<Command, capnp::E
         Ok(message_envelope::ProduceResponse(envelope_produce_response)) => {
             info!("{}", envelope_produce_response?.get_topic_name()?);
             Ok(Command::Invalid { 
-                conn_id: conn_id,
+                conn_id,
                 error_message: "Produce response is an invalid request".to_string(),
                 capmessage_data: Vec::new()
             })
         }
+        Ok(message_envelope::GroupResponse(envelope_group_response)) => {
+            info!("{}", envelope_group_response?.get_topic_name()?);
+            Ok(Command::Invalid { 
+                conn_id,
+                error_message: "Group response is an invalid request".to_string(),
+                capmessage_data: Vec::new()
+            })
+        }
+        Ok(message_envelope::CommitOffsetResponse(envelope_commit_offset_response)) => {
+            info!("{}", envelope_commit_offset_response?.get_topic_name()?);
+            Ok(Command::Invalid { 
+                conn_id,
+                error_message: "Commit offset response is an invalid request".to_string(),
+                capmessage_data: Vec::new()
+            })
+        }
+        Ok(message_envelope::SeekResponse(envelope_seek_response)) => {
+            info!("{}", envelope_seek_response?.get_topic_name()?);
+            Ok(Command::Invalid { 
+                conn_id,
+                error_message: "Seek response is an invalid request".to_string(),
+                capmessage_data: Vec::new()
+            })
+        }
+        Ok(message_envelope::DescribeGroupResponse(envelope_describe_group_response)) => {
+            info!("{}", envelope_describe_group_response?.get_topic_name()?);
+            Ok(Command::Invalid { 
+                conn_id,
+                error_message: "Describe group response is an invalid request".to_string(),
+                capmessage_data: Vec::new()
+            })
+        }
         Ok(message_envelope::InvalidResponse(envelope_invalid_response)) => {
             info!("{}", envelope_invalid_response?.get_error_message()?);
             Ok(Command::Invalid { 
-                conn_id: conn_id,
+                conn_id,
                 error_message: "Invalid response is an invalid request".to_string(),
                 capmessage_data: Vec::new()
             })
@@ -335,7 +596,7 @@ pub fn parse_request(conn_id: String, data: Vec<u8>) -> Result<Command, capnp::E
         Err(::capnp::NotInSchema(_)) => {
             info!("Unable to parse cap n p message");
             Ok(Command::Invalid { 
-                conn_id: conn_id,
+                conn_id,
                 error_message: "Not in schema".to_string(),
                 capmessage_data: Vec::new()
             })
@@ -1,29 +1,66 @@
 use crate::lucidmq_errors::{ConsumerError, BrokerError};
 use crate::topic::{Topic, ConsumerGroup};
 use log::{error, info};
-use nolan::CommitlogError;
+use nolan::Commitlog;
 use std::sync::atomic::Ordering;
 use std::sync::{Arc, RwLock};
 use std::thread;
 use std::time::{Duration, Instant};
 
+/// The most messages read by a single poll
+const MAX_POLL_RECORDS: usize = 1000;
+/// The most bytes of messages read by a single poll, consume responses are framed with a u16 length so they have to
+/// stay well under 64KB
+const MAX_POLL_BYTES: usize = 32 * 1024;
+
+/// Where a seek moves a consumer group to in a partition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekTarget {
+    /// The oldest offset still retained in the partition
+    Earliest,
+    /// The latest offset of the partition, skipping every message produced so far
+    Latest,
+    Offset(u64),
+    /// The first message produced at or after the timestamp, in milliseconds since the unix epoch
+    Timestamp(u64),
+    /// Moves the committed offset by a number of messages, negative to move back
+    Shift(i64),
+}
+
 /// Consumer struct for directly interacting with the commitlog in a consuming fashion
 pub struct Consumer {
     topic: Arc<RwLock<Topic>>,
     consumer_group: Arc<ConsumerGroup>,
+    /// The partitions the consumer reads from, in partition order
+    partitions: Vec<u32>,
+    /// Whether polling commits the messages it reads, otherwise they're handed out again until they're committed
+    auto_commit: bool,
     cb: Box<dyn Fn()->Result<(), BrokerError>>,
 }
 
 impl Consumer {
-    ///Initializes a new consumer
+    ///Initializes a new consumer reading from the given partitions of the topic
     pub fn new(
         consumer_topic: Arc<RwLock<Topic>>,
         new_consumer_group: Arc<ConsumerGroup>,
+        mut partitions: Vec<u32>,
         callback: Box<dyn Fn()->Result<(), BrokerError>>,
     ) -> Result<Consumer, ConsumerError> {
+        let partition_count = consumer_topic.read().map_err(|e| {
+            error!("{}", e);
+            ConsumerError::new("Unable to get lock on consumer topic")
+        })?.partition_count();
+        partitions.sort_unstable();
+        partitions.dedup();
+        if partitions.is_empty() || partitions.iter().any(|partition| *partition >= partition_count) {
+            error!("consumer partitions {:?} aren't in a topic with {} partitions", partitions, partition_count);
+            return Err(ConsumerError::new("Partition does not exist"));
+        }
         let mut consumer = Consumer {
             topic: consumer_topic,
             consumer_group: new_consumer_group,
+            partitions,
+            auto_commit: true,
             cb: callback,
         };
         consumer.consumer_group_initialize()?;
@@ -31,83 +68,234 @@ impl Consumer {
     }
 
     /**
-    Reads from the commitlog for a set amount of time and returns a vector is messages when complete. 
-    The offset where the starting read takes place is based off of the consumer group offset
+    Sets whether polling commits the messages it reads. Without auto commit every poll reads from the committed offset
+    of the consumer group, so messages are handed out again until they're committed with commit_offset and a consumer
+    that fails before committing doesn't lose them.
      */
-    pub fn poll(&mut self, timeout: u64) -> Result<Vec<Vec<u8>>, ConsumerError> {
-        //Let's check if there are any new segments added.
-        self.topic.write().map_err(|e| {
-            error!("{}", e);
-            ConsumerError::new("Unable to get lock on consumer topic")
-        })?.commitlog.reload_segments();
+    pub fn set_auto_commit(&mut self, auto_commit: bool) {
+        self.auto_commit = auto_commit;
+    }
+
+    /**
+    Polls the partitions of the consumer until one of them has messages after the consumer group offset or the timeout
+    is hit. Messages are read from a single partition per poll, partitions take turns so a busy partition doesn't
+    starve the others. Messages in closed segments are borrowed straight from the commitlog and handed to the callback
+    along with the partition they were read from and the offset after them, so a response can be built from them
+    without copying every message. Producers keep appending while the messages are read. At most MAX_POLL_RECORDS
+    messages, and as many as fit in MAX_POLL_BYTES, are read at once. The consumer group offset is moved past the
+    messages that were read, skipping any offsets removed by compaction, and with auto commit they're committed too.
+     */
+    pub fn poll<T>(&mut self, timeout: u64, callback: impl FnOnce(u32, u64, &[&[u8]]) -> T) -> Result<T, ConsumerError> {
         info!("polling for messages");
 
         let timeout_duration = Duration::from_millis(timeout);
         let ten_millis = Duration::from_millis(100);
-        let mut records: Vec<Vec<u8>> = Vec::new();
         let start_time = Instant::now();
+        let mut available_partition = self.available_partition()?;
+        while available_partition.is_none() && start_time.elapsed() < timeout_duration {
+            thread::sleep(ten_millis.min(timeout_duration.saturating_sub(start_time.elapsed())));
+            available_partition = self.available_partition()?;
+        }
+        // Without messages the partition that's up next is read, which returns nothing
+        let partition = available_partition.unwrap_or_else(|| self.partitions_in_turn()[0]);
 
-        let mut elapsed_duration = start_time.elapsed();
-        //let mut n = usize::try_from(self.consumer_group.offset.load(Ordering::SeqCst)).expect("Unable to get offset");
-        while timeout_duration > elapsed_duration {
-            let n = usize::try_from(self.consumer_group.offset.load(Ordering::SeqCst)).map_err(|e| {
+        let (result, read_messages) = {
+            let topic = self.topic.read().map_err(|e| {
                 error!("{}", e);
-                ConsumerError::new("Unable to get offset")
+                ConsumerError::new("Unable to get lock on consumer topic")
             })?;
-            let mut topic = self.topic.write().map_err(|e| {
+            let commitlog = partition_commitlog(&topic, partition)?;
+            let offset = self.start_offset(commitlog, partition);
+            let records = commitlog
+                .read_range(offset, MAX_POLL_RECORDS, MAX_POLL_BYTES)
+                .map_err(|e| {
+                    error!("{}", e);
+                    ConsumerError::new("Error when reading commitlong")
+                })?;
+            // The topic lock isn't needed to read the range, it holds on to the segments it reads
+            drop(topic);
+            let mut next_offset = offset;
+            let mut messages = Vec::new();
+            for record in &records {
+                let record = record.map_err(|e| {
+                    error!("{}", e);
+                    ConsumerError::new("Error when reading commitlong")
+                })?;
+                next_offset = record.offset + 1;
+                messages.push(record.payload);
+            }
+            self.consumer_group.offsets[partition as usize].store(next_offset, Ordering::SeqCst);
+            if self.auto_commit {
+                self.consumer_group.committed_offsets[partition as usize].store(next_offset, Ordering::SeqCst);
+            }
+            self.consumer_group.next_partition.store(partition + 1, Ordering::SeqCst);
+            // Messages from compressed batches are owned by the records, so borrow every payload for the callback
+            let messages: Vec<&[u8]> = messages.iter().map(|message| message.as_ref()).collect();
+            (callback(partition, next_offset, &messages), !messages.is_empty())
+        };
+        if read_messages && self.auto_commit {
+            self.save_info()?;
+        }
+        Ok(result)
+    }
+
+    /**
+    Returns the partitions of the consumer in the order they take turns, starting at the partition after the one the
+    consumer group last read from.
+     */
+    fn partitions_in_turn(&self) -> Vec<u32> {
+        let next_partition = self.consumer_group.next_partition.load(Ordering::SeqCst);
+        let start = self
+            .partitions
+            .iter()
+            .position(|partition| *partition >= next_partition)
+            .unwrap_or(0);
+        let mut partitions = self.partitions[start..].to_vec();
+        partitions.extend_from_slice(&self.partitions[..start]);
+        partitions
+    }
+
+    /**
+    Returns the first partition in turn that has messages after the consumer group offset.
+     */
+    fn available_partition(&self) -> Result<Option<u32>, ConsumerError> {
+        let topic = self.topic.read().map_err(|e| {
+            error!("{}", e);
+            ConsumerError::new("Unable to get lock on consumer topic")
+        })?;
+        for partition in self.partitions_in_turn() {
+            let commitlog = partition_commitlog(&topic, partition)?;
+            if commitlog.get_latest_offset() > self.start_offset(commitlog, partition) {
+                return Ok(Some(partition));
+            }
+        }
+        Ok(None)
+    }
+
+    /**
+    Returns the offset the consumer group reads from next in a partition, without auto commit that's the committed
+    offset. Messages may have been cleaned up since the consumer group last read so it's never older than the oldest
+    offset in the commitlog of the partition.
+     */
+    fn start_offset(&self, commitlog: &Commitlog, partition: u32) -> u64 {
+        let offsets = if self.auto_commit {
+            &self.consumer_group.offsets
+        } else {
+            &self.consumer_group.committed_offsets
+        };
+        offsets[partition as usize]
+            .load(Ordering::SeqCst)
+            .max(commitlog.get_oldest_offset())
+    }
+
+    /**
+    Commits every message of a partition before the offset as processed by the consumer group. The offset can't be
+    past the latest offset of the partition. The consumer group reads on from the committed offset if it hasn't read
+    that far yet.
+     */
+    pub fn commit_offset(&mut self, partition: u32, offset: u64) -> Result<(), ConsumerError> {
+        if !self.partitions.contains(&partition) {
+            error!("consumer doesn't read from partition {}", partition);
+            return Err(ConsumerError::new("Partition does not exist"));
+        }
+        {
+            let topic = self.topic.read().map_err(|e| {
                 error!("{}", e);
                 ConsumerError::new("Unable to get lock on consumer topic")
             })?;
-            match topic.commitlog.read(n) {
-                Ok(buffer) => {
-                    records.push(buffer);
-                    self.update_consumer_group_offset();
-                }
-                Err(err) => {
-                    let offset_dne_error = CommitlogError::new("Offset does not exist in the commitlog");
-                    if err == offset_dne_error {
-                        topic.commitlog.reload_segments();
-                        thread::sleep(ten_millis);
-                        elapsed_duration = start_time.elapsed();
-                    } else {
-                        error!("{}", err);
-                        return Err(ConsumerError::new("Error when reading commitlong"));
-                    }
-                }
-            };
+            let latest_offset = partition_commitlog(&topic, partition)?.get_latest_offset();
+            if offset > latest_offset {
+                error!("offset {} is past the latest offset {} of partition {}", offset, latest_offset, partition);
+                return Err(ConsumerError::new("Offset is past the latest offset of the partition"));
+            }
         }
-        if !records.is_empty() {
-            self.save_info()?;
-        }
-        Ok(records)
+        info!(
+            "committing offset {} in partition {} for consumer group {}",
+            offset, partition, self.consumer_group.name
+        );
+        self.consumer_group.committed_offsets[partition as usize].store(offset, Ordering::SeqCst);
+        self.consumer_group.offsets[partition as usize].fetch_max(offset, Ordering::SeqCst);
+        self.save_info()
     }
 
     /**
-    Given a starting offset and a max_records to return, fetch will read all of the offsets and return the records until there is no more records
-    or the max records limit has been hit.
+    Moves the consumer group to the target in every partition of the consumer, both the offset it reads from and the
+    committed offset. Every target is checked against the oldest and latest offset of its partition before any
+    partition is moved, so a rejected seek leaves the consumer group where it was. Returns the offsets the consumer
+    group was moved to in partition order.
      */
-    pub fn _fetch(&mut self, starting_offset: usize, max_records: usize) -> Vec<Vec<u8>> {
-        let commitlog = &mut self.topic.write().expect("Unable to get topic from lock").commitlog;
-        commitlog.reload_segments();
-        let mut offset = starting_offset;
-        let mut records: Vec<Vec<u8>> = Vec::new();
-        while records.len() < max_records {
-            match commitlog.read(offset) {
-                Ok(buffer) => {
-                    records.push(buffer);
-                    offset += 1;
-                }
-                Err(err) => {
-                    let offset_dne_error = CommitlogError::new("Offset does not exist in the commitlog");
-                    if err == offset_dne_error {
-                        break;
-                    } else {
-                        panic!("Unexpected error found")
+    pub fn seek(&mut self, target: SeekTarget) -> Result<Vec<u64>, ConsumerError> {
+        let mut offsets = Vec::with_capacity(self.partitions.len());
+        {
+            let topic = self.topic.read().map_err(|e| {
+                error!("{}", e);
+                ConsumerError::new("Unable to get lock on consumer topic")
+            })?;
+            for partition in &self.partitions {
+                let commitlog = partition_commitlog(&topic, *partition)?;
+                let oldest_offset = commitlog.get_oldest_offset();
+                let latest_offset = commitlog.get_latest_offset();
+                let offset = match target {
+                    SeekTarget::Earliest => oldest_offset,
+                    SeekTarget::Latest => latest_offset,
+                    SeekTarget::Offset(offset) => offset,
+                    SeekTarget::Timestamp(timestamp) => commitlog
+                        .offset_for_timestamp(timestamp)
+                        .unwrap_or(latest_offset),
+                    SeekTarget::Shift(shift) => {
+                        let committed_offset = self.consumer_group.committed_offsets[*partition as usize]
+                            .load(Ordering::SeqCst)
+                            .max(oldest_offset);
+                        committed_offset.checked_add_signed(shift).ok_or_else(|| {
+                            error!("shifting offset {} by {} overflows", committed_offset, shift);
+                            ConsumerError::new("Offset is outside the oldest and latest offset of the partition")
+                        })?
                     }
+                };
+                if offset < oldest_offset || offset > latest_offset {
+                    error!(
+                        "offset {} is outside offsets {} to {} of partition {}",
+                        offset, oldest_offset, latest_offset, partition
+                    );
+                    return Err(ConsumerError::new("Offset is outside the oldest and latest offset of the partition"));
                 }
-            };
+                offsets.push(offset);
+            }
         }
-        records
+        for (partition, offset) in self.partitions.iter().zip(&offsets) {
+            info!(
+                "moving consumer group {} to offset {} in partition {}",
+                self.consumer_group.name, offset, partition
+            );
+            self.consumer_group.offsets[*partition as usize].store(*offset, Ordering::SeqCst);
+            self.consumer_group.committed_offsets[*partition as usize].store(*offset, Ordering::SeqCst);
+        }
+        self.save_info()?;
+        Ok(offsets)
+    }
+
+    /**
+    Moves the consumer group to the first offset with a message produced at or after the timestamp in every partition
+    of the consumer, so a topic can be replayed from a point in time. If every message of a partition is older, the
+    consumer group is moved to the latest offset of the partition.
+     */
+    pub fn seek_to_timestamp(&mut self, timestamp: u64) -> Result<Vec<u64>, ConsumerError> {
+        self.seek(SeekTarget::Timestamp(timestamp))
+    }
+
+    /**
+    Given a partition, a starting offset and a max_records to return, fetch will read all of the offsets and return the
+    records until there is no more records or the max records limit has been hit.
+     */
+    pub fn _fetch(&mut self, partition: u32, starting_offset: u64, max_records: usize) -> Vec<Vec<u8>> {
+        let topic = self.topic.read().expect("Unable to get topic from lock");
+        partition_commitlog(&topic, partition)
+            .expect("Unable to get partition commitlog")
+            .read_range(starting_offset, max_records, usize::MAX)
+            .expect("Unable to read range from commitlog")
+            .iter()
+            .map(|record| record.expect("Unexpected error found").payload.to_vec())
+            .collect()
     }
 
     /**
@@ -117,43 +305,53 @@ impl Consumer {
         self.topic.read().expect("Unable to get lock on consumer topic").name.clone()
     }
 
-    pub fn _get_oldest_offset(&mut self) -> usize{
-        self.topic.read().expect("Unable to get lock on consumer topic").commitlog.get_oldest_offset()
+    pub fn _get_oldest_offset(&mut self, partition: u32) -> u64{
+        let topic = self.topic.read().expect("Unable to get lock on consumer topic");
+        partition_commitlog(&topic, partition).expect("Unable to get partition commitlog").get_oldest_offset()
     }
 
 
-    pub fn _get_latest_offset(&mut self) -> usize{
-        self.topic.read().expect("Unable to get lock on consumer topic").commitlog.get_latest_offset()
+    pub fn _get_latest_offset(&mut self, partition: u32) -> u64{
+        let topic = self.topic.read().expect("Unable to get lock on consumer topic");
+        partition_commitlog(&topic, partition).expect("Unable to get partition commitlog").get_latest_offset()
     }
 
     /**
-    Verifies and fixes if the consumer group is set to something that is older than the oldest offset in the commitlog.
-    If it is older, it will set it to the oldest possible offset.
+    Verifies and fixes if the consumer group is set to something that is older than the oldest offset in the commitlog
+    of a partition. If it is older, it will set it to the oldest possible offset.
      */
     fn consumer_group_initialize(&mut self) -> Result<(), ConsumerError>{
-        let oldest_offset = self.topic.read().map_err(|e| {
-            error!("{}", e);
-            ConsumerError::new("Unable to get lock on consumer topic")
-        })?.commitlog.get_oldest_offset();
-        let n = usize::try_from(self.consumer_group.offset.load(Ordering::SeqCst)).unwrap();
-        if n < oldest_offset {
-            let new_consumer_group_offset: u32 = oldest_offset.try_into().map_err(|e| {
+        let mut moved = false;
+        {
+            let topic = self.topic.read().map_err(|e| {
                 error!("{}", e);
-                ConsumerError::new("Oldest offset does not map to a u32")
+                ConsumerError::new("Unable to get lock on consumer topic")
             })?;
-            self.consumer_group
-                .offset
-                .store(new_consumer_group_offset, Ordering::SeqCst);
+            for partition in &self.partitions {
+                let oldest_offset = partition_commitlog(&topic, *partition)?.get_oldest_offset();
+                let offsets = [
+                    &self.consumer_group.offsets[*partition as usize],
+                    &self.consumer_group.committed_offsets[*partition as usize],
+                ];
+                for offset in offsets {
+                    if offset.load(Ordering::SeqCst) < oldest_offset {
+                        offset.store(oldest_offset, Ordering::SeqCst);
+                        moved = true;
+                    }
+                }
+            }
+        }
+        if moved {
             self.save_info()?;
         }
         Ok(())
     }
 
     /**
-    Updates the consumer_group offset counter by 1.
+    Updates the consumer_group offset counter of a partition by 1.
     */
-    pub fn update_consumer_group_offset(&self) {
-        self.consumer_group.offset.fetch_add(1, Ordering::SeqCst);
+    pub fn _update_consumer_group_offset(&self, partition: u32) {
+        self.consumer_group.offsets[partition as usize].fetch_add(1, Ordering::SeqCst);
     }
     
     //save info calls a callback function which will sync and persist the state.
@@ -166,17 +364,37 @@ impl Consumer {
     }
 }
 
+/// Returns the commitlog of a partition of the topic the consumer reads from.
+fn partition_commitlog(topic: &Topic, partition: u32) -> Result<&Commitlog, ConsumerError> {
+    topic.partition(partition).map_err(|e| {
+        error!("{}", e);
+        ConsumerError::new("Partition does not exist")
+    })
+}
+
 #[cfg(test)]
 mod consumer_tests {
+    use std::fs;
     use std::sync::atomic::Ordering;
     use std::sync::{Arc, RwLock};
-    use crate::lucidmq_errors::BrokerError;
-    use crate::topic::{Topic, ConsumerGroup};
-    use crate::consumer::Consumer;
+    use crate::lucidmq_errors::{BrokerError, ConsumerError};
+    use crate::topic::{Topic, TopicConfig, ConsumerGroup, CleanupPolicy};
+    use nolan::Compression;
+    use crate::consumer::{Consumer, SeekTarget, MAX_POLL_BYTES};
+    use crate::lucid_schema_capnp::message;
+    use capnp::{message::Builder, serialize};
+    use std::thread;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
     use tempdir::TempDir;
 
     fn dummy_flush() -> Result<(), BrokerError>{Ok(())}
 
+    fn poll_messages(consumer: &mut Consumer, timeout: u64) -> Vec<Vec<u8>> {
+        consumer
+            .poll(timeout, |_partition, _next_offset, messages| messages.iter().map(|message| message.to_vec()).collect())
+            .expect("unable to poll")
+    }
+
     #[test]
     fn test_consumer_cg_initialization() {
         let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
@@ -184,21 +402,24 @@ mod consumer_tests {
             .path()
             .to_str()
             .expect("Unable to conver path to string");
-        let mut topic = Topic::new(
+        let topic = Topic::new(
             "test_topic".to_string(),
             String::from(tmp_dir_string),
-            10,
-            100,
+            TopicConfig {
+                max_segment_size: 50,
+                max_topic_size: 500,
+                ..TopicConfig::default()
+            },
         ).unwrap();
         let bytes = "hello".as_bytes();
-        topic.commitlog.append(bytes).expect("unable to append to commitlog");
+        topic.partitions[0].append(bytes).expect("unable to append to commitlog");
 
         let locked_topic = Arc::new(RwLock::new(topic));
-        let cg: Arc<ConsumerGroup> = Arc::new(ConsumerGroup::new("testcg"));
-        let mut consumer = Consumer::new(locked_topic, cg, Box::new(move || dummy_flush())).unwrap();
+        let cg: Arc<ConsumerGroup> = Arc::new(ConsumerGroup::new("testcg", 1));
+        let mut consumer = Consumer::new(locked_topic, cg, vec![0], Box::new(dummy_flush)).unwrap();
         consumer.consumer_group_initialize().expect("Unable to init cg");
 
-        assert!(usize::try_from(consumer.consumer_group.offset.load(Ordering::SeqCst)).unwrap() == 0);
+        assert!(consumer.consumer_group.offsets[0].load(Ordering::SeqCst) == 0);
     }
 
     #[test]
@@ -208,23 +429,27 @@ mod consumer_tests {
             .path()
             .to_str()
             .expect("Unable to conver path to string");
-        let mut topic = Topic::new(
+        let topic = Topic::new(
             "test_topic".to_string(),
             String::from(tmp_dir_string),
-            40,
-            200,
+            TopicConfig {
+                max_segment_size: 98,
+                max_topic_size: 490,
+                ..TopicConfig::default()
+            },
         ).unwrap();
+        // Each 20 byte message takes 45 bytes as a record, so a 98 byte segment(8 byte log header) fits 2 of them.
         // TODO: the math here is fuzzy, let's reason about why at 14 iterations of 20 bytes = 280 fits into a topic of 200 size and segment size of 40
         for _i in 0..15 {
             let bytes: [u8; 20] = [0; 20];
-            topic.commitlog.append(&bytes).expect("unable to append to commitlog");
+            topic.partitions[0].append(&bytes).expect("unable to append to commitlog");
         }
 
         let locked_topic = Arc::new(RwLock::new(topic));
-        let cg: Arc<ConsumerGroup> = Arc::new(ConsumerGroup::new("testcg"));
-        let mut consumer = Consumer::new(locked_topic, cg, Box::new(move || dummy_flush())).unwrap();
+        let cg: Arc<ConsumerGroup> = Arc::new(ConsumerGroup::new("testcg", 1));
+        let mut consumer = Consumer::new(locked_topic, cg, vec![0], Box::new(dummy_flush)).unwrap();
         consumer.consumer_group_initialize().expect("Unable to init cg");
-        assert!(usize::try_from(consumer.consumer_group.offset.load(Ordering::SeqCst)).unwrap() == 2);
+        assert!(consumer.consumer_group.offsets[0].load(Ordering::SeqCst) == 2);
     }
 
     #[test]
@@ -234,23 +459,26 @@ mod consumer_tests {
             .path()
             .to_str()
             .expect("Unable to conver path to string");
-        let mut topic = Topic::new(
+        let topic = Topic::new(
             "test_topic".to_string(),
             String::from(tmp_dir_string),
-            10,
-            100,
+            TopicConfig {
+                max_segment_size: 50,
+                max_topic_size: 500,
+                ..TopicConfig::default()
+            },
         ).unwrap();
         let bytes = "hello".as_bytes();
-        topic.commitlog.append(bytes).expect("unable to append to commitlog");
+        topic.partitions[0].append(bytes).expect("unable to append to commitlog");
         
         let locked_topic = Arc::new(RwLock::new(topic));
-        let cg: Arc<ConsumerGroup> = Arc::new(ConsumerGroup::new("testcg"));
-        let mut consumer = Consumer::new(locked_topic, cg, Box::new(move || dummy_flush())).unwrap();
+        let cg: Arc<ConsumerGroup> = Arc::new(ConsumerGroup::new("testcg", 1));
+        let mut consumer = Consumer::new(locked_topic, cg, vec![0], Box::new(dummy_flush)).unwrap();
         // Initialize to offset of 0
         consumer.consumer_group_initialize().expect("Unable to init cg");
         // Bump the cg by 1
-        consumer.update_consumer_group_offset();
-        assert!(usize::try_from(consumer.consumer_group.offset.load(Ordering::SeqCst)).unwrap() == 1);
+        consumer._update_consumer_group_offset(0);
+        assert!(consumer.consumer_group.offsets[0].load(Ordering::SeqCst) == 1);
     }
 
     #[test]
@@ -260,21 +488,24 @@ mod consumer_tests {
             .path()
             .to_str()
             .expect("Unable to conver path to string");
-        let mut topic = Topic::new(
+        let topic = Topic::new(
             "test_topic".to_string(),
             String::from(tmp_dir_string),
-            10,
-            100,
+            TopicConfig {
+                max_segment_size: 50,
+                max_topic_size: 500,
+                ..TopicConfig::default()
+            },
         ).unwrap();
         let bytes = "hello".as_bytes();
-        topic.commitlog.append(bytes).expect("unable to append to commitlog");
+        topic.partitions[0].append(bytes).expect("unable to append to commitlog");
 
         let locked_topic = Arc::new(RwLock::new(topic));
-        let cg: Arc<ConsumerGroup> = Arc::new(ConsumerGroup::new("testcg"));
-        let mut consumer = Consumer::new(locked_topic, cg, Box::new(move || dummy_flush())).unwrap();
+        let cg: Arc<ConsumerGroup> = Arc::new(ConsumerGroup::new("testcg", 1));
+        let mut consumer = Consumer::new(locked_topic, cg, vec![0], Box::new(dummy_flush)).unwrap();
 
-        let msgs = consumer.poll(10).expect("unable to poll");
-        assert!(bytes == &msgs[0]);
+        let msgs = poll_messages(&mut consumer, 10);
+        assert!(bytes == msgs[0]);
     }
 
     #[test]
@@ -284,27 +515,354 @@ mod consumer_tests {
             .path()
             .to_str()
             .expect("Unable to conver path to string");
-        let mut topic = Topic::new(
+        let topic = Topic::new(
             "another_test_topic".to_string(),
             String::from(tmp_dir_string),
-            1000,
-            10000,
+            TopicConfig {
+                max_segment_size: 1000,
+                max_topic_size: 10000,
+                ..TopicConfig::default()
+            },
         ).unwrap();
         let mut msg_vec: Vec<Vec<u8>> = Vec::new();
         for i in 0..10 {
             let string_message = format!("hello{}", i);
-            topic.commitlog.append(string_message.as_bytes()).expect("unable to append to commitlog");
+            topic.partitions[0].append(string_message.as_bytes()).expect("unable to append to commitlog");
             msg_vec.push(string_message.as_bytes().to_vec());
         }
 
         let locked_topic = Arc::new(RwLock::new(topic));
-        let cg: Arc<ConsumerGroup> = Arc::new(ConsumerGroup::new("testcg"));
-        let mut consumer = Consumer::new(locked_topic, cg, Box::new(move || dummy_flush())).unwrap();
+        let cg: Arc<ConsumerGroup> = Arc::new(ConsumerGroup::new("testcg", 1));
+        let mut consumer = Consumer::new(locked_topic, cg, vec![0], Box::new(dummy_flush)).unwrap();
 
-        let consumer_msgs = consumer.poll(10).expect("unable to poll");
+        let consumer_msgs = poll_messages(&mut consumer, 10);
         for (i, msg) in msg_vec.iter().enumerate() {
             assert!(msg == &consumer_msgs[i]);
         }
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_consumer_poll_max_bytes() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_string = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let topic = Topic::new(
+            "test_topic".to_string(),
+            String::from(tmp_dir_string),
+            TopicConfig {
+                max_segment_size: 10000,
+                max_topic_size: 100000,
+                ..TopicConfig::default()
+            },
+        ).unwrap();
+        // 40 messages of 1KB spread over a few segments, more than a single poll reads
+        for i in 0..40 {
+            topic.partitions[0].append(&[i as u8; 1024]).expect("unable to append to commitlog");
+        }
+
+        let locked_topic = Arc::new(RwLock::new(topic));
+        let cg: Arc<ConsumerGroup> = Arc::new(ConsumerGroup::new("testcg", 1));
+        let mut consumer = Consumer::new(locked_topic, cg, vec![0], Box::new(dummy_flush)).unwrap();
+
+        let first_msgs = poll_messages(&mut consumer, 10);
+        assert_eq!(MAX_POLL_BYTES / 1024, first_msgs.len());
+        let second_msgs = poll_messages(&mut consumer, 10);
+        assert_eq!(40, first_msgs.len() + second_msgs.len());
+        assert_eq!(vec![39; 1024], second_msgs[second_msgs.len() - 1]);
+        assert_eq!(40, consumer.consumer_group.offsets[0].load(Ordering::SeqCst));
+        assert!(poll_messages(&mut consumer, 10).is_empty());
+    }
+
+    #[test]
+    fn test_consumer_poll_compressed_topic() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_string = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let topic = Topic::new(
+            "test_topic".to_string(),
+            String::from(tmp_dir_string),
+            TopicConfig {
+                max_segment_size: 10000,
+                max_topic_size: 100000,
+                compression: Compression::Lz4,
+                ..TopicConfig::default()
+            },
+        ).unwrap();
+        let messages: Vec<Vec<u8>> = (0..20).map(|i| format!("compressed message {}", i).into_bytes()).collect();
+        let batch: Vec<&[u8]> = messages.iter().map(|message| message.as_slice()).collect();
+        topic.partitions[0].append_batch(&batch).expect("unable to append batch to commitlog");
+
+        let locked_topic = Arc::new(RwLock::new(topic));
+        let cg: Arc<ConsumerGroup> = Arc::new(ConsumerGroup::_new_cg("testcg", vec![5]));
+        let mut consumer = Consumer::new(locked_topic, cg, vec![0], Box::new(dummy_flush)).unwrap();
+
+        // The consumer group starts in the middle of the compressed batch
+        assert_eq!(messages[5..].to_vec(), poll_messages(&mut consumer, 10));
+        assert_eq!(20, consumer.consumer_group.offsets[0].load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_consumer_poll_encrypted_topic() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_string = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let key_file = tmp_dir.path().join("keys").to_str().unwrap().to_string();
+        fs::write(&key_file, format!("1 {}\n", "ab".repeat(32))).unwrap();
+        let topic = Topic::new(
+            "test_topic".to_string(),
+            String::from(tmp_dir_string),
+            TopicConfig {
+                max_segment_size: 10000,
+                max_topic_size: 100000,
+                compression: Compression::Lz4,
+                encryption_key_file: Some(key_file),
+                ..TopicConfig::default()
+            },
+        ).unwrap();
+        let messages: Vec<Vec<u8>> = (0..20).map(|i| format!("encrypted message {}", i).into_bytes()).collect();
+        let batch: Vec<&[u8]> = messages.iter().map(|message| message.as_slice()).collect();
+        topic.partitions[0].append_batch(&batch).expect("unable to append batch to commitlog");
+        topic.partitions[0].append("single encrypted message".as_bytes()).expect("unable to append to commitlog");
+
+        let locked_topic = Arc::new(RwLock::new(topic));
+        let cg: Arc<ConsumerGroup> = Arc::new(ConsumerGroup::_new_cg("testcg", vec![0]));
+        let mut consumer = Consumer::new(locked_topic, cg, vec![0], Box::new(dummy_flush)).unwrap();
+
+        let mut expected_messages = messages.clone();
+        expected_messages.push("single encrypted message".as_bytes().to_vec());
+        assert_eq!(expected_messages, poll_messages(&mut consumer, 10));
+        assert_eq!(21, consumer.consumer_group.offsets[0].load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_consumer_seek_to_timestamp() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_string = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let topic = Topic::new(
+            "test_topic".to_string(),
+            String::from(tmp_dir_string),
+            TopicConfig {
+                max_segment_size: 100,
+                max_topic_size: 1000,
+                ..TopicConfig::default()
+            },
+        ).unwrap();
+        for i in 0..5 {
+            topic.partitions[0].append(format!("hello{}", i).as_bytes()).expect("unable to append to commitlog");
+        }
+        thread::sleep(Duration::from_millis(20));
+        let replay_timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        for i in 5..10 {
+            topic.partitions[0].append(format!("hello{}", i).as_bytes()).expect("unable to append to commitlog");
+        }
+
+        let locked_topic = Arc::new(RwLock::new(topic));
+        let cg: Arc<ConsumerGroup> = Arc::new(ConsumerGroup::new("testcg", 1));
+        let mut consumer = Consumer::new(locked_topic, cg, vec![0], Box::new(dummy_flush)).unwrap();
+        assert_eq!(10, poll_messages(&mut consumer, 10).len());
+
+        // Replay the messages produced after the timestamp
+        assert_eq!(vec![5], consumer.seek_to_timestamp(replay_timestamp).expect("unable to seek"));
+        let consumer_msgs = poll_messages(&mut consumer, 10);
+        assert_eq!(5, consumer_msgs.len());
+        assert_eq!("hello5".as_bytes(), &*consumer_msgs[0]);
+
+        // Every message is older than a timestamp in the future
+        assert_eq!(vec![10], consumer.seek_to_timestamp(u64::MAX).expect("unable to seek"));
+    }
+
+    #[test]
+    fn test_consumer_seek() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_string = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let topic = Topic::new(
+            "test_topic".to_string(),
+            String::from(tmp_dir_string),
+            TopicConfig {
+                max_segment_size: 100,
+                max_topic_size: 1000,
+                ..TopicConfig::default()
+            },
+        ).unwrap();
+        for i in 0..10 {
+            topic.partitions[0].append(format!("hello{}", i).as_bytes()).expect("unable to append to commitlog");
+        }
+
+        let locked_topic = Arc::new(RwLock::new(topic));
+        let cg: Arc<ConsumerGroup> = Arc::new(ConsumerGroup::new("testcg", 1));
+        let mut consumer = Consumer::new(locked_topic, cg.clone(), vec![0], Box::new(dummy_flush)).unwrap();
+        assert_eq!(10, poll_messages(&mut consumer, 10).len());
+
+        assert_eq!(vec![0], consumer.seek(SeekTarget::Earliest).expect("unable to seek"));
+        assert_eq!(10, poll_messages(&mut consumer, 10).len());
+        assert_eq!(vec![4], consumer.seek(SeekTarget::Offset(4)).expect("unable to seek"));
+        assert_eq!(vec![6], consumer.seek(SeekTarget::Shift(2)).expect("unable to seek"));
+        assert_eq!(vec![3], consumer.seek(SeekTarget::Shift(-3)).expect("unable to seek"));
+        let consumer_msgs = poll_messages(&mut consumer, 10);
+        assert_eq!(7, consumer_msgs.len());
+        assert_eq!("hello3".as_bytes(), &*consumer_msgs[0]);
+        assert_eq!(vec![10], consumer.seek(SeekTarget::Latest).expect("unable to seek"));
+        assert!(poll_messages(&mut consumer, 10).is_empty());
+
+        // Rejected seeks leave the consumer group where it was
+        let out_of_range = Err(ConsumerError::new("Offset is outside the oldest and latest offset of the partition"));
+        assert_eq!(out_of_range, consumer.seek(SeekTarget::Offset(11)));
+        assert_eq!(out_of_range, consumer.seek(SeekTarget::Shift(1)));
+        assert_eq!(out_of_range, consumer.seek(SeekTarget::Shift(-11)));
+        assert_eq!(10, cg.offsets[0].load(Ordering::SeqCst));
+        assert_eq!(10, cg.committed_offsets[0].load(Ordering::SeqCst));
+    }
+
+    fn new_test_message(key: &str, value: &str) -> Vec<u8> {
+        let mut message_builder = Builder::new_default();
+        let mut message = message_builder.init_root::<message::Builder>();
+        message.set_key(key.as_bytes());
+        message.set_value(value.as_bytes());
+        serialize::write_message_to_words(&message_builder)
+    }
+
+    #[test]
+    fn test_consumer_compacted_topic() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_string = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        // Every segment fits two of the test messages
+        let topic = Topic::new(
+            "compacted_topic".to_string(),
+            String::from(tmp_dir_string),
+            TopicConfig {
+                max_segment_size: 200,
+                max_topic_size: 500,
+                cleanup_policy: CleanupPolicy::Compact,
+                ..TopicConfig::default()
+            },
+        ).unwrap();
+        let messages = [("k1", "v1"), ("k1", "v2"), ("k2", "v1"), ("k2", "v2"), ("k1", "v3")];
+        for (key, value) in messages {
+            topic.partitions[0].append(&new_test_message(key, value)).expect("unable to append to commitlog");
+        }
+        topic.compact().expect("unable to compact topic");
+
+        let locked_topic = Arc::new(RwLock::new(topic));
+        let cg: Arc<ConsumerGroup> = Arc::new(ConsumerGroup::new("testcg", 1));
+        let mut consumer = Consumer::new(locked_topic, cg, vec![0], Box::new(dummy_flush)).unwrap();
+
+        // The first value of each key is removed, consumers skip over the removed offsets
+        let consumer_msgs = poll_messages(&mut consumer, 10);
+        assert_eq!(3, consumer_msgs.len());
+        assert_eq!(new_test_message("k1", "v2"), consumer_msgs[0]);
+        assert_eq!(new_test_message("k2", "v2"), consumer_msgs[1]);
+        assert_eq!(new_test_message("k1", "v3"), consumer_msgs[2]);
+        assert_eq!(5, consumer.consumer_group.offsets[0].load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_consumer_partitions() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_string = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let topic = Topic::new(
+            "partitioned_topic".to_string(),
+            String::from(tmp_dir_string),
+            TopicConfig {
+                max_segment_size: 100,
+                max_topic_size: 1000,
+                partitions: 3,
+                ..TopicConfig::default()
+            },
+        ).unwrap();
+        topic.partitions[0].append("p0".as_bytes()).expect("unable to append to commitlog");
+        topic.partitions[2].append("p2-0".as_bytes()).expect("unable to append to commitlog");
+        topic.partitions[2].append("p2-1".as_bytes()).expect("unable to append to commitlog");
+
+        let locked_topic = Arc::new(RwLock::new(topic));
+        let cg: Arc<ConsumerGroup> = Arc::new(ConsumerGroup::new("testcg", 3));
+        let err = Consumer::new(locked_topic.clone(), cg.clone(), vec![3], Box::new(dummy_flush)).err().unwrap();
+        assert_eq!(ConsumerError::new("Partition does not exist"), err);
+
+        // Partitions with messages take turns, each poll reads from one of them
+        let mut consumer = Consumer::new(locked_topic.clone(), cg.clone(), vec![0, 1, 2], Box::new(dummy_flush)).unwrap();
+        let poll = |consumer: &mut Consumer| {
+            consumer
+                .poll(10, |partition, _next_offset, messages| (partition, messages.iter().map(|message| message.to_vec()).collect::<Vec<_>>()))
+                .expect("unable to poll")
+        };
+        assert_eq!((0, vec!["p0".as_bytes().to_vec()]), poll(&mut consumer));
+        assert_eq!((2, vec!["p2-0".as_bytes().to_vec(), "p2-1".as_bytes().to_vec()]), poll(&mut consumer));
+        locked_topic.read().unwrap().partitions[1].append("p1".as_bytes()).expect("unable to append to commitlog");
+        locked_topic.read().unwrap().partitions[0].append("p0-1".as_bytes()).expect("unable to append to commitlog");
+        // Turns wrap around to the first partition after the last one
+        assert_eq!((0, vec!["p0-1".as_bytes().to_vec()]), poll(&mut consumer));
+        assert_eq!((1, vec!["p1".as_bytes().to_vec()]), poll(&mut consumer));
+        assert!(poll(&mut consumer).1.is_empty());
+        assert_eq!(vec![2, 1, 2], cg.offsets.iter().map(|offset| offset.load(Ordering::SeqCst)).collect::<Vec<u64>>());
+
+        // A consumer of a single partition only reads that partition
+        locked_topic.read().unwrap().partitions[0].append("p0-2".as_bytes()).expect("unable to append to commitlog");
+        let mut single_consumer = Consumer::new(locked_topic, cg, vec![2], Box::new(dummy_flush)).unwrap();
+        assert!(poll(&mut single_consumer).1.is_empty());
+    }
+
+    #[test]
+    fn test_consumer_manual_commit() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_string = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let topic = Topic::new(
+            "test_topic".to_string(),
+            String::from(tmp_dir_string),
+            TopicConfig {
+                max_segment_size: 100,
+                max_topic_size: 1000,
+                ..TopicConfig::default()
+            },
+        ).unwrap();
+        for message in ["m0", "m1", "m2"] {
+            topic.partitions[0].append(message.as_bytes()).expect("unable to append to commitlog");
+        }
+
+        let locked_topic = Arc::new(RwLock::new(topic));
+        let cg: Arc<ConsumerGroup> = Arc::new(ConsumerGroup::new("testcg", 1));
+        let mut consumer = Consumer::new(locked_topic, cg.clone(), vec![0], Box::new(dummy_flush)).unwrap();
+        consumer.set_auto_commit(false);
+        let poll = |consumer: &mut Consumer| {
+            consumer
+                .poll(10, |_partition, next_offset, messages| (next_offset, messages.len()))
+                .expect("unable to poll")
+        };
+        // Messages are handed out again until they're committed
+        assert_eq!((3, 3), poll(&mut consumer));
+        assert_eq!((3, 3), poll(&mut consumer));
+        assert_eq!(3, cg.offsets[0].load(Ordering::SeqCst));
+        assert_eq!(0, cg.committed_offsets[0].load(Ordering::SeqCst));
+
+        consumer.commit_offset(0, 2).expect("unable to commit offset");
+        assert_eq!((3, 1), poll(&mut consumer));
+        consumer.commit_offset(0, 3).expect("unable to commit offset");
+        assert_eq!((3, 0), poll(&mut consumer));
+        assert_eq!(
+            Err(ConsumerError::new("Offset is past the latest offset of the partition")),
+            consumer.commit_offset(0, 4)
+        );
+        assert_eq!(Err(ConsumerError::new("Partition does not exist")), consumer.commit_offset(1, 0));
+        assert_eq!(3, cg.committed_offsets[0].load(Ordering::SeqCst));
+    }
+}
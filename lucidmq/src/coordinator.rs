@@ -0,0 +1,367 @@
+use crate::lucidmq_errors::CoordinatorError;
+use log::{error, info};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// The longest session timeout a member can join with, a member that stops heartbeating holds on to its partitions for
+/// this long at most.
+pub const MAX_SESSION_TIMEOUT_MS: u64 = 5 * 60 * 1000;
+
+/// Decides how the partitions of a topic are split between the members of a consumer group.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum AssignmentStrategy {
+    /// Every member gets a contiguous range of partitions, the members that joined first get one more partition when
+    /// the partitions don't split evenly
+    Range,
+    /// Partitions are dealt out to the members one at a time in the order they joined
+    RoundRobin,
+}
+
+impl AssignmentStrategy {
+    /// Split the partitions between a number of members, returns the partitions of every member in the order they
+    /// joined. Members are left without partitions when there are more members than partitions.
+    fn assign(&self, partition_count: u32, member_count: usize) -> Vec<Vec<u32>> {
+        let mut assignments = vec![Vec::new(); member_count];
+        if member_count == 0 {
+            return assignments;
+        }
+        match self {
+            AssignmentStrategy::Range => {
+                let per_member = partition_count as usize / member_count;
+                let extra = partition_count as usize % member_count;
+                let mut partitions = 0..partition_count;
+                for (i, assignment) in assignments.iter_mut().enumerate() {
+                    let count = per_member + usize::from(i < extra);
+                    assignment.extend(partitions.by_ref().take(count));
+                }
+            }
+            AssignmentStrategy::RoundRobin => {
+                for partition in 0..partition_count {
+                    assignments[partition as usize % member_count].push(partition);
+                }
+            }
+        }
+        assignments
+    }
+}
+
+/// The partitions a member of a consumer group consumes from.
+#[derive(Debug, PartialEq)]
+pub struct Assignment {
+    pub member_id: String,
+    /// Goes up every time the partitions of the consumer group are reassigned
+    pub generation: u64,
+    pub partitions: Vec<u32>,
+}
+
+/// A consumer that joined a consumer group, it's removed once it goes longer than its session timeout without a
+/// heartbeat.
+struct Member {
+    id: String,
+    session_timeout: Duration,
+    last_heartbeat: Instant,
+    partitions: Vec<u32>,
+}
+
+/// The members of a consumer group and the partitions assigned to them. Membership isn't persisted, members join
+/// again when the broker restarts.
+struct GroupMembership {
+    strategy: AssignmentStrategy,
+    generation: u64,
+    partition_count: u32,
+    /// Members in the order they joined
+    members: Vec<Member>,
+}
+
+impl GroupMembership {
+    /// Assign the partitions to the current members and start a new generation.
+    fn rebalance(&mut self) {
+        self.generation += 1;
+        let assignments = self.strategy.assign(self.partition_count, self.members.len());
+        for (member, partitions) in self.members.iter_mut().zip(assignments) {
+            member.partitions = partitions;
+        }
+    }
+
+    /// Remove the members that haven't sent a heartbeat within their session timeout, rebalancing if there were any.
+    fn expire_members(&mut self, now: Instant) {
+        let member_count = self.members.len();
+        self.members.retain(|member| {
+            let alive = now.duration_since(member.last_heartbeat) <= member.session_timeout;
+            if !alive {
+                info!("member {} missed its session timeout", member.id);
+            }
+            alive
+        });
+        if self.members.len() != member_count {
+            self.rebalance();
+        }
+    }
+
+    /// Returns where a member is in the consumer group.
+    fn member_index(&self, member_id: &str) -> Result<usize, CoordinatorError> {
+        self.members.iter().position(|member| member.id == member_id).ok_or_else(|| {
+            error!("member {} is not part of the consumer group", member_id);
+            CoordinatorError::new("Member is not part of the consumer group")
+        })
+    }
+
+    /// Returns the partitions assigned to the member at the index.
+    fn assignment(&self, index: usize) -> Assignment {
+        let member = &self.members[index];
+        Assignment {
+            member_id: member.id.clone(),
+            generation: self.generation,
+            partitions: member.partitions.clone(),
+        }
+    }
+}
+
+/// Keeps track of the members of every consumer group and assigns the partitions of a topic to them, so every
+/// partition is read by a single member of a consumer group. The consumer groups are rebalanced whenever a member
+/// joins, leaves or misses its session timeout.
+#[derive(Default)]
+pub struct GroupCoordinator {
+    /// Membership of every consumer group, keyed by topic name and consumer group name
+    groups: HashMap<(String, String), GroupMembership>,
+}
+
+impl GroupCoordinator {
+    /**
+    Add a member to a consumer group and return the partitions assigned to it. A member that already joined and joins
+    again keeps its partitions, a member that isn't part of the consumer group anymore joins with a new member id. The
+    first member to join decides the assignment strategy of the consumer group.
+     */
+    pub fn join(
+        &mut self,
+        topic_name: &str,
+        consumer_group_name: &str,
+        member_id: &str,
+        partition_count: u32,
+        strategy: AssignmentStrategy,
+        session_timeout_ms: u64,
+    ) -> Result<Assignment, CoordinatorError> {
+        if session_timeout_ms == 0 || session_timeout_ms > MAX_SESSION_TIMEOUT_MS {
+            error!("session timeout of {}ms is out of range", session_timeout_ms);
+            return Err(CoordinatorError::new(&format!(
+                "Session timeout has to be between 1 and {} milliseconds",
+                MAX_SESSION_TIMEOUT_MS
+            )));
+        }
+        let now = Instant::now();
+        let group = self
+            .groups
+            .entry((topic_name.to_string(), consumer_group_name.to_string()))
+            .or_insert_with(|| GroupMembership {
+                strategy,
+                generation: 0,
+                partition_count,
+                members: Vec::new(),
+            });
+        group.expire_members(now);
+        if group.members.is_empty() {
+            group.strategy = strategy;
+        } else if group.strategy != strategy {
+            error!(
+                "member joined consumer group {} with {:?} but the group uses {:?}",
+                consumer_group_name, strategy, group.strategy
+            );
+            return Err(CoordinatorError::new("Assignment strategy does not match the consumer group"));
+        }
+        if let Some(index) = group.members.iter().position(|member| member.id == member_id) {
+            let member = &mut group.members[index];
+            member.session_timeout = Duration::from_millis(session_timeout_ms);
+            member.last_heartbeat = now;
+            return Ok(group.assignment(index));
+        }
+        let new_member_id = generate_member_id(consumer_group_name);
+        info!("member {} joined consumer group {} of topic {}", new_member_id, consumer_group_name, topic_name);
+        group.members.push(Member {
+            id: new_member_id,
+            session_timeout: Duration::from_millis(session_timeout_ms),
+            last_heartbeat: now,
+            partitions: Vec::new(),
+        });
+        group.rebalance();
+        Ok(group.assignment(group.members.len() - 1))
+    }
+
+    /**
+    Keep a member in its consumer group and return the partitions currently assigned to it. Members find out the
+    consumer group was rebalanced by the generation going up.
+     */
+    pub fn heartbeat(
+        &mut self,
+        topic_name: &str,
+        consumer_group_name: &str,
+        member_id: &str,
+    ) -> Result<Assignment, CoordinatorError> {
+        let now = Instant::now();
+        let group = self.membership(topic_name, consumer_group_name)?;
+        group.expire_members(now);
+        let index = group.member_index(member_id)?;
+        group.members[index].last_heartbeat = now;
+        Ok(group.assignment(index))
+    }
+
+    /// Remove a member from its consumer group and hand its partitions to the other members. Returns the generation the
+    /// consumer group was rebalanced to.
+    pub fn leave(&mut self, topic_name: &str, consumer_group_name: &str, member_id: &str) -> Result<u64, CoordinatorError> {
+        let group = self.membership(topic_name, consumer_group_name)?;
+        group.expire_members(Instant::now());
+        let index = group.member_index(member_id)?;
+        group.members.remove(index);
+        info!("member {} left consumer group {} of topic {}", member_id, consumer_group_name, topic_name);
+        group.rebalance();
+        Ok(group.generation)
+    }
+
+    /// Whether a consumer group has members that haven't missed their session timeout.
+    pub fn has_members(&mut self, topic_name: &str, consumer_group_name: &str) -> bool {
+        match self.groups.get_mut(&(topic_name.to_string(), consumer_group_name.to_string())) {
+            Some(group) => {
+                group.expire_members(Instant::now());
+                !group.members.is_empty()
+            }
+            None => false,
+        }
+    }
+
+    /// Forget the consumer groups of a deleted topic.
+    pub fn remove_topic(&mut self, topic_name: &str) {
+        self.groups.retain(|(group_topic, _), _| group_topic != topic_name);
+    }
+
+    fn membership(&mut self, topic_name: &str, consumer_group_name: &str) -> Result<&mut GroupMembership, CoordinatorError> {
+        self.groups
+            .get_mut(&(topic_name.to_string(), consumer_group_name.to_string()))
+            .ok_or_else(|| {
+                error!("consumer group {} of topic {} has no members", consumer_group_name, topic_name);
+                CoordinatorError::new("Member is not part of the consumer group")
+            })
+    }
+}
+
+/// Generates a new random member id prefixed with the consumer group name.
+fn generate_member_id(consumer_group_name: &str) -> String {
+    let rand_string: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(10)
+        .map(char::from)
+        .collect();
+    format!("{}-{}", consumer_group_name, rand_string)
+}
+
+#[cfg(test)]
+mod coordinator_tests {
+    use crate::coordinator::{AssignmentStrategy, GroupCoordinator, MAX_SESSION_TIMEOUT_MS};
+    use crate::lucidmq_errors::CoordinatorError;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_assignment_strategies() {
+        assert_eq!(
+            vec![vec![0, 1, 2], vec![3, 4]],
+            AssignmentStrategy::Range.assign(5, 2)
+        );
+        assert_eq!(
+            vec![vec![0, 2, 4], vec![1, 3]],
+            AssignmentStrategy::RoundRobin.assign(5, 2)
+        );
+        assert_eq!(
+            vec![vec![0], vec![1], vec![]],
+            AssignmentStrategy::Range.assign(2, 3)
+        );
+        assert!(AssignmentStrategy::RoundRobin.assign(4, 0).is_empty());
+    }
+
+    #[test]
+    fn test_rebalance_on_join_and_leave() {
+        let mut coordinator = GroupCoordinator::default();
+        let first = coordinator
+            .join("topic", "cg", "", 4, AssignmentStrategy::Range, 10000)
+            .expect("Unable to join consumer group");
+        assert_eq!(1, first.generation);
+        assert_eq!(vec![0, 1, 2, 3], first.partitions);
+
+        let second = coordinator
+            .join("topic", "cg", "", 4, AssignmentStrategy::Range, 10000)
+            .expect("Unable to join consumer group");
+        assert_ne!(first.member_id, second.member_id);
+        assert_eq!(2, second.generation);
+        assert_eq!(vec![2, 3], second.partitions);
+
+        // The first member finds out about the rebalance from its heartbeat
+        let first = coordinator
+            .heartbeat("topic", "cg", &first.member_id)
+            .expect("Unable to heartbeat");
+        assert_eq!(2, first.generation);
+        assert_eq!(vec![0, 1], first.partitions);
+
+        // Joining again doesn't rebalance
+        let rejoined = coordinator
+            .join("topic", "cg", &first.member_id, 4, AssignmentStrategy::Range, 10000)
+            .expect("Unable to join consumer group");
+        assert_eq!(first, rejoined);
+
+        let generation = coordinator.leave("topic", "cg", &first.member_id).expect("Unable to leave consumer group");
+        assert_eq!(3, generation);
+        let second = coordinator
+            .heartbeat("topic", "cg", &second.member_id)
+            .expect("Unable to heartbeat");
+        assert_eq!(3, second.generation);
+        assert_eq!(vec![0, 1, 2, 3], second.partitions);
+        assert_eq!(
+            Err(CoordinatorError::new("Member is not part of the consumer group")),
+            coordinator.heartbeat("topic", "cg", &first.member_id)
+        );
+    }
+
+    #[test]
+    fn test_session_timeout() {
+        let mut coordinator = GroupCoordinator::default();
+        let short_lived = coordinator
+            .join("topic", "cg", "", 2, AssignmentStrategy::RoundRobin, 50)
+            .expect("Unable to join consumer group");
+        let long_lived = coordinator
+            .join("topic", "cg", "", 2, AssignmentStrategy::RoundRobin, 10000)
+            .expect("Unable to join consumer group");
+        assert_eq!(vec![1], long_lived.partitions);
+
+        thread::sleep(Duration::from_millis(100));
+        let long_lived = coordinator
+            .heartbeat("topic", "cg", &long_lived.member_id)
+            .expect("Unable to heartbeat");
+        assert_eq!(vec![0, 1], long_lived.partitions);
+        assert_eq!(
+            Err(CoordinatorError::new("Member is not part of the consumer group")),
+            coordinator.heartbeat("topic", "cg", &short_lived.member_id)
+        );
+    }
+
+    #[test]
+    fn test_invalid_join() {
+        let mut coordinator = GroupCoordinator::default();
+        assert_eq!(
+            Err(CoordinatorError::new(&format!(
+                "Session timeout has to be between 1 and {} milliseconds",
+                MAX_SESSION_TIMEOUT_MS
+            ))),
+            coordinator.join("topic", "cg", "", 2, AssignmentStrategy::Range, 0)
+        );
+        coordinator
+            .join("topic", "cg", "", 2, AssignmentStrategy::Range, 10000)
+            .expect("Unable to join consumer group");
+        assert_eq!(
+            Err(CoordinatorError::new("Assignment strategy does not match the consumer group")),
+            coordinator.join("topic", "cg", "", 2, AssignmentStrategy::RoundRobin, 10000)
+        );
+        // Other consumer groups pick their own strategy
+        coordinator
+            .join("topic", "other_cg", "", 2, AssignmentStrategy::RoundRobin, 10000)
+            .expect("Unable to join consumer group");
+    }
+}
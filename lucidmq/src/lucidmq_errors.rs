@@ -156,4 +156,30 @@ impl Error for ProducerError {
     fn description(&self) -> &str {
         &self.details
     }
-}
\ No newline at end of file
+}
+
+//------------Coordinator Error--------------------
+#[derive(Debug, PartialEq)]
+pub struct CoordinatorError {
+    details: String,
+}
+
+impl CoordinatorError {
+    pub fn new(msg: &str) -> CoordinatorError {
+        CoordinatorError {
+            details: msg.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for CoordinatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for CoordinatorError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
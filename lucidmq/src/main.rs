@@ -1,6 +1,7 @@
 mod broker;
 mod cap_n_proto_helper;
 mod consumer;
+mod coordinator;
 pub mod lucid_schema_capnp;
 mod producer;
 mod lucidmq_errors;
@@ -9,8 +10,8 @@ mod topic;
 mod types;
 
 use std::env;
+use std::time::Duration;
 
-use env_logger;
 use log::info;
 use tokio::sync::mpsc;
 
@@ -25,8 +26,18 @@ pub async fn main() {
     let host = get_env_variable("HOST", "127.0.0.1");
     let port = get_env_variable("PORT", "6969");
     let lucidmq_directory = get_env_variable("LUCIDMQ_DIRECTORY", "test_log");
+    let clean_interval_ms: u64 = get_env_variable("CLEAN_INTERVAL_MS", "60000")
+        .parse()
+        .expect("CLEAN_INTERVAL_MS must be a number of milliseconds");
+    let encryption_key_file = env::var("LUCIDMQ_KEY_FILE").ok();
 
-    let broker = broker::Broker::new(lucidmq_directory).unwrap();
+    let broker = broker::Broker::new(lucidmq_directory, encryption_key_file).unwrap();
+    let cleaner_broker = broker.clone();
+    tokio::spawn(async move {
+        cleaner_broker
+            .run_cleaner(Duration::from_millis(clean_interval_ms))
+            .await;
+    });
     tokio::spawn(async move {
         broker
             .run(request_channel_reciever, response_channel_sender)
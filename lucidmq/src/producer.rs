@@ -1,5 +1,7 @@
 use crate::{topic::Topic, lucidmq_errors::ProducerError};
 use log::error;
+use std::collections::BTreeMap;
+use std::ops::Range;
 use std::sync::{Arc, RwLock};
 
 pub struct Producer {
@@ -14,22 +16,70 @@ impl Producer {
         }
     }
 
-    /// Produce a single message to the commitlog, returning the offset in the commitlog where it's located
-    pub fn produce_bytes(&mut self, bytes: &[u8]) -> Result<u16, ProducerError> {
-        let written_offset = self.topic.write().unwrap().commitlog.append(&bytes).map_err(|e| {
+    /// Produce a single message to the commitlog of a partition, returning the offset in the commitlog where it's located
+    pub fn _produce_bytes(&mut self, partition: u32, bytes: &[u8]) -> Result<u64, ProducerError> {
+        let topic = self.topic.read().unwrap();
+        let commitlog = topic.partition(partition).map_err(|e| {
+            error!("{}", e);
+            ProducerError::new("Partition does not exist")
+        })?;
+        let written_offset = commitlog.append(bytes).map_err(|e| {
             error!("{}", e);
             ProducerError::new("Unable to produce message to the commitlog")
         })?;
         Ok(written_offset)
     }
 
-    pub fn _produce_bytes_vector(&mut self, bytes_vector: Vec<Vec<u8>>) -> u16 {
-        let mut last_offset = 0;
-        let commitlog = &mut self.topic.write().unwrap().commitlog;
-        for bytes in bytes_vector {
-            last_offset = commitlog.append(&bytes).expect("Unable to produce messsage to commitlog");
+    /// Produce a batch of messages to the commitlog of each partition, with a single append per partition. Every
+    /// batch is checked before any of them is appended, so a produce that's rejected doesn't write anything. Returns
+    /// the range of offsets in the commitlog where the messages of each partition are located
+    pub fn produce_batches(
+        &mut self,
+        partition_batches: &BTreeMap<u32, Vec<Vec<u8>>>,
+    ) -> Result<Vec<(u32, Range<u64>)>, ProducerError> {
+        let partition_batches: Vec<(u32, Vec<&[u8]>)> = partition_batches
+            .iter()
+            .map(|(partition, batch)| (*partition, batch.iter().map(|bytes| bytes.as_slice()).collect()))
+            .collect();
+        let topic = self.topic.read().map_err(|e| {
+            error!("{}", e);
+            ProducerError::new("Unable to get lock on producer topic")
+        })?;
+        for (partition, batch) in &partition_batches {
+            Producer::check_batch(&topic, *partition, batch)?;
         }
-        last_offset
+        let mut partition_offsets = Vec::with_capacity(partition_batches.len());
+        for (partition, batch) in &partition_batches {
+            let commitlog = topic.partition(*partition).map_err(|e| {
+                error!("{}", e);
+                ProducerError::new("Partition does not exist")
+            })?;
+            let written_offsets = commitlog.append_batch(batch).map_err(|e| {
+                error!("{}", e);
+                ProducerError::new("Unable to produce messages to the commitlog")
+            })?;
+            partition_offsets.push((*partition, written_offsets));
+        }
+        Ok(partition_offsets)
+    }
+
+    /// Check that a batch can be appended to the partition, without writing anything.
+    fn check_batch(topic: &Topic, partition: u32, batch: &[&[u8]]) -> Result<(), ProducerError> {
+        if let Some(max_message_size) = topic.max_message_size {
+            if batch.iter().any(|bytes| bytes.len() as u64 > max_message_size) {
+                error!("message is larger than the max message size {} of topic {}", max_message_size, topic.name);
+                return Err(ProducerError::new("Message is larger than the max message size of the topic"));
+            }
+        }
+        let commitlog = topic.partition(partition).map_err(|e| {
+            error!("{}", e);
+            ProducerError::new("Partition does not exist")
+        })?;
+        commitlog.check_batch(batch).map_err(|e| {
+            error!("{}", e);
+            ProducerError::new("Message is larger than the max segment size of the topic")
+        })?;
+        Ok(())
     }
 
     pub fn _get_topic(&self) -> String {
@@ -39,9 +89,10 @@ impl Producer {
 
 #[cfg(test)]
 mod producer_tests {
+    use std::collections::BTreeMap;
     use std::sync::{Arc, RwLock};
     use crate::lucidmq_errors::ProducerError;
-    use crate::topic::Topic;
+    use crate::topic::{Topic, TopicConfig};
     use crate::producer::Producer;
     use tempdir::TempDir;
 
@@ -55,19 +106,22 @@ mod producer_tests {
         let topic = Topic::new(
             "test_topic".to_string(),
             String::from(tmp_dir_string),
-            10,
-            100,
+            TopicConfig {
+                max_segment_size: 50,
+                max_topic_size: 500,
+                ..TopicConfig::default()
+            },
         ).unwrap();
 
         let locked_topic = Arc::new(RwLock::new(topic));
         let mut producer = Producer::new(locked_topic.clone());
         let bytes = "hello".as_bytes();
         // check the offset
-        let offset = producer.produce_bytes(bytes).expect("Unable to produce bytes");
+        let offset = producer._produce_bytes(0, bytes).expect("Unable to produce bytes");
         assert!(offset == 0);
         // check the message provided
-        let msg = locked_topic.write().expect("unable to get lock").commitlog.read(0).expect("unable to read commitlog");
-        assert!(bytes == &msg);
+        let msg = locked_topic.read().expect("unable to get lock").partitions[0].read(0).expect("unable to read commitlog");
+        assert!(bytes == msg);
     }
 
     #[test]
@@ -80,14 +134,17 @@ mod producer_tests {
         let topic = Topic::new(
             "test_topic".to_string(),
             String::from(tmp_dir_string),
-            10,
-            100,
+            TopicConfig {
+                max_segment_size: 50,
+                max_topic_size: 500,
+                ..TopicConfig::default()
+            },
         ).unwrap();
 
         let locked_topic = Arc::new(RwLock::new(topic));
         let mut producer = Producer::new(locked_topic.clone());
-        let bytes: [u8; 11] = [0; 11];
-        let producer_error = producer.produce_bytes(&bytes).unwrap_err();
+        let bytes: [u8; 50] = [0; 50];
+        let producer_error = producer._produce_bytes(0, &bytes).unwrap_err();
         let wanted_error =
             ProducerError::new("Unable to produce message to the commitlog");
         assert_eq!(wanted_error, producer_error);
@@ -103,8 +160,11 @@ mod producer_tests {
         let topic = Topic::new(
             "test_topic".to_string(),
             String::from(tmp_dir_string),
-            10,
-            100,
+            TopicConfig {
+                max_segment_size: 50,
+                max_topic_size: 500,
+                ..TopicConfig::default()
+            },
         ).unwrap();
 
         let locked_topic = Arc::new(RwLock::new(topic));
@@ -113,16 +173,16 @@ mod producer_tests {
             let string_message = format!("hellow{}", i);
             let test_data = string_message.as_bytes();
             // check the offset
-            let offset = producer.produce_bytes(test_data).expect("Unable to produce bytes");
+            let offset = producer._produce_bytes(0, test_data).expect("Unable to produce bytes");
             assert!(offset == i);
             // check the message provided
-            let msg = locked_topic.write().expect("unable to get lock").commitlog.read(i.into()).expect("unable to read commitlog");
-            assert!(test_data == &msg);
+            let msg = locked_topic.read().expect("unable to get lock").partitions[0].read(i).expect("unable to read commitlog");
+            assert!(test_data == msg);
         }
     }
 
     #[test]
-    fn test_producer_produce_bytes_vector() {
+    fn test_producer_produce_batch() {
         let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
         let tmp_dir_string = tmp_dir
             .path()
@@ -131,8 +191,11 @@ mod producer_tests {
         let topic = Topic::new(
             "test_topic".to_string(),
             String::from(tmp_dir_string),
-            10,
-            100,
+            TopicConfig {
+                max_segment_size: 50,
+                max_topic_size: 500,
+                ..TopicConfig::default()
+            },
         ).unwrap();
 
         let locked_topic = Arc::new(RwLock::new(topic));
@@ -143,15 +206,86 @@ mod producer_tests {
             let test_data = string_message.as_bytes().to_vec();
             msg_vec.push(test_data);
         }
-        // check the offset
-        let offset = producer._produce_bytes_vector(msg_vec.clone());
-        let thin= u16::try_from(msg_vec.len()-1).expect("Unable to convert u16");
-        assert!(offset == thin);
+        // check the offsets
+        let offsets = producer
+            .produce_batches(&BTreeMap::from([(0, msg_vec.clone())]))
+            .expect("Unable to produce batch");
+        assert_eq!(vec![(0, 0..10)], offsets);
         for (i, msg) in msg_vec.iter().enumerate() {
             // check the message provided
-            let commitlog_msg = locked_topic.write().expect("unable to get lock").commitlog.read(i.into()).expect("unable to read commitlog");
+            let commitlog_msg = locked_topic.read().expect("unable to get lock").partitions[0].read(i as u64).expect("unable to read commitlog");
             assert!(&commitlog_msg == msg);
         }
+
+        // A batch with a message that doesn't fit in a segment isn't produced at all
+        let batch = vec!["hello".as_bytes().to_vec(), vec![0; 50]];
+        let producer_error = producer.produce_batches(&BTreeMap::from([(0, batch)])).unwrap_err();
+        let wanted_error =
+            ProducerError::new("Message is larger than the max segment size of the topic");
+        assert_eq!(wanted_error, producer_error);
+        assert_eq!(10, locked_topic.read().expect("unable to get lock").partitions[0].get_latest_offset());
+    }
+
+    #[test]
+    fn test_producer_max_message_size() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_string = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let topic = Topic::new(
+            "test_topic".to_string(),
+            String::from(tmp_dir_string),
+            TopicConfig {
+                max_segment_size: 50,
+                max_topic_size: 500,
+                max_message_size: Some(5),
+                ..TopicConfig::default()
+            },
+        ).unwrap();
+
+        let locked_topic = Arc::new(RwLock::new(topic));
+        let mut producer = Producer::new(locked_topic.clone());
+        let offsets = producer
+            .produce_batches(&BTreeMap::from([(0, vec!["hello".as_bytes().to_vec()])]))
+            .expect("Unable to produce batch");
+        assert_eq!(vec![(0, 0..1)], offsets);
+
+        // A batch with a message over the max message size isn't produced at all
+        let batch = vec!["hello".as_bytes().to_vec(), "hellow".as_bytes().to_vec()];
+        let producer_error = producer.produce_batches(&BTreeMap::from([(0, batch)])).unwrap_err();
+        let wanted_error =
+            ProducerError::new("Message is larger than the max message size of the topic");
+        assert_eq!(wanted_error, producer_error);
+        assert_eq!(1, locked_topic.read().expect("unable to get lock").partitions[0].get_latest_offset());
+    }
+
+    #[test]
+    fn test_producer_checks_every_partition_first() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_string = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let topic = Topic::new(
+            "test_topic".to_string(),
+            String::from(tmp_dir_string),
+            TopicConfig {
+                max_segment_size: 50,
+                max_topic_size: 500,
+                partitions: 2,
+                ..TopicConfig::default()
+            },
+        ).unwrap();
+
+        let locked_topic = Arc::new(RwLock::new(topic));
+        let mut producer = Producer::new(locked_topic.clone());
+        // A bad batch for one partition keeps the batch for the other partition from being produced
+        for bad_batch in [(1, vec![vec![0; 50]]), (2, vec!["hello".as_bytes().to_vec()])] {
+            let partition_batches = BTreeMap::from([(0, vec!["hello".as_bytes().to_vec()]), bad_batch]);
+            producer.produce_batches(&partition_batches).unwrap_err();
+            assert_eq!(0, locked_topic.read().expect("unable to get lock").partitions[0].get_latest_offset());
+        }
     }
 
 }
@@ -41,8 +41,8 @@ impl LucidTcpServer {
         Ok(LucidTcpServer {
             peer_map: PeerMap::new(Mutex::new(HashMap::new())),
             address: addr,
-            sender: sender,
-            reciever: reciever,
+            sender,
+            reciever,
         })
     }
 
@@ -91,10 +91,7 @@ async fn handle_request(conn_id: String, recv: OwnedReadHalf, sender: SenderType
         let bytes_read = recv.try_read(&mut buf);
         let message_size: u16 = match bytes_read {
             Ok(0) => break,
-            Ok(_total) => {
-                let message_size = u16::from_le_bytes(buf);
-                message_size
-            }
+            Ok(_total) => u16::from_le_bytes(buf),
             Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
                 continue;
             }
@@ -177,5 +174,5 @@ fn generate_connection_string() -> String {
         .take(10)
         .map(char::from)
         .collect();
-    return rand_string;
+    rand_string
 }
@@ -1,35 +1,118 @@
-use log::{debug, error};
-use nolan::Commitlog;
+use crate::lucid_schema_capnp::message;
+use capnp::{message::ReaderOptions, serialize};
+use log::{debug, error, info};
+use nolan::{Commitlog, CommitlogConfig, Compression, KeyExtractor, RecordKey};
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
 use std::path::Path;
 use std::str;
-use std::{sync::atomic::AtomicU32, sync::Arc};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
 use crate::lucidmq_errors::TopicError;
 
+/// How long tombstones are kept in a compacted topic before they're removed, so consumers have a chance to see the delete.
+const TOMBSTONE_RETENTION_MS: u64 = 24 * 60 * 60 * 1000;
+/// Segments are rolled once they're a week old by default, so retention applies to topics that are rarely produced to.
+const DEFAULT_SEGMENT_MS: u64 = 7 * 24 * 60 * 60 * 1000;
+/// The most partitions a topic can be created with, every partition is a commitlog with its own files. Describe and seek
+/// responses list every partition, so this keeps them well inside the 64KB message frame.
+pub const MAX_PARTITIONS: u32 = 256;
+
+/// Names of the topic settings in create topic requests and describe topic responses.
+pub const MAX_SEGMENT_BYTES: &str = "max_segment_bytes";
+pub const MAX_RETENTION_BYTES: &str = "max_retention_bytes";
+pub const RETENTION_MS: &str = "retention_ms";
+pub const SEGMENT_MS: &str = "segment_ms";
+pub const MAX_MESSAGE_BYTES: &str = "max_message_bytes";
+pub const CLEANUP_POLICY: &str = "cleanup_policy";
+pub const COMPRESSION: &str = "compression";
+pub const PARTITIONS: &str = "partitions";
+
+/// Decides how old messages are removed from a topic.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum CleanupPolicy {
+    /// Whole segments are deleted once the topic is over its max size or the messages are older than the retention time
+    #[default]
+    Delete,
+    /// Only the latest message for each key is kept, messages are never deleted based on size or age
+    Compact,
+}
+
+/// Pulls the key out of the messages stored in the commitlog, messages without a value are tombstones.
+struct MessageKeyExtractor;
+
+impl KeyExtractor for MessageKeyExtractor {
+    fn extract_key(&self, payload: &[u8]) -> Option<RecordKey> {
+        let message_reader = serialize::read_message(payload, ReaderOptions::new()).ok()?;
+        let message = message_reader.get_root::<message::Reader>().ok()?;
+        let key = message.get_key().ok()?;
+        if key.is_empty() {
+            return None;
+        }
+        Some(RecordKey {
+            key: key.to_vec(),
+            tombstone: !message.has_value(),
+        })
+    }
+}
+
 /// Consumer groups are used by consumers as a way to denote what the last read message offset is in underlying commitlog.
 /// This allows for multiple consumers to read from the same topic, but consumer messages at their own pace.
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(from = "DeserConsumerGroup")]
 pub struct ConsumerGroup {
     pub name: String,
-    pub offset: AtomicU32,
+    /// The offset the consumer group reads from next in every partition, indexed by partition
+    pub offsets: Vec<AtomicU64>,
+    /// The offset every message before has been processed by the consumer group in every partition, indexed by
+    /// partition. Consumers that don't commit automatically are handed the messages after it until they commit
+    pub committed_offsets: Vec<AtomicU64>,
+    /// Partition a consumer reading from every partition polls first, so each partition gets its turn
+    #[serde(skip)]
+    pub next_partition: AtomicU32,
 }
 
 impl ConsumerGroup {
-    /// Initialize a new consumer group with just a name
-    pub fn new(consumer_group_name: &str) -> ConsumerGroup {
+    /// Initialize a new consumer group with just a name, reading every partition from the start
+    pub fn new(consumer_group_name: &str, partition_count: u32) -> ConsumerGroup {
+        ConsumerGroup::_new_cg(consumer_group_name, vec![0; partition_count as usize])
+    }
+
+    pub fn _new_cg(consumer_group_name: &str, offsets_in: Vec<u64>) -> ConsumerGroup {
         ConsumerGroup {
             name: consumer_group_name.to_string(),
-            offset: 0.into(),
+            committed_offsets: offsets_in.iter().copied().map(AtomicU64::new).collect(),
+            offsets: offsets_in.into_iter().map(AtomicU64::new).collect(),
+            next_partition: AtomicU32::new(0),
         }
     }
+}
 
-    pub fn _new_cg(consumer_group_name: &str, offset_in: AtomicU32) -> ConsumerGroup {
-        ConsumerGroup {
-            name: consumer_group_name.to_string(),
-            offset: offset_in,
+/// Deserialize a consumer group from the metadata file. Consumer groups written before topics had partitions have a
+/// single offset, which becomes the offset of their only partition. Consumer groups written before offsets were
+/// committed separately count everything they read as committed.
+#[derive(Deserialize)]
+struct DeserConsumerGroup {
+    name: String,
+    #[serde(default)]
+    offset: u64,
+    #[serde(default)]
+    offsets: Vec<u64>,
+    #[serde(default)]
+    committed_offsets: Vec<u64>,
+}
+
+impl From<DeserConsumerGroup> for ConsumerGroup {
+    fn from(mut tmp: DeserConsumerGroup) -> Self {
+        if tmp.offsets.is_empty() {
+            tmp.offsets.push(tmp.offset);
         }
+        let mut consumer_group = ConsumerGroup::_new_cg(&tmp.name, tmp.offsets);
+        if consumer_group.committed_offsets.len() == tmp.committed_offsets.len() {
+            consumer_group.committed_offsets = tmp.committed_offsets.into_iter().map(AtomicU64::new).collect();
+        }
+        consumer_group
     }
 }
 
@@ -43,47 +126,321 @@ pub struct Topic {
     pub consumer_groups: Vec<Arc<ConsumerGroup>>,
     pub max_segment_size: u64,
     pub max_topic_size: u64,
+    /// How long messages are kept for in milliseconds, if not set messages are only removed based on the topic size
+    pub retention_ms: Option<u64>,
+    pub cleanup_policy: CleanupPolicy,
+    /// Codec batches of messages are compressed with when they're produced to the topic
+    pub compression: Compression,
+    /// Key file the messages of the topic are encrypted at rest with, messages are stored in plaintext if not set
+    pub encryption_key_file: Option<String>,
+    /// How long in milliseconds a segment is produced to before it's rolled, segments only roll once full if not set
+    pub segment_ms: Option<u64>,
+    /// Largest message in bytes that can be produced to the topic, messages only have to fit in a segment if not set
+    pub max_message_size: Option<u64>,
+    /// Directory of the commitlog of every partition, indexed by partition
+    pub partition_directories: Vec<String>,
+    /// The commitlog of every partition, indexed by partition
+    #[serde(skip_serializing)]
+    pub partitions: Vec<Commitlog>,
+    /// Partition the next message without a key is produced to
     #[serde(skip_serializing)]
-    pub commitlog: Commitlog,
+    next_partition: AtomicU32,
 }
 
-/// Deserialize a topic from bytes the topic struct
+/// Deserialize a topic from bytes the topic struct. Settings added after a topic was written keep their default, a
+/// topic without partition directories has its directory as its only partition.
 #[derive(Deserialize)]
 struct DeserTopic {
     name: String,
     directory: String,
+    #[serde(default)]
     consumer_groups: Vec<Arc<ConsumerGroup>>,
     pub max_segment_size: u64,
     pub max_topic_size: u64,
+    #[serde(default)]
+    pub retention_ms: Option<u64>,
+    #[serde(default)]
+    pub cleanup_policy: CleanupPolicy,
+    #[serde(default)]
+    pub compression: Compression,
+    #[serde(default)]
+    pub encryption_key_file: Option<String>,
+    #[serde(default = "default_segment_ms")]
+    pub segment_ms: Option<u64>,
+    #[serde(default)]
+    pub max_message_size: Option<u64>,
+    #[serde(default)]
+    pub partition_directories: Vec<String>,
+}
+
+fn default_segment_ms() -> Option<u64> {
+    Some(DEFAULT_SEGMENT_MS)
 }
 
 impl From<DeserTopic> for Topic {
-    fn from(tmp: DeserTopic) -> Self {
-        let commitlog = nolan::Commitlog::new(
-            &tmp.directory,
-            tmp.max_segment_size,
-            tmp.max_topic_size,
-        )
-        .expect("Unable to create commitlog for topic");
+    fn from(mut tmp: DeserTopic) -> Self {
+        if tmp.partition_directories.is_empty() {
+            tmp.partition_directories.push(tmp.directory.clone());
+        }
+        let config = TopicConfig {
+            max_segment_size: tmp.max_segment_size,
+            max_topic_size: tmp.max_topic_size,
+            retention_ms: tmp.retention_ms,
+            cleanup_policy: tmp.cleanup_policy,
+            compression: tmp.compression,
+            encryption_key_file: tmp.encryption_key_file,
+            segment_ms: tmp.segment_ms,
+            max_message_size: tmp.max_message_size,
+            partitions: tmp.partition_directories.len() as u32,
+        };
+        let partitions = tmp
+            .partition_directories
+            .iter()
+            .map(|directory| create_commitlog(directory, &config).expect("Unable to create commitlog for topic"))
+            .collect();
         Self {
             name: tmp.name,
             directory: tmp.directory,
             consumer_groups: tmp.consumer_groups,
+            max_segment_size: config.max_segment_size,
+            max_topic_size: config.max_topic_size,
+            retention_ms: config.retention_ms,
+            cleanup_policy: config.cleanup_policy,
+            compression: config.compression,
+            encryption_key_file: config.encryption_key_file,
+            segment_ms: config.segment_ms,
+            max_message_size: config.max_message_size,
+            partition_directories: tmp.partition_directories,
+            partitions,
+            next_partition: AtomicU32::new(0),
+        }
+    }
+}
+
+/// Consumer group as it was persisted before offsets were widened to 64 bits.
+#[derive(Deserialize)]
+pub struct LegacyConsumerGroup {
+    name: String,
+    offset: u32,
+}
+
+impl From<LegacyConsumerGroup> for ConsumerGroup {
+    fn from(tmp: LegacyConsumerGroup) -> Self {
+        ConsumerGroup::_new_cg(&tmp.name, vec![u64::from(tmp.offset)])
+    }
+}
+
+/// Topic as it was persisted before consumer group offsets were widened to 64 bits.
+#[derive(Deserialize)]
+pub struct LegacyTopic {
+    name: String,
+    directory: String,
+    consumer_groups: Vec<LegacyConsumerGroup>,
+    max_segment_size: u64,
+    max_topic_size: u64,
+}
+
+impl From<LegacyTopic> for Topic {
+    fn from(tmp: LegacyTopic) -> Self {
+        Topic::from(DeserTopic {
+            name: tmp.name,
+            partition_directories: vec![tmp.directory.clone()],
+            directory: tmp.directory,
+            consumer_groups: tmp
+                .consumer_groups
+                .into_iter()
+                .map(|cg| Arc::new(ConsumerGroup::from(cg)))
+                .collect(),
             max_segment_size: tmp.max_segment_size,
             max_topic_size: tmp.max_topic_size,
-            commitlog: commitlog,
+            retention_ms: None,
+            cleanup_policy: CleanupPolicy::Delete,
+            compression: Compression::None,
+            encryption_key_file: None,
+            segment_ms: Some(DEFAULT_SEGMENT_MS),
+            max_message_size: None,
+        })
+    }
+}
+
+/// The settings a topic is created with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicConfig {
+    pub max_segment_size: u64,
+    pub max_topic_size: u64,
+    /// How long messages are kept for in milliseconds, if not set messages are only removed based on the topic size
+    pub retention_ms: Option<u64>,
+    pub cleanup_policy: CleanupPolicy,
+    /// Codec batches of messages are compressed with when they're produced to the topic
+    pub compression: Compression,
+    /// Key file the messages of the topic are encrypted at rest with, messages are stored in plaintext if not set
+    pub encryption_key_file: Option<String>,
+    /// How long in milliseconds a segment is produced to before it's rolled, segments only roll once full if not set
+    pub segment_ms: Option<u64>,
+    /// Largest message in bytes that can be produced to the topic, messages only have to fit in a segment if not set
+    pub max_message_size: Option<u64>,
+    /// How many partitions the messages of the topic are spread over
+    pub partitions: u32,
+}
+
+impl Default for TopicConfig {
+    fn default() -> Self {
+        TopicConfig {
+            max_segment_size: 100000, //100kb
+            max_topic_size: 1000000, //1mb
+            retention_ms: None,
+            cleanup_policy: CleanupPolicy::Delete,
+            compression: Compression::None,
+            encryption_key_file: None,
+            segment_ms: Some(DEFAULT_SEGMENT_MS),
+            max_message_size: None,
+            partitions: 1,
         }
     }
 }
 
+impl TopicConfig {
+    /// Build the config of a new topic from the settings in a create topic request, settings that aren't given keep
+    /// their default. Returns an error for unknown settings, values that can't be parsed or settings that conflict.
+    pub fn from_entries(entries: &[(String, String)], defaults: TopicConfig) -> Result<TopicConfig, TopicError> {
+        let mut config = defaults;
+        for (index, (key, value)) in entries.iter().enumerate() {
+            if entries[..index].iter().any(|(previous_key, _)| previous_key == key) {
+                error!("topic setting {} is given more than once", key);
+                return Err(TopicError::new("Topic setting is given more than once"));
+            }
+            match key.as_str() {
+                MAX_SEGMENT_BYTES => config.max_segment_size = parse_setting(key, value)?,
+                MAX_RETENTION_BYTES => config.max_topic_size = parse_setting(key, value)?,
+                RETENTION_MS => config.retention_ms = Some(parse_setting(key, value)?),
+                SEGMENT_MS => config.segment_ms = Some(parse_setting(key, value)?),
+                MAX_MESSAGE_BYTES => config.max_message_size = Some(parse_setting(key, value)?),
+                PARTITIONS => {
+                    config.partitions = u32::try_from(parse_setting(key, value)?).map_err(|e| {
+                        error!("{}", e);
+                        TopicError::new("Invalid value for topic setting")
+                    })?
+                }
+                CLEANUP_POLICY => {
+                    config.cleanup_policy = match value.as_str() {
+                        "delete" => CleanupPolicy::Delete,
+                        "compact" => CleanupPolicy::Compact,
+                        _ => {
+                            error!("unknown cleanup policy {}", value);
+                            return Err(TopicError::new("Invalid value for topic setting"));
+                        }
+                    }
+                }
+                COMPRESSION => {
+                    config.compression = match value.as_str() {
+                        "none" => Compression::None,
+                        "zstd" => Compression::Zstd,
+                        "lz4" => Compression::Lz4,
+                        "snappy" => Compression::Snappy,
+                        _ => {
+                            error!("unknown compression codec {}", value);
+                            return Err(TopicError::new("Invalid value for topic setting"));
+                        }
+                    }
+                }
+                _ => {
+                    error!("unknown topic setting {}", key);
+                    return Err(TopicError::new("Unknown topic setting"));
+                }
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check the settings make sense together.
+    fn validate(&self) -> Result<(), TopicError> {
+        if self.max_segment_size == 0 {
+            return Err(TopicError::new("Max segment bytes has to be greater than 0"));
+        }
+        if self.max_topic_size < self.max_segment_size {
+            return Err(TopicError::new("Max retention bytes has to be at least the max segment bytes"));
+        }
+        if self.retention_ms == Some(0) || self.segment_ms == Some(0) {
+            return Err(TopicError::new("Retention and segment time have to be greater than 0"));
+        }
+        if self.partitions == 0 || self.partitions > MAX_PARTITIONS {
+            return Err(TopicError::new(&format!("Partitions has to be between 1 and {}", MAX_PARTITIONS)));
+        }
+        // A message is stored as a single record, so it has to fit in a segment
+        if let Some(max_message_size) = self.max_message_size {
+            if max_message_size == 0 || max_message_size > self.max_segment_size {
+                return Err(TopicError::new("Max message bytes has to be between 1 and the max segment bytes"));
+            }
+        }
+        Ok(())
+    }
+
+    /// The settings as key value pairs, the way they're sent in create requests and describe responses. Settings that
+    /// aren't set are left out and the encryption key file is never sent, it's configured on the broker.
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let mut entries = vec![
+            (MAX_SEGMENT_BYTES.to_string(), self.max_segment_size.to_string()),
+            (MAX_RETENTION_BYTES.to_string(), self.max_topic_size.to_string()),
+        ];
+        if let Some(retention_ms) = self.retention_ms {
+            entries.push((RETENTION_MS.to_string(), retention_ms.to_string()));
+        }
+        if let Some(segment_ms) = self.segment_ms {
+            entries.push((SEGMENT_MS.to_string(), segment_ms.to_string()));
+        }
+        if let Some(max_message_size) = self.max_message_size {
+            entries.push((MAX_MESSAGE_BYTES.to_string(), max_message_size.to_string()));
+        }
+        let cleanup_policy = match self.cleanup_policy {
+            CleanupPolicy::Delete => "delete",
+            CleanupPolicy::Compact => "compact",
+        };
+        entries.push((CLEANUP_POLICY.to_string(), cleanup_policy.to_string()));
+        let compression = match self.compression {
+            Compression::None => "none",
+            Compression::Zstd => "zstd",
+            Compression::Lz4 => "lz4",
+            Compression::Snappy => "snappy",
+        };
+        entries.push((COMPRESSION.to_string(), compression.to_string()));
+        entries.push((PARTITIONS.to_string(), self.partitions.to_string()));
+        entries
+    }
+}
+
+/// Parse the value of a numeric topic setting.
+fn parse_setting(key: &str, value: &str) -> Result<u64, TopicError> {
+    value.parse::<u64>().map_err(|e| {
+        error!("invalid value {} for topic setting {}: {}", value, key, e);
+        TopicError::new("Invalid value for topic setting")
+    })
+}
+
+/// Create the commitlog that backs a topic with the topics settings.
+/// Compacted topics keep the latest message for every key, so the cleaner never deletes their segments.
+fn create_commitlog(directory: &str, config: &TopicConfig) -> Result<Commitlog, nolan::CommitlogError> {
+    let (retention_bytes, retention_ms) = match config.cleanup_policy {
+        CleanupPolicy::Delete => (config.max_topic_size, config.retention_ms),
+        CleanupPolicy::Compact => (u64::MAX, None),
+    };
+    let commitlog_config = CommitlogConfig {
+        max_segment_size: config.max_segment_size,
+        retention_bytes,
+        retention_ms,
+        compression: config.compression,
+        encryption_key_file: config.encryption_key_file.clone(),
+        segment_manifest: true,
+        segment_ms: config.segment_ms,
+        ..CommitlogConfig::default()
+    };
+    Commitlog::with_config(directory, commitlog_config)
+}
+
 impl Topic {
-    /// Initializes a new topic instance and builds the commitlog with the parmeters passed in.
-    pub fn new(
-        topic_name: String,
-        base_directory: String,
-        max_segment_size: u64,
-        max_topic_size: u64,
-    ) -> Result<Topic, TopicError> {
+    /// Initializes a new topic instance and builds the commitlog of every partition with the config passed in.
+    /// Partitions are kept in numbered directories inside the topic directory.
+    pub fn new(topic_name: String, base_directory: String, config: TopicConfig) -> Result<Topic, TopicError> {
         debug!("Creating a new topic {}", topic_name);
         let path = Path::new(&base_directory);
         // Generate a random directory name
@@ -94,16 +451,23 @@ impl Topic {
             .collect();
         let new_path = &path.join(directory_name);
         let new_consumer_groups = Vec::new();
-        let new_commitlog = nolan::Commitlog::new(
-            new_path
-                .to_str()
-                .expect("unable to convert to string"),
-            max_segment_size,
-            max_topic_size,
-        ).map_err(|err| {
-            error!("{}", err);
-            TopicError::new("Unable to create commitlog for topic")
-        })?;
+        let partition_directories: Vec<String> = (0..config.partitions)
+            .map(|partition| {
+                new_path
+                    .join(partition.to_string())
+                    .to_str()
+                    .expect("unable to convert to string")
+                    .to_string()
+            })
+            .collect();
+        let mut partitions = Vec::with_capacity(partition_directories.len());
+        for partition_directory in &partition_directories {
+            let commitlog = create_commitlog(partition_directory, &config).map_err(|err| {
+                error!("{}", err);
+                TopicError::new("Unable to create commitlog for topic")
+            })?;
+            partitions.push(commitlog);
+        }
         Ok(Topic {
             name: topic_name,
             directory: new_path
@@ -111,12 +475,70 @@ impl Topic {
                 .expect("unable to convert to string")
                 .to_string(),
             consumer_groups: new_consumer_groups,
-            commitlog: new_commitlog,
-            max_segment_size: max_segment_size,
-            max_topic_size: max_topic_size,
+            partition_directories,
+            partitions,
+            next_partition: AtomicU32::new(0),
+            max_segment_size: config.max_segment_size,
+            max_topic_size: config.max_topic_size,
+            retention_ms: config.retention_ms,
+            cleanup_policy: config.cleanup_policy,
+            compression: config.compression,
+            encryption_key_file: config.encryption_key_file,
+            segment_ms: config.segment_ms,
+            max_message_size: config.max_message_size,
         })
     }
 
+    /// The settings the topic was created with.
+    pub fn config(&self) -> TopicConfig {
+        TopicConfig {
+            max_segment_size: self.max_segment_size,
+            max_topic_size: self.max_topic_size,
+            retention_ms: self.retention_ms,
+            cleanup_policy: self.cleanup_policy,
+            compression: self.compression,
+            encryption_key_file: self.encryption_key_file.clone(),
+            segment_ms: self.segment_ms,
+            max_message_size: self.max_message_size,
+            partitions: self.partition_count(),
+        }
+    }
+
+    /// Returns how many partitions the topic has.
+    pub fn partition_count(&self) -> u32 {
+        self.partitions.len() as u32
+    }
+
+    /// Returns the commitlog of a partition of the topic.
+    pub fn partition(&self, partition: u32) -> Result<&Commitlog, TopicError> {
+        self.partitions.get(partition as usize).ok_or_else(|| {
+            error!("topic {} has no partition {}", self.name, partition);
+            TopicError::new("Partition does not exist")
+        })
+    }
+
+    /// Get the oldest and latest offset of every partition.
+    pub fn get_partitions(&self) -> Vec<SimplePartition> {
+        self.partitions
+            .iter()
+            .enumerate()
+            .map(|(partition, commitlog)| SimplePartition {
+                partition: partition as u32,
+                oldest_offset: commitlog.get_oldest_offset(),
+                latest_offset: commitlog.get_latest_offset(),
+            })
+            .collect()
+    }
+
+    /// Pick the partition a message is produced to. Messages with the same key always go to the same partition so
+    /// they stay in order, messages without a key are spread over the partitions in turn.
+    pub fn partition_for_key(&self, key: &[u8]) -> u32 {
+        if key.is_empty() {
+            return self.next_partition.fetch_add(1, Ordering::SeqCst) % self.partition_count();
+        }
+        fnv1a_hash(key) % self.partition_count()
+    }
+
     /// Given a consumer group name, return the matching consumer group from the consumer groups in the topic.
     /// If it is not found: create a new consumer group, add it to the topics consumer groups and return it
     pub fn load_consumer_group(&mut self, consumer_group_name: &str) -> Arc<ConsumerGroup> {
@@ -125,7 +547,7 @@ impl Topic {
                 return group.clone();
             }
         }
-        let new_consumer_group = Arc::new(ConsumerGroup::new(consumer_group_name));
+        let new_consumer_group = Arc::new(ConsumerGroup::new(consumer_group_name, self.partition_count()));
         self.consumer_groups.push(new_consumer_group.clone());
         new_consumer_group
     }
@@ -151,12 +573,70 @@ impl Topic {
             .iter()
             .map(|x| x.name.clone())
             .collect();
-        return cg_names;
+        cg_names
+    }
+
+    /// Get the position of every consumer group in every partition, or of a single consumer group when a name is
+    /// given. Lag counts the retained messages after the committed offset, messages the consumer group never committed
+    /// but that have been cleaned up since don't count.
+    pub fn describe_consumer_groups(&self, consumer_group_name: Option<&str>) -> Vec<SimpleConsumerGroup> {
+        self.consumer_groups
+            .iter()
+            .filter(|group| consumer_group_name.is_none_or(|name| group.name == name))
+            .map(|group| {
+                let partitions: Vec<SimpleGroupPartition> = self
+                    .partitions
+                    .iter()
+                    .enumerate()
+                    .map(|(partition, commitlog)| {
+                        let committed_offset = group.committed_offsets[partition].load(Ordering::SeqCst);
+                        let oldest_offset = commitlog.get_oldest_offset();
+                        let latest_offset = commitlog.get_latest_offset();
+                        SimpleGroupPartition {
+                            partition: partition as u32,
+                            committed_offset,
+                            oldest_offset,
+                            latest_offset,
+                            lag: latest_offset.saturating_sub(committed_offset.max(oldest_offset)),
+                        }
+                    })
+                    .collect();
+                SimpleConsumerGroup {
+                    name: group.name.clone(),
+                    lag: partitions.iter().map(|partition| partition.lag).sum(),
+                    partitions,
+                }
+            })
+            .collect()
     }
 
     pub fn get_max_segment_size(&self) -> u64 {
         self.max_segment_size
     }
+
+    /// Compact the commitlog of every partition, keeping only the latest message for every key. Does nothing unless
+    /// the topic uses the compact cleanup policy.
+    pub fn compact(&self) -> Result<(), TopicError> {
+        if self.cleanup_policy != CleanupPolicy::Compact {
+            return Ok(());
+        }
+        for (partition, commitlog) in self.partitions.iter().enumerate() {
+            let removed = commitlog
+                .compact(&MessageKeyExtractor, TOMBSTONE_RETENTION_MS)
+                .map_err(|err| {
+                    error!("{}", err);
+                    TopicError::new("Unable to compact topic")
+                })?;
+            info!("Compacted partition {} of topic {}, removed {} messages", partition, self.name, removed);
+        }
+        Ok(())
+    }
+}
+
+/// 32 bit FNV-1a hash of a message key. It's stable across restarts and versions, so a key keeps mapping to the same
+/// partition.
+fn fnv1a_hash(key: &[u8]) -> u32 {
+    key.iter().fold(0x811c9dc5, |hash, byte| (hash ^ u32::from(*byte)).wrapping_mul(0x01000193))
 }
 
 
@@ -164,4 +644,173 @@ impl Topic {
 pub struct SimpleTopic {
     pub topic_name: String,
     pub consumer_groups: Vec<String>
-}
\ No newline at end of file
+}
+
+/// Struct used for sending the offsets of a partition of a topic for messages
+pub struct SimplePartition {
+    pub partition: u32,
+    pub oldest_offset: u64,
+    pub latest_offset: u64,
+}
+
+/// Struct used for sending the position of a consumer group in every partition of a topic for messages
+pub struct SimpleConsumerGroup {
+    pub name: String,
+    pub lag: u64,
+    pub partitions: Vec<SimpleGroupPartition>,
+}
+
+/// Struct used for sending the position of a consumer group in a partition of a topic for messages
+pub struct SimpleGroupPartition {
+    pub partition: u32,
+    pub committed_offset: u64,
+    pub oldest_offset: u64,
+    pub latest_offset: u64,
+    pub lag: u64,
+}
+
+#[cfg(test)]
+mod topic_tests {
+    use crate::lucidmq_errors::TopicError;
+    use crate::topic::{CleanupPolicy, Topic, TopicConfig, MAX_PARTITIONS};
+    use nolan::Compression;
+    use std::collections::HashSet;
+    use std::sync::atomic::Ordering;
+    use tempdir::TempDir;
+
+    fn entries(settings: &[(&str, &str)]) -> Vec<(String, String)> {
+        settings
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_config_from_entries() {
+        let config = TopicConfig::from_entries(
+            &entries(&[
+                ("max_segment_bytes", "1000"),
+                ("max_retention_bytes", "10000"),
+                ("retention_ms", "60000"),
+                ("segment_ms", "1000"),
+                ("max_message_bytes", "100"),
+                ("cleanup_policy", "compact"),
+                ("compression", "lz4"),
+                ("partitions", "2"),
+            ]),
+            TopicConfig::default(),
+        )
+        .expect("Unable to parse topic config");
+        let wanted_config = TopicConfig {
+            max_segment_size: 1000,
+            max_topic_size: 10000,
+            retention_ms: Some(60000),
+            cleanup_policy: CleanupPolicy::Compact,
+            compression: Compression::Lz4,
+            encryption_key_file: None,
+            segment_ms: Some(1000),
+            max_message_size: Some(100),
+            partitions: 2,
+        };
+        assert_eq!(wanted_config, config);
+        // The entries of a config parse back to the same config
+        let parsed = TopicConfig::from_entries(&config.entries(), TopicConfig::default())
+            .expect("Unable to parse topic config");
+        assert_eq!(config, parsed);
+
+        let defaults = TopicConfig::from_entries(&[], TopicConfig::default()).expect("Unable to parse topic config");
+        assert_eq!(TopicConfig::default(), defaults);
+    }
+
+    #[test]
+    fn test_invalid_config_entries() {
+        let partitions_error = format!("Partitions has to be between 1 and {}", MAX_PARTITIONS);
+        let cases = [
+            (vec![("segment_size", "1000")], "Unknown topic setting"),
+            (vec![("max_segment_bytes", "big")], "Invalid value for topic setting"),
+            (vec![("compression", "gzip")], "Invalid value for topic setting"),
+            (
+                vec![("max_segment_bytes", "1000"), ("max_segment_bytes", "2000")],
+                "Topic setting is given more than once",
+            ),
+            (vec![("max_segment_bytes", "0")], "Max segment bytes has to be greater than 0"),
+            (
+                vec![("max_segment_bytes", "1000"), ("max_retention_bytes", "500")],
+                "Max retention bytes has to be at least the max segment bytes",
+            ),
+            (vec![("segment_ms", "0")], "Retention and segment time have to be greater than 0"),
+            (vec![("partitions", "0")], partitions_error.as_str()),
+            (
+                vec![("max_message_bytes", "200000")],
+                "Max message bytes has to be between 1 and the max segment bytes",
+            ),
+        ];
+        for (settings, wanted_error) in cases {
+            let err = TopicConfig::from_entries(&entries(&settings), TopicConfig::default()).unwrap_err();
+            assert_eq!(TopicError::new(wanted_error), err);
+        }
+    }
+
+    #[test]
+    fn test_partition_for_key() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_string = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let topic = Topic::new(
+            "partitioned_topic".to_string(),
+            String::from(tmp_dir_string),
+            TopicConfig {
+                partitions: 4,
+                ..TopicConfig::default()
+            },
+        ).unwrap();
+        // A key always maps to the same partition
+        let partition = topic.partition_for_key("key".as_bytes());
+        assert!(partition < 4);
+        assert_eq!(partition, topic.partition_for_key("key".as_bytes()));
+        let spread: HashSet<u32> = (0..100).map(|i| topic.partition_for_key(format!("key{}", i).as_bytes())).collect();
+        assert_eq!(4, spread.len());
+        // Messages without a key go round robin
+        let round_robin: Vec<u32> = (0..5).map(|_| topic.partition_for_key(&[])).collect();
+        assert_eq!(vec![0, 1, 2, 3, 0], round_robin);
+        assert_eq!(TopicError::new("Partition does not exist"), topic.partition(4).err().unwrap());
+    }
+
+    #[test]
+    fn test_describe_consumer_groups() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_string = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let mut topic = Topic::new(
+            "lagging_topic".to_string(),
+            String::from(tmp_dir_string),
+            TopicConfig {
+                partitions: 2,
+                ..TopicConfig::default()
+            },
+        ).unwrap();
+        for i in 0..5 {
+            topic.partitions[0].append(format!("hello{}", i).as_bytes()).expect("unable to append to commitlog");
+        }
+        topic.partitions[1].append("hello".as_bytes()).expect("unable to append to commitlog");
+        let group = topic.load_consumer_group("cg");
+        group.committed_offsets[0].store(3, Ordering::SeqCst);
+        topic.load_consumer_group("other");
+
+        let groups = topic.describe_consumer_groups(None);
+        assert_eq!(vec!["cg", "other"], groups.iter().map(|group| group.name.as_str()).collect::<Vec<&str>>());
+        assert_eq!(3, groups[0].lag);
+        let partition = &groups[0].partitions[0];
+        assert_eq!((3, 0, 5, 2), (partition.committed_offset, partition.oldest_offset, partition.latest_offset, partition.lag));
+        assert_eq!(1, groups[0].partitions[1].lag);
+        assert_eq!(6, groups[1].lag);
+
+        let groups = topic.describe_consumer_groups(Some("other"));
+        assert_eq!(1, groups.len());
+        assert!(topic.describe_consumer_groups(Some("missing")).is_empty());
+    }
+}
@@ -2,7 +2,8 @@ use std::fmt;
 
 use capnp::message::{TypedReader, Builder, HeapAllocator};
 use tokio::sync::mpsc::{Sender, Receiver};
-use crate::lucid_schema_capnp::{produce_request, topic_request, consume_request};
+use crate::lucid_schema_capnp::{produce_request, topic_request, consume_request, group_request, commit_offset_request, seek_request,
+    describe_group_request};
 
 pub enum Command{
     TopicRequest {
@@ -19,6 +20,26 @@ pub enum Command{
         conn_id: String,
         capmessage: TypedReader::<Builder<HeapAllocator>, consume_request::Owned>
 
+    },
+    GroupRequest {
+        conn_id: String,
+        capmessage: TypedReader::<Builder<HeapAllocator>, group_request::Owned>
+
+    },
+    CommitOffsetRequest {
+        conn_id: String,
+        capmessage: TypedReader::<Builder<HeapAllocator>, commit_offset_request::Owned>
+
+    },
+    SeekRequest {
+        conn_id: String,
+        capmessage: TypedReader::<Builder<HeapAllocator>, seek_request::Owned>
+
+    },
+    DescribeGroupRequest {
+        conn_id: String,
+        capmessage: TypedReader::<Builder<HeapAllocator>, describe_group_request::Owned>
+
     },
     Response {
         conn_id: String,
@@ -33,7 +54,7 @@ pub enum Command{
 
 impl fmt::Debug for Command {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match &*self {
+        match self {
             Command::TopicRequest { conn_id, capmessage: _ } => {
                 f.debug_struct("Command")
                 .field("Command Type", &"TopicRequest")
@@ -52,6 +73,30 @@ impl fmt::Debug for Command {
                 .field("Connection ID", &conn_id)
                 .finish()
             },
+            Command::GroupRequest { conn_id, capmessage: _ } => {
+                f.debug_struct("Command")
+                .field("Command Type", &"GroupRequest")
+                .field("Connection ID", &conn_id)
+                .finish()
+            },
+            Command::CommitOffsetRequest { conn_id, capmessage: _ } => {
+                f.debug_struct("Command")
+                .field("Command Type", &"CommitOffsetRequest")
+                .field("Connection ID", &conn_id)
+                .finish()
+            },
+            Command::SeekRequest { conn_id, capmessage: _ } => {
+                f.debug_struct("Command")
+                .field("Command Type", &"SeekRequest")
+                .field("Connection ID", &conn_id)
+                .finish()
+            },
+            Command::DescribeGroupRequest { conn_id, capmessage: _ } => {
+                f.debug_struct("Command")
+                .field("Command Type", &"DescribeGroupRequest")
+                .field("Connection ID", &conn_id)
+                .finish()
+            },
             Command::Response { conn_id, capmessagedata: _ } => {
                 f.debug_struct("Command")
                 .field("Command Type", &"Response")
@@ -0,0 +1,165 @@
+use std::io::Write;
+use std::path::Path;
+use std::process;
+
+use clap::{arg, value_parser, ArgAction, Command};
+use env_logger::Builder;
+use log::LevelFilter;
+use nolan::{Commitlog, CommitlogConfig, DumpedRecord, MANIFEST_FILE};
+
+const VERIFY: &str = "verify";
+const DUMP: &str = "dump";
+const REBUILD_INDEX: &str = "rebuild-index";
+const TRUNCATE: &str = "truncate";
+
+fn cli() -> Command<'static> {
+    Command::new("nolan-tool")
+        .about("Verify and repair nolan commitlog directories while nothing has them open")
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .subcommand(
+            Command::new(VERIFY)
+                .about("Check every segment for torn or corrupt records and indexes that don't match their log")
+                .arg(arg!(<DIRECTORY> "The commitlog directory to verify"))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new(DUMP)
+                .about("Print the offset, position, size and payload of every stored record")
+                .arg(arg!(<DIRECTORY> "The commitlog directory to dump"))
+                .arg(
+                    arg!(--segment <OFFSET> "Only dump the segment starting at the offset")
+                        .required(false)
+                        .value_parser(value_parser!(u64)),
+                )
+                .arg(arg!(--"key-file" <FILE> "File with the keys to decrypt encrypted records").required(false))
+                .arg(arg!(--hex "Print payloads as hex, even if they are valid utf8").action(ArgAction::SetTrue))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new(REBUILD_INDEX)
+                .about("Rebuild the index and time index of every segment from its log")
+                .arg(arg!(<DIRECTORY> "The commitlog directory to rebuild the indexes of"))
+                .arg(
+                    arg!(--"index-interval-bytes" <BYTES> "Build sparse indexes with an entry every interval bytes")
+                        .required(false)
+                        .value_parser(value_parser!(u32)),
+                )
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new(TRUNCATE)
+                .about("Drop every record at and after the offset")
+                .arg(arg!(<DIRECTORY> "The commitlog directory to truncate"))
+                .arg(arg!(<OFFSET> "The first offset to drop").value_parser(value_parser!(u64)))
+                .arg(arg!(--"key-file" <FILE> "File with the keys of the commitlog, if it's encrypted").required(false))
+                .arg(
+                    arg!(--"index-interval-bytes" <BYTES> "The index interval the commitlog is configured with")
+                        .required(false)
+                        .value_parser(value_parser!(u32)),
+                )
+                .arg_required_else_help(true),
+        )
+}
+
+/// Print the problems of every segment, returning false if any segment has a problem.
+fn verify(directory: &str) -> Result<bool, String> {
+    let reports = nolan::verify(directory).map_err(|e| e.to_string())?;
+    let mut healthy = true;
+    for report in reports {
+        println!(
+            "segment {}: {} offsets, {}/{} bytes valid",
+            report.starting_offset, report.records, report.valid_bytes, report.log_bytes
+        );
+        for problem in &report.problems {
+            println!("    {}", problem);
+        }
+        healthy &= report.problems.is_empty();
+    }
+    println!("{}", if healthy { "No problems found" } else { "Problems found" });
+    Ok(healthy)
+}
+
+fn format_payload(payload: &Option<Vec<u8>>, hex: bool) -> String {
+    match payload {
+        None => "<encrypted>".to_string(),
+        Some(payload) => match std::str::from_utf8(payload) {
+            Ok(text) if !hex => format!("{:?}", text),
+            _ => payload.iter().map(|byte| format!("{:02x}", byte)).collect(),
+        },
+    }
+}
+
+fn dump(directory: &str, segment: Option<u64>, key_file: Option<&str>, hex: bool) -> Result<(), String> {
+    let segments = match segment {
+        Some(segment) => vec![segment],
+        None => nolan::list_segments(directory).map_err(|e| e.to_string())?,
+    };
+    let mut stdout = std::io::stdout().lock();
+    for segment in segments {
+        writeln!(stdout, "segment {}", segment).map_err(|e| e.to_string())?;
+        for record in nolan::dump_segment(directory, segment, key_file).map_err(|e| e.to_string())? {
+            let DumpedRecord { offset, position, size, timestamp, compression, encrypted, payload } = record;
+            writeln!(
+                stdout,
+                "offset: {} position: {} size: {} timestamp: {} compression: {:?} encrypted: {} payload: {}",
+                offset,
+                position,
+                size,
+                timestamp,
+                compression,
+                encrypted,
+                format_payload(&payload, hex)
+            )
+            .map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), String> {
+    Builder::new().filter_level(LevelFilter::Info).init();
+    let matches = cli().get_matches();
+    match matches.subcommand() {
+        Some((VERIFY, sub_matches)) => {
+            let directory = sub_matches.get_one::<String>("DIRECTORY").expect("required");
+            if !verify(directory)? {
+                process::exit(1);
+            }
+            Ok(())
+        }
+        Some((DUMP, sub_matches)) => {
+            let directory = sub_matches.get_one::<String>("DIRECTORY").expect("required");
+            let segment = sub_matches.get_one::<u64>("segment").copied();
+            let key_file = sub_matches.get_one::<String>("key-file").map(|key_file| key_file.as_str());
+            dump(directory, segment, key_file, sub_matches.get_flag("hex"))
+        }
+        Some((REBUILD_INDEX, sub_matches)) => {
+            let directory = sub_matches.get_one::<String>("DIRECTORY").expect("required");
+            let index_interval_bytes = sub_matches.get_one::<u32>("index-interval-bytes").copied();
+            let rebuilt_segments = nolan::rebuild_indexes(directory, index_interval_bytes).map_err(|e| e.to_string())?;
+            println!("Rebuilt the indexes of {} segments", rebuilt_segments.len());
+            Ok(())
+        }
+        Some((TRUNCATE, sub_matches)) => {
+            let directory = sub_matches.get_one::<String>("DIRECTORY").expect("required");
+            let offset = *sub_matches.get_one::<u64>("OFFSET").expect("required");
+            // Opening a commitlog creates its directory, which would hide a mistyped path
+            if !Path::new(directory).is_dir() {
+                return Err("Commitlog directory does not exist".to_string());
+            }
+            let config = CommitlogConfig {
+                index_interval_bytes: sub_matches.get_one::<u32>("index-interval-bytes").copied(),
+                encryption_key_file: sub_matches.get_one::<String>("key-file").cloned(),
+                // A commitlog with a manifest has to keep it up to date, opening it without one removes it
+                segment_manifest: Path::new(directory).join(MANIFEST_FILE).exists(),
+                ..CommitlogConfig::default()
+            };
+            let commitlog = Commitlog::with_config(directory, config).map_err(|e| e.to_string())?;
+            let next_offset = commitlog.truncate_to(offset).map_err(|e| e.to_string())?;
+            println!("Truncated the commitlog, the next offset is {}", next_offset);
+            Ok(())
+        }
+        _ => unreachable!(), // If all subcommands are defined above, anything else is unreachabe!()
+    }
+}
@@ -0,0 +1,77 @@
+//! Compares reading from a commitlog with a dense index against one with sparse indexes. Along with the lookup
+//! latency, the size of the index files is printed since that is what the index keeps in memory.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use nolan::{Commitlog, CommitlogConfig, FsyncPolicy};
+use rand::Rng;
+use std::fs;
+use tempdir::TempDir;
+
+const TOTAL_RECORDS: u64 = 100_000;
+const PAYLOAD_SIZE: usize = 32;
+
+/// Fill a commitlog with small records, the kind of topic where a dense index rivals the size of the log.
+fn create_commitlog(directory: &str, index_interval_bytes: Option<u32>) -> Commitlog {
+    let config = CommitlogConfig {
+        max_segment_size: 1024 * 1024,
+        retention_bytes: u64::MAX,
+        fsync_policy: FsyncPolicy::Never,
+        index_interval_bytes,
+        ..CommitlogConfig::default()
+    };
+    let commitlog = Commitlog::with_config(directory, config.clone()).expect("Unable to create commitlog");
+    let payload = vec![b'm'; PAYLOAD_SIZE];
+    for _ in 0..TOTAL_RECORDS {
+        commitlog.append(&payload).expect("Unable to append to commitlog");
+    }
+    commitlog.sync().expect("Unable to sync commitlog");
+    // Reload so every segment but the current one is read through the closed segment path
+    Commitlog::with_config(directory, config).expect("Unable to reload commitlog")
+}
+
+/// Total size in bytes of the files with the extension in the directory.
+fn files_size(directory: &str, extension: &str) -> u64 {
+    fs::read_dir(directory)
+        .expect("Unable to read commitlog directory")
+        .flatten()
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == extension))
+        .map(|entry| entry.metadata().map(|metadata| metadata.len()).unwrap_or(0))
+        .sum()
+}
+
+fn index_lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("index_lookup");
+    for index_interval_bytes in [None, Some(1024), Some(4096), Some(16384)] {
+        let tmp_dir = TempDir::new("bench").expect("Unable to create temp directory");
+        let directory = tmp_dir.path().to_str().expect("Unable to convert path to string");
+        let commitlog = create_commitlog(directory, index_interval_bytes);
+        let name = match index_interval_bytes {
+            None => "dense".to_string(),
+            Some(interval) => format!("sparse_{}", interval),
+        };
+        println!(
+            "{}: index {} bytes, log {} bytes",
+            name,
+            files_size(directory, "index"),
+            files_size(directory, "log"),
+        );
+
+        let mut rng = rand::thread_rng();
+        group.bench_function(BenchmarkId::new("random_read", &name), |b| {
+            b.iter(|| {
+                let offset = rng.gen_range(0..TOTAL_RECORDS);
+                commitlog.read(offset).expect("Unable to read from commitlog")
+            })
+        });
+        let mut offset = 0;
+        group.bench_function(BenchmarkId::new("sequential_read", &name), |b| {
+            b.iter(|| {
+                offset = (offset + 1) % TOTAL_RECORDS;
+                commitlog.read(offset).expect("Unable to read from commitlog")
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, index_lookup);
+criterion_main!(benches);
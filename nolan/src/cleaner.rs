@@ -1,50 +1,70 @@
 use crate::nolan_errors::CleanerError;
 use crate::segment::Segment;
-use log::error;
+use crate::utils;
+use log::info;
+use std::sync::Arc;
 
 #[derive(Default)]
 pub struct Cleaner {
     retention_bytes: u64,
+    retention_ms: Option<u64>,
 }
 
 impl Cleaner {
-    /// Initialize a new cleaner, based on a retention size in bytes
-    pub fn new(bytes_to_retain: u64) -> Cleaner {
+    /// Initialize a new cleaner, based on a retention size in bytes and optionally how long records are retained
+    /// for in milliseconds.
+    pub fn new(bytes_to_retain: u64, retention_ms: Option<u64>) -> Cleaner {
         Cleaner {
             retention_bytes: bytes_to_retain,
+            retention_ms,
         }
     }
 
-    /// Cleans up the segments based on the cleaners retention bytes. 
-    /// If the total bytes stored on segments exceed the max bytes, segments will be removed.
-    pub fn clean(&self, segments: &mut Vec<Segment>) -> Result<bool, CleanerError> {
+    /// Cleans up the segments based on the cleaners retention policy.
+    /// If the total bytes stored on segments exceed the max bytes, segments will be removed. If a retention time is set,
+    /// segments where every record is older than the retention time will be removed as well. The removed segments are
+    /// returned instead of deleted, so they can be taken out of the manifest before their files are deleted.
+    pub fn clean(&self, segments: &mut Vec<Arc<Segment>>) -> Result<Vec<Arc<Segment>>, CleanerError> {
+        let segment_sizes: Vec<(u64, u64)> = segments
+            .iter()
+            .map(|segment| (segment.position as u64, segment.max_timestamp))
+            .collect();
+        let segment_postion = self.segments_to_remove(&segment_sizes);
+        let mut removed_segments = Vec::new();
+        for _j in 0..segment_postion {
+            if segments.is_empty() {
+                return Err(CleanerError::new("Unable to get segment for deletion"));
+            }
+            //Remove the first index of the segment
+            removed_segments.push(segments.remove(0));
+        }
+        Ok(removed_segments)
+    }
+
+    /// Given the size in bytes and the newest timestamp of every segment, ordered oldest first, return how many of the
+    /// oldest segments the retention policy removes.
+    pub fn segments_to_remove(&self, segments: &[(u64, u64)]) -> usize {
         let mut total_bytes = 0;
         let mut segment_postion = segments.len();
-        for i in (0..segment_postion).rev() {
+        for (segment_size, _) in segments.iter().rev() {
             if total_bytes > self.retention_bytes {
                 break;
             }
-            match segments.get(i) {
-                Some(segment) => {
-                    total_bytes += segment.position as u64;
-                    segment_postion -= 1;
-                },
-                None => return Err(CleanerError::new("Unable to get index of the segment"))
-            }
+            total_bytes += segment_size;
+            segment_postion -= 1;
         }
-        for _j in 0..segment_postion {
-            match segments.get_mut(0) {
-                Some(segment) => {
-                    segment.delete().map_err(|e| {
-                        error!("{}", e);
-                        CleanerError::new("unable to delete segment")
-                    })?;
-                    //Remove the first index of the segment
-                    segments.remove(0);
-                },
-                None => return Err(CleanerError::new("Unable to get segment for deletion"))
+        if let Some(retention_ms) = self.retention_ms {
+            let cutoff_timestamp = utils::current_time_millis().saturating_sub(retention_ms);
+            // Segments are ordered oldest first, so only the leading segments can be expired
+            let expired_segments = segments
+                .iter()
+                .take_while(|(_, max_timestamp)| *max_timestamp < cutoff_timestamp)
+                .count();
+            if expired_segments > segment_postion {
+                info!("Removing {} segments older than {}ms", expired_segments, retention_ms);
+                segment_postion = expired_segments;
             }
         }
-        Ok(true)
+        segment_postion
     }
 }
@@ -1,63 +1,348 @@
 //! Nolan is a crate that specifies a implemeation for a commitlog storage structure.
 use log::{error, info, warn};
-use std::fs;
 
 use crate::cleaner::Cleaner;
+use crate::compactor::{Compactor, KeyExtractor};
+use crate::compression::Compression;
+use crate::config::{CommitlogConfig, FsyncPolicy};
+use crate::encryption::Keyring;
+use crate::manifest::{ManifestEntry, SegmentManifest, SegmentState};
 use crate::nolan_errors::{CommitlogError, SegmentError};
+use crate::record;
+use crate::record_range::{RecordRange, SegmentLog};
 use crate::segment::Segment;
+use crate::storage::{FilesystemStorage, StorageBackend};
+use crate::tiered_storage::{RemoteStorage, TieredStorage};
 use crate::virtual_segment::VirtualSegment;
-use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use crate::utils;
 
 /// Commitlog is a struct that represents the logs stored on memory and on disc.
 /// At it's core, a commitlog is a directory that is made up of segments(log and index files)
-/// that are stored within that directory.
+/// that are stored within that directory. The directory is a storage backend, which by default is on the filesystem.
+///
+/// Every method takes `&self`, so a commitlog can be shared between threads. Appends and anything else that changes the
+/// segments take turns, while reads go on in parallel with them. Closed segments never change, they're only added,
+/// removed or replaced as a whole, so readers hold on to the segments they read instead of locking out appends.
 pub struct Commitlog {
-    directory: String,
-    segments: Vec<Segment>,
+    /// Storage backend the segment files live in
+    backend: Arc<dyn StorageBackend>,
+    /// Closed segments in offset order
+    segments: RwLock<Vec<Arc<Segment>>>,
     cleaner: Cleaner,
     max_segment_size: u64,
-    current_segment: VirtualSegment,
+    fsync_policy: FsyncPolicy,
+    index_interval_bytes: Option<u32>,
+    compression: Compression,
+    /// Keys records are encrypted with, shared by every segment
+    keyring: Option<Arc<Keyring>>,
+    /// Offloads closed segments to remote storage, if the commitlog was created with one
+    tiered_storage: Option<TieredStorage>,
+    /// The segment records are appended to, readers only lock it for as long as it takes to copy records out of it
+    current_segment: RwLock<VirtualSegment>,
+    /// The offset after the last record that was appended, so readers can check for new records without locking the
+    /// current segment
+    high_water_mark: AtomicU64,
+    /// Held while the segments are changed, so there's only ever one writer
+    writer: Mutex<()>,
+    /// Held while segments are uploaded to or fetched from remote storage, without holding up the writer
+    remote_transfers: Mutex<()>,
+    /// Keep a manifest of the segments in storage, written whenever segments are added or removed
+    segment_manifest: bool,
+    /// How long in milliseconds the current segment is appended to before it's rolled, even if it isn't full
+    segment_ms: Option<u64>,
 }
 
 impl Commitlog {
     /// new creates a new commitlog taking in a base directory(where the segments live),
     /// a max segment size in bytes and a the max bytes the that the cleaner will retain
-    /// for the entire commitlog.
+    /// for the entire commitlog. Everything else uses the default config.
     pub fn new(
         base_directory: &str,
         max_segment_size_bytes: u64,
         cleaner_bytes_to_retain: u64,
     ) -> Result<Commitlog, CommitlogError> {
-        let vec = Vec::new();
-        let new_cleaner = Cleaner::new(cleaner_bytes_to_retain);
-        let mut commitlog = Commitlog {
-            directory: base_directory.to_string(),
-            segments: vec,
-            cleaner: new_cleaner,
+        let config = CommitlogConfig {
             max_segment_size: max_segment_size_bytes,
-            current_segment: VirtualSegment::new(&base_directory, max_segment_size_bytes, 0), // This is just a placeholder
+            retention_bytes: cleaner_bytes_to_retain,
+            ..CommitlogConfig::default()
         };
-        fs::create_dir_all(base_directory).map_err(|e| {
+        Commitlog::with_config(base_directory, config)
+    }
+
+    /// Create a new commitlog in the base directory using the provided config. Existing segments in the
+    /// directory are loaded, with the latest one becoming the current segment.
+    pub fn with_config(base_directory: &str, config: CommitlogConfig) -> Result<Commitlog, CommitlogError> {
+        Commitlog::open(Commitlog::filesystem_storage(base_directory)?, config, None)
+    }
+
+    /// Create a new commitlog that keeps its segment files in the storage backend, using the provided config.
+    /// Existing segments in the storage are loaded, with the latest one becoming the current segment.
+    pub fn with_storage_backend(
+        backend: Arc<dyn StorageBackend>,
+        config: CommitlogConfig,
+    ) -> Result<Commitlog, CommitlogError> {
+        Commitlog::open(backend, config, None)
+    }
+
+    /// Create a new commitlog in the base directory that offloads closed segments to the remote storage. Closed
+    /// segments are uploaded by `clean`, and once they take up more than the local retention bytes of the
+    /// config they're removed from the base directory. Reading an offloaded segment fetches it back from remote storage.
+    /// The retention of the config applies to the remote storage, so it should be set to how much history to keep.
+    pub fn with_remote_storage(
+        base_directory: &str,
+        config: CommitlogConfig,
+        remote_storage: Arc<dyn RemoteStorage>,
+    ) -> Result<Commitlog, CommitlogError> {
+        Commitlog::open(Commitlog::filesystem_storage(base_directory)?, config, Some(remote_storage))
+    }
+
+    /// Create a storage backend for a commitlog in the base directory, creating the directory if it doesn't exist.
+    fn filesystem_storage(base_directory: &str) -> Result<Arc<dyn StorageBackend>, CommitlogError> {
+        let backend = FilesystemStorage::new(base_directory).map_err(|e| {
             error!("{}", e);
             CommitlogError::new("Unable to create commitlog directory")
         })?;
-        commitlog.load_segments()?;
+        Ok(Arc::new(backend))
+    }
+
+    /// Open the commitlog in the storage backend, loading the segments in remote storage if there is one.
+    fn open(
+        backend: Arc<dyn StorageBackend>,
+        config: CommitlogConfig,
+        remote_storage: Option<Arc<dyn RemoteStorage>>,
+    ) -> Result<Commitlog, CommitlogError> {
+        let tiered_storage = match remote_storage {
+            Some(remote_storage) => Some(
+                TieredStorage::load(remote_storage, config.local_retention_bytes).map_err(|e| {
+                    error!("{}", e);
+                    CommitlogError::new("Unable to load remote storage")
+                })?,
+            ),
+            None => None,
+        };
+        let keyring = match &config.encryption_key_file {
+            Some(key_file) => Some(Arc::new(Keyring::load(key_file).map_err(|e| {
+                error!("{}", e);
+                CommitlogError::new("Unable to load encryption keys")
+            })?)),
+            None => None,
+        };
+        Commitlog::migrate_segment_file_names(backend.as_ref())?;
+        let mut segments = Commitlog::load_segments(&backend, config.index_interval_bytes, keyring.clone())?;
+        let active_segment_offset = if config.segment_manifest {
+            Commitlog::validate_manifest(backend.as_ref(), &mut segments)?
+        } else {
+            // A manifest left from when it was turned on would be out of date if it's turned back on
+            SegmentManifest::remove(backend.as_ref()).map_err(|e| {
+                error!("{}", e);
+                CommitlogError::new("Unable to remove commitlog manifest")
+            })?;
+            None
+        };
+        // The latest segment in storage becomes the current segment, it's kept out of the closed segments
+        // so it gets reloaded once it is flushed on the next split. If the manifest has an active segment that's
+        // missing, it's created again.
+        let latest_segment = match segments.last() {
+            Some(segment) if segment.starting_offset >= active_segment_offset.unwrap_or(0) => segments.pop(),
+            _ => None,
+        };
+        let current_segment = match latest_segment {
+            Some(latest_segment) => VirtualSegment::load_segment(
+                backend.clone(),
+                latest_segment.starting_offset,
+                config.max_segment_size,
+                config.fsync_policy,
+                config.index_interval_bytes,
+                config.compression,
+                keyring.clone(),
+            )
+            .map_err(|e| {
+                error!("{}", e);
+                CommitlogError::new("unable to load virtual segment")
+            })?,
+            None => VirtualSegment::new(
+                backend.clone(),
+                config.max_segment_size,
+                active_segment_offset.unwrap_or(0),
+                config.fsync_policy,
+                config.index_interval_bytes,
+                config.compression,
+                keyring.clone(),
+            )
+            .map_err(|e| {
+                error!("{}", e);
+                CommitlogError::new("unable to create virtual segment")
+            })?,
+        };
+        let high_water_mark = current_segment.starting_offset + current_segment.next_offset;
+        let commitlog = Commitlog {
+            backend,
+            segments: RwLock::new(segments.into_iter().map(Arc::new).collect()),
+            cleaner: Cleaner::new(config.retention_bytes, config.retention_ms),
+            max_segment_size: config.max_segment_size,
+            fsync_policy: config.fsync_policy,
+            index_interval_bytes: config.index_interval_bytes,
+            compression: config.compression,
+            keyring,
+            tiered_storage,
+            current_segment: RwLock::new(current_segment),
+            high_water_mark: AtomicU64::new(high_water_mark),
+            writer: Mutex::new(()),
+            remote_transfers: Mutex::new(()),
+            segment_manifest: config.segment_manifest,
+            segment_ms: config.segment_ms,
+        };
+        commitlog.save_manifest()?;
         Ok(commitlog)
     }
 
+    /// Check the loaded segments against the manifest, deleting the segments that were left behind. Returns the
+    /// starting offset of the active segment in the manifest, if there is one.
+    fn validate_manifest(
+        backend: &dyn StorageBackend,
+        segments: &mut Vec<Segment>,
+    ) -> Result<Option<u64>, CommitlogError> {
+        let manifest = match SegmentManifest::load(backend).map_err(|e| {
+            error!("{}", e);
+            CommitlogError::new("Unable to load commitlog manifest")
+        })? {
+            Some(manifest) => manifest,
+            None => return Ok(None),
+        };
+        let found_segments: Vec<(u64, u64)> = segments
+            .iter()
+            .map(|segment| (segment.starting_offset, segment.next_offset))
+            .collect();
+        let left_behind_segments = manifest.validate(&found_segments).map_err(|e| {
+            error!("{}", e);
+            CommitlogError::new("Commitlog does not match its manifest")
+        })?;
+        for segment in segments.iter().filter(|segment| left_behind_segments.contains(&segment.starting_offset)) {
+            segment.delete().map_err(|e| {
+                error!("{}", e);
+                CommitlogError::new("Unable to delete segment left behind")
+            })?;
+        }
+        segments.retain(|segment| !left_behind_segments.contains(&segment.starting_offset));
+        Ok(manifest.active_offset())
+    }
+
+    /// Write the manifest listing the closed segments and the active segment, if the commitlog keeps one. The writer
+    /// lock has to be held, so the segments can't change while they're listed.
+    fn write_manifest(
+        &self,
+        segments: &[Arc<Segment>],
+        active_segment_offset: u64,
+    ) -> Result<(), CommitlogError> {
+        if !self.segment_manifest {
+            return Ok(());
+        }
+        let mut entries: Vec<ManifestEntry> = segments
+            .iter()
+            .map(|segment| {
+                let fetched = self.tiered_storage.as_ref().is_some_and(|tiered_storage| tiered_storage.is_fetched(segment.starting_offset));
+                ManifestEntry {
+                    starting_offset: segment.starting_offset,
+                    state: if fetched { SegmentState::Fetched } else { SegmentState::Closed },
+                }
+            })
+            .collect();
+        entries.push(ManifestEntry {
+            starting_offset: active_segment_offset,
+            state: SegmentState::Active,
+        });
+        SegmentManifest::new(entries).write(self.backend.as_ref()).map_err(|e| {
+            error!("{}", e);
+            CommitlogError::new("Unable to write commitlog manifest")
+        })
+    }
+
+    /// Write the manifest listing the segments as they are now. The writer lock has to be held and none of the other
+    /// locks can be.
+    fn save_manifest(&self) -> Result<(), CommitlogError> {
+        let segments = self.read_segments();
+        let current_segment = self.read_current_segment();
+        self.write_manifest(&segments, current_segment.starting_offset)
+    }
+
+    /// Delete the files of segments that were removed from the commitlog. Readers that still hold on to a removed
+    /// segment keep reading it from memory.
+    fn delete_segments(&self, removed_segments: Vec<Arc<Segment>>) -> Result<(), CommitlogError> {
+        for segment in removed_segments {
+            info!("Deleting segment {}", segment.starting_offset);
+            segment.delete().map_err(|e| {
+                error!("{}", e);
+                CommitlogError::new("Unable to delete segment")
+            })?;
+        }
+        Ok(())
+    }
+
+    // Locks are only held for short sections that don't panic, a poisoned lock means the commitlog is in an unknown state.
+    // When more than one lock is held they're taken in the order remote transfers, writer, segments, current segment.
+
+    fn lock_writer(&self) -> MutexGuard<'_, ()> {
+        self.writer.lock().expect("Unable to get lock on commitlog writer")
+    }
+
+    fn lock_remote_transfers(&self) -> MutexGuard<'_, ()> {
+        self.remote_transfers.lock().expect("Unable to get lock on commitlog remote transfers")
+    }
+
+    fn read_segments(&self) -> RwLockReadGuard<'_, Vec<Arc<Segment>>> {
+        self.segments.read().expect("Unable to get lock on commitlog segments")
+    }
+
+    fn write_segments(&self) -> RwLockWriteGuard<'_, Vec<Arc<Segment>>> {
+        self.segments.write().expect("Unable to get lock on commitlog segments")
+    }
+
+    fn read_current_segment(&self) -> RwLockReadGuard<'_, VirtualSegment> {
+        self.current_segment.read().expect("Unable to get lock on current segment")
+    }
+
+    fn write_current_segment(&self) -> RwLockWriteGuard<'_, VirtualSegment> {
+        self.current_segment.write().expect("Unable to get lock on current segment")
+    }
+
+    /// Move the high-water mark up to the end of the current segment, once an append is done.
+    fn update_high_water_mark(&self) {
+        let current_segment = self.read_current_segment();
+        self.high_water_mark
+            .store(current_segment.starting_offset + current_segment.next_offset, Ordering::SeqCst);
+    }
+
     /// Given bytes, append those bytes onto the current segment. If those bytes will not fit onto the segment,
     /// split the segment by creating a new one and append the bytes there.
-    pub fn append(&mut self, data: &[u8]) -> Result<u16, CommitlogError> {
-        match self.current_segment.write(data) {
-            Ok(segment_offset_written) => {
+    pub fn append(&self, data: &[u8]) -> Result<u64, CommitlogError> {
+        let _writer = self.lock_writer();
+        if self.segment_expired() {
+            self.split()?;
+        }
+        let commitlog_written_offset = self.write_record(data)?;
+        self.update_high_water_mark();
+        // The records are written by now, failing the append would only get them written again
+        if let Err(e) = self.clean_segments() {
+            error!("Unable to clean commitlog: {}", e);
+        }
+        Ok(commitlog_written_offset)
+    }
+
+    /// Write the bytes to the current segment, splitting it first if they don't fit. The writer lock has to be held.
+    fn write_record(&self, data: &[u8]) -> Result<u64, CommitlogError> {
+        let write_result = {
+            let mut current_segment = self.write_current_segment();
+            current_segment
+                .write(data)
+                .map(|segment_offset_written| current_segment.starting_offset + segment_offset_written)
+        };
+        match write_result {
+            Ok(commitlog_written_offset) => {
                 info!("Successfully wrote to segment");
-                let commitlog_written_offset =
-                    self.current_segment.starting_offset + segment_offset_written;
-                self.clean().map_err(|e| {
-                    error!("{}", e);
-                    CommitlogError::new("Unable to clean commitlog")
-                })?;
                 Ok(commitlog_written_offset)
             }
             Err(err) => {
@@ -65,8 +350,11 @@ impl Commitlog {
                     "Write not possible. Segment log would be greater than max bytes",
                 );
                 if err == split_err {
-                    self.split().expect("Unable to split commitlog");
-                    self.append(data)
+                    self.split().map_err(|e| {
+                        error!("{}", e);
+                        CommitlogError::new("Unable to split commitlog")
+                    })?;
+                    self.write_record(data)
                 } else {
                     Err(CommitlogError::new("Unknown error when writing occured"))
                 }
@@ -74,76 +362,169 @@ impl Commitlog {
         }
     }
 
-    /// Look through the directory of the commitlog and load the segments into memory.
+    /// Append a batch of records to the commitlog, returning the range of offsets the batch was written to. The
+    /// batch is checked before anything is written, if any record is too large for a segment none of the batch is
+    /// appended. A batch that fits in a segment is written to one segment as a whole, splitting first if the
+    /// current segment doesn't have room for it. Larger batches fill up the current segment and continue in new ones.
+    pub fn append_batch(&self, batch: &[&[u8]]) -> Result<Range<u64>, CommitlogError> {
+        let _writer = self.lock_writer();
+        let segment_capacity = self.max_segment_size.saturating_sub(record::LOG_HEADER_SIZE as u64);
+        let batch_size = self.check_batch(batch)?;
+
+        if self.segment_expired() && !batch.is_empty() {
+            self.split()?;
+        }
+        let first_offset = self.get_latest_offset();
+        let mut remaining = batch;
+        while !remaining.is_empty() {
+            let mut current_segment = self.write_current_segment();
+            let records_that_fit = current_segment.records_that_fit(remaining);
+            // Keep a batch that fits in a segment together, instead of spreading it over two segments
+            let split_required = records_that_fit == 0
+                || (records_that_fit < remaining.len() && batch_size <= segment_capacity);
+            if split_required && !current_segment.is_empty() {
+                drop(current_segment);
+                self.split()?;
+                continue;
+            }
+            let (segment_batch, rest) = remaining.split_at(records_that_fit);
+            current_segment.write_batch(segment_batch).map_err(|e| {
+                error!("{}", e);
+                CommitlogError::new("Unable to write batch to segment")
+            })?;
+            remaining = rest;
+        }
+        self.update_high_water_mark();
+        info!("Successfully wrote batch of {} records", batch.len());
+        // The records are written by now, failing the append would only get them written again
+        if let Err(e) = self.clean_segments() {
+            error!("Unable to clean commitlog: {}", e);
+        }
+        Ok(first_offset..self.get_latest_offset())
+    }
+
+    /// Check that every record of the batch fits in a segment, `append_batch` rejects a batch that doesn't before
+    /// anything is written. Returns the amount of bytes the batch takes in segments, before compression.
+    pub fn check_batch(&self, batch: &[&[u8]]) -> Result<u64, CommitlogError> {
+        let segment_capacity = self.max_segment_size.saturating_sub(record::LOG_HEADER_SIZE as u64);
+        let current_segment = self.read_current_segment();
+        let mut batch_size: u64 = 0;
+        for data in batch {
+            let record_size = current_segment.record_size(data.len()) as u64;
+            if record_size > segment_capacity {
+                return Err(CommitlogError::new("Data to write is greater than the allowed max segment size"));
+            }
+            batch_size += record_size;
+        }
+        Ok(batch_size)
+    }
+
+    /// Segment files used to be named with a 5 digit starting offset(`00000.log`), which capped a commitlog at
+    /// u16 offsets. Rename any segment files still using the old naming scheme to the current zero padded width,
+    /// so they can be loaded like any other segment.
+    fn migrate_segment_file_names(backend: &dyn StorageBackend) -> Result<(), CommitlogError> {
+        let file_names = backend.list().map_err(|e| {
+            error!("{}", e);
+            CommitlogError::new("Unable to read commitlog directory")
+        })?;
+        for file_name in file_names {
+            let path = Path::new(&file_name);
+            let (file_stem, extension) = match (path.file_stem(), path.extension()) {
+                (Some(stem), Some(ext)) => (stem.to_string_lossy(), ext.to_string_lossy()),
+                _ => continue,
+            };
+            if extension != utils::LOG_EXTENSION && extension != utils::INDEX_EXTENSION {
+                continue;
+            }
+            if file_stem.len() == utils::SEGMENT_NAME_WIDTH {
+                continue;
+            }
+            let starting_offset = match utils::parse_segment_file_stem(&file_stem) {
+                Ok(offset) => offset,
+                Err(_) => continue,
+            };
+            let suffix = format!(".{}", extension);
+            let new_file_name = utils::segment_file_name(starting_offset, &suffix);
+            if backend.exists(&new_file_name) {
+                warn!("Not migrating {}, {} already exists", file_name, new_file_name);
+                continue;
+            }
+            info!("Migrating segment file {} to {}", file_name, new_file_name);
+            backend.rename(&file_name, &new_file_name).map_err(|e| {
+                error!("{}", e);
+                CommitlogError::new("Unable to migrate segment file name")
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Look through the storage of the commitlog and load the segments into memory.
     /// Also performs some cleanup on non-matching logs and indexes(for example, if there is a log file with a non-matching
-    /// index or vice versa)
-    fn load_segments(&mut self) -> Result<(), CommitlogError> {
-        //let mut files_to_clean: HashMap<String, String> = HashMap::new();
-        //let paths = fs::read_dir(&self.directory).expect("Unable to read files in directory.");
+    /// index or vice versa). Every segment goes through recovery before it's loaded, so torn or corrupt records at the
+    /// end of a log are dropped. Recovery also rebuilds indexes that don't match the configured index interval.
+    fn load_segments(
+        backend: &Arc<dyn StorageBackend>,
+        index_interval_bytes: Option<u32>,
+        keyring: Option<Arc<Keyring>>,
+    ) -> Result<Vec<Segment>, CommitlogError> {
         let mut valid_segment_files: Vec<String> = Vec::new();
         let mut files_to_clean: Vec<String> = Vec::new();
-        if let Ok(entries) = fs::read_dir(&self.directory) {
-            for entry in entries.flatten() {
-                //if let Ok(entry) = entry {
-                let path = entry.path();
-                if let Some(extension) = path.extension() {
-                    if extension == utils::LOG_EXTENSION {
-                        let mut corresponding_index_path = entry.path();
-                        corresponding_index_path.set_extension(utils::INDEX_EXTENSION);
-                        if !corresponding_index_path.is_file() {
-                            files_to_clean.push(path.to_str().unwrap().into());
-                        }
-                    } else if extension == utils::INDEX_EXTENSION {
-                        let mut corresponding_log_path = entry.path();
-                        corresponding_log_path.set_extension(utils::LOG_EXTENSION);
-                        if corresponding_log_path.is_file() {
-                            if let Some(file_stem) = path.file_stem() {
-                                valid_segment_files.push(file_stem.to_str().unwrap().into());
-                            }
-                        } else {
-                            files_to_clean.push(path.to_str().unwrap().into());
-                        }
+        let mut segments: Vec<Segment> = Vec::new();
+        for file_name in backend.list().unwrap_or_default() {
+            let path = Path::new(&file_name);
+            let (file_stem, extension) = match (path.file_stem().and_then(|stem| stem.to_str()), path.extension()) {
+                (Some(stem), Some(ext)) => (stem, ext),
+                _ => continue,
+            };
+            if extension == utils::LOG_EXTENSION {
+                let corresponding_index_name = format!("{}{}", file_stem, utils::INDEX_SUFFIX);
+                if !backend.exists(&corresponding_index_name) {
+                    // A log in the record format is enough to rebuild its index from
+                    if record::file_has_log_header(backend.as_ref(), &file_name) {
+                        valid_segment_files.push(file_stem.into());
+                    } else {
+                        files_to_clean.push(file_name.clone());
                     }
                 }
-                //}
+            } else if extension == utils::INDEX_EXTENSION {
+                let corresponding_log_name = format!("{}{}", file_stem, utils::LOG_SUFFIX);
+                if backend.exists(&corresponding_log_name) {
+                    valid_segment_files.push(file_stem.into());
+                } else {
+                    files_to_clean.push(file_name.clone());
+                }
+            } else if extension == utils::TIME_INDEX_EXTENSION {
+                // Time indexes are rebuilt by recovery, so they only need cleaning up once their log is gone
+                let corresponding_log_name = format!("{}{}", file_stem, utils::LOG_SUFFIX);
+                if !backend.exists(&corresponding_log_name) {
+                    files_to_clean.push(file_name.clone());
+                }
             }
         }
         for segment_file in valid_segment_files {
-            let loaded_segment = Segment::load_segment(&self.directory, segment_file).map_err(|e| {
+            let segment_offset = utils::parse_segment_file_stem(&segment_file).map_err(|e| {
+                error!("{}", e);
+                CommitlogError::new("unable to parse segment file name")
+            })?;
+            Segment::recover(backend.as_ref(), segment_offset, index_interval_bytes).map_err(|e| {
+                error!("{}", e);
+                CommitlogError::new("unable to recover segment")
+            })?;
+            let loaded_segment = Segment::load_segment(backend.clone(), segment_file, keyring.clone()).map_err(|e| {
                 error!("{}", e);
                 CommitlogError::new("unable to load segment")
             })?;
-            self.segments.push(loaded_segment);
-        }
-
-        let mut latest_segment_index = self.segments.len();
-        if latest_segment_index == 0 {
-            return Ok(());
-        } else {
-            self.segments
-                .sort_by(|a, b| a.starting_offset.cmp(&b.starting_offset));
-            latest_segment_index -= 1;
-            if latest_segment_index > 0 {
-                let current_segment_from_loaded_segments = self
-                    .segments
-                    .get(latest_segment_index)
-                    .expect("Unable to set current segment from loaded segments");
-                self.current_segment = VirtualSegment::load_segment(
-                    &self.directory,
-                    current_segment_from_loaded_segments.starting_offset,
-                    self.max_segment_size,
-                )
-                .expect("unable to load virtual segment");
-            }
+            segments.push(loaded_segment);
         }
+        segments.sort_by_key(|segment| segment.starting_offset);
 
         for file_to_clean in files_to_clean {
-            fs::remove_file(file_to_clean).map_err(|e| {
+            backend.delete(&file_to_clean).map_err(|e| {
                 error!("{}", e);
                 CommitlogError::new("unable to remove files during cleanup")
             })?;
         }
-        Ok(())
+        Ok(segments)
     }
 
     // /**
@@ -168,190 +549,475 @@ impl Commitlog {
         self.max_segment_size
     }
 
-    /// Load segments in from the commitlog directory that have not been loaded into memory yet.
-    pub fn reload_segments(&mut self) {
-        //self.reload_current_segment();
-        let mut segment_map: HashMap<String, String> = HashMap::new();
-        let mut valid_segments_found: Vec<String> = Vec::new();
-        if let Ok(entries) = fs::read_dir(&self.directory) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if let Some(extension) = path.extension() {
-                    let file_stem = path
-                        .file_stem()
-                        .expect("Unable to get file stem")
-                        .to_str()
-                        .unwrap();
-                    //let base_file_name = path.to_str().unwrap();
-                    if extension == utils::LOG_EXTENSION {
-                        if segment_map.contains_key(file_stem) {
-                            valid_segments_found.push(file_stem.into())
-                        } else {
-                            segment_map.insert(file_stem.into(), utils::LOG_EXTENSION.to_string());
-                        }
-                    } else if extension == utils::INDEX_EXTENSION {
-                        if segment_map.contains_key(file_stem) {
-                            valid_segments_found.push(file_stem.into())
-                        } else {
-                            segment_map.insert(file_stem.into(), utils::INDEX_EXTENSION.to_string());
-                        }
-                    } else {
-                        warn!("extension not found {:?}", extension);
-                    }
-                }
-            }
+    /// Roll the current segment if its first record is older than the segment time of the config, so retention can
+    /// remove it once it's closed. Appends roll the segment as well, this only has to be called for a commitlog that
+    /// might not be appended to for a while. Returns if the segment was rolled.
+    pub fn roll(&self) -> Result<bool, CommitlogError> {
+        let _writer = self.lock_writer();
+        if !self.segment_expired() {
+            return Ok(false);
         }
+        info!("Rolling commitlog segment, it's older than {:?}ms", self.segment_ms);
+        self.split()?;
+        Ok(true)
+    }
 
-        let segments_to_add: Vec<String> = valid_segments_found
-            .into_iter()
-            .filter(|segment| {
-                let segment_offset = segment
-                    .clone()
-                    .parse::<u16>()
-                    .expect("Unable to parse segment base into int.");
-                let mut segment_exists = false;
-                for existing_segment in &self.segments {
-                    if segment_offset == existing_segment.starting_offset {
-                        segment_exists = true;
-                        break;
-                    }
-                }
-                !segment_exists
-            })
-            .collect();
+    /// Check if the first record of the current segment is older than the segment time. Empty segments never expire.
+    fn segment_expired(&self) -> bool {
+        let segment_ms = match self.segment_ms {
+            Some(segment_ms) => segment_ms,
+            None => return false,
+        };
+        self.read_current_segment()
+            .first_timestamp()
+            .is_some_and(|first_timestamp| utils::current_time_millis().saturating_sub(first_timestamp) >= segment_ms)
+    }
 
-        if segments_to_add.is_empty() {
-            return;
+    /// Create a new segment and set the latest segment value to that new segment. 
+    /// Also flushes the sement to the disk, before creating and updating the segment. The flushed segment is loaded as
+    /// a closed segment and swapped in along with the new segment, so readers never see one without the other. The
+    /// writer lock has to be held.
+    fn split(&self) -> Result<(), CommitlogError> {
+        info!("Spliting commitlog segment");
+        //Flush the current virtual segment to disk
+        let (closed_segment_offset, next_offset) = {
+            let mut current_segment = self.write_current_segment();
+            current_segment
+                .flush()
+                .map_err(|e| {
+                    error!("{}", e);
+                    CommitlogError::new("Unable to flush commitlog to disk")
+                })?;
+            (current_segment.starting_offset, current_segment.starting_offset + current_segment.next_offset)
+        };
+        let closed_segment = Segment::load_segment(
+            self.backend.clone(),
+            utils::segment_file_name(closed_segment_offset, ""),
+            self.keyring.clone(),
+        )
+        .map_err(|e| {
+            error!("{}", e);
+            CommitlogError::new("Unable to load closed segment")
+        })?;
+        // Get the next offset from current segment and create a new segment with it
+        let new_segment = VirtualSegment::new(
+            self.backend.clone(),
+            self.max_segment_size,
+            next_offset,
+            self.fsync_policy,
+            self.index_interval_bytes,
+            self.compression,
+            self.keyring.clone(),
+        )
+        .map_err(|e| {
+            error!("{}", e);
+            CommitlogError::new("Unable to create new segment")
+        })?;
+        {
+            let mut segments = self.write_segments();
+            let mut current_segment = self.write_current_segment();
+            segments.push(Arc::new(closed_segment));
+            *current_segment = new_segment;
         }
+        self.save_manifest()
+    }
 
-        for segment in segments_to_add {
-            info!("updating a new segment {}", segment);
-            let loaded_segment = Segment::load_segment(&self.directory, segment)
-                .expect("unable to laod segment");
-            self.segments.push(loaded_segment);
-        }
+    /// Sync any appended records that haven't been synced to disk yet. Useful when the fsync policy lets records
+    /// go unsynced and the caller wants to make sure they're durable, for example on a timer or before shutting down.
+    pub fn sync(&self) -> Result<(), CommitlogError> {
+        self.write_current_segment().flush().map_err(|e| {
+            error!("{}", e);
+            CommitlogError::new("Unable to sync commitlog to disk")
+        })
+    }
 
-        // let mut latest_segment_index = self.segments.len();
-        // if latest_segment_index != 0 {
-        //     self.segments
-        //         .sort_by(|a, b| a.starting_offset.cmp(&b.starting_offset));
-        //     latest_segment_index -= 1;
-        //     if latest_segment_index > 0 {
-        //         self.current_segment_index = AtomicUsize::new(latest_segment_index);
-        //     }
-        // }
+    /// Sync the records appended since the last sync if the fsync policy says they're due, so a policy that syncs every
+    /// so many milliseconds doesn't leave records unsynced when nothing else is appended. Appends sync as well, this
+    /// only has to be called periodically for a commitlog that might not be appended to for a while. Returns if
+    /// anything was synced.
+    pub fn sync_if_due(&self) -> Result<bool, CommitlogError> {
+        self.write_current_segment().sync_if_due().map_err(|e| {
+            error!("{}", e);
+            CommitlogError::new("Unable to sync commitlog to disk")
+        })
     }
 
-    /// Create a new segment and set the latest segment value to that new segment. 
-    /// Also flushes the sement to the disk, before creating and updating the segment
-    fn split(&mut self) -> Result<(), CommitlogError> {
-        info!("Spliting commitlog segment");
-        //Flush the current virtual segment to disk
-        self.current_segment
-            .flush()
+    /// Remove every record at and after the offset, so the commitlog can be rolled back to an earlier point. Used to
+    /// undo records that shouldn't have been appended, or to bring a log back in line with another copy of it. Segments
+    /// that only hold removed records are deleted and the segment the offset falls in is rewritten without them,
+    /// becoming the current segment. Offloaded segments are removed from remote storage as well. Offsets right before
+    /// the offset that were removed by compaction are given out again, so the offset the next append gets is returned.
+    pub fn truncate_to(&self, offset: u64) -> Result<u64, CommitlogError> {
+        let _remote_transfers = self.lock_remote_transfers();
+        // The segment the offset falls in has to be local to be rewritten
+        self.fetch_remote_segment(offset)?;
+        let _writer = self.lock_writer();
+        if offset >= self.get_latest_offset() {
+            return Ok(self.get_latest_offset());
+        }
+        info!("Truncating commitlog to offset {}", offset);
+        if let Some(tiered_storage) = &self.tiered_storage {
+            tiered_storage.truncate_to(offset).map_err(|e| {
+                error!("{}", e);
+                CommitlogError::new("Unable to truncate remote storage")
+            })?;
+        }
+        self.sync()?;
+        // Readers wait for the truncation, so they never see the commitlog part way through it
+        let mut segments = self.write_segments();
+        let mut current_segment = self.write_current_segment();
+        let current_segment_offset = current_segment.starting_offset;
+        let truncated_position = segments.partition_point(|segment| segment.starting_offset < offset);
+        let truncated_segments = segments.split_off(truncated_position);
+
+        let truncated_segment_offset = if current_segment_offset < offset {
+            Some(current_segment_offset)
+        } else {
+            match segments.last() {
+                Some(segment) if segment.next_offset > offset => segments.pop().map(|segment| segment.starting_offset),
+                _ => None,
+            }
+        };
+        // The truncated segments are taken out of the manifest before they're deleted
+        self.write_manifest(&segments, truncated_segment_offset.unwrap_or(offset))?;
+        if current_segment_offset >= offset {
+            self.delete_segment_files(current_segment_offset)?;
+        }
+        self.delete_segments(truncated_segments)?;
+        *current_segment = match truncated_segment_offset {
+            Some(segment_offset) => {
+                Segment::truncate(
+                    self.backend.as_ref(),
+                    segment_offset,
+                    offset,
+                    self.index_interval_bytes,
+                    self.keyring.as_deref(),
+                )
+                .map_err(|e| {
+                    error!("{}", e);
+                    CommitlogError::new("Unable to truncate segment")
+                })?;
+                VirtualSegment::load_segment(
+                    self.backend.clone(),
+                    segment_offset,
+                    self.max_segment_size,
+                    self.fsync_policy,
+                    self.index_interval_bytes,
+                    self.compression,
+                    self.keyring.clone(),
+                )
+                .map_err(|e| {
+                    error!("{}", e);
+                    CommitlogError::new("unable to load virtual segment")
+                })?
+            }
+            // Every segment left ends before the offset, so the next record starts a new segment
+            None => VirtualSegment::new(
+                self.backend.clone(),
+                self.max_segment_size,
+                offset,
+                self.fsync_policy,
+                self.index_interval_bytes,
+                self.compression,
+                self.keyring.clone(),
+            )
             .map_err(|e| {
                 error!("{}", e);
-                CommitlogError::new("Unable to flush commitlog to disk")
+                CommitlogError::new("Unable to create new segment")
+            })?,
+        };
+        let latest_offset = current_segment.starting_offset + current_segment.next_offset;
+        self.high_water_mark.store(latest_offset, Ordering::SeqCst);
+        Ok(latest_offset)
+    }
+
+    /// Delete the log, index and time index files of the segment with the starting offset from storage.
+    fn delete_segment_files(&self, segment_offset: u64) -> Result<(), CommitlogError> {
+        for suffix in [utils::LOG_SUFFIX, utils::INDEX_SUFFIX, utils::TIME_INDEX_SUFFIX] {
+            self.backend.delete(&utils::segment_file_name(segment_offset, suffix)).map_err(|e| {
+                error!("{}", e);
+                CommitlogError::new("Unable to delete truncated segment")
             })?;
-        // Get the next offset from current segment and create a new segment with it
-        let next_offset = self.current_segment.starting_offset + self.current_segment.next_offset;
-        self.current_segment =
-            VirtualSegment::new(&self.directory, self.max_segment_size, next_offset);
-        // Since our last current segment got flushed to disk, reload from disk to update the segments
-        self.reload_segments();
+        }
         Ok(())
     }
 
     /// Given an offset, find and read the value from the commitlog for the segment that it is located in.
-    pub fn read(&mut self, offset: usize) -> Result<Vec<u8>, CommitlogError> {
+    /// Offloaded segments are fetched back from remote storage.
+    pub fn read(&self, offset: u64) -> Result<Vec<u8>, CommitlogError> {
+        if offset >= self.get_latest_offset() {
+            error!("offset {} does not exist in the commtlog", offset);
+            return Err(CommitlogError::new("Offset does not exist in the commitlog"));
+        }
         //First check the current segment
-        if usize::from(self.current_segment.starting_offset) <= offset {
-            let search_offset = offset - usize::from(self.current_segment.starting_offset);
-            return match self.current_segment.read_at(search_offset) {
-                Ok(buffer) => Ok(buffer),
-                Err(err) => {
-                    let out_of_bounds = SegmentError::new("offset is out of bounds");
-                    if err == out_of_bounds {
-                        return Err(CommitlogError::new("Offset does not exist in the commitlog"));
-                    } else {
-                        return Err(CommitlogError::new("Unexpected error when reading commitlog"))
-                    }
-                }
-            };
+        {
+            let current_segment = self.read_current_segment();
+            if current_segment.starting_offset <= offset {
+                let search_offset = offset - current_segment.starting_offset;
+                return current_segment
+                    .read_at(search_offset as usize)
+                    .map_err(Commitlog::map_read_error);
+            }
         }
 
         // Check the segments on disk
-        let mut segment_index: Option<usize> = None;
-        for (i, segment) in self.segments.iter().enumerate() {
-            if usize::from(segment.starting_offset) <= offset {
-                segment_index = Some(i);
-            } else {
-                break;
+        let segment = match self.local_segment(offset) {
+            Some(segment) => Some(segment),
+            None => {
+                self.fetch_segment(offset)?;
+                self.local_segment(offset)
             }
+        };
+        if let Some(segment) = segment {
+            let search_offset = offset - segment.starting_offset;
+            segment
+                .read_at(search_offset as usize)
+                .map_err(Commitlog::map_read_error)
+        } else {
+            error!("offset {} does not exist in the commtlog", offset);
+            Err(CommitlogError::new("Offset does not exist in the commitlog"))
         }
+    }
 
-        if let Some(value) = segment_index {
-            let segment = self
-                .segments
-                .get_mut(value)
-                .expect("Unable to get current segment");
-            let search_offset = offset - usize::from(segment.starting_offset);
+    /// Return the closed segment on local disk holding the offset.
+    fn local_segment(&self, offset: u64) -> Option<Arc<Segment>> {
+        let segments = self.read_segments();
+        let position = segments.partition_point(|segment| segment.next_offset <= offset);
+        segments
+            .get(position)
+            .filter(|segment| segment.starting_offset <= offset)
+            .cloned()
+    }
 
-            match segment.read_at(search_offset) {
-                Ok(buffer) => Ok(buffer),
-                Err(err) => {
-                    let out_of_bounds = SegmentError::new("offset is out of bounds");
-                    if err == out_of_bounds {
-                        return Err(CommitlogError::new("Offset does not exist in the commitlog"));
-                    } else {
-                        return Err(CommitlogError::new("Unexpected error when reading commitlog"))
-                    }
-                }
+    /// Make sure the segment with the offset is on local disk, fetching it back from remote storage if it was
+    /// offloaded. `read` does this by itself, but `read_range` only reads the segments on local disk, so this
+    /// has to be called before reading a range that starts in an offloaded segment.
+    pub fn fetch_segment(&self, offset: u64) -> Result<(), CommitlogError> {
+        let _remote_transfers = self.lock_remote_transfers();
+        self.fetch_remote_segment(offset)
+    }
+
+    /// Fetch the segment with the offset back from remote storage. The remote transfers lock has to be held and the
+    /// writer lock can't be, appends carry on while the segment is downloaded and it's only swapped in under the writer.
+    fn fetch_remote_segment(&self, offset: u64) -> Result<(), CommitlogError> {
+        let tiered_storage = match &self.tiered_storage {
+            Some(tiered_storage) => tiered_storage,
+            None => return Ok(()),
+        };
+        let segments = self.read_segments().clone();
+        let fetched_segment = tiered_storage
+            .fetch(&self.backend, offset, self.keyring.clone(), &segments)
+            .map_err(|e| {
+                error!("{}", e);
+                CommitlogError::new("Unable to fetch segment from remote storage")
+            })?;
+        let fetched_segment = match fetched_segment {
+            Some(fetched_segment) => fetched_segment,
+            None => return Ok(()),
+        };
+        let _writer = self.lock_writer();
+        let evicted_segments = tiered_storage.add_fetched_segment(fetched_segment, &mut self.write_segments());
+        self.save_manifest()?;
+        self.delete_segments(evicted_segments)
+    }
+
+    /// Find the first offset in the commitlog with a record appended at or after the timestamp, so the commitlog can be
+    /// read from a point in time. Returns None if every record in the commitlog is older than the timestamp. When the
+    /// timestamp falls in an offloaded segment, the first offset of that segment is returned.
+    pub fn offset_for_timestamp(&self, timestamp: u64) -> Option<u64> {
+        let segments = self.read_segments();
+        let current_segment = self.read_current_segment();
+        let local_offset = segments
+            .iter()
+            .filter(|segment| segment.max_timestamp >= timestamp)
+            .find_map(|segment| segment.offset_for_timestamp(timestamp))
+            .or_else(|| {
+                current_segment
+                    .offset_for_timestamp(timestamp)
+                    .map(|relative_offset| current_segment.starting_offset + relative_offset)
+            });
+        let offloaded_offset = self
+            .tiered_storage
+            .as_ref()
+            .and_then(|tiered_storage| tiered_storage.offset_for_timestamp(timestamp, &segments));
+        offloaded_offset.into_iter().chain(local_offset).min()
+    }
+
+    /// Read a range of records starting at the offset, returning at most max records and stopping once the payloads
+    /// read would go past max bytes. Records in closed segments are borrowed from the segments instead of being copied,
+    /// and the range can span any amount of segments. The range holds on to the segments it reads, so it can be read
+    /// while records are appended. Offsets removed by compaction are skipped.
+    /// Only segments on local disk are read, the range ends before the first offloaded segment and reading a range that
+    /// starts in one returns an error until it's fetched with `fetch_segment`.
+    pub fn read_range(&self, start: u64, max_records: usize, max_bytes: usize) -> Result<RecordRange, CommitlogError> {
+        if start < self.get_oldest_offset() {
+            error!("offset {} does not exist in the commtlog", start);
+            return Err(CommitlogError::new("Offset does not exist in the commitlog"));
+        }
+        // Only records below the high-water mark are read, so a batch spread over segments is read as a whole
+        let end_offset = self.get_latest_offset();
+        // The current segment is locked along with the segments, so a split can't happen part way through
+        let segments = self.read_segments();
+        let current_segment = self.read_current_segment();
+        let mut segment_logs = Vec::new();
+        let mut next_offset = start;
+        for segment in segments.iter().filter(|segment| segment.next_offset > start) {
+            // Offloaded segments leave a gap between the segments on local disk
+            if segment.starting_offset > next_offset {
+                break;
             }
+            let position = segment
+                .log_position(start.saturating_sub(segment.starting_offset))
+                .map_err(Commitlog::map_read_error)?;
+            segment_logs.push(SegmentLog::Closed { segment: segment.clone(), position });
+            next_offset = segment.next_offset;
+        }
+        if current_segment.starting_offset <= next_offset {
+            let current_segment_log = current_segment
+                .copy_log_from(start.saturating_sub(current_segment.starting_offset), max_records, max_bytes)
+                .map_err(Commitlog::map_read_error)?;
+            segment_logs.push(SegmentLog::Copied(current_segment_log));
+        }
+        if segment_logs.is_empty() {
+            error!("offset {} is in a segment offloaded to remote storage", start);
+            return Err(CommitlogError::new("Offset was offloaded to remote storage"));
+        }
+        Ok(RecordRange::new(segment_logs, start, end_offset, max_records, max_bytes, self.keyring.clone()))
+    }
+
+    /// Convert an error from reading a segment into the error returned to readers of the commitlog.
+    fn map_read_error(err: SegmentError) -> CommitlogError {
+        if err == SegmentError::new("offset is out of bounds") {
+            CommitlogError::new("Offset does not exist in the commitlog")
+        } else if err == SegmentError::new("offset was removed by compaction") {
+            CommitlogError::new("Offset was removed by compaction")
+        } else if [
+            SegmentError::new("record is encrypted and no keys were loaded"),
+            SegmentError::new("record was encrypted with an unknown key"),
+            SegmentError::new("unable to decrypt record"),
+        ]
+        .contains(&err)
+        {
+            error!("{}", err);
+            CommitlogError::new("Unable to decrypt record")
         } else {
-            error!("offset {} does not exist in the commtlog", offset);
-            Err(CommitlogError::new("Offset does not exist in the commitlog"))
+            error!("{}", err);
+            CommitlogError::new("Unexpected error when reading commitlog")
         }
     }
 
+    /// Compact the closed segments of the commitlog, keeping only the latest record for each key found by the key
+    /// extractor. Offsets of the remaining records don't change, reading a removed offset returns an error so readers
+    /// can skip past it. Tombstones are removed once they're older than the tombstone retention. Returns the amount of
+    /// records removed.
+    pub fn compact(&self, key_extractor: &dyn KeyExtractor, tombstone_retention_ms: u64) -> Result<u64, CommitlogError> {
+        // Segment files aren't rewritten while they're being uploaded
+        let _remote_transfers = self.lock_remote_transfers();
+        let _writer = self.lock_writer();
+        info!("attempting to compact commitlog");
+        let compactor = Compactor::new(key_extractor, tombstone_retention_ms);
+        // Readers keep reading the segments as they were until the compacted segments are swapped in
+        let mut segments = self.read_segments().clone();
+        let removed_records = compactor
+            .compact(&mut segments, &self.read_current_segment())
+            .map_err(|e| {
+                error!("{}", e);
+                CommitlogError::new("Unable to compact the commitlog")
+            })?;
+        *self.write_segments() = segments;
+        Ok(removed_records)
+    }
+
     /// Clean calls the cleaner to clean up the commitlog directory, it then updates the latest segment pointer.
-    fn clean(&mut self) -> Result<(), CommitlogError>{
+    /// Cleaning happens on every append, when time based retention is used this should also be called periodically
+    /// so segments of a commitlog that isn't being written to still expire. With remote storage, only this uploads
+    /// closed segments and applies the retention policy to remote storage, so it has to be called periodically. Closed
+    /// segments past the local retention are offloaded once they're uploaded, even if other segments fail to upload.
+    pub fn clean(&self) -> Result<(), CommitlogError>{
+        let tier_result = self.tier_segments();
+        let _writer = self.lock_writer();
+        self.clean_segments()?;
+        tier_result
+    }
+
+    /// Upload the closed segments to remote storage and apply the retention policy to it. Appends and readers carry on
+    /// while segments are uploaded, local segments are only removed by the next call to `clean_segments`.
+    fn tier_segments(&self) -> Result<(), CommitlogError> {
+        let tiered_storage = match &self.tiered_storage {
+            Some(tiered_storage) => tiered_storage,
+            None => return Ok(()),
+        };
+        let _remote_transfers = self.lock_remote_transfers();
+        let segments = self.read_segments().clone();
+        tiered_storage
+            .upload_segments(self.backend.as_ref(), &segments)
+            .and_then(|_| tiered_storage.expire_segments(&self.cleaner))
+            .map_err(|e| {
+                error!("{}", e);
+                CommitlogError::new("Unable to tier the commitlog")
+            })
+    }
+
+    /// Apply the retention policy to the segments, the writer lock has to be held. With remote storage nothing is
+    /// transferred, only the segments that are already uploaded are removed from local disk.
+    fn clean_segments(&self) -> Result<(), CommitlogError> {
         info!("attempting to clean commitlog");
-        let cleaner_response = self.cleaner.clean(&mut self.segments);
-        let _cleaner_response = match cleaner_response {
-            Ok(_res) => info!("Cleaned commitlog successfully."),
-            Err(error) => { 
-                error!("{}", error);
-                return Err(CommitlogError::new("Unbale to clean the commitlog"));
-            },
+        let removed_segments = match &self.tiered_storage {
+            Some(tiered_storage) => tiered_storage.remove_local_segments(&mut self.write_segments()),
+            None => self.cleaner.clean(&mut self.write_segments()).map_err(|e| {
+                error!("{}", e);
+                CommitlogError::new("Unbale to clean the commitlog")
+            })?,
         };
-        Ok(())
-        // let latest_segment_index = self.segments.len() - 1;
-        // self.current_segment_index = AtomicUsize::new(latest_segment_index);
+        if removed_segments.is_empty() {
+            return Ok(());
+        }
+        info!("Cleaned commitlog successfully.");
+        // The removed segments are taken out of the manifest before they're deleted
+        self.save_manifest()?;
+        self.delete_segments(removed_segments)
     }
 
-    /// Returns the first offset of the oldest segment stored in the commitlog.
-    pub fn get_oldest_offset(&self) -> usize {
-        // If there is no segments intialized, just return 0
-        if self.segments.len() == 0 {
-            return 0;
+    /// Returns the first offset of the oldest segment stored in the commitlog, including segments in remote storage.
+    pub fn get_oldest_offset(&self) -> u64 {
+        let oldest_remote_offset = self
+            .tiered_storage
+            .as_ref()
+            .and_then(|tiered_storage| tiered_storage.oldest_offset());
+        // If there are no closed segments, the current segment is the oldest one
+        let oldest_local_offset = match self.read_segments().first() {
+            Some(oldest_segment) => oldest_segment.starting_offset,
+            None => self.read_current_segment().starting_offset,
+        };
+        match oldest_remote_offset {
+            Some(oldest_remote_offset) => oldest_remote_offset.min(oldest_local_offset),
+            None => oldest_local_offset,
         }
-        let oldest_segment = &self.segments[0];
-        let offset = oldest_segment.starting_offset;
-        usize::from(offset)
     }
 
-    /// Returns the first offset of the first segment.
-    pub fn get_latest_offset(&self) -> usize {
-        let offset: u16 = self.current_segment.next_offset;
-        usize::from(offset)
+    /// Returns the next offset that will be written to in the commitlog.
+    pub fn get_latest_offset(&self) -> u64 {
+        self.high_water_mark.load(Ordering::SeqCst)
     }
 }
 
 #[cfg(test)]
 mod commitlog_tests {
     use crate::{commitlog::Commitlog, CommitlogError};
+    use crate::compression::Compression;
+    use crate::config::{CommitlogConfig, FsyncPolicy};
+    use crate::index::Entry;
+    use crate::manifest;
+    use crate::record;
+    use crate::storage::{MemoryStorage, StorageBackend};
+    use crate::tiered_storage::FilesystemRemoteStorage;
+    use crate::utils;
+    use std::fs;
+    use std::io::Write;
     use std::path::Path;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
     use tempdir::TempDir;
 
     #[test]
@@ -361,8 +1027,9 @@ mod commitlog_tests {
             .path()
             .to_str()
             .expect("Unable to conver path to string");
-        let commitlog = Commitlog::new(tmp_dir_string, 100, 1000).expect("Unable to create commitlog");
-        assert!(Path::new(&commitlog.directory).is_dir());
+        let commitlog_dir = Path::new(tmp_dir_string).join("commitlog");
+        Commitlog::new(commitlog_dir.to_str().unwrap(), 100, 1000).expect("Unable to create commitlog");
+        assert!(commitlog_dir.is_dir());
     }
 
     #[test]
@@ -372,7 +1039,7 @@ mod commitlog_tests {
             .path()
             .to_str()
             .expect("Unable to conver path to string");
-        let mut cl =
+        let cl =
             Commitlog::new(tmp_dir_path, 100, 1000).expect("Unable to create commitlog");
         let test_data = "producer1".as_bytes();
         cl.append(test_data).expect("Unable to append message");
@@ -388,7 +1055,7 @@ mod commitlog_tests {
             .path()
             .to_str()
             .expect("Unable to conver path to string");
-        let mut cl = Commitlog::new(tmp_dir_path, 1000, 10000).expect("Unable to create commitlog");
+        let cl = Commitlog::new(tmp_dir_path, 1000, 10000).expect("Unable to create commitlog");
         let test_data = "message".as_bytes();
         for n in 0..10 {
             let offset = cl.append(test_data).expect("Unable to append message");
@@ -404,7 +1071,7 @@ mod commitlog_tests {
             .path()
             .to_str()
             .expect("Unable to conver path to string");
-        let mut cl = Commitlog::new(tmp_dir_path, 100, 1000).expect("Unable to create commitlog");
+        let cl = Commitlog::new(tmp_dir_path, 100, 1000).expect("Unable to create commitlog");
 
         for i in 0..number_of_iterations {
             let string_message = format!("myTestMessage{}", i);
@@ -417,7 +1084,7 @@ mod commitlog_tests {
         for i in 0..number_of_iterations {
             let string_message = format!("myTestMessage{}", i);
             let test_data = string_message.as_bytes();
-            let retrived_message = cl.read(i.into()).expect("Unable to retrieve message");
+            let retrived_message = cl.read(i).expect("Unable to retrieve message");
             assert_eq!(test_data, &*retrived_message);
         }
     }
@@ -430,7 +1097,7 @@ mod commitlog_tests {
             .path()
             .to_str()
             .expect("Unable to conver path to string");
-        let mut cl = Commitlog::new(tmp_dir_path, 1000, 10000).expect("Unable to create commitlog");
+        let cl = Commitlog::new(tmp_dir_path, 1000, 10000).expect("Unable to create commitlog");
 
         for i in 0..number_of_iterations {
             let string_message = format!("myTestMessage{}", i);
@@ -450,7 +1117,7 @@ mod commitlog_tests {
             .path()
             .to_str()
             .expect("Unable to conver path to string");
-        let mut cl = Commitlog::new(tmp_dir_path, 1000, 10000).expect("Unable to create commitlog");
+        let cl = Commitlog::new(tmp_dir_path, 1000, 10000).expect("Unable to create commitlog");
 
         for i in 0..number_of_iterations {
             let string_message = format!("myTestMessage{}", i);
@@ -463,18 +1130,975 @@ mod commitlog_tests {
     }
 
     #[test]
-    fn test_append_message_bigger_than_segment() {
+    fn test_append_batch() {
         let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
         let tmp_dir_path = tmp_dir
             .path()
             .to_str()
             .expect("Unable to conver path to string");
-        let mut cl =
-            Commitlog::new(tmp_dir_path, 10, 100).expect("Unable to create commitlog");
-        let bytes: [u8; 11] = [0; 11];
-        let commitlog_error = cl.append(&bytes).unwrap_err();
-        let wanted_error =
-            CommitlogError::new("Unknown error when writing occured");
+        // Every segment fits 2 of the test messages
+        let cl = Commitlog::new(tmp_dir_path, 100, 10000).expect("Unable to create commitlog");
+        let messages: Vec<String> = (0..8).map(|i| format!("batchMessage{}", i)).collect();
+        let batch: Vec<&[u8]> = messages.iter().map(|message| message.as_bytes()).collect();
+        cl.append(batch[0]).expect("Unable to append message");
+
+        // The batch doesn't fit in the rest of the current segment, so it's written to a new one as a whole
+        assert_eq!(1..3, cl.append_batch(&batch[1..3]).expect("Unable to append batch"));
+        assert_eq!(1, cl.read_segments().len());
+        assert_eq!(1, cl.read_current_segment().starting_offset);
+
+        // A batch larger than a segment is spread over as many segments as it needs
+        assert_eq!(3..8, cl.append_batch(&batch[3..]).expect("Unable to append batch"));
+        assert_eq!(4, cl.read_segments().len());
+        for (offset, message) in messages.iter().enumerate() {
+            assert_eq!(message.as_bytes(), &*cl.read(offset as u64).unwrap());
+        }
+        assert_eq!(8..8, cl.append_batch(&[]).expect("Unable to append empty batch"));
+    }
+
+    #[test]
+    fn test_append_batch_too_large() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let cl = Commitlog::new(tmp_dir_path, 100, 10000).expect("Unable to create commitlog");
+        let large_message = [0; 100];
+        let batch: Vec<&[u8]> = vec!["batchMessage0".as_bytes(), &large_message];
+        let commitlog_error = cl.append_batch(&batch).unwrap_err();
+        let wanted_error = CommitlogError::new("Data to write is greater than the allowed max segment size");
         assert_eq!(wanted_error, commitlog_error);
+        // None of the batch was appended
+        assert_eq!(0, cl.get_latest_offset());
+        assert!(cl.read(0).is_err());
+    }
+
+    #[test]
+    fn test_recover_partial_batch() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let cl = Commitlog::new(tmp_dir_path, 1000, 10000).expect("Unable to create commitlog");
+        cl.append("batchMessage0".as_bytes()).expect("Unable to append message");
+        let batch: Vec<&[u8]> = vec!["batchMessage1".as_bytes(), "batchMessage2".as_bytes(), "batchMessage3".as_bytes()];
+        assert_eq!(1..4, cl.append_batch(&batch).expect("Unable to append batch"));
+        drop(cl);
+
+        // Simulate a crash that cut off the last record of the batch
+        let log_file_name = Path::new(tmp_dir_path).join(utils::segment_file_name(0, utils::LOG_SUFFIX));
+        let log_file = fs::OpenOptions::new().write(true).open(&log_file_name).unwrap();
+        let log_len = log_file.metadata().unwrap().len();
+        log_file.set_len(log_len - record::encoded_len(13) as u64).unwrap();
+
+        // The rest of the batch is dropped along with it
+        let cl = Commitlog::new(tmp_dir_path, 1000, 10000).expect("Unable to reload commitlog");
+        assert_eq!(1, cl.get_latest_offset());
+        assert_eq!("batchMessage0".as_bytes(), &*cl.read(0).unwrap());
+        assert!(cl.read(1).is_err());
+    }
+
+    /// Read a range from the commitlog, returning the offset and payload of every record
+    fn read_test_range(cl: &Commitlog, start: u64, max_records: usize, max_bytes: usize) -> Vec<(u64, String)> {
+        cl.read_range(start, max_records, max_bytes)
+            .expect("Unable to read range")
+            .iter()
+            .map(|record| {
+                let record = record.expect("Unable to read record");
+                (record.offset, String::from_utf8(record.payload.to_vec()).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_read_range() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        // Every segment fits 2 of the test messages
+        let cl = Commitlog::new(tmp_dir_path, 100, 10000).expect("Unable to create commitlog");
+        let messages: Vec<(u64, String)> = (0..9).map(|i| (i, format!("rangeMessage{}", i))).collect();
+        for (_, message) in &messages {
+            cl.append(message.as_bytes()).expect("Unable to append message");
+        }
+        assert_eq!(4, cl.read_segments().len());
+
+        // Ranges span closed segments and the current segment
+        assert_eq!(messages, read_test_range(&cl, 0, 100, 1000));
+        assert_eq!(messages[3..].to_vec(), read_test_range(&cl, 3, 100, 1000));
+        assert_eq!(messages[3..6].to_vec(), read_test_range(&cl, 3, 3, 1000));
+        // Each payload is 13 bytes, a record that would go past the max bytes isn't returned
+        assert_eq!(messages[1..3].to_vec(), read_test_range(&cl, 1, 100, 30));
+        // The first record is always returned, even if it's larger than the max bytes
+        assert_eq!(messages[1..2].to_vec(), read_test_range(&cl, 1, 100, 1));
+        assert!(read_test_range(&cl, 9, 100, 1000).is_empty());
+        assert!(read_test_range(&cl, 100, 100, 1000).is_empty());
+    }
+
+    #[test]
+    fn test_read_while_appending() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let cl = Arc::new(Commitlog::new(tmp_dir_path, 100, 100000).expect("Unable to create commitlog"));
+        let messages: Vec<(u64, String)> = (0..200).map(|i| (i, format!("message{:03}", i))).collect();
+        let writer_cl = cl.clone();
+        let writer_messages = messages.clone();
+        let writer = thread::spawn(move || {
+            for (_, message) in &writer_messages {
+                writer_cl.append(message.as_bytes()).expect("Unable to append message");
+            }
+        });
+
+        // Every range read while segments are split has to be an unbroken run of the appended messages
+        let mut next_offset = 0;
+        while next_offset < messages.len() as u64 {
+            let range = read_test_range(&cl, next_offset, 7, 1000);
+            assert!(next_offset + range.len() as u64 <= cl.get_latest_offset());
+            for record in &range {
+                assert_eq!(messages[next_offset as usize], *record);
+                next_offset += 1;
+            }
+        }
+        writer.join().expect("Unable to join writer thread");
+        assert_eq!(messages, read_test_range(&cl, 0, 1000, 10000));
+    }
+
+    fn create_compressed_commitlog(tmp_dir_path: &str, compression: Compression) -> Commitlog {
+        let config = CommitlogConfig {
+            max_segment_size: 1000,
+            retention_bytes: 100000,
+            compression,
+            ..CommitlogConfig::default()
+        };
+        Commitlog::with_config(tmp_dir_path, config).expect("Unable to create commitlog")
+    }
+
+    #[test]
+    fn test_compressed_batches() {
+        for compression in [Compression::Zstd, Compression::Lz4, Compression::Snappy] {
+            let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+            let tmp_dir_path = tmp_dir
+                .path()
+                .to_str()
+                .expect("Unable to conver path to string");
+            let cl = create_compressed_commitlog(tmp_dir_path, compression);
+            let messages: Vec<(u64, String)> = (0..60).map(|i| (i, format!("compressedMessage{:0>2}", i))).collect();
+            for batch in messages.chunks(10) {
+                let batch: Vec<&[u8]> = batch.iter().map(|(_, message)| message.as_bytes()).collect();
+                cl.append_batch(&batch).expect("Unable to append batch");
+            }
+            assert!(!cl.read_segments().is_empty());
+
+            for cl in [cl, create_compressed_commitlog(tmp_dir_path, compression)].iter_mut() {
+                for (offset, message) in &messages {
+                    assert_eq!(message.as_bytes(), &*cl.read(*offset).expect("Unable to read message"));
+                }
+                // Ranges can start and stop in the middle of a compressed batch
+                assert_eq!(messages, read_test_range(cl, 0, 100, 10000));
+                assert_eq!(messages[15..22].to_vec(), read_test_range(cl, 15, 7, 10000));
+                assert_eq!(messages[55..].to_vec(), read_test_range(cl, 55, 100, 10000));
+                assert_eq!(60, cl.get_latest_offset());
+            }
+        }
+    }
+
+    #[test]
+    fn test_change_compression() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let messages: Vec<(u64, String)> = (0..40).map(|i| (i, format!("compressedMessage{:0>2}", i))).collect();
+        // Every codec writes part of the same segment
+        let codecs = [Compression::Zstd, Compression::None, Compression::Lz4, Compression::Snappy];
+        for (compression, batch) in codecs.iter().zip(messages.chunks(10)) {
+            let cl = create_compressed_commitlog(tmp_dir_path, *compression);
+            let batch: Vec<&[u8]> = batch.iter().map(|(_, message)| message.as_bytes()).collect();
+            cl.append_batch(&batch).expect("Unable to append batch");
+        }
+
+        let cl = create_compressed_commitlog(tmp_dir_path, Compression::None);
+        for (offset, message) in &messages {
+            assert_eq!(message.as_bytes(), &*cl.read(*offset).expect("Unable to read message"));
+        }
+        assert_eq!(messages[5..35].to_vec(), read_test_range(&cl, 5, 30, 10000));
+    }
+
+    /// Read every file of the commitlog into one buffer
+    fn commitlog_file_contents(tmp_dir_path: &str) -> Vec<u8> {
+        let mut contents = Vec::new();
+        for entry in fs::read_dir(tmp_dir_path).unwrap().flatten() {
+            if entry.path().is_file() {
+                contents.extend(fs::read(entry.path()).unwrap());
+            }
+        }
+        contents
+    }
+
+    #[test]
+    fn test_encrypted_commitlog() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let commitlog_dir = tmp_dir.path().join("commitlog");
+        let commitlog_dir = commitlog_dir.to_str().unwrap();
+        let key_file = tmp_dir.path().join("keys");
+        fs::write(&key_file, format!("1 {}\n", "01".repeat(32))).unwrap();
+        let config = CommitlogConfig {
+            max_segment_size: 1000,
+            retention_bytes: 100000,
+            encryption_key_file: Some(key_file.to_str().unwrap().to_string()),
+            ..CommitlogConfig::default()
+        };
+        let messages: Vec<(u64, String)> = (0..40).map(|i| (i, format!("secretMessage{:0>2}", i))).collect();
+        let cl = Commitlog::with_config(commitlog_dir, config.clone()).expect("Unable to create commitlog");
+        for (_, message) in &messages[..10] {
+            cl.append(message.as_bytes()).expect("Unable to append message");
+        }
+        let batch: Vec<&[u8]> = messages[10..20].iter().map(|(_, message)| message.as_bytes()).collect();
+        cl.append_batch(&batch).expect("Unable to append batch");
+        drop(cl);
+
+        // Rotate the key and compress, records written with the old key stay readable
+        fs::write(&key_file, format!("1 {}\n2 {}\n", "01".repeat(32), "02".repeat(32))).unwrap();
+        let rotated_config = CommitlogConfig {
+            compression: Compression::Zstd,
+            ..config.clone()
+        };
+        let cl = Commitlog::with_config(commitlog_dir, rotated_config).expect("Unable to load commitlog");
+        for batch in messages[20..].chunks(10) {
+            let batch: Vec<&[u8]> = batch.iter().map(|(_, message)| message.as_bytes()).collect();
+            cl.append_batch(&batch).expect("Unable to append batch");
+        }
+        assert!(!cl.read_segments().is_empty());
+        for (offset, message) in &messages {
+            assert_eq!(message.as_bytes(), &*cl.read(*offset).expect("Unable to read message"));
+        }
+        assert_eq!(messages[5..35].to_vec(), read_test_range(&cl, 5, 30, 10000));
+        let file_contents = commitlog_file_contents(commitlog_dir);
+        assert!(!file_contents.windows(6).any(|window| window == "secret".as_bytes()));
+
+        // Without the keys the records can't be read
+        let unkeyed_config = CommitlogConfig {
+            encryption_key_file: None,
+            ..config
+        };
+        let cl = Commitlog::with_config(commitlog_dir, unkeyed_config).expect("Unable to load commitlog");
+        assert_eq!(CommitlogError::new("Unable to decrypt record"), cl.read(0).unwrap_err());
+    }
+
+    #[test]
+    fn test_read_range_sparse_index() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let config = CommitlogConfig {
+            max_segment_size: 1000,
+            retention_bytes: 10000,
+            retention_ms: None,
+            fsync_policy: FsyncPolicy::Never,
+            index_interval_bytes: Some(100),
+            compression: Compression::None,
+            encryption_key_file: None,
+            local_retention_bytes: None,
+            segment_manifest: false,
+            segment_ms: None,
+        };
+        let cl = Commitlog::with_config(tmp_dir_path, config).expect("Unable to create commitlog");
+        let messages: Vec<(u64, String)> = (0..40).map(|i| (i, format!("rangeMessage{:0>2}", i))).collect();
+        for (_, message) in &messages {
+            cl.append(message.as_bytes()).expect("Unable to append message");
+        }
+        assert!(!cl.read_segments().is_empty());
+        for start in [0, 7, 25, 39] {
+            assert_eq!(messages[start..].to_vec(), read_test_range(&cl, start as u64, 100, 10000));
+        }
+    }
+
+    #[test]
+    fn test_read_range_cleaned_offset() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let cl = Commitlog::new(tmp_dir_path, 100, 200).expect("Unable to create commitlog");
+        for i in 0..20 {
+            let string_message = format!("myTestMessage{}", i);
+            cl.append(string_message.as_bytes()).expect("Unable to append message");
+        }
+        assert!(cl.get_oldest_offset() > 0);
+        let commitlog_error = cl.read_range(0, 100, 1000).err().expect("Expected an error");
+        assert_eq!(CommitlogError::new("Offset does not exist in the commitlog"), commitlog_error);
+        let oldest_offset = cl.get_oldest_offset();
+        let records = read_test_range(&cl, oldest_offset, 100, 1000);
+        assert_eq!(cl.get_latest_offset() - oldest_offset, records.len() as u64);
+    }
+
+    #[test]
+    fn test_append_message_bigger_than_segment() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let cl =
+            Commitlog::new(tmp_dir_path, 10, 100).expect("Unable to create commitlog");
+        let bytes: [u8; 11] = [0; 11];
+        let commitlog_error = cl.append(&bytes).unwrap_err();
+        let wanted_error =
+            CommitlogError::new("Unknown error when writing occured");
+        assert_eq!(wanted_error, commitlog_error);
+    }
+
+    #[test]
+    fn test_append_past_u16_offsets() {
+        let number_of_iterations: u64 = 70000;
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let config = CommitlogConfig {
+            max_segment_size: 10000,
+            retention_bytes: 1000000,
+            retention_ms: None,
+            fsync_policy: FsyncPolicy::Never,
+            index_interval_bytes: None,
+            compression: Compression::None,
+            encryption_key_file: None,
+            local_retention_bytes: None,
+            segment_manifest: false,
+            segment_ms: None,
+        };
+        let cl = Commitlog::with_config(tmp_dir_path, config).expect("Unable to create commitlog");
+
+        let test_data = "m".as_bytes();
+        for i in 0..number_of_iterations {
+            let offset = cl.append(test_data).expect("Unable to append message");
+            assert_eq!(i, offset);
+        }
+        let last_offset = number_of_iterations - 1;
+        let retrived_message = cl.read(last_offset).expect("Unable to retrieve message");
+        assert_eq!(test_data, &*retrived_message);
+        assert_eq!(number_of_iterations, cl.get_latest_offset());
+    }
+
+    #[test]
+    fn test_reload_commitlog() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        {
+            let cl = Commitlog::new(tmp_dir_path, 100, 1000).expect("Unable to create commitlog");
+            for i in 0..20 {
+                let string_message = format!("myTestMessage{}", i);
+                cl.append(string_message.as_bytes()).expect("Unable to append message");
+            }
+        }
+        let cl = Commitlog::new(tmp_dir_path, 100, 1000).expect("Unable to reload commitlog");
+        let latest_offset = cl.get_latest_offset();
+        assert_eq!(20, latest_offset);
+        let offset = cl.append("afterReload".as_bytes()).expect("Unable to append message");
+        assert_eq!(latest_offset, offset);
+        let retrived_message = cl.read(offset).expect("Unable to retrieve message");
+        assert_eq!("afterReload".as_bytes(), &*retrived_message);
+    }
+
+    fn create_manifest_commitlog(tmp_dir_path: &str) -> Result<Commitlog, CommitlogError> {
+        let config = CommitlogConfig {
+            max_segment_size: 100,
+            retention_bytes: 10000,
+            segment_manifest: true,
+            ..CommitlogConfig::default()
+        };
+        Commitlog::with_config(tmp_dir_path, config)
+    }
+
+    #[test]
+    fn test_segment_manifest() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let manifest_path = tmp_dir.path().join(manifest::MANIFEST_FILE);
+        // Every segment fits 2 of the test messages
+        let early_manifest = {
+            let cl = create_manifest_commitlog(tmp_dir_path).expect("Unable to create commitlog");
+            for i in 0..6 {
+                cl.append(format!("myTestMessage{}", i).as_bytes()).expect("Unable to append message");
+            }
+            let early_manifest = fs::read(&manifest_path).expect("Unable to read manifest");
+            for i in 6..8 {
+                cl.append(format!("myTestMessage{}", i).as_bytes()).expect("Unable to append message");
+            }
+            early_manifest
+        };
+
+        // A split that didn't make it into the manifest keeps its segment, segments left behind by a deletion are removed
+        fs::write(&manifest_path, early_manifest).expect("Unable to write manifest");
+        fs::copy(
+            tmp_dir.path().join(utils::segment_file_name(2, utils::LOG_SUFFIX)),
+            tmp_dir.path().join(utils::segment_file_name(20, utils::LOG_SUFFIX)),
+        )
+        .expect("Unable to copy segment");
+        {
+            let cl = create_manifest_commitlog(tmp_dir_path).expect("Unable to load commitlog");
+            assert_eq!(8, cl.get_latest_offset());
+            assert_eq!(4, log_files_count(tmp_dir.path()));
+            for i in 0..8 {
+                assert_eq!(format!("myTestMessage{}", i).as_bytes(), &*cl.read(i).unwrap());
+            }
+        }
+
+        // A closed segment in the manifest that's missing from storage is an error
+        fs::remove_file(tmp_dir.path().join(utils::segment_file_name(2, utils::LOG_SUFFIX)))
+            .expect("Unable to remove segment");
+        let open_error = create_manifest_commitlog(tmp_dir_path).err().expect("Expected an error");
+        assert_eq!(CommitlogError::new("Commitlog does not match its manifest"), open_error);
+
+        // Without the manifest the commitlog is loaded from whatever is in storage, and the stale manifest is removed
+        let cl = Commitlog::new(tmp_dir_path, 100, 10000).expect("Unable to load commitlog");
+        assert_eq!(8, cl.get_latest_offset());
+        assert!(!manifest_path.exists());
+    }
+
+    #[test]
+    fn test_truncate_to() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let cl = Commitlog::new(tmp_dir_path, 100, 10000).expect("Unable to create commitlog");
+        for i in 0..20 {
+            let string_message = format!("myTestMessage{}", i);
+            cl.append(string_message.as_bytes()).expect("Unable to append message");
+        }
+        // Truncating past the end leaves the commitlog as it is
+        assert_eq!(20, cl.truncate_to(25).unwrap());
+
+        // The offset falls in a closed segment, which becomes the current segment
+        assert_eq!(7, cl.truncate_to(7).expect("Unable to truncate commitlog"));
+        assert_eq!("myTestMessage6".as_bytes(), &*cl.read(6).unwrap());
+        assert!(cl.read(7).is_err());
+        assert_eq!(7, cl.append("afterTruncate".as_bytes()).unwrap());
+        for i in 8..20 {
+            cl.append(format!("myTestMessage{}", i).as_bytes()).unwrap();
+        }
+
+        let cl = Commitlog::new(tmp_dir_path, 100, 10000).expect("Unable to reload commitlog");
+        assert_eq!(20, cl.get_latest_offset());
+        assert_eq!("afterTruncate".as_bytes(), &*cl.read(7).unwrap());
+        assert_eq!("myTestMessage19".as_bytes(), &*cl.read(19).unwrap());
+        // Truncating to the oldest offset removes every record
+        assert_eq!(0, cl.truncate_to(0).unwrap());
+        assert_eq!(1, log_files_count(tmp_dir.path()));
+        assert_eq!(0, cl.append("first".as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn test_truncate_to_compressed_batch() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let cl = create_compressed_commitlog(tmp_dir_path, Compression::Lz4);
+        let batch: Vec<&[u8]> = vec!["hellohellohello".as_bytes(); 5];
+        cl.append_batch(&batch).expect("Unable to append batch");
+        // The offset falls in the middle of the compressed batch in the current segment
+        assert_eq!(2, cl.truncate_to(2).expect("Unable to truncate commitlog"));
+        assert!(cl.read(2).is_err());
+
+        let cl = create_compressed_commitlog(tmp_dir_path, Compression::Lz4);
+        assert_eq!(2, cl.get_latest_offset());
+        assert_eq!("hellohellohello".as_bytes(), &*cl.read(1).unwrap());
+        assert_eq!(2, cl.append("world".as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn test_memory_storage() {
+        let backend: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        let config = CommitlogConfig {
+            max_segment_size: 100,
+            retention_bytes: 300,
+            ..CommitlogConfig::default()
+        };
+        {
+            let cl = Commitlog::with_storage_backend(backend.clone(), config.clone())
+                .expect("Unable to create commitlog");
+            for i in 0..20 {
+                let string_message = format!("myTestMessage{}", i);
+                cl.append(string_message.as_bytes()).expect("Unable to append message");
+            }
+            // The cleaner removed the oldest segments from the storage
+            assert!(cl.get_oldest_offset() > 0);
+            assert!(!backend.exists(&utils::segment_file_name(0, utils::LOG_SUFFIX)));
+        }
+        // Reopening the commitlog on the same storage loads the segments back
+        let cl = Commitlog::with_storage_backend(backend, config).expect("Unable to reload commitlog");
+        assert_eq!(20, cl.get_latest_offset());
+        for i in cl.get_oldest_offset()..20 {
+            let string_message = format!("myTestMessage{}", i);
+            assert_eq!(string_message.as_bytes(), &*cl.read(i).unwrap());
+        }
+        let offset = cl.append("afterReload".as_bytes()).expect("Unable to append message");
+        assert_eq!(20, offset);
+    }
+
+    fn create_tiered_commitlog(tmp_dir_path: &Path, retention_bytes: u64) -> Commitlog {
+        let config = CommitlogConfig {
+            max_segment_size: 100,
+            retention_bytes,
+            local_retention_bytes: Some(200),
+            ..CommitlogConfig::default()
+        };
+        let remote_storage = FilesystemRemoteStorage::new(tmp_dir_path.join("remote").to_str().unwrap())
+            .expect("Unable to create remote storage");
+        Commitlog::with_remote_storage(tmp_dir_path.join("local").to_str().unwrap(), config, Arc::new(remote_storage))
+            .expect("Unable to create commitlog")
+    }
+
+    /// Amount of log files in the directory, objects in remote storage are named like segment files
+    fn log_files_count(directory: &Path) -> usize {
+        fs::read_dir(directory)
+            .unwrap()
+            .flatten()
+            .filter(|entry| entry.path().extension().is_some_and(|extension| extension == utils::LOG_EXTENSION))
+            .count()
+    }
+
+    #[test]
+    fn test_tiered_storage() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let messages: Vec<String> = (0..20).map(|i| format!("tieredMessage{:0>2}", i)).collect();
+        {
+            let cl = create_tiered_commitlog(tmp_dir.path(), u64::MAX);
+            for message in &messages {
+                cl.append(message.as_bytes()).expect("Unable to append message");
+            }
+            // Nothing is offloaded until the closed segments are uploaded by the cleaner
+            assert_eq!(0, log_files_count(&tmp_dir.path().join("remote")));
+            assert_eq!(10, log_files_count(&tmp_dir.path().join("local")));
+            cl.clean().expect("Unable to clean commitlog");
+            // Two records fit in a segment, every closed segment is uploaded and only two are kept locally
+            assert_eq!(9, log_files_count(&tmp_dir.path().join("remote")));
+            assert_eq!(3, log_files_count(&tmp_dir.path().join("local")));
+            assert_eq!(0, cl.get_oldest_offset());
+
+            // Ranges only read local segments, reading an offset fetches its segment back
+            assert_eq!(
+                CommitlogError::new("Offset was offloaded to remote storage"),
+                cl.read_range(0, 100, 10000).err().unwrap()
+            );
+            assert_eq!(messages[0].as_bytes(), &*cl.read(0).expect("Unable to read offloaded message"));
+            assert_eq!(4, log_files_count(&tmp_dir.path().join("local")));
+            let range: Vec<u64> = cl
+                .read_range(0, 100, 10000)
+                .unwrap()
+                .iter()
+                .map(|record| record.unwrap().offset)
+                .collect();
+            assert_eq!(vec![0, 1], range);
+
+            // Only the latest fetched segments are kept locally
+            cl.fetch_segment(2).expect("Unable to fetch segment");
+            cl.fetch_segment(5).expect("Unable to fetch segment");
+            assert_eq!(5, log_files_count(&tmp_dir.path().join("local")));
+            assert_eq!(4, cl.read_range(2, 100, 10000).unwrap().iter().count());
+        }
+
+        let cl = create_tiered_commitlog(tmp_dir.path(), u64::MAX);
+        assert_eq!(0, cl.get_oldest_offset());
+        assert_eq!(Some(0), cl.offset_for_timestamp(0));
+        for (offset, message) in messages.iter().enumerate() {
+            assert_eq!(message.as_bytes(), &*cl.read(offset as u64).expect("Unable to read message"));
+        }
+    }
+
+    #[test]
+    fn test_tiered_storage_retention() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let cl = create_tiered_commitlog(tmp_dir.path(), 300);
+        for i in 0..20 {
+            cl.append(format!("tieredMessage{:0>2}", i).as_bytes()).expect("Unable to append message");
+        }
+        cl.clean().expect("Unable to clean commitlog");
+        // Retention applies to every segment in remote storage, not just the ones on local disk
+        assert_eq!(4, log_files_count(&tmp_dir.path().join("remote")));
+        assert_eq!(10, cl.get_oldest_offset());
+        assert_eq!(CommitlogError::new("Offset does not exist in the commitlog"), cl.read(8).unwrap_err());
+        assert_eq!("tieredMessage10".as_bytes(), &*cl.read(10).expect("Unable to read offloaded message"));
+    }
+
+    #[test]
+    fn test_tiered_storage_upload_failure() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let cl = create_tiered_commitlog(tmp_dir.path(), u64::MAX);
+        for i in 0..6 {
+            cl.append(format!("tieredMessage{:0>2}", i).as_bytes()).expect("Unable to append message");
+        }
+        cl.clean().expect("Unable to clean commitlog");
+        assert_eq!(2, log_files_count(&tmp_dir.path().join("remote")));
+
+        // Segments uploaded before remote storage went away are still offloaded, the rest are kept on local disk
+        fs::remove_dir_all(tmp_dir.path().join("remote")).unwrap();
+        for i in 6..12 {
+            cl.append(format!("tieredMessage{:0>2}", i).as_bytes()).expect("Unable to append message");
+        }
+        assert_eq!(CommitlogError::new("Unable to tier the commitlog"), cl.clean().unwrap_err());
+        assert_eq!(4, log_files_count(&tmp_dir.path().join("local")));
+        assert_eq!("tieredMessage04".as_bytes(), &*cl.read(4).expect("Unable to read message"));
+    }
+
+    /// Total size in bytes of every index file in the directory
+    fn index_files_size(tmp_dir_path: &str) -> u64 {
+        fs::read_dir(tmp_dir_path)
+            .unwrap()
+            .flatten()
+            .filter(|entry| entry.path().extension().unwrap() == utils::INDEX_EXTENSION)
+            .map(|entry| entry.metadata().unwrap().len())
+            .sum()
+    }
+
+    #[test]
+    fn test_sparse_index() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let config = CommitlogConfig {
+            max_segment_size: 1000,
+            retention_bytes: 100000,
+            index_interval_bytes: Some(128),
+            ..CommitlogConfig::default()
+        };
+        let mut cl = Commitlog::with_config(tmp_dir_path, config.clone()).expect("Unable to create commitlog");
+        for i in 0..100 {
+            let string_message = format!("myTestMessage{}", i);
+            cl.append(string_message.as_bytes()).expect("Unable to append message");
+        }
+
+        let mut reloaded_cl = Commitlog::with_config(tmp_dir_path, config).expect("Unable to reload commitlog");
+        for cl in [&mut cl, &mut reloaded_cl] {
+            for i in 0..100 {
+                let retrived_message = cl.read(i).expect("Unable to retrieve message");
+                assert_eq!(format!("myTestMessage{}", i).as_bytes(), &*retrived_message);
+            }
+            assert_eq!(100, cl.get_latest_offset());
+            let read_error = cl.read(100).unwrap_err();
+            assert_eq!(CommitlogError::new("Offset does not exist in the commitlog"), read_error);
+        }
+    }
+
+    #[test]
+    fn test_switch_to_sparse_index() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        write_test_commitlog(tmp_dir_path, 50);
+        let dense_index_size = index_files_size(tmp_dir_path);
+
+        // Recovery rebuilds the existing dense indexes as sparse ones
+        let config = CommitlogConfig {
+            max_segment_size: 100,
+            retention_bytes: 10000,
+            index_interval_bytes: Some(64),
+            ..CommitlogConfig::default()
+        };
+        let cl = Commitlog::with_config(tmp_dir_path, config).expect("Unable to reload commitlog");
+        assert!(index_files_size(tmp_dir_path) < dense_index_size);
+        for i in 0..50 {
+            let retrived_message = cl.read(i).expect("Unable to retrieve message");
+            assert_eq!(format!("myTestMessage{}", i).as_bytes(), &*retrived_message);
+        }
+        let offset = cl.append("myTestMessage50".as_bytes()).expect("Unable to append message");
+        assert_eq!(50, offset);
+    }
+
+    #[test]
+    fn test_offset_for_timestamp() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let cl = Commitlog::new(tmp_dir_path, 100, 10000).expect("Unable to create commitlog");
+        for i in 0..10 {
+            let string_message = format!("myTestMessage{}", i);
+            cl.append(string_message.as_bytes()).expect("Unable to append message");
+        }
+        thread::sleep(Duration::from_millis(20));
+        let replay_timestamp = utils::current_time_millis();
+        for i in 10..20 {
+            let string_message = format!("myTestMessage{}", i);
+            cl.append(string_message.as_bytes()).expect("Unable to append message");
+        }
+        assert!(!cl.read_segments().is_empty());
+        assert_eq!(Some(0), cl.offset_for_timestamp(0));
+        assert_eq!(Some(10), cl.offset_for_timestamp(replay_timestamp));
+        assert_eq!(None, cl.offset_for_timestamp(u64::MAX));
+
+        // Missing time indexes are rebuilt from the logs
+        for entry in fs::read_dir(tmp_dir_path).unwrap().flatten() {
+            if entry.path().extension().unwrap() == utils::TIME_INDEX_EXTENSION {
+                fs::remove_file(entry.path()).unwrap();
+            }
+        }
+        let cl = Commitlog::new(tmp_dir_path, 100, 10000).expect("Unable to reload commitlog");
+        assert_eq!(Some(0), cl.offset_for_timestamp(0));
+        assert_eq!(Some(10), cl.offset_for_timestamp(replay_timestamp));
+        assert_eq!(None, cl.offset_for_timestamp(u64::MAX));
+    }
+
+    #[test]
+    fn test_time_retention() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let config = CommitlogConfig {
+            max_segment_size: 100,
+            retention_bytes: 10000,
+            retention_ms: Some(200),
+            fsync_policy: FsyncPolicy::Never,
+            index_interval_bytes: None,
+            compression: Compression::None,
+            encryption_key_file: None,
+            local_retention_bytes: None,
+            segment_manifest: false,
+            segment_ms: None,
+        };
+        let cl = Commitlog::with_config(tmp_dir_path, config).expect("Unable to create commitlog");
+        for i in 0..20 {
+            let string_message = format!("myTestMessage{}", i);
+            cl.append(string_message.as_bytes()).expect("Unable to append message");
+        }
+        assert_eq!(0, cl.get_oldest_offset());
+
+        thread::sleep(Duration::from_millis(300));
+        cl.clean().expect("Unable to clean commitlog");
+        // Only the current segment is left, it's never removed by the cleaner
+        assert!(cl.read_segments().is_empty());
+        assert_eq!(cl.read_current_segment().starting_offset, cl.get_oldest_offset());
+        assert!(cl.read(0).is_err());
+        let last_offset = cl.get_latest_offset() - 1;
+        assert_eq!("myTestMessage19".as_bytes(), &*cl.read(last_offset).unwrap());
+    }
+
+    #[test]
+    fn test_segment_ms() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let config = CommitlogConfig {
+            retention_bytes: 10000,
+            segment_ms: Some(100),
+            ..CommitlogConfig::default()
+        };
+        let cl = Commitlog::with_config(tmp_dir_path, config).expect("Unable to create commitlog");
+        // An empty segment is never rolled
+        thread::sleep(Duration::from_millis(150));
+        assert!(!cl.roll().expect("Unable to roll commitlog"));
+        cl.append(b"first").expect("Unable to append message");
+        cl.append(b"second").expect("Unable to append message");
+        assert!(!cl.roll().expect("Unable to roll commitlog"));
+        assert!(cl.read_segments().is_empty());
+
+        // Appending to an expired segment rolls it first
+        thread::sleep(Duration::from_millis(150));
+        assert_eq!(2, cl.append(b"third").expect("Unable to append message"));
+        assert_eq!(1, cl.read_segments().len());
+        assert_eq!(2, cl.read_current_segment().starting_offset);
+
+        // A segment that isn't appended to is rolled by roll
+        thread::sleep(Duration::from_millis(150));
+        assert!(cl.roll().expect("Unable to roll commitlog"));
+        assert_eq!(2, cl.read_segments().len());
+        assert_eq!(3, cl.read_current_segment().starting_offset);
+        assert_eq!(b"third", &*cl.read(2).unwrap());
+    }
+
+    #[test]
+    fn test_active_segment_durable_without_split() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        {
+            let cl = Commitlog::new(tmp_dir_path, 1000, 10000).expect("Unable to create commitlog");
+            for i in 0..3 {
+                let string_message = format!("myTestMessage{}", i);
+                cl.append(string_message.as_bytes()).expect("Unable to append message");
+            }
+        }
+        let cl = Commitlog::new(tmp_dir_path, 1000, 10000).expect("Unable to reload commitlog");
+        assert_eq!(3, cl.get_latest_offset());
+        for i in 0..3 {
+            let string_message = format!("myTestMessage{}", i);
+            let retrived_message = cl.read(i).expect("Unable to retrieve message");
+            assert_eq!(string_message.as_bytes(), &*retrived_message);
+        }
+    }
+
+    #[test]
+    fn test_migrate_legacy_segment_names() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let number_of_iterations = 20;
+        {
+            let cl = Commitlog::new(tmp_dir_path, 100, 10000).expect("Unable to create commitlog");
+            for i in 0..number_of_iterations {
+                let string_message = format!("myTestMessage{}", i);
+                cl.append(string_message.as_bytes()).expect("Unable to append message");
+            }
+        }
+        // Rename the segments to the old 5 digit naming scheme
+        for entry in fs::read_dir(tmp_dir_path).unwrap().flatten() {
+            let path = entry.path();
+            let file_stem = path.file_stem().unwrap().to_str().unwrap();
+            let extension = path.extension().unwrap().to_str().unwrap();
+            let starting_offset = utils::parse_segment_file_stem(file_stem).unwrap();
+            let legacy_name = format!("{:0>5}.{}", starting_offset, extension);
+            fs::rename(&path, Path::new(tmp_dir_path).join(legacy_name)).unwrap();
+        }
+        assert!(Path::new(tmp_dir_path).join("00000.log").exists());
+
+        let cl = Commitlog::new(tmp_dir_path, 100, 10000).expect("Unable to reload commitlog");
+        assert!(!Path::new(tmp_dir_path).join("00000.log").exists());
+        let migrated_name = Path::new(tmp_dir_path).join(utils::segment_file_name(0, utils::LOG_SUFFIX));
+        assert!(Path::new(&migrated_name).exists());
+        assert_eq!(number_of_iterations, cl.get_latest_offset());
+        for i in 0..number_of_iterations {
+            let string_message = format!("myTestMessage{}", i);
+            let retrived_message = cl.read(i).expect("Unable to retrieve message");
+            assert_eq!(string_message.as_bytes(), &*retrived_message);
+        }
+    }
+
+    fn write_test_commitlog(tmp_dir_path: &str, number_of_iterations: u64) {
+        let cl = Commitlog::new(tmp_dir_path, 100, 10000).expect("Unable to create commitlog");
+        for i in 0..number_of_iterations {
+            let string_message = format!("myTestMessage{}", i);
+            cl.append(string_message.as_bytes()).expect("Unable to append message");
+        }
+    }
+
+    #[test]
+    fn test_recover_torn_write() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        write_test_commitlog(tmp_dir_path, 20);
+        let latest_offset = Commitlog::new(tmp_dir_path, 100, 10000)
+            .expect("Unable to reload commitlog")
+            .get_latest_offset();
+
+        // Simulate a write that was cut off half way through the latest segment on disk
+        let mut latest_log = fs::read_dir(tmp_dir_path)
+            .unwrap()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().unwrap() == utils::LOG_EXTENSION)
+            .collect::<Vec<_>>();
+        latest_log.sort();
+        let latest_log = latest_log.pop().unwrap();
+        let mut log_file = fs::OpenOptions::new().append(true).open(&latest_log).unwrap();
+        log_file.write_all(&[40, 0, 0, 0, 1, 2, 3]).unwrap();
+        let torn_len = log_file.metadata().unwrap().len();
+
+        let cl = Commitlog::new(tmp_dir_path, 100, 10000).expect("Unable to reload commitlog");
+        assert!(fs::metadata(&latest_log).unwrap().len() < torn_len);
+        assert_eq!(latest_offset, cl.get_latest_offset());
+        let offset = cl.append("afterRecovery".as_bytes()).expect("Unable to append message");
+        assert_eq!(latest_offset, offset);
+        assert_eq!("afterRecovery".as_bytes(), &*cl.read(offset).unwrap());
+    }
+
+    #[test]
+    fn test_recover_corrupt_record() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        write_test_commitlog(tmp_dir_path, 20);
+
+        // Flip the last byte of the first segment, which belongs to its last record
+        let first_log = Path::new(tmp_dir_path).join(utils::segment_file_name(0, utils::LOG_SUFFIX));
+        let mut log_contents = fs::read(&first_log).unwrap();
+        let last = log_contents.len() - 1;
+        log_contents[last] ^= 0xff;
+        fs::write(&first_log, log_contents).unwrap();
+
+        let cl = Commitlog::new(tmp_dir_path, 100, 10000).expect("Unable to reload commitlog");
+        assert_eq!("myTestMessage0".as_bytes(), &*cl.read(0).unwrap());
+        let commitlog_error = cl.read(1).unwrap_err();
+        assert_eq!(CommitlogError::new("Offset does not exist in the commitlog"), commitlog_error);
+        assert_eq!("myTestMessage2".as_bytes(), &*cl.read(2).unwrap());
+    }
+
+    #[test]
+    fn test_recover_missing_index() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        write_test_commitlog(tmp_dir_path, 20);
+        let first_index = Path::new(tmp_dir_path).join(utils::segment_file_name(0, utils::INDEX_SUFFIX));
+        fs::remove_file(&first_index).unwrap();
+
+        let cl = Commitlog::new(tmp_dir_path, 100, 10000).expect("Unable to reload commitlog");
+        assert!(Path::new(&first_index).exists());
+        assert_eq!("myTestMessage1".as_bytes(), &*cl.read(1).unwrap());
+    }
+
+    #[test]
+    fn test_convert_legacy_log() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        // Logs used to be raw payloads with bincode entries in the index
+        let log_file_name = Path::new(tmp_dir_path).join(utils::segment_file_name(0, utils::LOG_SUFFIX));
+        let index_file_name = Path::new(tmp_dir_path).join(utils::segment_file_name(0, utils::INDEX_SUFFIX));
+        fs::write(&log_file_name, "helloworld").unwrap();
+        let mut index_contents = Vec::new();
+        for (start, total) in [(0, 5), (5, 5)] {
+            bincode::serialize_into(&mut index_contents, &Entry { start, total }).unwrap();
+        }
+        fs::write(&index_file_name, index_contents).unwrap();
+
+        let cl = Commitlog::new(tmp_dir_path, 100, 10000).expect("Unable to load legacy commitlog");
+        assert_eq!(2, cl.get_latest_offset());
+        assert_eq!("hello".as_bytes(), &*cl.read(0).unwrap());
+        assert_eq!("world".as_bytes(), &*cl.read(1).unwrap());
+        assert!(record::file_has_log_header(cl.backend.as_ref(), &utils::segment_file_name(0, utils::LOG_SUFFIX)));
     }
 }
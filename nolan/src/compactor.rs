@@ -0,0 +1,330 @@
+use crate::nolan_errors::CompactorError;
+use crate::record::Record;
+use crate::segment::Segment;
+use crate::utils;
+use crate::virtual_segment::VirtualSegment;
+use log::{error, info};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The key of a record, as far as compaction is concerned.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RecordKey {
+    /// Records with the same key replace each other, only the latest one is kept
+    pub key: Vec<u8>,
+    /// A tombstone marks a key as deleted, so every older record for the key is removed
+    pub tombstone: bool,
+}
+
+/// Nolan stores records as opaque bytes, a key extractor is what knows how to pull the key out of a record's payload.
+pub trait KeyExtractor {
+    /// Return the key of the record, records without a key are never removed by compaction.
+    fn extract_key(&self, payload: &[u8]) -> Option<RecordKey>;
+}
+
+/// The compactor rewrites closed segments, keeping only the latest record for each key. Records keep their offsets,
+/// the offsets of removed records are left as holes in the segment.
+pub struct Compactor<'a> {
+    key_extractor: &'a dyn KeyExtractor,
+    tombstone_retention_ms: u64,
+}
+
+impl<'a> Compactor<'a> {
+    /// Initialize a new compactor, tombstones are kept for the given milliseconds so consumers have a chance to see them
+    /// before they're removed.
+    pub fn new(key_extractor: &'a dyn KeyExtractor, tombstone_retention_ms: u64) -> Compactor<'a> {
+        Compactor {
+            key_extractor,
+            tombstone_retention_ms,
+        }
+    }
+
+    /// Compact the closed segments, records in the current segment are taken into account when finding the latest record
+    /// for a key but the current segment itself is never rewritten. The last record of every segment is always kept, so
+    /// segments keep covering the same offsets. Returns the amount of records removed.
+    ///
+    /// The records are streamed once to find the latest offset of every key, then the segments are rewritten one at a
+    /// time, so only the keys and the records of a single segment are held in memory.
+    pub fn compact(&self, segments: &mut [Arc<Segment>], current_segment: &VirtualSegment) -> Result<u64, CompactorError> {
+        let mut latest_offsets: HashMap<Vec<u8>, u64> = HashMap::new();
+        for segment in segments.iter() {
+            segment
+                .for_each_record(|record| self.add_latest_offset(&mut latest_offsets, record))
+                .map_err(|e| {
+                    error!("{}", e);
+                    CompactorError::new("unable to read records from segment")
+                })?;
+        }
+        current_segment
+            .for_each_record(|record| self.add_latest_offset(&mut latest_offsets, record))
+            .map_err(|e| {
+                error!("{}", e);
+                CompactorError::new("unable to read records from the current segment")
+            })?;
+
+        let tombstone_cutoff = utils::current_time_millis().saturating_sub(self.tombstone_retention_ms);
+        let mut total_removed = 0;
+        for segment in segments.iter_mut() {
+            let last_offset = segment.next_offset.saturating_sub(1);
+            let mut total_records = 0;
+            let mut kept_records: Vec<Record> = Vec::new();
+            segment
+                .for_each_record(|record| {
+                    total_records += 1;
+                    let keep = record.offset == last_offset
+                        || match self.key_extractor.extract_key(&record.payload) {
+                            Some(record_key) => {
+                                let is_latest = latest_offsets.get(&record_key.key) == Some(&record.offset);
+                                let expired_tombstone = record_key.tombstone && record.timestamp < tombstone_cutoff;
+                                is_latest && !expired_tombstone
+                            }
+                            None => true,
+                        };
+                    if keep {
+                        kept_records.push(record);
+                    }
+                })
+                .map_err(|e| {
+                    error!("{}", e);
+                    CompactorError::new("unable to read records from segment")
+                })?;
+            let removed = total_records - kept_records.len();
+            if removed == 0 {
+                continue;
+            }
+            info!("Compacting segment {}, removing {} records", segment.file_name, removed);
+            let rewritten_segment = segment.rewrite(&kept_records).map_err(|e| {
+                error!("{}", e);
+                CompactorError::new("unable to rewrite segment")
+            })?;
+            *segment = Arc::new(rewritten_segment);
+            total_removed += removed as u64;
+        }
+        Ok(total_removed)
+    }
+
+    /// Record the offset of a keyed record, records are read in offset order so later records replace earlier ones.
+    fn add_latest_offset(&self, latest_offsets: &mut HashMap<Vec<u8>, u64>, record: Record) {
+        if let Some(record_key) = self.key_extractor.extract_key(&record.payload) {
+            latest_offsets.insert(record_key.key, record.offset);
+        }
+    }
+}
+
+#[cfg(test)]
+mod compactor_tests {
+    use crate::commitlog::Commitlog;
+    use crate::compactor::{KeyExtractor, RecordKey};
+    use crate::compression::Compression;
+    use crate::{CommitlogConfig, CommitlogError};
+    use std::fs;
+    use std::thread;
+    use std::time::Duration;
+    use tempdir::TempDir;
+
+    /// Test payloads look like `key:value`, an empty value is a tombstone
+    struct TestKeyExtractor;
+
+    impl KeyExtractor for TestKeyExtractor {
+        fn extract_key(&self, payload: &[u8]) -> Option<RecordKey> {
+            let payload = std::str::from_utf8(payload).ok()?;
+            let (key, value) = payload.split_once(':')?;
+            Some(RecordKey {
+                key: key.as_bytes().to_vec(),
+                tombstone: value.is_empty(),
+            })
+        }
+    }
+
+    /// Every segment holds 3 of the small test messages
+    fn create_test_commitlog(tmp_dir_path: &str, messages: &[&str]) -> Commitlog {
+        let cl = Commitlog::new(tmp_dir_path, 95, 10000).expect("Unable to create commitlog");
+        for message in messages {
+            cl.append(message.as_bytes()).expect("Unable to append message");
+        }
+        cl
+    }
+
+    #[test]
+    fn test_compact_keeps_latest_record() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let messages = ["a:1", "b:1", "a:2", "b:2", "c:1", "a:3", "z:1"];
+        let cl = create_test_commitlog(tmp_dir_path, &messages);
+
+        let removed = cl.compact(&TestKeyExtractor, 60000).expect("Unable to compact commitlog");
+        // a:2 is superseded too, but it's kept as the last record of its segment
+        assert_eq!(2, removed);
+
+        let removed_error = CommitlogError::new("Offset was removed by compaction");
+        for cl in [cl, Commitlog::new(tmp_dir_path, 95, 10000).expect("Unable to reload commitlog")].iter_mut() {
+            assert_eq!(removed_error, cl.read(0).unwrap_err());
+            assert_eq!(removed_error, cl.read(1).unwrap_err());
+            for (offset, message) in messages.iter().enumerate().skip(2) {
+                let retrived_message = cl.read(offset as u64).expect("Unable to retrieve message");
+                assert_eq!(message.as_bytes(), &*retrived_message);
+            }
+            assert_eq!(messages.len() as u64, cl.get_latest_offset());
+        }
+    }
+
+    #[test]
+    fn test_compact_read_range() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let messages = ["a:1", "b:1", "a:2", "b:2", "c:1", "a:3", "z:1"];
+        let cl = create_test_commitlog(tmp_dir_path, &messages);
+        cl.compact(&TestKeyExtractor, 60000).expect("Unable to compact commitlog");
+
+        // Ranges starting at or running over removed offsets skip them
+        let records: Vec<(u64, Vec<u8>)> = cl
+            .read_range(0, 100, 1000)
+            .expect("Unable to read range")
+            .iter()
+            .map(|record| record.map(|record| (record.offset, record.payload.to_vec())))
+            .collect::<Result<_, _>>()
+            .expect("Unable to read record");
+        let wanted_records: Vec<(u64, Vec<u8>)> = messages
+            .iter()
+            .enumerate()
+            .skip(2)
+            .map(|(offset, message)| (offset as u64, message.as_bytes().to_vec()))
+            .collect();
+        assert_eq!(wanted_records, records);
+    }
+
+    #[test]
+    fn test_compact_compressed_batches() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let config = CommitlogConfig {
+            max_segment_size: 130,
+            retention_bytes: 10000,
+            compression: Compression::Zstd,
+            ..CommitlogConfig::default()
+        };
+        let cl = Commitlog::with_config(tmp_dir_path, config).expect("Unable to create commitlog");
+        let messages = ["a:1", "b:1", "a:2", "b:2", "c:1", "a:3", "z:1"];
+        let batch: Vec<&[u8]> = messages.iter().map(|message| message.as_bytes()).collect();
+        // Each batch fills a segment
+        cl.append_batch(&batch[..4]).expect("Unable to append batch");
+        cl.append_batch(&batch[4..]).expect("Unable to append batch");
+
+        // The first batch is rewritten uncompressed without the records that were superseded
+        let removed = cl.compact(&TestKeyExtractor, 60000).expect("Unable to compact commitlog");
+        assert_eq!(3, removed);
+        let removed_error = CommitlogError::new("Offset was removed by compaction");
+        for offset in 0..3 {
+            assert_eq!(removed_error, cl.read(offset).unwrap_err());
+        }
+        for (offset, message) in messages.iter().enumerate().skip(3) {
+            let retrived_message = cl.read(offset as u64).expect("Unable to retrieve message");
+            assert_eq!(message.as_bytes(), &*retrived_message);
+        }
+    }
+
+    #[test]
+    fn test_compact_encrypted_records() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let commitlog_dir = tmp_dir.path().join("commitlog");
+        let commitlog_dir = commitlog_dir.to_str().unwrap();
+        let key_file = tmp_dir.path().join("keys");
+        fs::write(&key_file, format!("7 {}\n", "0f".repeat(32))).unwrap();
+        let config = CommitlogConfig {
+            max_segment_size: 230,
+            retention_bytes: 10000,
+            encryption_key_file: Some(key_file.to_str().unwrap().to_string()),
+            ..CommitlogConfig::default()
+        };
+        let cl = Commitlog::with_config(commitlog_dir, config).expect("Unable to create commitlog");
+        let messages = ["a:secret1", "b:secret1", "a:secret2", "b:secret2", "c:secret1", "a:secret3", "z:secret1"];
+        for message in messages {
+            cl.append(message.as_bytes()).expect("Unable to append message");
+        }
+
+        // Rewritten segments are encrypted like the rest of the commitlog
+        let removed = cl.compact(&TestKeyExtractor, 60000).expect("Unable to compact commitlog");
+        assert_eq!(2, removed);
+        for entry in fs::read_dir(commitlog_dir).unwrap().flatten() {
+            let file_contents = fs::read(entry.path()).unwrap();
+            assert!(!file_contents.windows(6).any(|window| window == "secret".as_bytes()));
+        }
+        for (offset, message) in messages.iter().enumerate().skip(2) {
+            let retrived_message = cl.read(offset as u64).expect("Unable to retrieve message");
+            assert_eq!(message.as_bytes(), &*retrived_message);
+        }
+    }
+
+    #[test]
+    fn test_compact_without_keys() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let messages = ["abc", "abc", "abc", "abc", "abc", "abc", "abc"];
+        let cl = create_test_commitlog(tmp_dir_path, &messages);
+
+        let removed = cl.compact(&TestKeyExtractor, 60000).expect("Unable to compact commitlog");
+        assert_eq!(0, removed);
+        assert_eq!("abc".as_bytes(), &*cl.read(0).unwrap());
+    }
+
+    #[test]
+    fn test_compact_tombstones() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let messages = ["a:1", "a:", "b:1", "c:1", "d:1", "e:1", "z:1"];
+        let cl = create_test_commitlog(tmp_dir_path, &messages);
+
+        // The tombstone removes the value before it, but is kept until it's older than the tombstone retention
+        assert_eq!(1, cl.compact(&TestKeyExtractor, 60000).expect("Unable to compact commitlog"));
+        assert!(cl.read(0).is_err());
+        assert_eq!("a:".as_bytes(), &*cl.read(1).unwrap());
+
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(1, cl.compact(&TestKeyExtractor, 0).expect("Unable to compact commitlog"));
+        assert!(cl.read(1).is_err());
+        assert_eq!("b:1".as_bytes(), &*cl.read(2).unwrap());
+    }
+
+    #[test]
+    fn test_compact_sparse_index() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let tmp_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to conver path to string");
+        let config = CommitlogConfig {
+            max_segment_size: 95,
+            retention_bytes: 10000,
+            index_interval_bytes: Some(1),
+            ..CommitlogConfig::default()
+        };
+        let messages = ["a:1", "b:1", "a:2", "b:2", "c:1", "a:3", "z:1"];
+        let cl = Commitlog::with_config(tmp_dir_path, config.clone()).expect("Unable to create commitlog");
+        for message in messages {
+            cl.append(message.as_bytes()).expect("Unable to append message");
+        }
+
+        assert_eq!(2, cl.compact(&TestKeyExtractor, 60000).expect("Unable to compact commitlog"));
+        let reloaded_cl = Commitlog::with_config(tmp_dir_path, config).expect("Unable to reload commitlog");
+        let removed_error = CommitlogError::new("Offset was removed by compaction");
+        assert_eq!(removed_error, reloaded_cl.read(0).unwrap_err());
+        assert_eq!(removed_error, reloaded_cl.read(1).unwrap_err());
+        assert_eq!("a:2".as_bytes(), &*reloaded_cl.read(2).unwrap());
+        assert_eq!("b:2".as_bytes(), &*reloaded_cl.read(3).unwrap());
+    }
+}
@@ -0,0 +1,102 @@
+use crate::nolan_errors::SegmentError;
+use log::error;
+use serde::{Deserialize, Serialize};
+
+/// Bits of the record attributes that hold the compression codec of the record.
+pub const COMPRESSION_MASK: u8 = 0b0000_0111;
+
+/// The codec used to compress batches of records written to the commitlog. The codec is stored in the attributes of
+/// every compressed record, so changing it only affects new records and segments can hold records of any codec.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum Compression {
+    /// Records are stored as they are
+    #[default]
+    None,
+    Zstd,
+    Lz4,
+    Snappy,
+}
+
+impl Compression {
+    /// Return the attribute bits that mark a record as compressed with the codec.
+    pub fn attributes(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Zstd => 1,
+            Compression::Lz4 => 2,
+            Compression::Snappy => 3,
+        }
+    }
+
+    /// Read the codec out of the attributes of a record.
+    pub fn from_attributes(attributes: u8) -> Result<Compression, SegmentError> {
+        match attributes & COMPRESSION_MASK {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Zstd),
+            2 => Ok(Compression::Lz4),
+            3 => Ok(Compression::Snappy),
+            _ => Err(SegmentError::new("unknown compression codec")),
+        }
+    }
+
+    /// Compress the data with the codec.
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>, SegmentError> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Zstd => zstd::bulk::compress(data, zstd::DEFAULT_COMPRESSION_LEVEL).map_err(|e| {
+                error!("{}", e);
+                SegmentError::new("unable to compress with zstd")
+            }),
+            Compression::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            Compression::Snappy => snap::raw::Encoder::new().compress_vec(data).map_err(|e| {
+                error!("{}", e);
+                SegmentError::new("unable to compress with snappy")
+            }),
+        }
+    }
+
+    /// Decompress data that was compressed with the codec.
+    pub fn decompress(self, data: &[u8]) -> Result<Vec<u8>, SegmentError> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Zstd => zstd::stream::decode_all(data).map_err(|e| {
+                error!("{}", e);
+                SegmentError::new("unable to decompress with zstd")
+            }),
+            Compression::Lz4 => lz4_flex::decompress_size_prepended(data).map_err(|e| {
+                error!("{}", e);
+                SegmentError::new("unable to decompress with lz4")
+            }),
+            Compression::Snappy => snap::raw::Decoder::new().decompress_vec(data).map_err(|e| {
+                error!("{}", e);
+                SegmentError::new("unable to decompress with snappy")
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod compression_tests {
+    use crate::compression::Compression;
+    use crate::nolan_errors::SegmentError;
+
+    #[test]
+    fn test_compress_decompress() {
+        let data = "{\"hello\": \"world\", \"hello again\": \"world\"}".repeat(10);
+        for compression in [Compression::None, Compression::Zstd, Compression::Lz4, Compression::Snappy] {
+            let compressed = compression.compress(data.as_bytes()).expect("Unable to compress");
+            if compression != Compression::None {
+                assert!(compressed.len() < data.len());
+            }
+            let decompressed = compression.decompress(&compressed).expect("Unable to decompress");
+            assert_eq!(data.as_bytes(), &*decompressed);
+            assert_eq!(compression, Compression::from_attributes(compression.attributes()).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_unknown_codec() {
+        let codec_error = Compression::from_attributes(0b0000_0111).unwrap_err();
+        assert_eq!(SegmentError::new("unknown compression codec"), codec_error);
+    }
+}
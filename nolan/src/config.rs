@@ -0,0 +1,68 @@
+use crate::compression::Compression;
+use serde::{Deserialize, Serialize};
+
+/// Controls how often writes to the active segment are synced to disk. Records are always written to the
+/// segment files as they're appended, the policy only decides when the OS is asked to make them durable.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum FsyncPolicy {
+    /// Sync after every record that is appended
+    #[default]
+    Always,
+    /// Sync once the given amount of records have been appended since the last sync
+    EveryRecords(u64),
+    /// Sync on the first append after the given amount of milliseconds have passed since the last sync, records that
+    /// aren't followed by another append are synced by `Commitlog::sync_if_due`
+    EveryMillis(u64),
+    /// Never sync, leave it up to the OS
+    Never,
+}
+
+/// Configuration used to create a commitlog.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct CommitlogConfig {
+    /// Max size in bytes of a single segment
+    pub max_segment_size: u64,
+    /// Max bytes the cleaner will retain for the entire commitlog
+    pub retention_bytes: u64,
+    /// How long in milliseconds the cleaner will retain records for, records are kept regardless of age if not set
+    pub retention_ms: Option<u64>,
+    /// How often appended records are synced to disk
+    pub fsync_policy: FsyncPolicy,
+    /// Write an index entry every this many bytes of log instead of one for every record. Lookups scan forward
+    /// in the log from the closest entry, trading some lookup time for a much smaller index.
+    pub index_interval_bytes: Option<u32>,
+    /// Codec appended batches are compressed with. Changing it only affects records appended afterwards, records
+    /// already in the commitlog stay readable.
+    pub compression: Compression,
+    /// Key file to encrypt appended records with, records are stored in plaintext if not set. Every line of the key
+    /// file holds a key id and a hex encoded 32 byte key, the last key in the file is used for new records.
+    pub encryption_key_file: Option<String>,
+    /// Max bytes of closed segments kept on local disk when the commitlog has remote storage, older segments are
+    /// only kept in remote storage. Every segment is kept on local disk as well if not set.
+    pub local_retention_bytes: Option<u64>,
+    /// Keep a manifest file listing the segments, which is checked against the segment files when the commitlog is
+    /// opened. Closed segments that go missing are reported instead of silently skipped, and segments left behind by a
+    /// deletion that didn't finish are removed.
+    pub segment_manifest: bool,
+    /// Roll the active segment once its first record is older than this many milliseconds, even if it isn't full, so
+    /// retention can remove the records of a commitlog that's rarely appended to. Segments only roll once they're full
+    /// if not set.
+    pub segment_ms: Option<u64>,
+}
+
+impl Default for CommitlogConfig {
+    fn default() -> Self {
+        CommitlogConfig {
+            max_segment_size: 1024 * 1024,
+            retention_bytes: 1024 * 1024 * 1024,
+            retention_ms: None,
+            fsync_policy: FsyncPolicy::default(),
+            index_interval_bytes: None,
+            compression: Compression::default(),
+            encryption_key_file: None,
+            local_retention_bytes: None,
+            segment_manifest: false,
+            segment_ms: None,
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use crate::nolan_errors::SegmentError;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use log::error;
+use std::collections::BTreeMap;
+use std::fs;
+
+/// Attribute flag set on records whose payload is encrypted.
+pub const ENCRYPTED: u8 = 0b0000_1000;
+/// Size of the id of the key an encrypted payload was sealed with.
+const KEY_ID_SIZE: usize = 4;
+/// Size of the nonce stored with every encrypted payload.
+const NONCE_SIZE: usize = 12;
+/// Size of the authentication tag at the end of every encrypted payload.
+const TAG_SIZE: usize = 16;
+/// Bytes an encrypted payload takes on top of the plaintext.
+pub const ENVELOPE_SIZE: usize = KEY_ID_SIZE + NONCE_SIZE + TAG_SIZE;
+/// Size of an AES-256 key.
+const KEY_SIZE: usize = 32;
+
+/// The keys used to encrypt and decrypt record payloads. New records are encrypted with the active key, and every
+/// encrypted payload is stored as `[key id u32][nonce][ciphertext][tag]` so records keep the key they were written
+/// with after the keys are rotated. The offset of the record is authenticated along with the payload, so an
+/// encrypted payload can't be moved to another record.
+pub struct Keyring {
+    keys: BTreeMap<u32, Aes256Gcm>,
+    active_key_id: u32,
+}
+
+impl Keyring {
+    /// Load the keys from a key file. Every line of the file holds a key id and a 32 byte key in hex separated by
+    /// whitespace, empty lines and lines starting with `#` are ignored. The last key in the file is the active key,
+    /// so keys are rotated by appending a new key to the file. Old keys have to stay in the file for as long as
+    /// there are records encrypted with them.
+    pub fn load(key_file: &str) -> Result<Keyring, SegmentError> {
+        let key_file_contents = fs::read_to_string(key_file).map_err(|e| {
+            error!("{}: {}", key_file, e);
+            SegmentError::new("unable to read key file")
+        })?;
+        let mut keys = Vec::new();
+        for line in key_file_contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let key = line
+                .split_once(char::is_whitespace)
+                .and_then(|(key_id, key)| Some((key_id.parse().ok()?, decode_key(key.trim())?)))
+                .ok_or_else(|| SegmentError::new("invalid key in key file"))?;
+            keys.push(key);
+        }
+        Keyring::from_keys(&keys)
+    }
+
+    /// Create a keyring from key ids and keys, the last key is the active key.
+    pub fn from_keys(keys: &[(u32, [u8; KEY_SIZE])]) -> Result<Keyring, SegmentError> {
+        let (active_key_id, _) = keys.last().ok_or_else(|| SegmentError::new("no encryption keys were given"))?;
+        let mut ciphers = BTreeMap::new();
+        for (key_id, key) in keys {
+            let cipher = Aes256Gcm::new_from_slice(key).map_err(|e| {
+                error!("{}", e);
+                SegmentError::new("invalid encryption key")
+            })?;
+            if ciphers.insert(*key_id, cipher).is_some() {
+                return Err(SegmentError::new("duplicate encryption key id"));
+            }
+        }
+        Ok(Keyring {
+            keys: ciphers,
+            active_key_id: *active_key_id,
+        })
+    }
+
+    /// Encrypt the payload of the record at the offset with the active key.
+    pub fn encrypt(&self, offset: u64, plaintext: &[u8]) -> Result<Vec<u8>, SegmentError> {
+        let cipher = &self.keys[&self.active_key_id];
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let aad = offset.to_le_bytes();
+        let ciphertext = cipher
+            .encrypt(&nonce, Payload { msg: plaintext, aad: &aad })
+            .map_err(|e| {
+                error!("{}", e);
+                SegmentError::new("unable to encrypt record")
+            })?;
+        let mut envelope = Vec::with_capacity(KEY_ID_SIZE + NONCE_SIZE + ciphertext.len());
+        envelope.extend_from_slice(&self.active_key_id.to_le_bytes());
+        envelope.extend_from_slice(&nonce);
+        envelope.extend_from_slice(&ciphertext);
+        Ok(envelope)
+    }
+
+    /// Decrypt the encrypted payload of the record at the offset, with the key it was encrypted with.
+    pub fn decrypt(&self, offset: u64, envelope: &[u8]) -> Result<Vec<u8>, SegmentError> {
+        if envelope.len() < ENVELOPE_SIZE {
+            return Err(SegmentError::new("encrypted record is incomplete"));
+        }
+        let key_id = u32::from_le_bytes(envelope[..KEY_ID_SIZE].try_into().unwrap());
+        let cipher = self.keys.get(&key_id).ok_or_else(|| {
+            error!("encryption key {} is not in the key file", key_id);
+            SegmentError::new("record was encrypted with an unknown key")
+        })?;
+        let nonce = Nonce::from_slice(&envelope[KEY_ID_SIZE..KEY_ID_SIZE + NONCE_SIZE]);
+        let aad = offset.to_le_bytes();
+        cipher
+            .decrypt(nonce, Payload { msg: &envelope[KEY_ID_SIZE + NONCE_SIZE..], aad: &aad })
+            .map_err(|e| {
+                error!("{}", e);
+                SegmentError::new("unable to decrypt record")
+            })
+    }
+}
+
+/// Decode a key written as hex.
+fn decode_key(hex_key: &str) -> Option<[u8; KEY_SIZE]> {
+    if hex_key.len() != KEY_SIZE * 2 {
+        return None;
+    }
+    let mut key = [0; KEY_SIZE];
+    for (i, key_byte) in key.iter_mut().enumerate() {
+        *key_byte = u8::from_str_radix(hex_key.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(key)
+}
+
+#[cfg(test)]
+mod encryption_tests {
+    use crate::encryption::{Keyring, ENVELOPE_SIZE};
+    use crate::nolan_errors::SegmentError;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let keyring = Keyring::from_keys(&[(1, [1; 32])]).unwrap();
+        let envelope = keyring.encrypt(42, "hello".as_bytes()).expect("Unable to encrypt");
+        assert_eq!(5 + ENVELOPE_SIZE, envelope.len());
+        assert_eq!("hello".as_bytes(), &*keyring.decrypt(42, &envelope).expect("Unable to decrypt"));
+
+        // The payload is bound to the offset it was written at
+        assert_eq!(SegmentError::new("unable to decrypt record"), keyring.decrypt(43, &envelope).unwrap_err());
+        let mut tampered_envelope = envelope.clone();
+        tampered_envelope[ENVELOPE_SIZE] ^= 0xff;
+        assert_eq!(SegmentError::new("unable to decrypt record"), keyring.decrypt(42, &tampered_envelope).unwrap_err());
+    }
+
+    #[test]
+    fn test_load_rotated_keys() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let key_file = tmp_dir.path().join("keys");
+        let key_file = key_file.to_str().unwrap();
+        fs::write(key_file, format!("# lucidmq keys\n1 {}\n", "01".repeat(32))).unwrap();
+        let old_keyring = Keyring::load(key_file).expect("Unable to load keys");
+        let old_envelope = old_keyring.encrypt(0, "hello".as_bytes()).unwrap();
+
+        // Rotating appends a new key, payloads encrypted with the old key are still readable
+        fs::write(key_file, format!("1 {}\n2 {}\n", "01".repeat(32), "ab".repeat(32))).unwrap();
+        let keyring = Keyring::load(key_file).expect("Unable to load keys");
+        let new_envelope = keyring.encrypt(0, "hello".as_bytes()).unwrap();
+        assert_eq!(2, u32::from_le_bytes(new_envelope[..4].try_into().unwrap()));
+        assert_eq!("hello".as_bytes(), &*keyring.decrypt(0, &old_envelope).unwrap());
+        assert_eq!(
+            SegmentError::new("record was encrypted with an unknown key"),
+            old_keyring.decrypt(0, &new_envelope).unwrap_err()
+        );
+
+        fs::write(key_file, "1 not-a-key\n").unwrap();
+        assert_eq!(SegmentError::new("invalid key in key file"), Keyring::load(key_file).err().unwrap());
+    }
+}
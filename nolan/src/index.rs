@@ -1,12 +1,21 @@
 use std::str;
 use crate::nolan_errors::IndexError;
+use crate::record::LOG_HEADER_SIZE;
 use log::error;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::fs::File;
-use std::fs::OpenOptions;
-use std::io::prelude::*;
-use std::io::ErrorKind;
-use std::io::SeekFrom;
+use crate::storage::StorageBackend;
+use std::sync::Arc;
+
+/// Magic bytes at the start of a sparse index file. Dense index files have no header, their first 4 bytes are the
+/// start of the first record in the log which never matches the magic.
+pub const SPARSE_INDEX_MAGIC: &[u8; 4] = b"NIDX";
+/// Version of the sparse index format, stored right after the magic bytes.
+pub const SPARSE_INDEX_VERSION: u8 = 1;
+/// Size of the sparse index header, the magic and version padded out to 8 bytes followed by the interval in bytes.
+pub const SPARSE_INDEX_HEADER_SIZE: usize = 12;
+/// Size of a single encoded entry, dense and sparse entries are both 8 bytes.
+const ENTRY_SIZE: usize = 8;
 
 /// An entry is metadata representation of an entry of the commitlog, it's used by the index to do quick lookups by offset.
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
@@ -17,143 +26,320 @@ pub struct Entry {
     pub total: u32,
 }
 
+/// A sparse entry maps an offset to where its record starts in the log. Sparse indexes only have an entry every
+/// interval bytes of log, the records in between are found by scanning forward from the closest entry.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct SparseEntry {
+    /// Offset of the record relative to the starting offset of the segment
+    pub offset: u32,
+    /// starting point of the record within the log file
+    pub position: u32,
+}
+
+/// Where to find the record for an offset in the log, as far as the index knows.
+#[derive(PartialEq, Debug)]
+pub enum IndexLookup {
+    /// The record starts at start in the log and is total bytes long
+    Record { start: u64, total: usize },
+    /// The record for the offset was removed by compaction, the next record that wasn't starts at next_start
+    Removed { next_start: u64 },
+    /// The record, unless it was removed by compaction, is at or after this position in the log
+    ScanFrom(u64),
+}
+
+/// The entries of an index, kept in memory for quick lookups.
+#[derive(PartialEq, Debug)]
+pub enum IndexEntries {
+    /// One entry for every offset in the segment, offsets removed by compaction have an entry with a total of 0
+    Dense(Vec<Entry>),
+    /// One entry every interval bytes of log
+    Sparse {
+        interval_bytes: u32,
+        entries: Vec<SparseEntry>,
+    },
+}
+
+impl IndexEntries {
+    /// Create empty entries, a sparse index is used if an interval is given.
+    pub fn new(index_interval_bytes: Option<u32>) -> IndexEntries {
+        match index_interval_bytes {
+            None => IndexEntries::Dense(Vec::new()),
+            Some(interval_bytes) => IndexEntries::Sparse {
+                interval_bytes,
+                entries: Vec::new(),
+            },
+        }
+    }
+
+    /// Decode the contents of an index file, sparse indexes are recognized by their header.
+    /// A partially written entry at the end of the file is ignored.
+    pub fn decode(index_contents: &[u8]) -> Result<IndexEntries, IndexError> {
+        if !index_contents.starts_with(SPARSE_INDEX_MAGIC) {
+            return Ok(IndexEntries::Dense(decode_entries(index_contents)?));
+        }
+        if index_contents.len() < SPARSE_INDEX_HEADER_SIZE {
+            return Err(IndexError::new("sparse index header is incomplete"));
+        }
+        if index_contents[SPARSE_INDEX_MAGIC.len()] != SPARSE_INDEX_VERSION {
+            return Err(IndexError::new("unsupported sparse index version"));
+        }
+        let interval_bytes = u32::from_le_bytes(index_contents[8..12].try_into().unwrap());
+        Ok(IndexEntries::Sparse {
+            interval_bytes,
+            entries: decode_entries(&index_contents[SPARSE_INDEX_HEADER_SIZE..])?,
+        })
+    }
+
+    /// Return the header an index file starts with, dense indexes don't have one.
+    pub fn header(&self) -> Vec<u8> {
+        match self {
+            IndexEntries::Dense(_) => Vec::new(),
+            IndexEntries::Sparse { interval_bytes, .. } => {
+                let mut header = vec![0; SPARSE_INDEX_HEADER_SIZE];
+                header[..SPARSE_INDEX_MAGIC.len()].copy_from_slice(SPARSE_INDEX_MAGIC);
+                header[SPARSE_INDEX_MAGIC.len()] = SPARSE_INDEX_VERSION;
+                header[8..12].copy_from_slice(&interval_bytes.to_le_bytes());
+                header
+            }
+        }
+    }
+
+    /// Encode the entries into the contents of an index file.
+    pub fn encode(&self) -> Result<Vec<u8>, IndexError> {
+        let mut index_contents = self.header();
+        match self {
+            IndexEntries::Dense(entries) => encode_entries(&mut index_contents, entries)?,
+            IndexEntries::Sparse { entries, .. } => encode_entries(&mut index_contents, entries)?,
+        }
+        Ok(index_contents)
+    }
+
+    /// Add the record at the relative offset to the entries, returning the encoded bytes of the entries that were
+    /// added. Dense indexes get an entry for every offset, a total of 0 marks an offset removed by compaction.
+    /// Sparse indexes only get an entry once interval bytes of log were written since the last entry.
+    pub fn push(&mut self, relative_offset: u64, start: u32, total: u32) -> Result<Vec<u8>, IndexError> {
+        let mut encoded_entries = Vec::new();
+        match self {
+            IndexEntries::Dense(entries) => {
+                let entry = Entry { start, total };
+                encode_entries(&mut encoded_entries, &[entry])?;
+                entries.push(entry);
+            }
+            IndexEntries::Sparse { interval_bytes, entries } => {
+                let last_position = entries
+                    .last()
+                    .map(|entry| entry.position)
+                    .unwrap_or(LOG_HEADER_SIZE as u32);
+                if total == 0 || start.saturating_sub(last_position) < *interval_bytes {
+                    return Ok(encoded_entries);
+                }
+                let offset = u32::try_from(relative_offset).map_err(|e| {
+                    error!("{}", e);
+                    IndexError::new("unable to convert from u64 to u32")
+                })?;
+                let entry = SparseEntry { offset, position: start };
+                encode_entries(&mut encoded_entries, &[entry])?;
+                entries.push(entry);
+            }
+        }
+        Ok(encoded_entries)
+    }
+
+    /// Return the interval between entries if the index is sparse.
+    pub fn interval_bytes(&self) -> Option<u32> {
+        match self {
+            IndexEntries::Dense(_) => None,
+            IndexEntries::Sparse { interval_bytes, .. } => Some(*interval_bytes),
+        }
+    }
+
+    /// Return the amount of entries
+    pub fn len(&self) -> usize {
+        match self {
+            IndexEntries::Dense(entries) => entries.len(),
+            IndexEntries::Sparse { entries, .. } => entries.len(),
+        }
+    }
+
+    /// Drop every entry after the first len entries.
+    pub fn truncate(&mut self, len: usize) {
+        match self {
+            IndexEntries::Dense(entries) => entries.truncate(len),
+            IndexEntries::Sparse { entries, .. } => entries.truncate(len),
+        }
+    }
+
+    /// Return the size of the index file holding the entries.
+    pub fn encoded_len(&self) -> usize {
+        self.header().len() + self.len() * ENTRY_SIZE
+    }
+
+    /// Given an offset relative to the start of the segment, find where its record is in the log.
+    /// Sparse indexes binary search for the closest entry at or before the offset.
+    pub fn lookup(&self, relative_offset: u64) -> Result<IndexLookup, IndexError> {
+        match self {
+            IndexEntries::Dense(entries) => {
+                let entry = usize::try_from(relative_offset)
+                    .ok()
+                    .and_then(|offset| entries.get(offset))
+                    .ok_or_else(|| IndexError::new("offset is greater than entries length"))?;
+                if entry.total == 0 {
+                    return Ok(IndexLookup::Removed { next_start: entry.start.into() });
+                }
+                let total: usize = usize::try_from(entry.total).map_err(|e| {
+                    error!("{}", e);
+                    IndexError::new("unable to convert from u32 to usize")
+                })?;
+                Ok(IndexLookup::Record {
+                    start: entry.start.into(),
+                    total,
+                })
+            }
+            IndexEntries::Sparse { entries, .. } => {
+                let closest = entries.partition_point(|entry| u64::from(entry.offset) <= relative_offset);
+                let position = match closest {
+                    0 => LOG_HEADER_SIZE as u64,
+                    _ => entries[closest - 1].position.into(),
+                };
+                Ok(IndexLookup::ScanFrom(position))
+            }
+        }
+    }
+}
+
+/// Decode 8 byte entries, ignoring a partial entry at the end.
+fn decode_entries<T: DeserializeOwned>(bytes: &[u8]) -> Result<Vec<T>, IndexError> {
+    bytes
+        .chunks_exact(ENTRY_SIZE)
+        .map(|entry_bytes| {
+            bincode::deserialize(entry_bytes).map_err(|e| {
+                error!("{}", e);
+                IndexError::new("unable to deserialize entry")
+            })
+        })
+        .collect()
+}
+
+/// Encode entries onto the end of the buffer.
+fn encode_entries<T: Serialize>(buffer: &mut Vec<u8>, entries: &[T]) -> Result<(), IndexError> {
+    for entry in entries {
+        bincode::serialize_into(&mut *buffer, entry).map_err(|e| {
+            error!("{}", e);
+            IndexError::new("unable to serialize entry")
+        })?;
+    }
+    Ok(())
+}
+
 /// Data structure that allows us to map segment entries to offsets for quick lookups. 
 /// This is stored on disk and on startup allows us to build our commitlog representation.
 pub struct Index {
-    ///The file name of the index on disc
+    ///The file name of the index in storage
     pub file_name: String,
     /// Memory representation of the index entries
-    entries: Vec<Entry>,
-    /// Actual file object that struct method modifies
-    index_file: File,
+    entries: IndexEntries,
+    /// Storage backend the index file lives in
+    backend: Arc<dyn StorageBackend>,
 }
 
 impl Index {
-    /// Create a new index given a file path
-    pub fn new(index_path: &str) -> Result<Index, IndexError> {
-        let error_message = format!("Unable to create and open file {}", index_path);
-        let file = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .append(true)
-            .open(index_path.clone())
-            .map_err(|e| {
-                error!("{}", e);
-                IndexError::new(&error_message)
-            })?;
-        let empty_entry_vec = Vec::new();
+    /// Create a new index given a file name in the storage backend
+    pub fn new(backend: Arc<dyn StorageBackend>, index_file_name: &str) -> Result<Index, IndexError> {
+        let error_message = format!("Unable to create and open file {}", index_file_name);
+        backend.open(index_file_name, false).map_err(|e| {
+            error!("{}", e);
+            IndexError::new(&error_message)
+        })?;
         Ok(Index {
-            file_name: index_path.to_string(),
-            entries: empty_entry_vec,
-            index_file: file,
+            file_name: index_file_name.to_string(),
+            entries: IndexEntries::new(None),
+            backend,
         })
     }
 
     
-    /// Load the index from disk into memory
-    pub fn load_index(&mut self) -> Result<u16, IndexError> {
-        self.index_file.seek(SeekFrom::Start(0)).map_err(|e| {
+    /// Load the index from storage into memory, returning the amount of entries in the index
+    pub fn load_index(&mut self) -> Result<u64, IndexError> {
+        let index_contents = self.backend.read(&self.file_name).map_err(|e| {
             error!("{}", e);
-            IndexError::new("unable seek to begining of the index")
+            IndexError::new("unable to read from index file")
         })?;
-        let mut circut_break: bool = false;
-        loop {
-            let mut buffer = [0; 8];
-            match self.index_file.read_exact(&mut buffer) {
-                Ok(_) => {},
-                Err(error) => {
-                    if error.kind() == ErrorKind::UnexpectedEof {
-                    circut_break = true;
-                } else {
-                    return Err(IndexError::new("unable seek to begining of the index"));
-                }},
-            }
-            if circut_break {
-                break;
-            }
-            let decoded_entry: Entry = bincode::deserialize(&buffer).map_err(|e| {
-                error!("{}", e);
-                IndexError::new("unable to deserialize entry")
-            })?;
-            self.entries.push(decoded_entry);
-        }
-        let value = u16::try_from(self.entries.len()).map_err(|e| {
+        self.entries = IndexEntries::decode(&index_contents)?;
+        let value = u64::try_from(self.entries.len()).map_err(|e| {
             error!("{}", e);
-            IndexError::new("unable to convert usize to u16")
+            IndexError::new("unable to convert usize to u64")
         })?;
         Ok(value)
     }
 
-    /// Given an offset, return the entry start and total bytes
-    pub fn return_entry_details_by_offset(
-        &self,
-        offset: usize,
-    ) -> Result<(u64, usize), IndexError> {
-        if offset >= self.entries.len() {
-            return Err(IndexError::new("offset is greater than entries length"));
-        }
-        let entry = self.entries[offset];
-        let start_offset: u64 = entry.start.into();
-        let total_bytes: usize = usize::try_from(entry.total).map_err(|e| {
-            error!("{}", e);
-            IndexError::new("unable to convert from u32 to usize")
-        })?;
-        Ok((start_offset, total_bytes))
+    /// Given an offset relative to the start of the segment, find where its record is in the log.
+    pub fn lookup(&self, offset: u64) -> Result<IndexLookup, IndexError> {
+        self.entries.lookup(offset)
+    }
+
+    /// Return the interval between entries if the index is sparse.
+    pub fn interval_bytes(&self) -> Option<u32> {
+        self.entries.interval_bytes()
     }
 }
 
 #[cfg(test)]
 mod index_tests {
     use std::str;
-    use std::path::Path;
+    use std::sync::Arc;
     use tempdir::TempDir;
     use rand::{distributions::Alphanumeric, Rng}; // 0.8
-    use crate::index::Index;
+    use crate::index::{Index, IndexEntries, IndexLookup, SparseEntry};
     use crate::nolan_errors::IndexError;
+    use crate::record;
     use crate::utils;
+    use crate::compression::Compression;
+    use crate::config::FsyncPolicy;
+    use crate::storage::{FilesystemStorage, StorageBackend};
     use crate::virtual_segment::VirtualSegment;
 
-    fn create_index_file(test_dir_path: &str, message_to_write: &[u8]) -> String{
-        let mut vs = VirtualSegment::new(test_dir_path, 100, 0);
+    fn create_test_backend(tmp_dir: &TempDir) -> Arc<dyn StorageBackend> {
+        let test_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to convert path to string");
+        Arc::new(FilesystemStorage::new(test_dir_path).expect("Unable to create storage"))
+    }
+
+    fn create_index_file(backend: &Arc<dyn StorageBackend>, message_to_write: &[u8]) -> String{
+        let mut vs = VirtualSegment::new(backend.clone(), 100, 0, FsyncPolicy::Always, None, Compression::None, None)
+            .expect("Unable to create virtual segment");
         vs
             .write(message_to_write)
             .expect("unable to write data to virtual segment");
         vs.flush().expect("Unable to flush");
-        let file_name = vs.full_log_path.clone();
+        let file_name = vs.log_file_name.clone();
         let thing = str::strip_suffix(&file_name, utils::LOG_SUFFIX).expect("unable to strip");
-        let index_file_name = format!("{}{}", thing, utils::INDEX_SUFFIX);
-        return index_file_name
+        format!("{}{}", thing, utils::INDEX_SUFFIX)
     }
 
     #[test]
     fn test_new_index() {
         let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
-        let test_dir_path = tmp_dir
-            .path()
-            .to_str()
-            .expect("Unable to convert path to string");
+        let backend = create_test_backend(&tmp_dir);
         let s: String = rand::thread_rng()
             .sample_iter(&Alphanumeric)
             .take(7)
             .map(char::from)
             .collect();
-        let index_file_path = format!("{}{}{}", test_dir_path.to_string(), &s, utils::INDEX_SUFFIX);
-        let index = Index::new(&index_file_path).expect("Error creating index");  
+        let index_file_name = format!("{}{}", &s, utils::INDEX_SUFFIX);
+        let index = Index::new(backend, &index_file_name).expect("Error creating index");  
         //Check if the index file exists
-        assert!(Path::new(&index.file_name).exists());
+        assert!(tmp_dir.path().join(&index.file_name).exists());
     }
 
     #[test]
     fn test_load_index() {
         let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
-        let test_dir_path = tmp_dir
-            .path()
-            .to_str()
-            .expect("Unable to convert path to string");
-        let index_file_name = create_index_file(test_dir_path, "hello".as_bytes());
+        let backend = create_test_backend(&tmp_dir);
+        let index_file_name = create_index_file(&backend, "hello".as_bytes());
 
-        let mut index = Index::new(&index_file_name).expect("Error creating index");
+        let mut index = Index::new(backend, &index_file_name).expect("Error creating index");
         index.load_index().expect("unable to load index");
         assert!(index.entries.len() == 1);
     }
@@ -161,37 +347,77 @@ mod index_tests {
     #[test]
     fn return_entry_details_by_offset() {
         let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
-        let test_dir_path = tmp_dir
-            .path()
-            .to_str()
-            .expect("Unable to convert path to string");
+        let backend = create_test_backend(&tmp_dir);
         let message = "hello";
-        let index_file_name = create_index_file(test_dir_path, message.as_bytes());
+        let index_file_name = create_index_file(&backend, message.as_bytes());
 
-        let mut index = Index::new(&index_file_name).expect("Error creating index");
+        let mut index = Index::new(backend, &index_file_name).expect("Error creating index");
         index.load_index().expect("unable to load index");
 
-        let (start, total) = index.return_entry_details_by_offset(0).expect("Unable to get entry details");
-        assert!(start == 0);
-        assert!(total == message.len());
+        let lookup = index.lookup(0).expect("Unable to get entry details");
+        let wanted_lookup = IndexLookup::Record {
+            start: record::LOG_HEADER_SIZE as u64,
+            total: record::encoded_len(message.len()),
+        };
+        assert_eq!(wanted_lookup, lookup);
     }
 
     #[test]
     fn return_entry_details_by_offset_dne() {
         let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
-        let test_dir_path = tmp_dir
-            .path()
-            .to_str()
-            .expect("Unable to convert path to string");
+        let backend = create_test_backend(&tmp_dir);
         let message = "hello";
-        let index_file_name = create_index_file(test_dir_path, message.as_bytes());
-        let mut index = Index::new(&index_file_name).expect("Error creating index");
+        let index_file_name = create_index_file(&backend, message.as_bytes());
+        let mut index = Index::new(backend, &index_file_name).expect("Error creating index");
         index.load_index().expect("unable to load index");
 
-        let index_error = index.return_entry_details_by_offset(1).unwrap_err();
+        let index_error = index.lookup(1).unwrap_err();
         let wanted_error =
             IndexError::new("offset is greater than entries length");
         assert_eq!(wanted_error, index_error);
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_sparse_entries_interval() {
+        let mut entries = IndexEntries::new(Some(100));
+        // Records are 40 bytes, so every third record is at least 100 bytes past the last entry
+        for i in 0..10 {
+            let start = record::LOG_HEADER_SIZE as u32 + i * 40;
+            entries.push(u64::from(i), start, 40).expect("Unable to push entry");
+        }
+        let wanted_entries = IndexEntries::Sparse {
+            interval_bytes: 100,
+            entries: vec![
+                SparseEntry { offset: 3, position: 128 },
+                SparseEntry { offset: 6, position: 248 },
+                SparseEntry { offset: 9, position: 368 },
+            ],
+        };
+        assert_eq!(wanted_entries, entries);
+
+        assert_eq!(IndexLookup::ScanFrom(record::LOG_HEADER_SIZE as u64), entries.lookup(2).unwrap());
+        assert_eq!(IndexLookup::ScanFrom(128), entries.lookup(3).unwrap());
+        assert_eq!(IndexLookup::ScanFrom(248), entries.lookup(8).unwrap());
+        assert_eq!(IndexLookup::ScanFrom(368), entries.lookup(20).unwrap());
+    }
+
+    #[test]
+    fn test_encode_decode_entries() {
+        let mut dense_entries = IndexEntries::new(None);
+        let mut sparse_entries = IndexEntries::new(Some(0));
+        for i in 0..5 {
+            let start = record::LOG_HEADER_SIZE as u32 + i * 40;
+            dense_entries.push(u64::from(i), start, 40).expect("Unable to push entry");
+            sparse_entries.push(u64::from(i), start, 40).expect("Unable to push entry");
+        }
+        let dense_contents = dense_entries.encode().expect("Unable to encode entries");
+        let sparse_contents = sparse_entries.encode().expect("Unable to encode entries");
+        assert_eq!(40, dense_contents.len());
+        assert!(sparse_contents.starts_with(b"NIDX"));
+
+        assert_eq!(dense_entries, IndexEntries::decode(&dense_contents).unwrap());
+        assert_eq!(sparse_entries, IndexEntries::decode(&sparse_contents).unwrap());
+        // A torn entry at the end of the file is ignored
+        assert_eq!(4, IndexEntries::decode(&dense_contents[..36]).unwrap().len());
+    }
+}
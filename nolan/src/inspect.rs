@@ -0,0 +1,402 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use log::{error, info};
+
+use crate::compression::Compression;
+use crate::encryption::{Keyring, ENCRYPTED};
+use crate::index::IndexEntries;
+use crate::nolan_errors::CommitlogError;
+use crate::record;
+use crate::segment;
+use crate::storage::{FilesystemStorage, StorageBackend};
+use crate::utils;
+
+/// What verifying a segment of a commitlog directory found.
+#[derive(Debug, PartialEq)]
+pub struct SegmentReport {
+    /// The starting offset of the segment
+    pub starting_offset: u64,
+    /// Amount of offsets covered by the valid records of the log
+    pub records: u64,
+    /// Size of the log file in bytes
+    pub log_bytes: u64,
+    /// Bytes at the start of the log that hold valid records, anything after them is torn or corrupt
+    pub valid_bytes: u64,
+    /// Everything that's wrong with the segment, empty if the segment is healthy
+    pub problems: Vec<String>,
+}
+
+/// A record stored in a log, as it's shown by a dump.
+#[derive(Debug, PartialEq)]
+pub struct DumpedRecord {
+    /// The commitlog offset the record was written at
+    pub offset: u64,
+    /// Position of the stored record in the log, the records of a compressed batch share the position of the batch
+    pub position: u64,
+    /// Bytes the stored record takes in the log
+    pub size: u64,
+    /// Time the record was appended, in milliseconds since the unix epoch
+    pub timestamp: u64,
+    /// Codec the stored record was compressed with
+    pub compression: Compression,
+    /// If the stored record is encrypted
+    pub encrypted: bool,
+    /// The user supplied data, None if the record is encrypted and no keys were given. An encrypted compressed batch
+    /// can't be split into its records without the keys, so it's dumped as a single record.
+    pub payload: Option<Vec<u8>>,
+}
+
+/// Check every segment of the commitlog directory without changing any of its files. The log of every segment is
+/// scanned for torn or corrupt records and its indexes are compared to the ones recovery would build.
+pub fn verify(directory: &str) -> Result<Vec<SegmentReport>, CommitlogError> {
+    let backend = open_directory(directory)?;
+    let mut reports = Vec::new();
+    let mut next_offset = None;
+    for starting_offset in segment_offsets(&backend)? {
+        let report = verify_segment(&backend, starting_offset, next_offset);
+        if backend.exists(&utils::segment_file_name(starting_offset, utils::LOG_SUFFIX)) {
+            next_offset = Some(starting_offset + report.records);
+        }
+        reports.push(report);
+    }
+    Ok(reports)
+}
+
+/// Return the starting offsets of every segment in the commitlog directory, in order.
+pub fn list_segments(directory: &str) -> Result<Vec<u64>, CommitlogError> {
+    let backend = open_directory(directory)?;
+    Ok(segment_offsets(&backend)?.into_iter().collect())
+}
+
+/// Return the records stored in the log of the segment, in the order they are stored. Only the valid records at the
+/// start of the log are returned. Keys are needed to show the payloads of encrypted records.
+pub fn dump_segment(
+    directory: &str,
+    starting_offset: u64,
+    key_file: Option<&str>,
+) -> Result<Vec<DumpedRecord>, CommitlogError> {
+    let backend = open_directory(directory)?;
+    let keyring = match key_file {
+        Some(key_file) => Some(Keyring::load(key_file).map_err(|e| {
+            error!("{}", e);
+            CommitlogError::new("Unable to load encryption keys")
+        })?),
+        None => None,
+    };
+    let log_contents = read_log(&backend, starting_offset)?;
+    let (_entries, valid_bytes) = record::scan_records(&log_contents, starting_offset);
+    let mut records = Vec::new();
+    let mut position = record::LOG_HEADER_SIZE.min(valid_bytes);
+    while position < valid_bytes {
+        let (stored_record, total) = record::decode_record_ref(&log_contents[position..valid_bytes]).map_err(|e| {
+            error!("{}", e);
+            CommitlogError::new("Unable to decode record")
+        })?;
+        let compression = Compression::from_attributes(stored_record.attributes).map_err(|e| {
+            error!("{}", e);
+            CommitlogError::new("Unable to decode record")
+        })?;
+        let encrypted = stored_record.attributes & ENCRYPTED != 0;
+        if encrypted && keyring.is_none() {
+            records.push(DumpedRecord {
+                offset: stored_record.offset,
+                position: position as u64,
+                size: total as u64,
+                timestamp: stored_record.timestamp,
+                compression,
+                encrypted,
+                payload: None,
+            });
+        } else {
+            let unpacked_records = record::unpack_records(stored_record, keyring.as_ref()).map_err(|e| {
+                error!("{}", e);
+                CommitlogError::new("Unable to unpack record")
+            })?;
+            records.extend(unpacked_records.into_iter().map(|unpacked_record| DumpedRecord {
+                offset: unpacked_record.offset,
+                position: position as u64,
+                size: total as u64,
+                timestamp: unpacked_record.timestamp,
+                compression,
+                encrypted,
+                payload: Some(unpacked_record.payload),
+            }));
+        }
+        position += total;
+    }
+    Ok(records)
+}
+
+/// Rebuild the index and time index of every segment in the commitlog directory from its log. The index is sparse if
+/// an interval is given. Torn or corrupt records at the end of a log are left out of the indexes, but the log itself
+/// isn't changed. Returns the starting offsets of the segments that were rebuilt.
+pub fn rebuild_indexes(directory: &str, index_interval_bytes: Option<u32>) -> Result<Vec<u64>, CommitlogError> {
+    let backend = open_directory(directory)?;
+    let mut rebuilt_segments = Vec::new();
+    for starting_offset in segment_offsets(&backend)? {
+        let log_file_name = utils::segment_file_name(starting_offset, utils::LOG_SUFFIX);
+        if !backend.exists(&log_file_name) {
+            continue;
+        }
+        let log_contents = read_log(&backend, starting_offset)?;
+        if !record::has_log_header(&log_contents).unwrap_or(false) {
+            return Err(CommitlogError::new("Unable to rebuild index of a log without a header"));
+        }
+        let (entries, _valid_bytes) = record::scan_records(&log_contents, starting_offset);
+        let (index_contents, time_index_contents) =
+            segment::build_index_contents(&log_contents, &entries, index_interval_bytes).map_err(|e| {
+                error!("{}", e);
+                CommitlogError::new("Unable to build index")
+            })?;
+        for (suffix, contents) in [
+            (utils::INDEX_SUFFIX, &index_contents),
+            (utils::TIME_INDEX_SUFFIX, &time_index_contents),
+        ] {
+            backend
+                .write(&utils::segment_file_name(starting_offset, suffix), contents)
+                .map_err(|e| {
+                    error!("{}", e);
+                    CommitlogError::new("Unable to write index")
+                })?;
+        }
+        info!("Rebuilt indexes of segment {}", starting_offset);
+        rebuilt_segments.push(starting_offset);
+    }
+    Ok(rebuilt_segments)
+}
+
+/// Open an existing commitlog directory, unlike a commitlog the directory isn't created if it's missing.
+fn open_directory(directory: &str) -> Result<FilesystemStorage, CommitlogError> {
+    if !Path::new(directory).is_dir() {
+        return Err(CommitlogError::new("Commitlog directory does not exist"));
+    }
+    FilesystemStorage::new(directory).map_err(|e| {
+        error!("{}", e);
+        CommitlogError::new("Unable to open commitlog directory")
+    })
+}
+
+/// Return the starting offsets of every segment that has a log or index file in the storage, in order.
+fn segment_offsets(backend: &dyn StorageBackend) -> Result<BTreeSet<u64>, CommitlogError> {
+    let file_names = backend.list().map_err(|e| {
+        error!("{}", e);
+        CommitlogError::new("Unable to read commitlog directory")
+    })?;
+    let mut offsets = BTreeSet::new();
+    for file_name in file_names {
+        let path = Path::new(&file_name);
+        let (file_stem, extension) = match (path.file_stem().and_then(|stem| stem.to_str()), path.extension()) {
+            (Some(stem), Some(ext)) => (stem, ext),
+            _ => continue,
+        };
+        if extension != utils::LOG_EXTENSION
+            && extension != utils::INDEX_EXTENSION
+            && extension != utils::TIME_INDEX_EXTENSION
+        {
+            continue;
+        }
+        if let Ok(offset) = utils::parse_segment_file_stem(file_stem) {
+            offsets.insert(offset);
+        }
+    }
+    Ok(offsets)
+}
+
+fn read_log(backend: &dyn StorageBackend, starting_offset: u64) -> Result<Vec<u8>, CommitlogError> {
+    backend
+        .read(&utils::segment_file_name(starting_offset, utils::LOG_SUFFIX))
+        .map_err(|e| {
+            error!("{}", e);
+            CommitlogError::new("Unable to read log file")
+        })
+}
+
+/// Verify the files of a single segment, the next offset is where the previous segment ended.
+fn verify_segment(backend: &dyn StorageBackend, starting_offset: u64, next_offset: Option<u64>) -> SegmentReport {
+    let mut report = SegmentReport {
+        starting_offset,
+        records: 0,
+        log_bytes: 0,
+        valid_bytes: 0,
+        problems: Vec::new(),
+    };
+    let index_file_name = utils::segment_file_name(starting_offset, utils::INDEX_SUFFIX);
+    let time_index_file_name = utils::segment_file_name(starting_offset, utils::TIME_INDEX_SUFFIX);
+    let log_contents = match backend.read(&utils::segment_file_name(starting_offset, utils::LOG_SUFFIX)) {
+        Ok(log_contents) => log_contents,
+        Err(_) => {
+            report.problems.push("index files have no log file".to_string());
+            return report;
+        }
+    };
+    report.log_bytes = log_contents.len() as u64;
+    match record::has_log_header(&log_contents) {
+        Ok(true) => {}
+        Ok(false) if log_contents.is_empty() => {
+            report.problems.push("log file is empty".to_string());
+            return report;
+        }
+        Ok(false) => {
+            report.problems.push("log is in the legacy format".to_string());
+            return report;
+        }
+        Err(_) => {
+            report.problems.push("log file version is unsupported".to_string());
+            return report;
+        }
+    }
+
+    let (entries, valid_bytes) = record::scan_records(&log_contents, starting_offset);
+    report.records = entries.len() as u64;
+    report.valid_bytes = valid_bytes as u64;
+    if valid_bytes < log_contents.len() {
+        report.problems.push(format!(
+            "{} bytes after offset {} are torn or corrupt",
+            log_contents.len() - valid_bytes,
+            starting_offset + report.records
+        ));
+    }
+    if let Some(next_offset) = next_offset {
+        if starting_offset < next_offset {
+            report.problems.push(format!("segment overlaps the previous segment, which ends at offset {}", next_offset));
+        }
+    }
+
+    let existing_index_contents = match backend.exists(&index_file_name) {
+        true => backend.read(&index_file_name).ok(),
+        false => None,
+    };
+    // The index is compared in the format it's in, a rebuild at another interval isn't a problem of the segment
+    let index_interval_bytes = match &existing_index_contents {
+        Some(contents) => match IndexEntries::decode(contents) {
+            Ok(index_entries) => Some(index_entries.interval_bytes()),
+            Err(_) => {
+                report.problems.push("index file can't be decoded".to_string());
+                None
+            }
+        },
+        None => {
+            report.problems.push("index file is missing".to_string());
+            None
+        }
+    };
+    let (index_contents, time_index_contents) =
+        match segment::build_index_contents(&log_contents, &entries, index_interval_bytes.flatten()) {
+            Ok(contents) => contents,
+            Err(_) => {
+                report.problems.push("indexes can't be built from the log".to_string());
+                return report;
+            }
+        };
+    if index_interval_bytes.is_some() && existing_index_contents.as_ref() != Some(&index_contents) {
+        report.problems.push("index doesn't match the log".to_string());
+    }
+    if !backend.exists(&time_index_file_name) {
+        report.problems.push("time index file is missing".to_string());
+    } else if backend.read(&time_index_file_name).ok() != Some(time_index_contents) {
+        report.problems.push("time index doesn't match the log".to_string());
+    }
+    report
+}
+
+#[cfg(test)]
+mod inspect_tests {
+    use crate::inspect::{dump_segment, rebuild_indexes, verify, DumpedRecord};
+    use crate::{utils, Commitlog, CommitlogConfig, CommitlogError, Compression};
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::PathBuf;
+    use tempdir::TempDir;
+
+    fn create_test_commitlog(test_dir_path: &str, compression: Compression) {
+        let config = CommitlogConfig {
+            max_segment_size: 100,
+            compression,
+            ..CommitlogConfig::default()
+        };
+        let cl = Commitlog::with_config(test_dir_path, config).expect("Unable to create commitlog");
+        for i in 0..6 {
+            cl.append(format!("record {}", i).as_bytes()).expect("Unable to append");
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let test_dir_path = tmp_dir.path().to_str().unwrap();
+        create_test_commitlog(test_dir_path, Compression::None);
+
+        let reports = verify(test_dir_path).expect("Unable to verify");
+        assert!(reports.len() > 1);
+        assert!(reports.iter().all(|report| report.problems.is_empty()));
+        assert_eq!(reports.iter().map(|report| report.records).sum::<u64>(), 6);
+
+        // Tear the tail of the last log and drop the index of the first segment
+        let last_offset = reports.last().unwrap().starting_offset;
+        let log_path: PathBuf = [test_dir_path, &utils::segment_file_name(last_offset, utils::LOG_SUFFIX)]
+            .iter()
+            .collect();
+        let mut log_file = OpenOptions::new().append(true).open(log_path).unwrap();
+        log_file.write_all(&[1, 2, 3]).unwrap();
+        let index_path: PathBuf = [test_dir_path, &utils::segment_file_name(0, utils::INDEX_SUFFIX)]
+            .iter()
+            .collect();
+        fs::remove_file(index_path).unwrap();
+
+        let reports = verify(test_dir_path).expect("Unable to verify");
+        assert_eq!(reports[0].problems, vec!["index file is missing".to_string()]);
+        let last_report = reports.last().unwrap();
+        assert_eq!(last_report.log_bytes, last_report.valid_bytes + 3);
+        assert_eq!(last_report.problems.len(), 1);
+
+        let result = verify("does-not-exist");
+        assert_eq!(result, Err(CommitlogError::new("Commitlog directory does not exist")));
+    }
+
+    #[test]
+    fn test_dump_segment() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let test_dir_path = tmp_dir.path().to_str().unwrap();
+        let config = CommitlogConfig {
+            compression: Compression::Zstd,
+            ..CommitlogConfig::default()
+        };
+        let cl = Commitlog::with_config(test_dir_path, config).expect("Unable to create commitlog");
+        let repeated_payload = vec![b'a'; 200];
+        cl.append(b"first").expect("Unable to append");
+        cl.append_batch(&[&repeated_payload, &repeated_payload])
+            .expect("Unable to append batch");
+        drop(cl);
+
+        let records = dump_segment(test_dir_path, 0, None).expect("Unable to dump segment");
+        let payloads: Vec<Option<Vec<u8>>> = records.iter().map(|record| record.payload.clone()).collect();
+        assert_eq!(
+            payloads,
+            vec![Some(b"first".to_vec()), Some(repeated_payload.clone()), Some(repeated_payload)]
+        );
+        let DumpedRecord { offset, position, size, compression, .. } = &records[1];
+        assert_eq!((*offset, *compression), (1, Compression::Zstd));
+        assert_eq!(records[2].position, *position);
+        assert_eq!(records[2].size, *size);
+    }
+
+    #[test]
+    fn test_rebuild_indexes() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let test_dir_path = tmp_dir.path().to_str().unwrap();
+        create_test_commitlog(test_dir_path, Compression::None);
+        for suffix in [utils::INDEX_SUFFIX, utils::TIME_INDEX_SUFFIX] {
+            let index_path: PathBuf = [test_dir_path, &utils::segment_file_name(0, suffix)].iter().collect();
+            fs::remove_file(index_path).unwrap();
+        }
+
+        let rebuilt_segments = rebuild_indexes(test_dir_path, None).expect("Unable to rebuild indexes");
+        assert_eq!(rebuilt_segments[0], 0);
+        let reports = verify(test_dir_path).expect("Unable to verify");
+        assert!(reports.iter().all(|report| report.problems.is_empty()));
+
+        let cl = Commitlog::new(test_dir_path, 100, 1000).expect("Unable to load commitlog");
+        assert_eq!(cl.read(0).expect("Unable to read"), b"record 0");
+    }
+}
@@ -1,11 +1,31 @@
 mod utils;
 mod cleaner;
 mod commitlog;
+mod compactor;
+mod compression;
+mod config;
+mod encryption;
 mod index;
+mod inspect;
+mod manifest;
 mod nolan_errors;
+mod record;
+mod record_range;
 mod segment;
+mod storage;
+mod tiered_storage;
+mod time_index;
 mod virtual_segment;
 mod virtual_index;
 
 pub use commitlog::Commitlog;
-pub use nolan_errors::CommitlogError;
+pub use compactor::{KeyExtractor, RecordKey};
+pub use compression::Compression;
+pub use config::{CommitlogConfig, FsyncPolicy};
+pub use manifest::MANIFEST_FILE;
+pub use inspect::{dump_segment, list_segments, rebuild_indexes, verify, DumpedRecord, SegmentReport};
+pub use nolan_errors::{CommitlogError, RemoteStorageError, StorageError};
+pub use record::RecordRef;
+pub use record_range::{RecordIter, RecordRange};
+pub use storage::{FileContents, FilesystemStorage, MemoryStorage, StorageBackend, StorageFile};
+pub use tiered_storage::{FilesystemRemoteStorage, RemoteStorage};
@@ -0,0 +1,177 @@
+use crate::nolan_errors::ManifestError;
+use crate::storage::StorageBackend;
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+
+/// Name of the manifest file in the storage of a commitlog.
+pub const MANIFEST_FILE: &str = "segments.manifest";
+
+/// The state of a segment listed in the manifest.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentState {
+    /// The segment records are appended to, there's only ever one
+    Active,
+    /// A segment that's no longer appended to
+    Closed,
+    /// A copy of an offloaded segment that was fetched back from remote storage
+    Fetched,
+}
+
+/// A segment listed in the manifest.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub starting_offset: u64,
+    pub state: SegmentState,
+}
+
+/// The manifest lists the segments a commitlog has in its storage. It's written after a segment is created and before
+/// a segment is deleted, so when the commitlog is opened every closed segment in the manifest has to be in storage,
+/// and a segment in storage that's missing from the manifest was either created by a split that didn't make it into the
+/// manifest or left behind by a deletion that didn't finish.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SegmentManifest {
+    /// Every segment in offset order, the active segment is the last one
+    entries: Vec<ManifestEntry>,
+}
+
+impl SegmentManifest {
+    /// Create a manifest listing the entries, which have to be in offset order.
+    pub fn new(entries: Vec<ManifestEntry>) -> SegmentManifest {
+        SegmentManifest { entries }
+    }
+
+    /// Load the manifest from the storage, returning None if there isn't one.
+    pub fn load(backend: &dyn StorageBackend) -> Result<Option<SegmentManifest>, ManifestError> {
+        if !backend.exists(MANIFEST_FILE) {
+            return Ok(None);
+        }
+        let contents = backend.read(MANIFEST_FILE).map_err(|e| {
+            error!("{}", e);
+            ManifestError::new("unable to read manifest")
+        })?;
+        let entries = bincode::deserialize(&contents).map_err(|e| {
+            error!("{}", e);
+            ManifestError::new("unable to decode manifest")
+        })?;
+        Ok(Some(SegmentManifest { entries }))
+    }
+
+    /// Write the manifest to the storage, replacing the previous one.
+    pub fn write(&self, backend: &dyn StorageBackend) -> Result<(), ManifestError> {
+        let contents = bincode::serialize(&self.entries).map_err(|e| {
+            error!("{}", e);
+            ManifestError::new("unable to encode manifest")
+        })?;
+        backend.write(MANIFEST_FILE, &contents).map_err(|e| {
+            error!("{}", e);
+            ManifestError::new("unable to write manifest")
+        })
+    }
+
+    /// Remove the manifest from the storage, if there is one.
+    pub fn remove(backend: &dyn StorageBackend) -> Result<(), ManifestError> {
+        if !backend.exists(MANIFEST_FILE) {
+            return Ok(());
+        }
+        backend.delete(MANIFEST_FILE).map_err(|e| {
+            error!("{}", e);
+            ManifestError::new("unable to remove manifest")
+        })
+    }
+
+    /// Returns the starting offset of the active segment.
+    pub fn active_offset(&self) -> Option<u64> {
+        self.entries
+            .iter()
+            .find(|entry| entry.state == SegmentState::Active)
+            .map(|entry| entry.starting_offset)
+    }
+
+    /// Check the segments found in storage against the manifest, given the starting and next offset of every segment
+    /// in offset order. Returns the starting offsets of the segments that were left behind and should be deleted.
+    /// A closed segment in the manifest that isn't in storage is an error. Fetched segments are only copies, so they're
+    /// allowed to go missing, and a missing active segment is created again when the commitlog is opened. Segments that
+    /// aren't in the manifest are kept if they continue on from the active segment, a split creates the new segment
+    /// before it's added to the manifest.
+    pub fn validate(&self, found_segments: &[(u64, u64)]) -> Result<Vec<u64>, ManifestError> {
+        for entry in &self.entries {
+            if found_segments.iter().any(|(starting_offset, _)| *starting_offset == entry.starting_offset) {
+                continue;
+            }
+            match entry.state {
+                SegmentState::Closed => {
+                    error!("segment {} is in the manifest but not in storage", entry.starting_offset);
+                    return Err(ManifestError::new("Segment listed in the manifest is missing"));
+                }
+                SegmentState::Active => warn!("Active segment {} is missing, creating it again", entry.starting_offset),
+                SegmentState::Fetched => {}
+            }
+        }
+
+        let active_offset = self.active_offset().unwrap_or(0);
+        let mut left_behind_segments = Vec::new();
+        let mut previous_next_offset = None;
+        for (starting_offset, next_offset) in found_segments {
+            let listed = self.entries.iter().any(|entry| entry.starting_offset == *starting_offset);
+            let continues_active = *starting_offset > active_offset && previous_next_offset == Some(*starting_offset);
+            if listed || continues_active {
+                if !listed {
+                    warn!("Keeping segment {}, it was created after the manifest was written", starting_offset);
+                }
+                previous_next_offset = Some(*next_offset);
+            } else {
+                warn!("Segment {} isn't in the manifest, it was left behind by a deletion", starting_offset);
+                left_behind_segments.push(*starting_offset);
+            }
+        }
+        Ok(left_behind_segments)
+    }
+}
+
+#[cfg(test)]
+mod manifest_tests {
+    use crate::manifest::{ManifestEntry, SegmentManifest, SegmentState};
+    use crate::nolan_errors::ManifestError;
+    use crate::storage::MemoryStorage;
+
+    fn entry(starting_offset: u64, state: SegmentState) -> ManifestEntry {
+        ManifestEntry { starting_offset, state }
+    }
+
+    #[test]
+    fn test_write_and_load() {
+        let backend = MemoryStorage::new();
+        assert_eq!(None, SegmentManifest::load(&backend).expect("Unable to load manifest"));
+        let manifest = SegmentManifest::new(vec![
+            entry(0, SegmentState::Fetched),
+            entry(5, SegmentState::Closed),
+            entry(9, SegmentState::Active),
+        ]);
+        manifest.write(&backend).expect("Unable to write manifest");
+        let loaded = SegmentManifest::load(&backend).expect("Unable to load manifest");
+        assert_eq!(Some(&manifest), loaded.as_ref());
+        assert_eq!(Some(9), manifest.active_offset());
+
+        SegmentManifest::remove(&backend).expect("Unable to remove manifest");
+        assert_eq!(None, SegmentManifest::load(&backend).expect("Unable to load manifest"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let manifest = SegmentManifest::new(vec![
+            entry(0, SegmentState::Fetched),
+            entry(5, SegmentState::Closed),
+            entry(9, SegmentState::Active),
+        ]);
+        assert!(manifest.validate(&[(0, 5), (5, 9), (9, 12)]).unwrap().is_empty());
+        // Fetched copies and the active segment can go missing
+        assert!(manifest.validate(&[(5, 9)]).unwrap().is_empty());
+        assert_eq!(
+            ManifestError::new("Segment listed in the manifest is missing"),
+            manifest.validate(&[(0, 5), (9, 12)]).unwrap_err()
+        );
+        // A segment continuing on from the active segment is kept, anything else is left behind
+        assert!(manifest.validate(&[(5, 9), (9, 12), (12, 14)]).unwrap().is_empty());
+        assert_eq!(vec![2, 13], manifest.validate(&[(2, 5), (5, 9), (9, 12), (13, 14)]).unwrap());
+    }
+}
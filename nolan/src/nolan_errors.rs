@@ -102,3 +102,107 @@ impl Error for CleanerError {
         &self.details
     }
 }
+
+//------------Compactor Error--------------------
+#[derive(Debug, PartialEq)]
+pub struct CompactorError {
+    details: String,
+}
+
+impl CompactorError {
+    pub fn new(msg: &str) -> CompactorError {
+        CompactorError {
+            details: msg.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for CompactorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for CompactorError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+//------------Remote Storage Error--------------------
+#[derive(Debug, PartialEq)]
+pub struct RemoteStorageError {
+    details: String,
+}
+
+impl RemoteStorageError {
+    pub fn new(msg: &str) -> RemoteStorageError {
+        RemoteStorageError {
+            details: msg.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for RemoteStorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for RemoteStorageError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+//------------Storage Error--------------------
+#[derive(Debug, PartialEq)]
+pub struct StorageError {
+    details: String,
+}
+
+impl StorageError {
+    pub fn new(msg: &str) -> StorageError {
+        StorageError {
+            details: msg.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for StorageError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+//------------Manifest Error--------------------
+#[derive(Debug, PartialEq)]
+pub struct ManifestError {
+    details: String,
+}
+
+impl ManifestError {
+    pub fn new(msg: &str) -> ManifestError {
+        ManifestError {
+            details: msg.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for ManifestError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
@@ -0,0 +1,648 @@
+use crate::compression::{Compression, COMPRESSION_MASK};
+use crate::encryption::{Keyring, ENCRYPTED};
+use crate::index::{Entry, IndexLookup};
+use crate::nolan_errors::SegmentError;
+use crate::storage::StorageBackend;
+use std::borrow::Cow;
+
+/// Magic bytes at the start of every log file written in the record format.
+pub const LOG_MAGIC: &[u8; 4] = b"NOLN";
+/// Version of the record format, stored right after the magic bytes.
+pub const LOG_VERSION: u8 = 1;
+/// Size of the log file header, the magic and version padded out to 8 bytes.
+pub const LOG_HEADER_SIZE: usize = 8;
+/// Size of the length and crc fields that frame every record.
+const FRAME_SIZE: usize = 8;
+/// Size of the attributes, offset and timestamp fields that follow the frame.
+const METADATA_SIZE: usize = 17;
+/// Total bytes a record takes on disk on top of its payload.
+pub const RECORD_HEADER_SIZE: usize = FRAME_SIZE + METADATA_SIZE;
+/// Attribute flag set on every record of a batch except the last, so a batch that was only partly written can be
+/// dropped as a whole.
+pub const BATCH_CONTINUES: u8 = 0b1000_0000;
+/// Size of the delta to the last offset that a compressed batch payload starts with.
+const LAST_OFFSET_DELTA_SIZE: usize = 4;
+
+/// A record is a single entry stored in the log file. On disk a record is laid out as
+/// `[length u32][crc32c u32][attributes u8][offset u64][timestamp u64][payload]`, where the length
+/// covers everything after the crc and the crc covers everything after itself.
+///
+/// A batch that was compressed is stored as a single record, with the codec in its attributes and the offset of the
+/// first record of the batch. Its payload is `[last offset delta u32][compressed records]`, where the compressed
+/// records are the records of the batch encoded like any other record.
+///
+/// Records written with a keyring have their payload encrypted, which is flagged in their attributes. For a compressed
+/// batch only the compressed records are encrypted, the last offset delta stays readable for recovery.
+#[derive(Debug, PartialEq)]
+pub struct Record {
+    /// Flags describing the record and its payload
+    pub attributes: u8,
+    /// The commitlog offset the record was written at
+    pub offset: u64,
+    /// Time the record was appended, in milliseconds since the unix epoch
+    pub timestamp: u64,
+    /// The user supplied data
+    pub payload: Vec<u8>,
+}
+
+/// A record borrowed from the log it's stored in, so it can be read without copying the payload. Records that were
+/// decompressed out of a compressed batch own their payload instead.
+#[derive(Debug, PartialEq)]
+pub struct RecordRef<'a> {
+    /// Flags describing the record and its payload
+    pub attributes: u8,
+    /// The commitlog offset the record was written at
+    pub offset: u64,
+    /// Time the record was appended, in milliseconds since the unix epoch
+    pub timestamp: u64,
+    /// The user supplied data
+    pub payload: Cow<'a, [u8]>,
+}
+
+impl From<RecordRef<'_>> for Record {
+    fn from(record: RecordRef<'_>) -> Record {
+        Record {
+            attributes: record.attributes,
+            offset: record.offset,
+            timestamp: record.timestamp,
+            payload: record.payload.into_owned(),
+        }
+    }
+}
+
+/// Return the header that every log file starts with.
+pub fn log_header() -> [u8; LOG_HEADER_SIZE] {
+    let mut header = [0; LOG_HEADER_SIZE];
+    header[..LOG_MAGIC.len()].copy_from_slice(LOG_MAGIC);
+    header[LOG_MAGIC.len()] = LOG_VERSION;
+    header
+}
+
+/// Check if the log file contents start with a nolan log header. Returns false for logs written
+/// before the record format existed, and an error if the header is for a version we can't read.
+pub fn has_log_header(log_contents: &[u8]) -> Result<bool, SegmentError> {
+    if log_contents.len() < LOG_HEADER_SIZE || &log_contents[..LOG_MAGIC.len()] != LOG_MAGIC {
+        return Ok(false);
+    }
+    if log_contents[LOG_MAGIC.len()] != LOG_VERSION {
+        return Err(SegmentError::new("unsupported log file version"));
+    }
+    Ok(true)
+}
+
+/// Check if the file in the storage backend starts with a nolan log header.
+pub fn file_has_log_header(backend: &dyn StorageBackend, file_name: &str) -> bool {
+    match backend.map(file_name) {
+        Ok(contents) => has_log_header((*contents).as_ref()).unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+/// Return the amount of bytes a record with the given payload size takes on disk.
+pub fn encoded_len(payload_len: usize) -> usize {
+    RECORD_HEADER_SIZE + payload_len
+}
+
+/// Encode a record and append it onto the end of the buffer.
+pub fn encode_record(buffer: &mut Vec<u8>, offset: u64, timestamp: u64, payload: &[u8]) -> Result<(), SegmentError> {
+    encode_record_with_attributes(buffer, 0, offset, timestamp, payload)
+}
+
+/// Encode a record with the given attributes and append it onto the end of the buffer.
+pub fn encode_record_with_attributes(
+    buffer: &mut Vec<u8>,
+    attributes: u8,
+    offset: u64,
+    timestamp: u64,
+    payload: &[u8],
+) -> Result<(), SegmentError> {
+    let length = u32::try_from(METADATA_SIZE + payload.len())
+        .map_err(|_e| SegmentError::new("record is too large to encode"))?;
+    let body_start = buffer.len() + FRAME_SIZE;
+    buffer.extend_from_slice(&length.to_le_bytes());
+    // Placeholder for the crc, it gets filled in once the body is written
+    buffer.extend_from_slice(&[0; 4]);
+    buffer.push(attributes);
+    buffer.extend_from_slice(&offset.to_le_bytes());
+    buffer.extend_from_slice(&timestamp.to_le_bytes());
+    buffer.extend_from_slice(payload);
+    let crc = crc32c::crc32c(&buffer[body_start..]);
+    buffer[body_start - 4..body_start].copy_from_slice(&crc.to_le_bytes());
+    Ok(())
+}
+
+/// Encode a record with the given attributes and append it onto the end of the buffer. The payload is encrypted with
+/// the active key if a keyring is given.
+pub fn encode_sealed_record(
+    buffer: &mut Vec<u8>,
+    attributes: u8,
+    offset: u64,
+    timestamp: u64,
+    payload: &[u8],
+    keyring: Option<&Keyring>,
+) -> Result<(), SegmentError> {
+    match keyring {
+        Some(keyring) => {
+            let encrypted_payload = keyring.encrypt(offset, payload)?;
+            encode_record_with_attributes(buffer, attributes | ENCRYPTED, offset, timestamp, &encrypted_payload)
+        }
+        None => encode_record_with_attributes(buffer, attributes, offset, timestamp, payload),
+    }
+}
+
+/// Given the bytes at the start of a record, return the total size of that record if the bytes
+/// hold a complete record with a valid checksum.
+fn checked_record_len(bytes: &[u8]) -> Result<usize, SegmentError> {
+    if bytes.len() < FRAME_SIZE {
+        return Err(SegmentError::new("record frame is incomplete"));
+    }
+    let length = u32::from_le_bytes(bytes[0..4].try_into().unwrap()) as usize;
+    if length < METADATA_SIZE {
+        return Err(SegmentError::new("record length is invalid"));
+    }
+    let total = FRAME_SIZE + length;
+    if bytes.len() < total {
+        return Err(SegmentError::new("record is incomplete"));
+    }
+    let stored_crc = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+    if crc32c::crc32c(&bytes[FRAME_SIZE..total]) != stored_crc {
+        return Err(SegmentError::new("record failed crc check"));
+    }
+    Ok(total)
+}
+
+/// Read the offset out of a record, the record must already have been checked.
+pub fn record_offset(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[9..17].try_into().unwrap())
+}
+
+/// Decode the record at the start of the bytes without copying its payload, verifying its checksum. Returns the
+/// record along with the total amount of bytes it takes in the log.
+pub fn decode_record_ref(bytes: &[u8]) -> Result<(RecordRef<'_>, usize), SegmentError> {
+    let total = checked_record_len(bytes)?;
+    let record = RecordRef {
+        attributes: bytes[8],
+        offset: record_offset(bytes),
+        timestamp: u64::from_le_bytes(bytes[17..25].try_into().unwrap()),
+        payload: Cow::Borrowed(&bytes[RECORD_HEADER_SIZE..total]),
+    };
+    Ok((record, total))
+}
+
+/// Compress the records of a batch with the codec and append them onto the end of the buffer as a single record. The
+/// compressed records are encrypted with the active key if a keyring is given.
+pub fn encode_compressed_batch(
+    buffer: &mut Vec<u8>,
+    compression: Compression,
+    offset: u64,
+    timestamp: u64,
+    batch: &[&[u8]],
+    keyring: Option<&Keyring>,
+) -> Result<(), SegmentError> {
+    let last_offset_delta = batch
+        .len()
+        .checked_sub(1)
+        .and_then(|delta| u32::try_from(delta).ok())
+        .ok_or_else(|| SegmentError::new("unable to compress batch"))?;
+    let mut batch_records = Vec::new();
+    for (i, data) in batch.iter().enumerate() {
+        encode_record(&mut batch_records, offset + i as u64, timestamp, data)?;
+    }
+    let mut payload = last_offset_delta.to_le_bytes().to_vec();
+    let compressed_records = compression.compress(&batch_records)?;
+    match keyring {
+        Some(keyring) => {
+            payload.extend_from_slice(&keyring.encrypt(offset, &compressed_records)?);
+            encode_record_with_attributes(buffer, compression.attributes() | ENCRYPTED, offset, timestamp, &payload)
+        }
+        None => {
+            payload.extend_from_slice(&compressed_records);
+            encode_record_with_attributes(buffer, compression.attributes(), offset, timestamp, &payload)
+        }
+    }
+}
+
+/// Check if a record has to be decompressed or decrypted before its payload can be read.
+pub fn is_sealed(attributes: u8) -> bool {
+    attributes & (COMPRESSION_MASK | ENCRYPTED) != 0
+}
+
+/// Return the bytes of a payload that was written at the offset, decrypting them if the record is encrypted.
+fn open_payload<'a>(
+    attributes: u8,
+    offset: u64,
+    payload: &'a [u8],
+    keyring: Option<&Keyring>,
+) -> Result<Cow<'a, [u8]>, SegmentError> {
+    if attributes & ENCRYPTED == 0 {
+        return Ok(Cow::Borrowed(payload));
+    }
+    let keyring = keyring.ok_or_else(|| SegmentError::new("record is encrypted and no keys were loaded"))?;
+    Ok(Cow::Owned(keyring.decrypt(offset, payload)?))
+}
+
+/// Return the records stored in a record. Encrypted payloads are decrypted and a compressed batch is decompressed into
+/// the records of the batch, any other record is returned as it is.
+pub fn unpack_records(record: RecordRef<'_>, keyring: Option<&Keyring>) -> Result<Vec<Record>, SegmentError> {
+    let compression = Compression::from_attributes(record.attributes)?;
+    if compression == Compression::None {
+        let payload = open_payload(record.attributes, record.offset, &record.payload, keyring)?.into_owned();
+        return Ok(vec![Record {
+            attributes: record.attributes & !ENCRYPTED,
+            offset: record.offset,
+            timestamp: record.timestamp,
+            payload,
+        }]);
+    }
+    let last_offset_delta = record
+        .payload
+        .get(..LAST_OFFSET_DELTA_SIZE)
+        .map(|delta_bytes| u32::from_le_bytes(delta_bytes.try_into().unwrap()))
+        .ok_or_else(|| SegmentError::new("compressed batch is incomplete"))?;
+    let compressed_records = open_payload(
+        record.attributes,
+        record.offset,
+        &record.payload[LAST_OFFSET_DELTA_SIZE..],
+        keyring,
+    )?;
+    let batch_records = compression.decompress(&compressed_records)?;
+    let mut records = Vec::new();
+    let mut position = 0;
+    while position < batch_records.len() {
+        let (batch_record, total) = decode_record_ref(&batch_records[position..])?;
+        records.push(Record::from(batch_record));
+        position += total;
+    }
+    let last_offset = record.offset + u64::from(last_offset_delta);
+    if records.last().map(|last_record| last_record.offset) != Some(last_offset) {
+        return Err(SegmentError::new("compressed batch does not match its header"));
+    }
+    Ok(records)
+}
+
+/// Return the record with the offset out of the records stored in a record, or None if it doesn't hold the offset.
+fn unpack_record(record: RecordRef<'_>, offset: u64, keyring: Option<&Keyring>) -> Result<Option<Record>, SegmentError> {
+    Ok(unpack_records(record, keyring)?
+        .into_iter()
+        .find(|unpacked_record| unpacked_record.offset == offset))
+}
+
+/// Read the timestamp out of the record at the start of the bytes, without verifying its checksum.
+pub fn record_timestamp(bytes: &[u8]) -> Result<u64, SegmentError> {
+    match bytes.get(17..25) {
+        Some(timestamp_bytes) => Ok(u64::from_le_bytes(timestamp_bytes.try_into().unwrap())),
+        None => Err(SegmentError::new("record is incomplete")),
+    }
+}
+
+/// Read the offset of the last record stored in the record at the start of the bytes, which is the offset of the
+/// record itself unless it's a compressed batch. The checksum of the record isn't verified.
+pub fn record_last_offset(bytes: &[u8]) -> Result<u64, SegmentError> {
+    let offset = record_offset(bytes);
+    if bytes[8] & COMPRESSION_MASK == 0 {
+        return Ok(offset);
+    }
+    match bytes.get(RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + LAST_OFFSET_DELTA_SIZE) {
+        Some(delta_bytes) => Ok(offset + u64::from(u32::from_le_bytes(delta_bytes.try_into().unwrap()))),
+        None => Err(SegmentError::new("compressed batch is incomplete")),
+    }
+}
+
+/// Read the total size of the record at the start of the bytes from its length field, without verifying its checksum.
+pub fn unchecked_record_len(bytes: &[u8]) -> Result<usize, SegmentError> {
+    if bytes.len() < RECORD_HEADER_SIZE {
+        return Err(SegmentError::new("record is incomplete"));
+    }
+    Ok(FRAME_SIZE + u32::from_le_bytes(bytes[0..4].try_into().unwrap()) as usize)
+}
+
+/// Scan forward from the position in the log for the record with the given offset. Returns None if the log
+/// doesn't have a record for the offset, which is the case for offsets removed by compaction.
+pub fn find_record(
+    log_contents: &[u8],
+    position: usize,
+    offset: u64,
+    keyring: Option<&Keyring>,
+) -> Result<Option<Record>, SegmentError> {
+    let mut position = position;
+    while let Some(remaining) = log_contents.get(position..).filter(|remaining| !remaining.is_empty()) {
+        let total = unchecked_record_len(remaining)?;
+        if record_offset(remaining) > offset {
+            return Ok(None);
+        }
+        if record_last_offset(remaining)? >= offset {
+            let (record, _total) = decode_record_ref(remaining)?;
+            return unpack_record(record, offset, keyring);
+        }
+        position += total;
+    }
+    Ok(None)
+}
+
+/// Find the position of the first record in the log at or after the offset, given where the index says the offset
+/// is. If the offset is in a compressed batch, the position of the batch is returned. Returns the length of the log
+/// if there is no such record.
+pub fn seek_record(log_contents: &[u8], lookup: IndexLookup, offset: u64) -> Result<usize, SegmentError> {
+    let mut position = match lookup {
+        IndexLookup::Record { start, .. } => return Ok(start as usize),
+        IndexLookup::Removed { next_start } => return Ok(next_start as usize),
+        IndexLookup::ScanFrom(position) => position as usize,
+    };
+    while let Some(remaining) = log_contents.get(position..).filter(|remaining| !remaining.is_empty()) {
+        let total = unchecked_record_len(remaining)?;
+        if record_last_offset(remaining)? >= offset {
+            return Ok(position);
+        }
+        position += total;
+    }
+    Ok(log_contents.len())
+}
+
+/// Read the record for the offset out of the log, using the result of looking the offset up in the index.
+pub fn read_indexed_record(
+    log_contents: &[u8],
+    lookup: IndexLookup,
+    offset: u64,
+    keyring: Option<&Keyring>,
+) -> Result<Record, SegmentError> {
+    let record = match lookup {
+        IndexLookup::Record { start, total } => {
+            let start = start as usize;
+            let record_bytes = log_contents
+                .get(start..start + total)
+                .ok_or_else(|| SegmentError::new("index entry is outside of the log"))?;
+            let (record, _total) = decode_record_ref(record_bytes)?;
+            unpack_record(record, offset, keyring)?
+                .ok_or_else(|| SegmentError::new("record offset does not match the index"))?
+        }
+        IndexLookup::Removed { .. } => return Err(SegmentError::new("offset was removed by compaction")),
+        IndexLookup::ScanFrom(position) => find_record(log_contents, position as usize, offset, keyring)?
+            .ok_or_else(|| SegmentError::new("offset was removed by compaction"))?,
+    };
+    if record.offset != offset {
+        return Err(SegmentError::new("record offset does not match the index"));
+    }
+    Ok(record)
+}
+
+/// Return the records stored in a log that was already checked by recovery, given the index entries from scanning it.
+/// Compressed batches are decompressed and encrypted records decrypted, so every record is returned on its own.
+pub fn unpack_log_records(
+    log_contents: &[u8],
+    entries: &[Entry],
+    keyring: Option<&Keyring>,
+) -> Result<Vec<Record>, SegmentError> {
+    let mut records = Vec::new();
+    for_each_log_record(log_contents, entries, keyring, |record| records.push(record))?;
+    Ok(records)
+}
+
+/// Hand every record stored in a log that was already checked by recovery to the visitor in offset order, without
+/// holding more than one compressed batch in memory at a time.
+pub fn for_each_log_record(
+    log_contents: &[u8],
+    entries: &[Entry],
+    keyring: Option<&Keyring>,
+    mut visit: impl FnMut(Record),
+) -> Result<(), SegmentError> {
+    let mut last_start = None;
+    for entry in entries.iter().filter(|entry| entry.total > 0) {
+        // Every offset of a compressed batch has an entry for the same record
+        if last_start == Some(entry.start) {
+            continue;
+        }
+        last_start = Some(entry.start);
+        let start = entry.start as usize;
+        let record_bytes = log_contents
+            .get(start..start + entry.total as usize)
+            .ok_or_else(|| SegmentError::new("index entry is outside of the log"))?;
+        let (record, _total) = decode_record_ref(record_bytes)?;
+        unpack_records(record, keyring)?.into_iter().for_each(&mut visit);
+    }
+    Ok(())
+}
+
+/// Walk the records of a log that was already checked by recovery, returning the offset of the last record and the
+/// newest timestamp of all the records. Returns None if the log has no records.
+pub fn last_offset_and_max_timestamp(log_contents: &[u8]) -> Result<Option<(u64, u64)>, SegmentError> {
+    let mut position = LOG_HEADER_SIZE.min(log_contents.len());
+    let mut last_record = None;
+    while position < log_contents.len() {
+        let remaining = &log_contents[position..];
+        let total = unchecked_record_len(remaining)?;
+        let max_timestamp = last_record.map(|(_, timestamp)| timestamp).unwrap_or(0);
+        last_record = Some((record_last_offset(remaining)?, max_timestamp.max(record_timestamp(remaining)?)));
+        position += total;
+    }
+    if position > log_contents.len() {
+        return Err(SegmentError::new("record is incomplete"));
+    }
+    Ok(last_record)
+}
+
+/// Walk the records of a log file, starting after the log header. The scan stops at the first record that is
+/// incomplete, fails its checksum or is out of sequence. Returns an index entry for every valid record along with
+/// the amount of bytes at the start of the log that are valid. Offsets skipped by the records(because they were
+/// removed by compaction) get an empty entry, so the entries stay dense. Every offset in a compressed batch gets an
+/// entry for the batch. Records of a batch that is missing its last record are left out, a batch is either valid as
+/// a whole or not at all.
+pub fn scan_records(log_contents: &[u8], starting_offset: u64) -> (Vec<Entry>, usize) {
+    let mut entries = Vec::new();
+    let mut position = LOG_HEADER_SIZE.min(log_contents.len());
+    // The entries length and position at the start of the batch that hasn't seen its last record yet
+    let mut open_batch: Option<(usize, usize)> = None;
+    while position < log_contents.len() {
+        let remaining = &log_contents[position..];
+        let total = match checked_record_len(remaining) {
+            Ok(total) => total,
+            Err(_) => break,
+        };
+        let offset = record_offset(remaining);
+        let last_offset = match record_last_offset(remaining) {
+            Ok(last_offset) => last_offset,
+            Err(_) => break,
+        };
+        let expected_offset = starting_offset + entries.len() as u64;
+        if offset < expected_offset {
+            break;
+        }
+        let (start, size) = match (u32::try_from(position), u32::try_from(total)) {
+            (Ok(start), Ok(size)) => (start, size),
+            _ => break,
+        };
+        if remaining[8] & BATCH_CONTINUES == 0 {
+            open_batch = None;
+        } else if open_batch.is_none() {
+            open_batch = Some((entries.len(), position));
+        }
+        for _ in expected_offset..offset {
+            entries.push(Entry { start, total: 0 });
+        }
+        for _ in offset..=last_offset {
+            entries.push(Entry { start, total: size });
+        }
+        position += total;
+    }
+    if let Some((batch_entries, batch_position)) = open_batch {
+        entries.truncate(batch_entries);
+        position = batch_position;
+    }
+    (entries, position)
+}
+
+#[cfg(test)]
+mod record_tests {
+    use crate::compression::Compression;
+    use crate::index::IndexLookup;
+    use crate::nolan_errors::SegmentError;
+    use crate::record;
+
+    fn create_log(starting_offset: u64, messages: &[&str]) -> Vec<u8> {
+        let mut log = record::log_header().to_vec();
+        for (i, message) in messages.iter().enumerate() {
+            record::encode_record(&mut log, starting_offset + i as u64, 1000, message.as_bytes())
+                .expect("Unable to encode record");
+        }
+        log
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let mut buffer = Vec::new();
+        record::encode_record(&mut buffer, 42, 1000, "hello".as_bytes()).expect("Unable to encode record");
+        assert_eq!(record::encoded_len(5), buffer.len());
+
+        let (decoded, total) = record::decode_record_ref(&buffer).expect("Unable to decode record");
+        assert_eq!(buffer.len(), total);
+        assert_eq!(42, decoded.offset);
+        assert_eq!(1000, decoded.timestamp);
+        assert_eq!(0, decoded.attributes);
+        assert_eq!("hello".as_bytes(), &*decoded.payload);
+    }
+
+    #[test]
+    fn test_decode_corrupt_record() {
+        let mut buffer = Vec::new();
+        record::encode_record(&mut buffer, 0, 1000, "hello".as_bytes()).expect("Unable to encode record");
+        let last = buffer.len() - 1;
+        buffer[last] ^= 0xff;
+        let segment_error = record::decode_record_ref(&buffer).unwrap_err();
+        assert_eq!(SegmentError::new("record failed crc check"), segment_error);
+    }
+
+    #[test]
+    fn test_has_log_header() {
+        assert!(record::has_log_header(&record::log_header()).unwrap());
+        assert!(!record::has_log_header("hello world".as_bytes()).unwrap());
+        let mut header = record::log_header();
+        header[4] = record::LOG_VERSION + 1;
+        assert!(record::has_log_header(&header).is_err());
+    }
+
+    #[test]
+    fn test_scan_records() {
+        let log = create_log(10, &["hello", "world"]);
+        let (entries, valid_len) = record::scan_records(&log, 10);
+        assert_eq!(2, entries.len());
+        assert_eq!(record::LOG_HEADER_SIZE as u32, entries[0].start);
+        assert_eq!(record::encoded_len(5) as u32, entries[1].total);
+        assert_eq!(log.len(), valid_len);
+    }
+
+    #[test]
+    fn test_scan_records_torn_write() {
+        let log = create_log(0, &["hello", "world"]);
+        let torn_log = &log[..log.len() - 3];
+        let (entries, valid_len) = record::scan_records(torn_log, 0);
+        assert_eq!(1, entries.len());
+        assert_eq!(record::LOG_HEADER_SIZE + record::encoded_len(5), valid_len);
+    }
+
+    #[test]
+    fn test_scan_records_partial_batch() {
+        let mut log = create_log(0, &["hello"]);
+        // A complete batch of two records followed by a batch whose last record never made it to the log
+        record::encode_record_with_attributes(&mut log, record::BATCH_CONTINUES, 1, 1000, "world".as_bytes()).unwrap();
+        record::encode_record(&mut log, 2, 1000, "world".as_bytes()).unwrap();
+        let complete_len = log.len();
+        record::encode_record_with_attributes(&mut log, record::BATCH_CONTINUES, 3, 1000, "again".as_bytes()).unwrap();
+        record::encode_record_with_attributes(&mut log, record::BATCH_CONTINUES, 4, 1000, "again".as_bytes()).unwrap();
+        let (entries, valid_len) = record::scan_records(&log, 0);
+        assert_eq!(3, entries.len());
+        assert_eq!(complete_len, valid_len);
+    }
+
+    #[test]
+    fn test_scan_records_stops_at_corruption() {
+        let mut log = create_log(0, &["hello", "world", "again"]);
+        // Flip a payload byte in the second record
+        let second_record_end = record::LOG_HEADER_SIZE + 2 * record::encoded_len(5);
+        log[second_record_end - 1] ^= 0xff;
+        let (entries, valid_len) = record::scan_records(&log, 0);
+        assert_eq!(1, entries.len());
+        assert_eq!(record::LOG_HEADER_SIZE + record::encoded_len(5), valid_len);
+    }
+
+    #[test]
+    fn test_scan_records_out_of_sequence() {
+        let log = create_log(5, &["hello"]);
+        let (entries, valid_len) = record::scan_records(&log, 10);
+        assert!(entries.is_empty());
+        assert_eq!(record::LOG_HEADER_SIZE, valid_len);
+    }
+
+    #[test]
+    fn test_scan_records_with_holes() {
+        let mut log = create_log(0, &["hello"]);
+        record::encode_record(&mut log, 3, 1000, "world".as_bytes()).expect("Unable to encode record");
+        let (entries, valid_len) = record::scan_records(&log, 0);
+        assert_eq!(4, entries.len());
+        assert_eq!(0, entries[1].total);
+        assert_eq!(0, entries[2].total);
+        assert_eq!(record::encoded_len(5) as u32, entries[3].total);
+        assert_eq!(log.len(), valid_len);
+    }
+
+    #[test]
+    fn test_find_record() {
+        let mut log = record::log_header().to_vec();
+        for offset in [0, 1, 3, 4] {
+            record::encode_record(&mut log, offset, 1000 + offset, "hello".as_bytes()).expect("Unable to encode record");
+        }
+        let start = record::LOG_HEADER_SIZE;
+
+        let found = record::find_record(&log, start, 3, None).expect("Unable to find record").unwrap();
+        assert_eq!(3, found.offset);
+        // Offset 2 was removed, the scan stops at the record after it
+        assert_eq!(None, record::find_record(&log, start, 2, None).unwrap());
+        assert_eq!(None, record::find_record(&log, start, 5, None).unwrap());
+        assert_eq!(Some((4, 1004)), record::last_offset_and_max_timestamp(&log).unwrap());
+        assert_eq!(None, record::last_offset_and_max_timestamp(&record::log_header()).unwrap());
+    }
+
+    #[test]
+    fn test_compressed_batch() {
+        let mut log = create_log(0, &["hello"]);
+        let batch_start = log.len();
+        let batch = ["hello world"; 10].map(|message| message.as_bytes());
+        record::encode_compressed_batch(&mut log, Compression::Zstd, 1, 1000, &batch, None).unwrap();
+        record::encode_record(&mut log, 11, 1000, "again".as_bytes()).unwrap();
+
+        // Every offset in the batch gets an entry for the compressed record
+        let (entries, valid_len) = record::scan_records(&log, 0);
+        assert_eq!(12, entries.len());
+        assert!(entries[1..11].iter().all(|entry| entry.start == batch_start as u32));
+        assert_eq!(log.len(), valid_len);
+        assert_eq!(Some((11, 1000)), record::last_offset_and_max_timestamp(&log).unwrap());
+
+        let found = record::find_record(&log, record::LOG_HEADER_SIZE, 5, None).unwrap().unwrap();
+        assert_eq!(5, found.offset);
+        assert_eq!("hello world".as_bytes(), &*found.payload);
+        let lookup = IndexLookup::ScanFrom(record::LOG_HEADER_SIZE as u64);
+        assert_eq!(batch_start, record::seek_record(&log, lookup, 5).unwrap());
+
+        let records = record::unpack_log_records(&log, &entries, None).unwrap();
+        let offsets: Vec<u64> = records.iter().map(|unpacked_record| unpacked_record.offset).collect();
+        assert_eq!((0..12).collect::<Vec<u64>>(), offsets);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::encryption::Keyring;
+use crate::nolan_errors::CommitlogError;
+use crate::record::{self, Record, RecordRef};
+use crate::segment::Segment;
+use log::error;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// The log of a segment that a range reads from, starting at the first record of the range in the segment.
+pub(crate) enum SegmentLog {
+    /// Closed segments are held on to, so they stay readable even if they're removed while the range is read
+    Closed { segment: Arc<Segment>, position: usize },
+    /// Records copied out of the current segment, so readers don't hold it while it's appended to
+    Copied(Vec<u8>),
+}
+
+impl SegmentLog {
+    fn bytes(&self) -> &[u8] {
+        match self {
+            SegmentLog::Closed { segment, position } => &segment.log()[*position..],
+            SegmentLog::Copied(contents) => contents,
+        }
+    }
+}
+
+/// A range of records in the commitlog, read with `iter`. The range holds on to the segments it spans instead of
+/// borrowing the commitlog, so it can be read while records are appended to the commitlog. Records appended after the
+/// range was read aren't part of it.
+pub struct RecordRange {
+    segment_logs: Vec<SegmentLog>,
+    start_offset: u64,
+    end_offset: u64,
+    keyring: Option<Arc<Keyring>>,
+    max_records: usize,
+    max_bytes: usize,
+}
+
+impl RecordRange {
+    /// Create a range over the given segment logs, which have to be in offset order. Records at or after the end
+    /// offset are left out of the range.
+    pub(crate) fn new(
+        segment_logs: Vec<SegmentLog>,
+        start_offset: u64,
+        end_offset: u64,
+        max_records: usize,
+        max_bytes: usize,
+        keyring: Option<Arc<Keyring>>,
+    ) -> RecordRange {
+        RecordRange {
+            segment_logs,
+            start_offset,
+            end_offset,
+            keyring,
+            max_records,
+            max_bytes,
+        }
+    }
+
+    /// Iterate over the records in the range.
+    pub fn iter(&self) -> RecordIter<'_> {
+        RecordIter::new(
+            self.segment_logs.iter().map(SegmentLog::bytes).collect(),
+            self.start_offset,
+            self.end_offset,
+            self.max_records,
+            self.max_bytes,
+            self.keyring.as_deref(),
+        )
+    }
+}
+
+impl<'a> IntoIterator for &'a RecordRange {
+    type Item = Result<RecordRef<'a>, CommitlogError>;
+    type IntoIter = RecordIter<'a>;
+
+    fn into_iter(self) -> RecordIter<'a> {
+        self.iter()
+    }
+}
+
+/// An iterator over a range of records in the commitlog. Records are borrowed straight from the logs of the segments
+/// the range spans, so no record is copied while iterating unless it has to be decrypted or decompressed out of a
+/// compressed batch. The range stops once it has returned the max amount of records, or once the next record would
+/// take the payloads returned past the max bytes. The first record is always returned, even if it's larger than the
+/// max bytes, so readers never get stuck on a large record.
+pub struct RecordIter<'a> {
+    /// The bytes of every segment in the range, starting at the first record of the range in each segment
+    segment_logs: Vec<&'a [u8]>,
+    segment_index: usize,
+    position: usize,
+    /// Records of a compressed batch that haven't been returned yet
+    batch_records: VecDeque<Record>,
+    /// Records before this offset are skipped, a segment log can start at a compressed batch with older records
+    start_offset: u64,
+    /// The range ends before this offset, records written after the range was read are left out
+    end_offset: u64,
+    /// Keys to decrypt encrypted records with
+    keyring: Option<&'a Keyring>,
+    remaining_records: usize,
+    remaining_bytes: usize,
+    returned_records: usize,
+}
+
+impl<'a> RecordIter<'a> {
+    fn new(
+        segment_logs: Vec<&'a [u8]>,
+        start_offset: u64,
+        end_offset: u64,
+        max_records: usize,
+        max_bytes: usize,
+        keyring: Option<&'a Keyring>,
+    ) -> RecordIter<'a> {
+        RecordIter {
+            segment_logs,
+            segment_index: 0,
+            position: 0,
+            batch_records: VecDeque::new(),
+            start_offset,
+            end_offset,
+            keyring,
+            remaining_records: max_records,
+            remaining_bytes: max_bytes,
+            returned_records: 0,
+        }
+    }
+
+    /// Return the next record stored in the segment logs, unpacking encrypted records and compressed batches as
+    /// they're reached.
+    fn next_record(&mut self) -> Option<Result<RecordRef<'a>, CommitlogError>> {
+        loop {
+            if let Some(batch_record) = self.batch_records.pop_front() {
+                return Some(Ok(RecordRef {
+                    attributes: batch_record.attributes,
+                    offset: batch_record.offset,
+                    timestamp: batch_record.timestamp,
+                    payload: Cow::Owned(batch_record.payload),
+                }));
+            }
+            let mut segment_log = *self.segment_logs.get(self.segment_index)?;
+            while self.position >= segment_log.len() {
+                self.segment_index += 1;
+                self.position = 0;
+                segment_log = *self.segment_logs.get(self.segment_index)?;
+            }
+            let (record, total) = match record::decode_record_ref(&segment_log[self.position..]) {
+                Ok(decoded_record) => decoded_record,
+                Err(e) => {
+                    error!("{}", e);
+                    return Some(Err(CommitlogError::new("Unable to decode record in range")));
+                }
+            };
+            self.position += total;
+            if !record::is_sealed(record.attributes) {
+                return Some(Ok(record));
+            }
+            match record::unpack_records(record, self.keyring) {
+                Ok(batch_records) => self.batch_records.extend(batch_records),
+                Err(e) => {
+                    error!("{}", e);
+                    return Some(Err(CommitlogError::new("Unable to unpack records in range")));
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for RecordIter<'a> {
+    type Item = Result<RecordRef<'a>, CommitlogError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.remaining_records > 0 {
+            let record = match self.next_record()? {
+                Ok(record) => record,
+                Err(e) => {
+                    self.remaining_records = 0;
+                    return Some(Err(e));
+                }
+            };
+            if record.offset < self.start_offset {
+                continue;
+            }
+            if record.offset >= self.end_offset {
+                self.remaining_records = 0;
+                return None;
+            }
+            if record.payload.len() > self.remaining_bytes && self.returned_records > 0 {
+                self.remaining_records = 0;
+                return None;
+            }
+            self.remaining_records -= 1;
+            self.remaining_bytes = self.remaining_bytes.saturating_sub(record.payload.len());
+            self.returned_records += 1;
+            return Some(Ok(record));
+        }
+        None
+    }
+}
@@ -1,215 +1,567 @@
-use log::error;
-use std::fs;
-use std::fs::File;
-use std::fs::OpenOptions;
-use std::io::prelude::*;
-use std::io::SeekFrom;
+use log::{error, warn};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use crate::encryption::Keyring;
+use crate::record::{self, Record};
+use crate::storage::{FileContents, StorageBackend};
 use crate::utils;
-use crate::index::Index;
+use crate::index::{Entry, Index, IndexEntries};
 use crate::nolan_errors::SegmentError;
+use crate::time_index::{self, TimeIndex};
 
 /// Segment is a data type that holds all of the byte data within the commitlog in nolan.
-/// It is made up of 2 main pieces the log and the index. The log is what actually
+/// It is made up of 3 main pieces the log, the index and the time index. The log is what actually
 /// user supplied data is stored. The index is used to quickly retrieve information
-/// that is persisted.
+/// that is persisted and the time index finds records by when they were appended.
+/// Segments are closed, so the log is memory mapped rather than read onto the heap.
 pub struct Segment {
-    /// The file path to the log file
+    /// The file name of the log file in storage
     pub file_name: String,
-    /// Current position of the cursor within the log file
+    /// Size of the log file in bytes, closed segments are never appended to
     pub position: u32,
     /// The Starting offset within the segment
-    pub starting_offset: u16,
+    pub starting_offset: u64,
     /// Next offset within the segment
-    pub next_offset: u16,
-    /// Parent directory/Commitlog directory
-    pub directory: String,
-    /// File ref to the segment file
-    log_file: File,
+    pub next_offset: u64,
+    /// The newest timestamp of all the records in the segment
+    pub max_timestamp: u64,
+    /// Read only contents of the log file, memory mapped when the segment is on disk
+    log: FileContents,
     /// Index ref to the index file
     index: Index,
+    /// Time index ref to the time index file
+    time_index: TimeIndex,
+    /// Keys to decrypt encrypted records with, and to encrypt records with when the segment is rewritten
+    keyring: Option<Arc<Keyring>>,
+    /// Storage backend the segment files live in
+    backend: Arc<dyn StorageBackend>,
 }
 
 
 impl Segment {
-    /// Given a directory and the base name of the log and index file, load a new
+    /// Given the base name of the log and index file in the storage backend, load a new
     /// segment into memory.
     pub fn load_segment(
-        base_directory: &str,
+        backend: Arc<dyn StorageBackend>,
         segment_base: String,
+        keyring: Option<Arc<Keyring>>,
         //max_segment_bytes: u64
     ) -> Result<Segment, SegmentError> {
-        let segment_offset = segment_base.parse::<u16>().map_err(|e| {
+        let segment_offset = utils::parse_segment_file_stem(&segment_base).map_err(|e| {
             error!("{}", e);
-            SegmentError::new("unable to parse base string into u16")
+            SegmentError::new("unable to parse base string into u64")
         })?;
-        let log_file_name = utils::create_segment_file_name(
-            base_directory,
-            segment_offset,
-            utils::LOG_SUFFIX,
-        ).map_err(|e| {
-            error!("{}", e);
-            SegmentError::new("Unable to create log file")
-        })?;
-
-        let file = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .append(true)
-            .open(log_file_name.clone())
-            .map_err(|e| {
-                error!("{}", e);
-                SegmentError::new("unable to open log file")
-            })?;
+        let log_file_name = utils::segment_file_name(segment_offset, utils::LOG_SUFFIX);
 
-        let metadata = file.metadata().map_err(|e| {
-            error!("{}", e);
-            SegmentError::new("unable get metadata for log file")
-        })?;
+        let log = map_log_file(backend.as_ref(), &log_file_name)?;
         // This would be unnesseary if we used u64 for the position
-        let current_segment_postion: u32 = u32::try_from(metadata.len()).map_err(|e| {
+        let current_segment_postion: u32 = u32::try_from((*log).as_ref().len()).map_err(|e| {
             error!("{}", e);
             SegmentError::new("unable to convert from u64 to u32")
         })?;
 
-        let index_file_name = utils::create_segment_file_name(
-            base_directory,
-            segment_offset,
-            utils::INDEX_SUFFIX,
-        ).map_err(|e| {
-            error!("{}", e);
-            SegmentError::new("Unable to create index file")
-        })?;
-        let mut loaded_index = Index::new(&index_file_name).map_err(|e| {
+        let index_file_name = utils::segment_file_name(segment_offset, utils::INDEX_SUFFIX);
+        let mut loaded_index = Index::new(backend.clone(), &index_file_name).map_err(|e| {
             SegmentError::new(&e.to_string())
         })?;
 
-        let mut total_entries = loaded_index.load_index().map_err(|e| {
+        loaded_index.load_index().map_err(|e| {
             error!("{}", e);
             SegmentError::new("unable to load index")
         })?;
-        total_entries += segment_offset;
+        let time_index_file_name = utils::segment_file_name(segment_offset, utils::TIME_INDEX_SUFFIX);
+        let mut loaded_time_index = TimeIndex::new(backend.clone(), &time_index_file_name).map_err(|e| {
+            SegmentError::new(&e.to_string())
+        })?;
+        loaded_time_index.load_index().map_err(|e| {
+            error!("{}", e);
+            SegmentError::new("unable to load time index")
+        })?;
+        // Sparse indexes don't have an entry for every record, so the log is what tells us where the segment ends
+        let (next_offset, max_timestamp) = match record::last_offset_and_max_timestamp((*log).as_ref())? {
+            Some((last_offset, max_timestamp)) => (last_offset + 1, max_timestamp),
+            None => (segment_offset, 0),
+        };
 
         let segment = Segment {
             file_name: log_file_name,
             position: current_segment_postion,
             //max_bytes: max_segment_bytes,
             starting_offset: segment_offset,
-            next_offset: total_entries,
-            directory: base_directory.to_string(),
-            log_file: file,
+            next_offset,
+            max_timestamp,
+            log,
             index: loaded_index,
+            time_index: loaded_time_index,
+            keyring,
+            backend,
         };
 
         Ok(segment)
     }
 
+    /// The contents of the log file.
+    pub fn log(&self) -> &[u8] {
+        (*self.log).as_ref()
+    }
+
     /// Given an offset, find the entry in the index and get the bytes fromt he log
-    pub fn read_at(&mut self, offset: usize) -> Result<Vec<u8>, SegmentError> {
-        // This condition is only applied when we're dealing with segment 0, can this be combined below??
-        if (self.starting_offset == 0 && offset >= usize::from(self.next_offset))
-            || (offset >= usize::from(self.next_offset - self.starting_offset))
-        {
+    pub fn read_at(&self, offset: usize) -> Result<Vec<u8>, SegmentError> {
+        let total_entries = self.next_offset - self.starting_offset;
+        if offset as u64 >= total_entries {
             return Err(SegmentError::new("offset is out of bounds"));
         }
-        let (start, total) = self
+        let lookup = self
             .index
-            .return_entry_details_by_offset(offset)
+            .lookup(offset as u64)
             .map_err(|e| {
                 error!("{}", e);
                 SegmentError::new("unable to get entry details from index")
             })?;
-        // Let's create our buffer
-        let mut buffer = vec![0; total];
-        // Seek to entries start position
-        self.log_file.seek(SeekFrom::Start(start)).map_err(|e| {
+        let record = record::read_indexed_record(
+            self.log(),
+            lookup,
+            self.starting_offset + offset as u64,
+            self.keyring.as_deref(),
+        )?;
+        Ok(record.payload)
+    }
+
+    /// Return the position in the log of the first record at or after the offset, or the end of the log if the
+    /// offset is past the last record. Used to read a range of records without copying them.
+    pub fn log_position(&self, offset: u64) -> Result<usize, SegmentError> {
+        if offset >= self.next_offset - self.starting_offset {
+            return Ok(self.log().len());
+        }
+        let lookup = self.index.lookup(offset).map_err(|e| {
+            error!("{}", e);
+            SegmentError::new("unable to get entry details from index")
+        })?;
+        record::seek_record(self.log(), lookup, self.starting_offset + offset)
+    }
+
+    /// Find the first offset in the segment with a record appended at or after the timestamp.
+    pub fn offset_for_timestamp(&self, timestamp: u64) -> Option<u64> {
+        self.time_index
+            .lookup(timestamp)
+            .map(|relative_offset| self.starting_offset + relative_offset)
+    }
+
+    /// Hand every record stored in the segment to the visitor, in offset order.
+    pub fn for_each_record(&self, visit: impl FnMut(Record)) -> Result<(), SegmentError> {
+        let (entries, _valid_bytes) = record::scan_records(self.log(), self.starting_offset);
+        record::for_each_log_record(self.log(), &entries, self.keyring.as_deref(), visit)
+    }
+
+    /// Replace the records stored in the segment with the given records, which have to be a subset of the
+    /// segments records in the same order. The offsets of records that were left out become empty entries
+    /// in the index, so the remaining records keep their offsets. The last record of the segment has to be
+    /// kept so the segment still covers the same offsets. Records are written uncompressed, even if they were
+    /// part of a compressed batch before, and encrypted with the active key if the segment has keys. Closed
+    /// segments never change, so the rewritten segment is returned to replace this one. The files are replaced
+    /// atomically, so readers still holding this segment keep reading the records it had.
+    pub fn rewrite(&self, records: &[Record]) -> Result<Segment, SegmentError> {
+        match records.last() {
+            Some(last_record) if last_record.offset + 1 == self.next_offset => {},
+            _ => return Err(SegmentError::new("the last record of the segment has to be kept")),
+        }
+        write_segment_files(
+            self.backend.as_ref(),
+            self.starting_offset,
+            records,
+            self.index.interval_bytes(),
+            self.keyring.as_deref(),
+        )?;
+        Segment::load_segment(
+            self.backend.clone(),
+            utils::segment_file_name(self.starting_offset, ""),
+            self.keyring.clone(),
+        )
+    }
+
+    /// Remove the records at and after the offset from the segment files, the segment has to be loaded again
+    /// afterwards. The records that are kept are rewritten like they are by compaction, and the index is rebuilt
+    /// as a sparse index if an interval is given.
+    pub fn truncate(
+        backend: &dyn StorageBackend,
+        segment_offset: u64,
+        offset: u64,
+        index_interval_bytes: Option<u32>,
+        keyring: Option<&Keyring>,
+    ) -> Result<(), SegmentError> {
+        let log_file_name = utils::segment_file_name(segment_offset, utils::LOG_SUFFIX);
+        let log_contents = backend.read(&log_file_name).map_err(|e| {
             error!("{}", e);
-            SegmentError::new("unable to seek to offset in the log file")
+            SegmentError::new("unable to read log file")
         })?;
-        // Read log file bytes into the buffer
-        self.log_file.read_exact(&mut buffer).map_err(|e| {
+        let (entries, _valid_bytes) = record::scan_records(&log_contents, segment_offset);
+        let mut records = record::unpack_log_records(&log_contents, &entries, keyring)?;
+        records.retain(|kept_record| kept_record.offset < offset);
+        write_segment_files(backend, segment_offset, &records, index_interval_bytes, keyring)?;
+        Ok(())
+    }
+
+    /// Check the log file of a segment for torn writes and corruption before the segment gets loaded.
+    /// The log is truncated at the first record that fails validation and the index and time index are rebuilt from
+    /// the records that remain, the index as a sparse index if an interval is given. Logs written before the record format existed
+    /// are converted using their index. Returns the total amount of records in the segment.
+    pub fn recover(
+        backend: &dyn StorageBackend,
+        segment_offset: u64,
+        index_interval_bytes: Option<u32>,
+    ) -> Result<u64, SegmentError> {
+        let log_file_name = utils::segment_file_name(segment_offset, utils::LOG_SUFFIX);
+        let index_file_name = utils::segment_file_name(segment_offset, utils::INDEX_SUFFIX);
+        let mut log_contents = backend.read(&log_file_name).map_err(|e| {
             error!("{}", e);
-            SegmentError::new("unable to read into buffer")
+            SegmentError::new("unable to read log file")
         })?;
-        Ok(buffer)
+
+        if !record::has_log_header(&log_contents)? {
+            warn!("Converting segment {} to the record format", log_file_name);
+            log_contents = Segment::convert_legacy_log(backend, &log_file_name, &index_file_name, &log_contents, segment_offset)?;
+            replace_file_contents(backend, &log_file_name, &log_contents)?;
+        }
+
+        let (entries, valid_bytes) = record::scan_records(&log_contents, segment_offset);
+        if valid_bytes < log_contents.len() {
+            warn!(
+                "Truncating segment {} after {} records, {} bytes are torn or corrupt",
+                log_file_name,
+                entries.len(),
+                log_contents.len() - valid_bytes
+            );
+            let mut log_file = backend.open(&log_file_name, false).map_err(|e| {
+                error!("{}", e);
+                SegmentError::new("unable to open log file for truncation")
+            })?;
+            log_file
+                .truncate(valid_bytes as u64)
+                .and_then(|_| log_file.sync())
+                .map_err(|e| {
+                    error!("{}", e);
+                    SegmentError::new("unable to truncate log file")
+                })?;
+        }
+
+        let (index_contents, time_index_contents) =
+            build_index_contents(&log_contents, &entries, index_interval_bytes)?;
+        let existing_index_contents = backend.read(&index_file_name).unwrap_or_default();
+        if existing_index_contents != index_contents {
+            warn!("Rebuilding index {} from its log", index_file_name);
+            replace_file_contents(backend, &index_file_name, &index_contents)?;
+        }
+
+        let time_index_file_name = utils::segment_file_name(segment_offset, utils::TIME_INDEX_SUFFIX);
+        let existing_time_index_contents = backend.read(&time_index_file_name).unwrap_or_default();
+        if existing_time_index_contents != time_index_contents {
+            warn!("Rebuilding time index {} from its log", time_index_file_name);
+            replace_file_contents(backend, &time_index_file_name, &time_index_contents)?;
+        }
+
+        u64::try_from(entries.len()).map_err(|e| {
+            error!("{}", e);
+            SegmentError::new("unable to convert usize to u64")
+        })
     }
 
-    /// Close the log file and the index file, then delete both of these files.
+    /// Rewrite a log of raw concatenated payloads into the record format, using the legacy index to find
+    /// where each payload starts. The log file's modified time is used as the timestamp for every record.
+    fn convert_legacy_log(
+        backend: &dyn StorageBackend,
+        log_file_name: &str,
+        index_file_name: &str,
+        log_contents: &[u8],
+        segment_offset: u64,
+    ) -> Result<Vec<u8>, SegmentError> {
+        let timestamp = backend
+            .modified(log_file_name)
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or_else(utils::current_time_millis);
+
+        let legacy_index_contents = backend.read(index_file_name).unwrap_or_default();
+        let legacy_entries = match IndexEntries::decode(&legacy_index_contents) {
+            Ok(IndexEntries::Dense(entries)) => entries,
+            _ => return Err(SegmentError::new("unable to load legacy index")),
+        };
+
+        let mut converted_log = record::log_header().to_vec();
+        for (i, entry) in legacy_entries.iter().enumerate() {
+            let start = entry.start as usize;
+            match log_contents.get(start..start + entry.total as usize) {
+                Some(payload) => record::encode_record(&mut converted_log, segment_offset + i as u64, timestamp, payload)?,
+                None => {
+                    warn!("Legacy index entry {} is outside of {}, dropping the rest of the segment", i, log_file_name);
+                    break;
+                }
+            }
+        }
+        Ok(converted_log)
+    }
+
+    /// The names of the log, index and time index files of the segment in storage.
+    pub fn file_names(&self) -> [&str; 3] {
+        [&self.file_name, &self.index.file_name, &self.time_index.file_name]
+    }
+
+    /// Close the log file and the index files, then delete all of these files.
     pub fn delete(&self) -> Result<bool, SegmentError> {
         //self.close();
-        fs::remove_file(&self.file_name).map_err(|e| {
+        self.backend.delete(&self.file_name).map_err(|e| {
             error!("{}", e);
             SegmentError::new("unable to delete log file")
         })?;
-        fs::remove_file(&self.index.file_name).map_err(|e| {
+        self.backend.delete(&self.index.file_name).map_err(|e| {
             error!("{}", e);
             SegmentError::new("unable to delete index file")
         })?;
+        self.backend.delete(&self.time_index.file_name).map_err(|e| {
+            error!("{}", e);
+            SegmentError::new("unable to delete time index file")
+        })?;
         Ok(true)
     }
 
 
 }
 
+/// Map the log file of a closed segment.
+fn map_log_file(backend: &dyn StorageBackend, log_file_name: &str) -> Result<FileContents, SegmentError> {
+    backend.map(log_file_name).map_err(|e| {
+        error!("{}", e);
+        SegmentError::new("unable to memory map log file")
+    })
+}
+
+/// Build the contents of the index and time index files of a log, given the index entries from scanning it. The index
+/// is sparse if an interval is given.
+pub fn build_index_contents(
+    log_contents: &[u8],
+    entries: &[Entry],
+    index_interval_bytes: Option<u32>,
+) -> Result<(Vec<u8>, Vec<u8>), SegmentError> {
+    let mut index_entries = IndexEntries::new(index_interval_bytes);
+    for (relative_offset, entry) in entries.iter().enumerate() {
+        push_index_entry(&mut index_entries, relative_offset as u64, entry.start, entry.total)?;
+    }
+    let mut record_timestamps = Vec::new();
+    for (relative_offset, entry) in entries.iter().enumerate().filter(|(_, entry)| entry.total > 0) {
+        let timestamp = record::record_timestamp(&log_contents[entry.start as usize..])?;
+        record_timestamps.push((relative_offset as u64, timestamp));
+    }
+    let time_index_contents = encode_time_index_entries(&build_time_index_entries(record_timestamps)?)?;
+    Ok((encode_index_entries(&index_entries)?, time_index_contents))
+}
+
+/// Write the records to the files of the segment starting at the offset, replacing the files that are there. Offsets
+/// between the records become empty entries in the index. Returns the contents of the log that was written.
+fn write_segment_files(
+    backend: &dyn StorageBackend,
+    starting_offset: u64,
+    records: &[Record],
+    index_interval_bytes: Option<u32>,
+    keyring: Option<&Keyring>,
+) -> Result<Vec<u8>, SegmentError> {
+    let mut log_contents = record::log_header().to_vec();
+    let mut entries = IndexEntries::new(index_interval_bytes);
+    let mut next_relative_offset = 0;
+    for kept_record in records {
+        let start = u32::try_from(log_contents.len()).map_err(|e| {
+            error!("{}", e);
+            SegmentError::new("unable to convert from usize to u32")
+        })?;
+        let relative_offset = kept_record.offset - starting_offset;
+        while next_relative_offset < relative_offset {
+            push_index_entry(&mut entries, next_relative_offset, start, 0)?;
+            next_relative_offset += 1;
+        }
+        // The records around a kept record may have been removed, so it no longer belongs to a batch
+        record::encode_sealed_record(
+            &mut log_contents,
+            kept_record.attributes & !record::BATCH_CONTINUES,
+            kept_record.offset,
+            kept_record.timestamp,
+            &kept_record.payload,
+            keyring,
+        )?;
+        let total = u32::try_from(log_contents.len()).map_err(|e| {
+            error!("{}", e);
+            SegmentError::new("unable to convert from usize to u32")
+        })? - start;
+        push_index_entry(&mut entries, relative_offset, start, total)?;
+        next_relative_offset = relative_offset + 1;
+    }
+
+    let time_entries = build_time_index_entries(records.iter().map(|kept_record| {
+        (kept_record.offset - starting_offset, kept_record.timestamp)
+    }))?;
+
+    // The log goes first, if the indexes don't get replaced recovery will rebuild them from the log
+    let log_file_name = utils::segment_file_name(starting_offset, utils::LOG_SUFFIX);
+    let index_file_name = utils::segment_file_name(starting_offset, utils::INDEX_SUFFIX);
+    let time_index_file_name = utils::segment_file_name(starting_offset, utils::TIME_INDEX_SUFFIX);
+    replace_file_contents(backend, &log_file_name, &log_contents)?;
+    replace_file_contents(backend, &index_file_name, &encode_index_entries(&entries)?)?;
+    replace_file_contents(backend, &time_index_file_name, &encode_time_index_entries(&time_entries)?)?;
+    Ok(log_contents)
+}
+
+/// Add a record to index entries that are being rebuilt.
+fn push_index_entry(entries: &mut IndexEntries, relative_offset: u64, start: u32, total: u32) -> Result<(), SegmentError> {
+    entries.push(relative_offset, start, total).map_err(|e| {
+        error!("{}", e);
+        SegmentError::new("unable to add entry to index")
+    })?;
+    Ok(())
+}
+
+/// Encode index entries into the bytes stored in an index file.
+fn encode_index_entries(entries: &IndexEntries) -> Result<Vec<u8>, SegmentError> {
+    entries.encode().map_err(|e| {
+        error!("{}", e);
+        SegmentError::new("unable to serialize index entries")
+    })
+}
+
+/// Build the time index entries for records that are being rebuilt, given as relative offsets and timestamps.
+fn build_time_index_entries<I>(records: I) -> Result<Vec<time_index::TimeEntry>, SegmentError>
+where
+    I: IntoIterator<Item = (u64, u64)>,
+{
+    time_index::build_time_entries(records).map_err(|e| {
+        error!("{}", e);
+        SegmentError::new("unable to build time index entries")
+    })
+}
+
+/// Encode time index entries into the bytes stored in a time index file.
+fn encode_time_index_entries(entries: &[time_index::TimeEntry]) -> Result<Vec<u8>, SegmentError> {
+    time_index::encode_time_entries(entries).map_err(|e| {
+        error!("{}", e);
+        SegmentError::new("unable to serialize time index entries")
+    })
+}
+
+/// Replace the contents of a file in storage, a crash part way through never leaves a half written file behind.
+fn replace_file_contents(backend: &dyn StorageBackend, file_name: &str, contents: &[u8]) -> Result<(), SegmentError> {
+    backend.write(file_name, contents).map_err(|e| {
+        error!("{}", e);
+        SegmentError::new("unable to replace file contents")
+    })
+}
+
 #[cfg(test)]
 mod segment_tests {
-    use std::path::Path;
+    use std::sync::Arc;
     use tempdir::TempDir;
     use crate::nolan_errors::SegmentError;
+    use crate::compression::Compression;
+    use crate::config::FsyncPolicy;
+    use crate::storage::{FilesystemStorage, StorageBackend};
     use crate::virtual_segment::VirtualSegment;
     use crate::utils;
     use crate::segment::Segment;
 
-    fn create_segment_file(test_dir_path: &str, message_to_write: &[u8]) -> String{
-        let mut vs = VirtualSegment::new(test_dir_path, 100, 0);
+    fn create_test_backend(tmp_dir: &TempDir) -> Arc<dyn StorageBackend> {
+        let test_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to convert path to string");
+        Arc::new(FilesystemStorage::new(test_dir_path).expect("Unable to create storage"))
+    }
+
+    fn create_segment_file(backend: &Arc<dyn StorageBackend>, message_to_write: &[u8]) -> String{
+        create_segment_file_at(backend, message_to_write, 0)
+    }
+
+    fn create_segment_file_at(backend: &Arc<dyn StorageBackend>, message_to_write: &[u8], starting_offset: u64) -> String{
+        let mut vs = VirtualSegment::new(
+            backend.clone(),
+            100,
+            starting_offset,
+            FsyncPolicy::Always,
+            None,
+            Compression::None,
+            None,
+        )
+        .expect("Unable to create virtual segment");
         vs
             .write(message_to_write)
             .expect("unable to write data to virtual segment");
         vs.flush().expect("Unable to flush");
-        let file_name = Path::new(&vs.full_log_path).file_name().unwrap().to_str().expect("Unbale to conver os string to string");
-        let segment_base = str::strip_suffix(&file_name, utils::LOG_SUFFIX).expect("unable to strip");
-        return segment_base.to_string();
+        let segment_base = str::strip_suffix(&vs.log_file_name, utils::LOG_SUFFIX).expect("unable to strip");
+        segment_base.to_string()
     }
 
     #[test]
     fn test_load_segment() {
         let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
-        let test_dir_path = tmp_dir
-            .path()
-            .to_str()
-            .expect("Unable to convert path to string");
-        let segment_base = create_segment_file(test_dir_path, "hello".as_bytes());
-        let segment = Segment::load_segment(test_dir_path, segment_base).expect("unable to load segment");
+        let backend = create_test_backend(&tmp_dir);
+        let segment_base = create_segment_file(&backend, "hello".as_bytes());
+        let segment = Segment::load_segment(backend.clone(), segment_base, None).expect("unable to load segment");
         //Check if the directory exists
-        assert!(Path::new(&segment.file_name).exists());
+        assert!(tmp_dir.path().join(&segment.file_name).exists());
     }
 
     #[test]
     fn test_read_at() {
         let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
-        let test_dir_path = tmp_dir
-            .path()
-            .to_str()
-            .expect("Unable to convert path to string");
+        let backend = create_test_backend(&tmp_dir);
         let message = "hello".as_bytes();
-        let segment_base = create_segment_file(test_dir_path, message);
-        let mut segment = Segment::load_segment(test_dir_path, segment_base).expect("unable to load segment");
+        let segment_base = create_segment_file(&backend, message);
+        let segment = Segment::load_segment(backend.clone(), segment_base, None).expect("unable to load segment");
 
         let result = segment.read_at(0).expect("Unable to read at offset");
 
         assert!(result.iter().eq(message.iter()));
     }
 
+    #[test]
+    fn test_read_at_many_records() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let backend = create_test_backend(&tmp_dir);
+        let mut vs = VirtualSegment::new(backend.clone(), 1000, 0, FsyncPolicy::Always, None, Compression::None, None)
+            .expect("Unable to create virtual segment");
+        let messages: Vec<String> = (0..10).map(|i| format!("hello{}", i)).collect();
+        for message in &messages {
+            vs.write(message.as_bytes()).expect("unable to write data to virtual segment");
+        }
+        let segment_base = format!("{:0>width$}", 0, width = utils::SEGMENT_NAME_WIDTH);
+        let segment = Segment::load_segment(backend.clone(), segment_base, None).expect("unable to load segment");
+
+        assert_eq!(10, segment.next_offset);
+        // Read out of order, the log is memory mapped so there is no file cursor to keep track of
+        for i in (0..10).rev() {
+            let result = segment.read_at(i).expect("Unable to read at offset");
+            assert_eq!(messages[i].as_bytes(), &*result);
+        }
+    }
+
+    #[test]
+    fn test_load_segment_large_offset() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let backend = create_test_backend(&tmp_dir);
+        let starting_offset = u64::from(u32::MAX) + 1;
+        let message = "hello".as_bytes();
+        let segment_base = create_segment_file_at(&backend, message, starting_offset);
+        let segment = Segment::load_segment(backend.clone(), segment_base, None).expect("unable to load segment");
+
+        assert_eq!(starting_offset, segment.starting_offset);
+        assert_eq!(starting_offset + 1, segment.next_offset);
+        assert!(segment.max_timestamp > 0);
+        let result = segment.read_at(0).expect("Unable to read at offset");
+        assert!(result.iter().eq(message.iter()));
+    }
+
     #[test]
     fn test_read_at_offset_dne() {
         let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
-        let test_dir_path = tmp_dir
-            .path()
-            .to_str()
-            .expect("Unable to convert path to string");
+        let backend = create_test_backend(&tmp_dir);
         let message = "hello".as_bytes();
-        let segment_base = create_segment_file(test_dir_path, message);
-        let mut segment = Segment::load_segment(test_dir_path, segment_base).expect("unable to load segment");
+        let segment_base = create_segment_file(&backend, message);
+        let segment = Segment::load_segment(backend.clone(), segment_base, None).expect("unable to load segment");
 
         let segment_error = segment.read_at(1).unwrap_err();
         let wanted_error =
@@ -220,20 +572,19 @@ mod segment_tests {
     #[test]
     fn test_delete() {
         let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
-        let test_dir_path = tmp_dir
-            .path()
-            .to_str()
-            .expect("Unable to convert path to string");
+        let backend = create_test_backend(&tmp_dir);
         let message = "hello".as_bytes();
-        let segment_base = create_segment_file(test_dir_path, message);
-        let segment = Segment::load_segment(test_dir_path, segment_base).expect("unable to load segment");
+        let segment_base = create_segment_file(&backend, message);
+        let segment = Segment::load_segment(backend.clone(), segment_base, None).expect("unable to load segment");
 
         let segment_path = segment.file_name.clone();
         let index_path = segment.index.file_name.clone();
+        let time_index_path = segment.time_index.file_name.clone();
 
         let delete_result = segment.delete().expect("Unable to delete segment");
         assert!(delete_result.eq(&true));
-        assert!(!Path::new(&segment_path).exists());
-        assert!(!Path::new(&index_path).exists());
+        assert!(!backend.exists(&segment_path));
+        assert!(!backend.exists(&index_path));
+        assert!(!backend.exists(&time_index_path));
     }
 }
@@ -0,0 +1,390 @@
+use crate::nolan_errors::StorageError;
+use log::error;
+use memmap2::Mmap;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// The contents of a file read through a storage backend, which the backend may share instead of copying.
+pub type FileContents = Box<dyn AsRef<[u8]> + Send + Sync>;
+
+/// A file opened through a storage backend. Files are only ever appended to, or cut short when a write fails part way.
+pub trait StorageFile: Send + Sync {
+    /// Append the data to the end of the file.
+    fn append(&mut self, data: &[u8]) -> Result<(), StorageError>;
+    /// Cut the file off after the given amount of bytes.
+    fn truncate(&mut self, len: u64) -> Result<(), StorageError>;
+    /// Make everything appended to the file durable.
+    fn sync(&self) -> Result<(), StorageError>;
+}
+
+/// Where a commitlog keeps its segment files. A backend holds the files of a single commitlog, which are addressed by
+/// their file name.
+pub trait StorageBackend: Send + Sync {
+    /// Open the file for appending, creating it if it doesn't exist. If truncate is set the file starts out empty.
+    fn open(&self, file_name: &str, truncate: bool) -> Result<Box<dyn StorageFile>, StorageError>;
+    /// Read the whole file.
+    fn read(&self, file_name: &str) -> Result<Vec<u8>, StorageError>;
+    /// Read a file that's no longer appended to, without copying it if the backend is able to.
+    fn map(&self, file_name: &str) -> Result<FileContents, StorageError>;
+    /// Replace the contents of the file, a crash part way through never leaves a half written file behind.
+    fn write(&self, file_name: &str, contents: &[u8]) -> Result<(), StorageError>;
+    /// Return the names of every file.
+    fn list(&self) -> Result<Vec<String>, StorageError>;
+    /// Rename the file, replacing any file with the new name.
+    fn rename(&self, file_name: &str, new_file_name: &str) -> Result<(), StorageError>;
+    /// Delete the file.
+    fn delete(&self, file_name: &str) -> Result<(), StorageError>;
+    /// Check if the file exists.
+    fn exists(&self, file_name: &str) -> bool;
+    /// Return when the file was last modified.
+    fn modified(&self, file_name: &str) -> Result<SystemTime, StorageError>;
+}
+
+/// Storage backend that keeps the files in a directory on disk.
+pub struct FilesystemStorage {
+    directory: PathBuf,
+}
+
+impl FilesystemStorage {
+    /// Initialize a storage backend in the directory, creating the directory if it doesn't exist.
+    pub fn new(directory: &str) -> Result<FilesystemStorage, StorageError> {
+        fs::create_dir_all(directory).map_err(|e| {
+            error!("{}", e);
+            StorageError::new("unable to create storage directory")
+        })?;
+        Ok(FilesystemStorage {
+            directory: PathBuf::from(directory),
+        })
+    }
+
+    fn path(&self, file_name: &str) -> PathBuf {
+        self.directory.join(file_name)
+    }
+}
+
+/// A file in a directory on disk.
+struct FilesystemFile {
+    file: File,
+    path: PathBuf,
+}
+
+impl StorageFile for FilesystemFile {
+    fn append(&mut self, data: &[u8]) -> Result<(), StorageError> {
+        self.file.write_all(data).map_err(|e| {
+            error!("{:?}: {}", self.path, e);
+            StorageError::new("unable to append to file")
+        })
+    }
+
+    fn truncate(&mut self, len: u64) -> Result<(), StorageError> {
+        self.file.set_len(len).map_err(|e| {
+            error!("{:?}: {}", self.path, e);
+            StorageError::new("unable to truncate file")
+        })
+    }
+
+    fn sync(&self) -> Result<(), StorageError> {
+        self.file.sync_data().map_err(|e| {
+            error!("{:?}: {}", self.path, e);
+            StorageError::new("unable to sync file")
+        })
+    }
+}
+
+impl StorageBackend for FilesystemStorage {
+    fn open(&self, file_name: &str, truncate: bool) -> Result<Box<dyn StorageFile>, StorageError> {
+        let path = self.path(file_name);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|file| {
+                if truncate {
+                    file.set_len(0)?;
+                }
+                Ok(file)
+            })
+            .map_err(|e| {
+                error!("{:?}: {}", path, e);
+                StorageError::new("unable to open file")
+            })?;
+        Ok(Box::new(FilesystemFile { file, path }))
+    }
+
+    fn read(&self, file_name: &str) -> Result<Vec<u8>, StorageError> {
+        fs::read(self.path(file_name)).map_err(|e| {
+            error!("{}: {}", file_name, e);
+            StorageError::new("unable to read file")
+        })
+    }
+
+    fn map(&self, file_name: &str) -> Result<FileContents, StorageError> {
+        let file = File::open(self.path(file_name)).map_err(|e| {
+            error!("{}: {}", file_name, e);
+            StorageError::new("unable to open file")
+        })?;
+        // Safety: mapped files are never written to in place. Recovery only truncates logs before they're mapped
+        // and compaction replaces the log with a new file, so the mapped file never changes underneath us.
+        let mapped_file = unsafe { Mmap::map(&file) }.map_err(|e| {
+            error!("{}: {}", file_name, e);
+            StorageError::new("unable to memory map file")
+        })?;
+        Ok(Box::new(mapped_file))
+    }
+
+    fn write(&self, file_name: &str, contents: &[u8]) -> Result<(), StorageError> {
+        // Write to a temporary file first and rename it over the original
+        let path = self.path(file_name);
+        let tmp_path = self.path(&format!("{}.tmp", file_name));
+        let mut tmp_file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&tmp_path)
+            .map_err(|e| {
+                error!("{}", e);
+                StorageError::new("unable to create temporary file")
+            })?;
+        tmp_file
+            .write_all(contents)
+            .and_then(|_| tmp_file.sync_all())
+            .map_err(|e| {
+                error!("{}", e);
+                StorageError::new("unable to write temporary file")
+            })?;
+        fs::rename(&tmp_path, &path).map_err(|e| {
+            error!("{}", e);
+            StorageError::new("unable to replace file with temporary file")
+        })
+    }
+
+    fn list(&self) -> Result<Vec<String>, StorageError> {
+        let entries = fs::read_dir(&self.directory).map_err(|e| {
+            error!("{}", e);
+            StorageError::new("unable to read storage directory")
+        })?;
+        Ok(entries
+            .flatten()
+            .filter(|entry| entry.path().is_file())
+            .filter_map(|entry| entry.file_name().to_str().map(String::from))
+            .collect())
+    }
+
+    fn rename(&self, file_name: &str, new_file_name: &str) -> Result<(), StorageError> {
+        fs::rename(self.path(file_name), self.path(new_file_name)).map_err(|e| {
+            error!("{}", e);
+            StorageError::new("unable to rename file")
+        })
+    }
+
+    fn delete(&self, file_name: &str) -> Result<(), StorageError> {
+        fs::remove_file(self.path(file_name)).map_err(|e| {
+            error!("{}: {}", file_name, e);
+            StorageError::new("unable to delete file")
+        })
+    }
+
+    fn exists(&self, file_name: &str) -> bool {
+        self.path(file_name).is_file()
+    }
+
+    fn modified(&self, file_name: &str) -> Result<SystemTime, StorageError> {
+        fs::metadata(self.path(file_name))
+            .and_then(|metadata| metadata.modified())
+            .map_err(|e| {
+                error!("{}: {}", file_name, e);
+                StorageError::new("unable to get modified time of file")
+            })
+    }
+}
+
+/// The contents of a file kept in memory, shared by the storage and every open handle to the file.
+type MemoryFileContents = Arc<Mutex<Vec<u8>>>;
+
+/// Storage backend that keeps the files in memory, nothing is written to disk and everything is gone once the
+/// storage is dropped. Useful for tests and for commitlogs that don't need to outlive the process.
+#[derive(Default)]
+pub struct MemoryStorage {
+    files: Mutex<HashMap<String, (MemoryFileContents, SystemTime)>>,
+}
+
+impl MemoryStorage {
+    /// Initialize an empty in memory storage backend.
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+
+    fn contents(&self, file_name: &str) -> Result<MemoryFileContents, StorageError> {
+        self.files
+            .lock()
+            .map_err(|_| StorageError::new("memory storage lock is poisoned"))?
+            .get(file_name)
+            .map(|(contents, _)| contents.clone())
+            .ok_or_else(|| StorageError::new("file does not exist"))
+    }
+}
+
+/// A file kept in memory.
+struct MemoryFile {
+    contents: MemoryFileContents,
+}
+
+impl StorageFile for MemoryFile {
+    fn append(&mut self, data: &[u8]) -> Result<(), StorageError> {
+        self.contents
+            .lock()
+            .map_err(|_| StorageError::new("memory file lock is poisoned"))?
+            .extend_from_slice(data);
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: u64) -> Result<(), StorageError> {
+        self.contents
+            .lock()
+            .map_err(|_| StorageError::new("memory file lock is poisoned"))?
+            .truncate(len as usize);
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<(), StorageError> {
+        Ok(())
+    }
+}
+
+impl StorageBackend for MemoryStorage {
+    fn open(&self, file_name: &str, truncate: bool) -> Result<Box<dyn StorageFile>, StorageError> {
+        let mut files = self
+            .files
+            .lock()
+            .map_err(|_| StorageError::new("memory storage lock is poisoned"))?;
+        let (contents, modified) = files
+            .entry(file_name.to_string())
+            .or_insert_with(|| (Arc::new(Mutex::new(Vec::new())), SystemTime::now()));
+        if truncate {
+            contents
+                .lock()
+                .map_err(|_| StorageError::new("memory file lock is poisoned"))?
+                .clear();
+            *modified = SystemTime::now();
+        }
+        Ok(Box::new(MemoryFile {
+            contents: contents.clone(),
+        }))
+    }
+
+    fn read(&self, file_name: &str) -> Result<Vec<u8>, StorageError> {
+        let contents = self.contents(file_name)?;
+        let contents = contents
+            .lock()
+            .map_err(|_| StorageError::new("memory file lock is poisoned"))?;
+        Ok(contents.clone())
+    }
+
+    fn map(&self, file_name: &str) -> Result<FileContents, StorageError> {
+        Ok(Box::new(self.read(file_name)?))
+    }
+
+    fn write(&self, file_name: &str, contents: &[u8]) -> Result<(), StorageError> {
+        // Handles to the replaced file keep the old contents, like they would for a file on disk
+        self.files
+            .lock()
+            .map_err(|_| StorageError::new("memory storage lock is poisoned"))?
+            .insert(file_name.to_string(), (Arc::new(Mutex::new(contents.to_vec())), SystemTime::now()));
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<String>, StorageError> {
+        Ok(self
+            .files
+            .lock()
+            .map_err(|_| StorageError::new("memory storage lock is poisoned"))?
+            .keys()
+            .cloned()
+            .collect())
+    }
+
+    fn rename(&self, file_name: &str, new_file_name: &str) -> Result<(), StorageError> {
+        let mut files = self
+            .files
+            .lock()
+            .map_err(|_| StorageError::new("memory storage lock is poisoned"))?;
+        let file = files
+            .remove(file_name)
+            .ok_or_else(|| StorageError::new("file does not exist"))?;
+        files.insert(new_file_name.to_string(), file);
+        Ok(())
+    }
+
+    fn delete(&self, file_name: &str) -> Result<(), StorageError> {
+        self.files
+            .lock()
+            .map_err(|_| StorageError::new("memory storage lock is poisoned"))?
+            .remove(file_name)
+            .map(|_| ())
+            .ok_or_else(|| StorageError::new("file does not exist"))
+    }
+
+    fn exists(&self, file_name: &str) -> bool {
+        self.contents(file_name).is_ok()
+    }
+
+    fn modified(&self, file_name: &str) -> Result<SystemTime, StorageError> {
+        self.files
+            .lock()
+            .map_err(|_| StorageError::new("memory storage lock is poisoned"))?
+            .get(file_name)
+            .map(|(_, modified)| *modified)
+            .ok_or_else(|| StorageError::new("file does not exist"))
+    }
+}
+
+#[cfg(test)]
+mod storage_tests {
+    use crate::storage::{FilesystemStorage, MemoryStorage, StorageBackend};
+    use tempdir::TempDir;
+
+    /// Run the same checks against every storage backend
+    fn check_storage_backend(storage: &dyn StorageBackend) {
+        let mut file = storage.open("test.log", false).expect("Unable to open file");
+        file.append("hello".as_bytes()).expect("Unable to append to file");
+        file.append("world".as_bytes()).expect("Unable to append to file");
+        file.sync().expect("Unable to sync file");
+        assert_eq!("helloworld".as_bytes(), storage.read("test.log").unwrap());
+        file.truncate(5).expect("Unable to truncate file");
+        assert_eq!("hello".as_bytes(), (*storage.map("test.log").unwrap()).as_ref());
+
+        storage.write("test.index", "index".as_bytes()).expect("Unable to write file");
+        let mut file_names = storage.list().expect("Unable to list files");
+        file_names.sort();
+        assert_eq!(vec!["test.index".to_string(), "test.log".to_string()], file_names);
+
+        storage.rename("test.index", "renamed.index").expect("Unable to rename file");
+        assert!(!storage.exists("test.index"));
+        assert_eq!("index".as_bytes(), storage.read("renamed.index").unwrap());
+        assert!(storage.modified("renamed.index").is_ok());
+
+        storage.delete("test.log").expect("Unable to delete file");
+        assert!(!storage.exists("test.log"));
+        assert!(storage.read("test.log").is_err());
+        let reopened_file = storage.open("renamed.index", true).expect("Unable to open file");
+        drop(reopened_file);
+        assert!(storage.read("renamed.index").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_filesystem_storage() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let storage = FilesystemStorage::new(tmp_dir.path().join("commitlog").to_str().unwrap())
+            .expect("Unable to create storage");
+        check_storage_backend(&storage);
+    }
+
+    #[test]
+    fn test_memory_storage() {
+        check_storage_backend(&MemoryStorage::new());
+    }
+}
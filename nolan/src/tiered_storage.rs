@@ -0,0 +1,490 @@
+use crate::cleaner::Cleaner;
+use crate::encryption::Keyring;
+use crate::nolan_errors::RemoteStorageError;
+use crate::segment::Segment;
+use crate::storage::StorageBackend;
+use crate::utils;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Name of the object that lists the segments in remote storage.
+const MANIFEST_OBJECT: &str = "segments.manifest";
+/// Suffix of the temporary files objects are written to before they're renamed into place.
+const TMP_SUFFIX: &str = ".tmp";
+/// The files that make up a segment, in the order `Segment::file_names` returns them.
+const SEGMENT_SUFFIXES: [&str; 3] = [utils::LOG_SUFFIX, utils::INDEX_SUFFIX, utils::TIME_INDEX_SUFFIX];
+/// Most offloaded segments that are kept on local disk after being fetched back for reading.
+const MAX_FETCHED_SEGMENTS: usize = 2;
+
+/// An object store closed segments can be offloaded to, like a bucket in an S3 compatible object store. Objects are
+/// always written and read whole. Every commitlog expects a remote storage of its own, for example its own prefix in
+/// a bucket, since objects that don't belong to one of its segments are removed.
+pub trait RemoteStorage: Send + Sync {
+    /// Store the data as an object with the name, replacing any object with the same name.
+    fn put(&self, name: &str, data: &[u8]) -> Result<(), RemoteStorageError>;
+    /// Return the data of the object with the name.
+    fn get(&self, name: &str) -> Result<Vec<u8>, RemoteStorageError>;
+    /// Return the names of every object in the storage.
+    fn list(&self) -> Result<Vec<String>, RemoteStorageError>;
+    /// Delete the object with the name, deleting an object that doesn't exist isn't an error.
+    fn delete(&self, name: &str) -> Result<(), RemoteStorageError>;
+}
+
+/// Remote storage that keeps every object as a file in a directory. Useful when the directory is a mounted network
+/// file system, and for testing tiered storage without an object store.
+pub struct FilesystemRemoteStorage {
+    directory: String,
+}
+
+impl FilesystemRemoteStorage {
+    /// Initialize a remote storage in the directory, creating the directory if it doesn't exist.
+    pub fn new(directory: &str) -> Result<FilesystemRemoteStorage, RemoteStorageError> {
+        fs::create_dir_all(directory).map_err(|e| {
+            error!("{}", e);
+            RemoteStorageError::new("unable to create remote storage directory")
+        })?;
+        Ok(FilesystemRemoteStorage {
+            directory: directory.to_string(),
+        })
+    }
+}
+
+impl RemoteStorage for FilesystemRemoteStorage {
+    fn put(&self, name: &str, data: &[u8]) -> Result<(), RemoteStorageError> {
+        // Objects are written to a temporary file first, so they show up whole like they would in an object store
+        let file_name = Path::new(&self.directory).join(name);
+        let tmp_file_name = Path::new(&self.directory).join(format!("{}{}", name, TMP_SUFFIX));
+        fs::write(&tmp_file_name, data)
+            .and_then(|_| fs::rename(&tmp_file_name, &file_name))
+            .map_err(|e| {
+                error!("{}", e);
+                RemoteStorageError::new("unable to write object")
+            })
+    }
+
+    fn get(&self, name: &str) -> Result<Vec<u8>, RemoteStorageError> {
+        fs::read(Path::new(&self.directory).join(name)).map_err(|e| {
+            error!("{}", e);
+            RemoteStorageError::new("unable to read object")
+        })
+    }
+
+    fn list(&self) -> Result<Vec<String>, RemoteStorageError> {
+        let entries = fs::read_dir(&self.directory).map_err(|e| {
+            error!("{}", e);
+            RemoteStorageError::new("unable to list objects")
+        })?;
+        Ok(entries
+            .flatten()
+            .filter_map(|entry| entry.file_name().to_str().map(String::from))
+            .filter(|name| !name.ends_with(TMP_SUFFIX))
+            .collect())
+    }
+
+    fn delete(&self, name: &str) -> Result<(), RemoteStorageError> {
+        match fs::remove_file(Path::new(&self.directory).join(name)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                error!("{}", e);
+                Err(RemoteStorageError::new("unable to delete object"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A segment that was uploaded to remote storage.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+struct RemoteSegment {
+    starting_offset: u64,
+    next_offset: u64,
+    /// Size in bytes of the log of the segment
+    size: u64,
+    max_timestamp: u64,
+}
+
+impl RemoteSegment {
+    /// The names of the objects the log, index and time index of the segment are stored as. The size is part of
+    /// the names, so uploading a compacted segment again never overwrites the objects of the previous upload.
+    fn object_names(&self) -> [String; 3] {
+        SEGMENT_SUFFIXES.map(|suffix| {
+            format!(
+                "{:0>width$}-{}{}",
+                self.starting_offset,
+                self.size,
+                suffix,
+                width = utils::SEGMENT_NAME_WIDTH
+            )
+        })
+    }
+}
+
+/// Tiered storage offloads closed segments to remote storage. Every closed segment is uploaded, and once the closed
+/// segments on local disk take up more than the local retention the oldest uploaded ones are only kept in remote
+/// storage. Offloaded segments are fetched back to local disk when they're read. The segments in remote storage are
+/// listed in a manifest object, which is written after a segment is uploaded and before a segment is deleted. That way
+/// a segment that was only partially uploaded or deleted is never read, and its objects are removed on the next load.
+/// Segments removed from local disk are returned instead of deleted, the commitlog deletes their files once they're
+/// out of its own manifest.
+/// Objects are transferred without holding any locks, so the methods that change remote storage or write fetched
+/// segments to local disk have to be called one at a time.
+pub struct TieredStorage {
+    storage: Arc<dyn RemoteStorage>,
+    local_retention_bytes: Option<u64>,
+    /// Every segment in remote storage, ordered oldest first
+    remote_segments: RwLock<Vec<RemoteSegment>>,
+    /// Segments ending at or before this offset were removed from remote storage by retention
+    expired_offset: AtomicU64,
+    /// Starting offsets of the offloaded segments that were fetched back to local disk, in the order they were fetched
+    fetched_segments: Mutex<VecDeque<u64>>,
+}
+
+impl TieredStorage {
+    /// Load the segments in the remote storage, removing any objects that don't belong to one of them. Closed segments
+    /// past the local retention bytes are only kept in remote storage, every segment is kept locally if it's not set.
+    pub fn load(
+        storage: Arc<dyn RemoteStorage>,
+        local_retention_bytes: Option<u64>,
+    ) -> Result<TieredStorage, RemoteStorageError> {
+        let object_names = storage.list()?;
+        let remote_segments: Vec<RemoteSegment> = if object_names.iter().any(|name| name == MANIFEST_OBJECT) {
+            bincode::deserialize(&storage.get(MANIFEST_OBJECT)?).map_err(|e| {
+                error!("{}", e);
+                RemoteStorageError::new("unable to decode remote storage manifest")
+            })?
+        } else {
+            Vec::new()
+        };
+        let segment_object_names: Vec<String> = remote_segments
+            .iter()
+            .flat_map(|remote_segment| remote_segment.object_names())
+            .collect();
+        for object_name in object_names {
+            if object_name != MANIFEST_OBJECT && !segment_object_names.contains(&object_name) {
+                warn!("Removing {} from remote storage, it isn't part of a segment", object_name);
+                storage.delete(&object_name)?;
+            }
+        }
+        Ok(TieredStorage {
+            storage,
+            local_retention_bytes,
+            remote_segments: RwLock::new(remote_segments),
+            expired_offset: AtomicU64::new(0),
+            fetched_segments: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    // Locks are only held for short sections that don't panic or call into the commitlog.
+
+    fn read_remote_segments(&self) -> RwLockReadGuard<'_, Vec<RemoteSegment>> {
+        self.remote_segments.read().expect("Unable to get lock on remote segments")
+    }
+
+    fn write_remote_segments(&self) -> RwLockWriteGuard<'_, Vec<RemoteSegment>> {
+        self.remote_segments.write().expect("Unable to get lock on remote segments")
+    }
+
+    fn lock_fetched_segments(&self) -> MutexGuard<'_, VecDeque<u64>> {
+        self.fetched_segments.lock().expect("Unable to get lock on fetched segments")
+    }
+
+    /// Check if the segment with the starting offset is a copy fetched back from remote storage.
+    pub fn is_fetched(&self, starting_offset: u64) -> bool {
+        self.lock_fetched_segments().contains(&starting_offset)
+    }
+
+    /// Returns the first offset of the oldest segment in remote storage.
+    pub fn oldest_offset(&self) -> Option<u64> {
+        self.read_remote_segments()
+            .first()
+            .map(|remote_segment| remote_segment.starting_offset)
+    }
+
+    /// Find the first offloaded segment with a record appended at or after the timestamp and return its starting offset.
+    /// The time index of an offloaded segment isn't on local disk, so the whole segment is returned.
+    pub fn offset_for_timestamp(&self, timestamp: u64, segments: &[Arc<Segment>]) -> Option<u64> {
+        self.read_remote_segments()
+            .iter()
+            .filter(|remote_segment| !is_local(segments, remote_segment.starting_offset))
+            .find(|remote_segment| remote_segment.max_timestamp >= timestamp)
+            .map(|remote_segment| remote_segment.starting_offset)
+    }
+
+    /// Upload the closed segments that aren't in remote storage yet, or that changed since they were uploaded, oldest
+    /// first. Stops at the first segment that can't be uploaded, it's kept on local disk and uploaded on the next call.
+    pub fn upload_segments(
+        &self,
+        backend: &dyn StorageBackend,
+        segments: &[Arc<Segment>],
+    ) -> Result<(), RemoteStorageError> {
+        for segment in segments {
+            if !self.is_uploaded(segment) {
+                self.upload(backend, segment).map_err(|e| {
+                    error!("Unable to upload segment {}: {}", segment.starting_offset, e);
+                    e
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the retention policy of the cleaner to every segment in remote storage. Local copies of the expired
+    /// segments are removed by the next call to `remove_local_segments`.
+    pub fn expire_segments(&self, cleaner: &Cleaner) -> Result<(), RemoteStorageError> {
+        let remote_segments = self.read_remote_segments().clone();
+        let segment_sizes: Vec<(u64, u64)> = remote_segments
+            .iter()
+            .map(|remote_segment| (remote_segment.size, remote_segment.max_timestamp))
+            .collect();
+        let expired_segments = cleaner.segments_to_remove(&segment_sizes);
+        if expired_segments == 0 {
+            return Ok(());
+        }
+        self.write_manifest(&remote_segments[expired_segments..])?;
+        self.write_remote_segments().drain(..expired_segments);
+        let expired = &remote_segments[..expired_segments];
+        if let Some(last_expired) = expired.last() {
+            self.expired_offset.fetch_max(last_expired.next_offset, Ordering::SeqCst);
+        }
+        for remote_segment in expired {
+            info!("Removing segment {} from remote storage", remote_segment.starting_offset);
+            self.lock_fetched_segments()
+                .retain(|offset| *offset != remote_segment.starting_offset);
+            self.delete_objects(remote_segment)?;
+        }
+        Ok(())
+    }
+
+    /// Remove the segments that expired from remote storage from local disk, then offload the oldest uploaded segments
+    /// past the local retention. Segments that aren't uploaded yet are kept, so local retention keeps applying to the
+    /// uploaded ones while remote storage can't be reached. Nothing is transferred, so it's safe to call on every append.
+    /// Returns the segments that were removed from local disk.
+    pub fn remove_local_segments(&self, segments: &mut Vec<Arc<Segment>>) -> Vec<Arc<Segment>> {
+        let expired_offset = self.expired_offset.load(Ordering::SeqCst);
+        let expired_position = segments.partition_point(|segment| segment.next_offset <= expired_offset);
+        let mut removed_segments: Vec<Arc<Segment>> = segments.drain(..expired_position).collect();
+
+        let local_retention_bytes = match self.local_retention_bytes {
+            Some(local_retention_bytes) => local_retention_bytes,
+            None => return removed_segments,
+        };
+        let fetched_segments = self.lock_fetched_segments();
+        // Fetched segments are kept around for readers, they're removed by evict_fetched_segments instead
+        let mut local_bytes: u64 = segments
+            .iter()
+            .filter(|segment| !fetched_segments.contains(&segment.starting_offset))
+            .map(|segment| segment.position as u64)
+            .sum();
+        let offloadable_segments: Vec<(u64, u64)> = segments
+            .iter()
+            .filter(|segment| !fetched_segments.contains(&segment.starting_offset) && self.is_uploaded(segment))
+            .map(|segment| (segment.starting_offset, segment.position as u64))
+            .collect();
+        for (starting_offset, size) in offloadable_segments {
+            if local_bytes <= local_retention_bytes {
+                break;
+            }
+            info!("Offloading segment {} to remote storage", starting_offset);
+            removed_segments.extend(remove_local_segment(segments, starting_offset));
+            local_bytes -= size;
+        }
+        removed_segments
+    }
+
+    /// Download the offloaded segment with the offset to local storage and load it. Returns None if the offset isn't
+    /// in an offloaded segment. The segment is added to the local segments with `add_fetched_segment`.
+    pub fn fetch(
+        &self,
+        backend: &Arc<dyn StorageBackend>,
+        offset: u64,
+        keyring: Option<Arc<Keyring>>,
+        segments: &[Arc<Segment>],
+    ) -> Result<Option<Segment>, RemoteStorageError> {
+        let remote_segment = {
+            let remote_segments = self.read_remote_segments();
+            let position = remote_segments.partition_point(|remote_segment| remote_segment.next_offset <= offset);
+            match remote_segments.get(position) {
+                Some(remote_segment) if remote_segment.starting_offset <= offset => *remote_segment,
+                _ => return Ok(None),
+            }
+        };
+        if is_local(segments, remote_segment.starting_offset) {
+            return Ok(None);
+        }
+        info!("Fetching segment {} from remote storage", remote_segment.starting_offset);
+        // The log is written last, so a fetch that stops part way never leaves a log behind with a mismatched index
+        for (object_name, suffix) in remote_segment.object_names().iter().zip(SEGMENT_SUFFIXES).rev() {
+            let contents = self.storage.get(object_name)?;
+            let file_name = utils::segment_file_name(remote_segment.starting_offset, suffix);
+            backend.write(&file_name, &contents).map_err(|e| {
+                error!("{}", e);
+                RemoteStorageError::new("unable to write fetched segment file")
+            })?;
+        }
+        let segment = Segment::load_segment(backend.clone(), remote_segment.starting_offset.to_string(), keyring)
+            .map_err(|e| {
+                error!("{}", e);
+                RemoteStorageError::new("unable to load fetched segment")
+            })?;
+        Ok(Some(segment))
+    }
+
+    /// Add a segment returned by `fetch` to the local segments. Only the most recently fetched segments are kept on
+    /// local disk, the segments that are no longer kept are returned.
+    pub fn add_fetched_segment(&self, segment: Segment, segments: &mut Vec<Arc<Segment>>) -> Vec<Arc<Segment>> {
+        let starting_offset = segment.starting_offset;
+        if is_local(segments, starting_offset) {
+            return Vec::new();
+        }
+        let position = segments.partition_point(|segment| segment.starting_offset < starting_offset);
+        segments.insert(position, Arc::new(segment));
+        self.lock_fetched_segments().push_back(starting_offset);
+        self.evict_fetched_segments(segments)
+    }
+
+    /// Remove the segments holding offsets at and after the offset from remote storage. The segment the offset falls
+    /// in has to be on local disk, it's no longer counted as fetched so it stays there until it's uploaded again.
+    pub fn truncate_to(&self, offset: u64) -> Result<(), RemoteStorageError> {
+        let remote_segments = self.read_remote_segments().clone();
+        let position = remote_segments.partition_point(|remote_segment| remote_segment.next_offset <= offset);
+        if position == remote_segments.len() {
+            return Ok(());
+        }
+        self.write_manifest(&remote_segments[..position])?;
+        self.write_remote_segments().truncate(position);
+        for remote_segment in &remote_segments[position..] {
+            info!("Removing truncated segment {} from remote storage", remote_segment.starting_offset);
+            self.lock_fetched_segments()
+                .retain(|offset| *offset != remote_segment.starting_offset);
+            self.delete_objects(remote_segment)?;
+        }
+        Ok(())
+    }
+
+    /// Remove the segments that were fetched the longest ago from local disk, once too many have been fetched.
+    fn evict_fetched_segments(&self, segments: &mut Vec<Arc<Segment>>) -> Vec<Arc<Segment>> {
+        let mut fetched_segments = self.lock_fetched_segments();
+        let mut evicted_segments = Vec::new();
+        while fetched_segments.len() > MAX_FETCHED_SEGMENTS {
+            if let Some(starting_offset) = fetched_segments.pop_front() {
+                evicted_segments.extend(remove_local_segment(segments, starting_offset));
+            }
+        }
+        evicted_segments
+    }
+
+    /// Check if the segment is in remote storage as it is now.
+    fn is_uploaded(&self, segment: &Segment) -> bool {
+        self.read_remote_segments().iter().any(|remote_segment| {
+            remote_segment.starting_offset == segment.starting_offset && remote_segment.size == segment.position as u64
+        })
+    }
+
+    /// Upload the files of the segment, then add it to the manifest. Objects from an earlier upload of the segment
+    /// are deleted once the manifest points to the new ones.
+    fn upload(&self, backend: &dyn StorageBackend, segment: &Segment) -> Result<(), RemoteStorageError> {
+        let remote_segment = RemoteSegment {
+            starting_offset: segment.starting_offset,
+            next_offset: segment.next_offset,
+            size: segment.position as u64,
+            max_timestamp: segment.max_timestamp,
+        };
+        for (file_name, object_name) in segment.file_names().iter().zip(remote_segment.object_names()) {
+            let contents = backend.read(file_name).map_err(|e| {
+                error!("{}", e);
+                RemoteStorageError::new("unable to read segment file")
+            })?;
+            self.storage.put(&object_name, &contents)?;
+        }
+        let mut remote_segments = self.read_remote_segments().clone();
+        let position = remote_segments.partition_point(|uploaded| uploaded.starting_offset < segment.starting_offset);
+        let replaced_segment = match remote_segments.get_mut(position) {
+            Some(uploaded) if uploaded.starting_offset == segment.starting_offset => {
+                Some(std::mem::replace(uploaded, remote_segment))
+            }
+            _ => {
+                remote_segments.insert(position, remote_segment);
+                None
+            }
+        };
+        self.write_manifest(&remote_segments)?;
+        *self.write_remote_segments() = remote_segments;
+        if let Some(replaced_segment) = replaced_segment {
+            self.delete_objects(&replaced_segment)?;
+        }
+        info!("Uploaded segment {} to remote storage", segment.starting_offset);
+        Ok(())
+    }
+
+    /// Write the manifest listing the remote segments.
+    fn write_manifest(&self, remote_segments: &[RemoteSegment]) -> Result<(), RemoteStorageError> {
+        let manifest = bincode::serialize(remote_segments).map_err(|e| {
+            error!("{}", e);
+            RemoteStorageError::new("unable to encode remote storage manifest")
+        })?;
+        self.storage.put(MANIFEST_OBJECT, &manifest)
+    }
+
+    /// Delete the objects of a remote segment.
+    fn delete_objects(&self, remote_segment: &RemoteSegment) -> Result<(), RemoteStorageError> {
+        for object_name in remote_segment.object_names() {
+            self.storage.delete(&object_name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Check if the segment with the starting offset is on local disk.
+fn is_local(segments: &[Arc<Segment>], starting_offset: u64) -> bool {
+    segments.iter().any(|segment| segment.starting_offset == starting_offset)
+}
+
+/// Remove the segment with the starting offset from the local segments, if it's on local disk.
+fn remove_local_segment(segments: &mut Vec<Arc<Segment>>, starting_offset: u64) -> Option<Arc<Segment>> {
+    let position = segments.iter().position(|segment| segment.starting_offset == starting_offset)?;
+    Some(segments.remove(position))
+}
+
+#[cfg(test)]
+mod tiered_storage_tests {
+    use crate::nolan_errors::RemoteStorageError;
+    use crate::tiered_storage::{FilesystemRemoteStorage, RemoteStorage, TieredStorage, MANIFEST_OBJECT};
+    use std::sync::Arc;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_filesystem_remote_storage() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let storage = FilesystemRemoteStorage::new(tmp_dir.path().join("remote").to_str().unwrap())
+            .expect("Unable to create remote storage");
+        storage.put("object", "hello".as_bytes()).expect("Unable to put object");
+        storage.put("object", "world".as_bytes()).expect("Unable to replace object");
+        assert_eq!("world".as_bytes(), storage.get("object").expect("Unable to get object"));
+        assert_eq!(vec!["object".to_string()], storage.list().expect("Unable to list objects"));
+
+        storage.delete("object").expect("Unable to delete object");
+        storage.delete("object").expect("Deleting a missing object should succeed");
+        assert!(storage.list().unwrap().is_empty());
+        assert_eq!(RemoteStorageError::new("unable to read object"), storage.get("object").unwrap_err());
+    }
+
+    #[test]
+    fn test_load_removes_unlisted_objects() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let storage = Arc::new(
+            FilesystemRemoteStorage::new(tmp_dir.path().to_str().unwrap()).expect("Unable to create remote storage"),
+        );
+        // An upload that never made it into the manifest
+        storage.put("00000000000000000000-100.log", &[0; 100]).unwrap();
+        storage.put(MANIFEST_OBJECT, &bincode::serialize(&Vec::<u64>::new()).unwrap()).unwrap();
+
+        let tiered_storage = TieredStorage::load(storage.clone(), None).expect("Unable to load tiered storage");
+        assert_eq!(None, tiered_storage.oldest_offset());
+        assert_eq!(vec![MANIFEST_OBJECT.to_string()], storage.list().unwrap());
+    }
+}
@@ -0,0 +1,206 @@
+use crate::nolan_errors::IndexError;
+use log::error;
+use serde::{Deserialize, Serialize};
+use crate::storage::{StorageBackend, StorageFile};
+use std::sync::Arc;
+
+/// Size of a single encoded time entry.
+const TIME_ENTRY_SIZE: usize = 12;
+
+/// A time entry marks the first record in a segment that is at least as new as the entries timestamp.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct TimeEntry {
+    /// The newest timestamp of the records up to and including the record at the offset
+    pub timestamp: u64,
+    /// Offset of the record relative to the starting offset of the segment
+    pub offset: u32,
+}
+
+/// The time index maps timestamps to offsets, so records can be looked up by the time they were appended.
+/// An entry is added whenever a record is newer than every record before it in the segment, which keeps the
+/// entries sorted by both timestamp and offset even if the clock goes backwards.
+pub struct TimeIndex {
+    /// The file name of the time index in storage
+    pub file_name: String,
+    entries: Vec<TimeEntry>,
+    index_file: Box<dyn StorageFile>,
+    /// Storage backend the time index file lives in
+    backend: Arc<dyn StorageBackend>,
+}
+
+impl TimeIndex {
+    /// Open the time index with the given file name, creating it if it doesn't exist.
+    pub fn new(backend: Arc<dyn StorageBackend>, index_file_name: &str) -> Result<TimeIndex, IndexError> {
+        let index_file = backend.open(index_file_name, false).map_err(|e| {
+            error!("{}", e);
+            IndexError::new("Unable to create and open time index file")
+        })?;
+        Ok(TimeIndex {
+            file_name: index_file_name.to_string(),
+            entries: Vec::new(),
+            index_file,
+            backend,
+        })
+    }
+
+    /// Load the time index from storage into memory, returning the amount of entries in the time index.
+    pub fn load_index(&mut self) -> Result<u64, IndexError> {
+        let index_contents = self.backend.read(&self.file_name).map_err(|e| {
+            error!("{}", e);
+            IndexError::new("unable to read from time index file")
+        })?;
+        self.entries = decode_time_entries(&index_contents)?;
+        u64::try_from(self.entries.len()).map_err(|e| {
+            error!("{}", e);
+            IndexError::new("unable to convert usize to u64")
+        })
+    }
+
+    /// Add a record that was appended to the segment, an entry is only written if the record is newer than
+    /// every record before it.
+    pub fn add_record(&mut self, relative_offset: u64, timestamp: u64) -> Result<bool, IndexError> {
+        let entry = match push_time_entry(&mut self.entries, relative_offset, timestamp)? {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        self.index_file.append(&encode_time_entries(&[entry])?).map_err(|e| {
+            error!("{}", e);
+            IndexError::new("Unable to write entry to time index file")
+        })?;
+        Ok(true)
+    }
+
+    /// Drop every entry after the first len entries, from memory and from the time index file.
+    pub fn truncate(&mut self, len: usize) -> Result<(), IndexError> {
+        self.entries.truncate(len);
+        self.index_file.truncate((self.entries.len() * TIME_ENTRY_SIZE) as u64).map_err(|e| {
+            error!("{}", e);
+            IndexError::new("Unable to truncate time index file")
+        })
+    }
+
+    /// Return the amount of entries in the time index.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Sync the entries written to the time index file to disk.
+    pub fn sync(&self) -> Result<(), IndexError> {
+        self.index_file.sync().map_err(|e| {
+            error!("{}: {}", self.file_name, e);
+            IndexError::new("Unable to sync time index file")
+        })
+    }
+
+    /// Find the relative offset of the first record with a timestamp at or after the given timestamp.
+    /// Returns None if every record in the segment is older.
+    pub fn lookup(&self, timestamp: u64) -> Option<u64> {
+        let first_newer = self.entries.partition_point(|entry| entry.timestamp < timestamp);
+        self.entries.get(first_newer).map(|entry| u64::from(entry.offset))
+    }
+
+    /// Returns the timestamp of the first record in the segment, or None if the segment is empty.
+    pub fn first_timestamp(&self) -> Option<u64> {
+        self.entries.first().map(|entry| entry.timestamp)
+    }
+}
+
+/// Add an entry for the record if it's newer than the newest entry, returning the entry that was added.
+fn push_time_entry(
+    entries: &mut Vec<TimeEntry>,
+    relative_offset: u64,
+    timestamp: u64,
+) -> Result<Option<TimeEntry>, IndexError> {
+    if entries.last().is_some_and(|last_entry| timestamp <= last_entry.timestamp) {
+        return Ok(None);
+    }
+    let offset = u32::try_from(relative_offset).map_err(|e| {
+        error!("{}", e);
+        IndexError::new("unable to convert from u64 to u32")
+    })?;
+    let entry = TimeEntry { timestamp, offset };
+    entries.push(entry);
+    Ok(Some(entry))
+}
+
+/// Build the time index entries for records, given as relative offsets and timestamps in offset order.
+pub fn build_time_entries<I>(records: I) -> Result<Vec<TimeEntry>, IndexError>
+where
+    I: IntoIterator<Item = (u64, u64)>,
+{
+    let mut entries = Vec::new();
+    for (relative_offset, timestamp) in records {
+        push_time_entry(&mut entries, relative_offset, timestamp)?;
+    }
+    Ok(entries)
+}
+
+/// Encode time entries into the bytes stored in a time index file.
+pub fn encode_time_entries(entries: &[TimeEntry]) -> Result<Vec<u8>, IndexError> {
+    let mut index_contents = Vec::with_capacity(entries.len() * TIME_ENTRY_SIZE);
+    for entry in entries {
+        bincode::serialize_into(&mut index_contents, entry).map_err(|e| {
+            error!("{}", e);
+            IndexError::new("unable to serialize time entry")
+        })?;
+    }
+    Ok(index_contents)
+}
+
+/// Decode the contents of a time index file, a partially written entry at the end of the file is ignored.
+fn decode_time_entries(index_contents: &[u8]) -> Result<Vec<TimeEntry>, IndexError> {
+    index_contents
+        .chunks_exact(TIME_ENTRY_SIZE)
+        .map(|entry_bytes| {
+            bincode::deserialize(entry_bytes).map_err(|e| {
+                error!("{}", e);
+                IndexError::new("unable to deserialize time entry")
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod time_index_tests {
+    use crate::storage::{FilesystemStorage, StorageBackend};
+    use crate::time_index::{self, TimeEntry, TimeIndex};
+    use std::sync::Arc;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_build_time_entries() {
+        // The clock going backwards for the third record doesn't add an entry
+        let records = [(0, 100), (1, 100), (2, 90), (3, 150), (4, 200)];
+        let entries = time_index::build_time_entries(records).expect("Unable to build time entries");
+        let wanted_entries = vec![
+            TimeEntry { timestamp: 100, offset: 0 },
+            TimeEntry { timestamp: 150, offset: 3 },
+            TimeEntry { timestamp: 200, offset: 4 },
+        ];
+        assert_eq!(wanted_entries, entries);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let backend: Arc<dyn StorageBackend> = Arc::new(
+            FilesystemStorage::new(tmp_dir.path().to_str().expect("Unable to convert path to string"))
+                .expect("Unable to create storage"),
+        );
+        let index_file_name = "test.timeindex";
+        let mut test_index = TimeIndex::new(backend.clone(), index_file_name).expect("Unable to create time index");
+        for (relative_offset, timestamp) in [(0, 100), (1, 100), (2, 150), (3, 200)] {
+            test_index.add_record(relative_offset, timestamp).expect("Unable to add record");
+        }
+
+        let mut loaded_index = TimeIndex::new(backend, index_file_name).expect("Unable to open time index");
+        assert_eq!(3, loaded_index.load_index().expect("Unable to load time index"));
+        for index in [&test_index, &loaded_index] {
+            assert_eq!(Some(0), index.lookup(0));
+            assert_eq!(Some(0), index.lookup(100));
+            assert_eq!(Some(2), index.lookup(101));
+            assert_eq!(Some(3), index.lookup(200));
+            assert_eq!(None, index.lookup(201));
+        }
+    }
+}
@@ -1,22 +1,57 @@
-use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 use crate::nolan_errors::SegmentError;
 
 pub const LOG_EXTENSION: &str = "log";
 pub const INDEX_EXTENSION: &str = "index";
+pub const TIME_INDEX_EXTENSION: &str = "timeindex";
 
 pub const LOG_SUFFIX: &str = ".log";
 pub const INDEX_SUFFIX: &str = ".index";
+pub const TIME_INDEX_SUFFIX: &str = ".timeindex";
 
-/// Given a directory, a starting offset and a file type suffix, create and return the path to the file.
-pub fn create_segment_file_name(
-    directory: &str,
-    starting_offset: u16,
-    suffix: &str,
-) -> Result<String, SegmentError> {
-    let file_name = format!("{:0>5}{}", starting_offset, suffix);
-    let new_file = Path::new(&directory).join(file_name);
-    match new_file.to_str() {
-        Some(file_as_string) => return Ok(String::from(file_as_string)),
-        None => return Err(SegmentError::new("unable to convert path to string"))
+/// Number of digits used for the starting offset in segment file names. 20 digits is enough to hold any u64.
+pub const SEGMENT_NAME_WIDTH: usize = 20;
+
+/// Given a starting offset and a file type suffix, create and return the name of the segment file.
+pub fn segment_file_name(starting_offset: u64, suffix: &str) -> String {
+    format!("{:0>width$}{}", starting_offset, suffix, width = SEGMENT_NAME_WIDTH)
+}
+
+/// Given the stem of a segment file name, parse out the starting offset of the segment.
+pub fn parse_segment_file_stem(file_stem: &str) -> Result<u64, SegmentError> {
+    file_stem
+        .parse::<u64>()
+        .map_err(|_e| SegmentError::new("unable to parse segment base into u64"))
+}
+
+/// Return the current time in milliseconds since the unix epoch.
+pub fn current_time_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod utils_tests {
+    use crate::utils;
+
+    #[test]
+    fn test_segment_file_name() {
+        let file_name = utils::segment_file_name(70000, utils::LOG_SUFFIX);
+        assert_eq!("00000000000000070000.log", file_name);
+    }
+
+    #[test]
+    fn test_segment_file_name_max_offset() {
+        let file_name = utils::segment_file_name(u64::MAX, utils::INDEX_SUFFIX);
+        assert_eq!("18446744073709551615.index", file_name);
+    }
+
+    #[test]
+    fn test_parse_segment_file_stem() {
+        assert_eq!(70000, utils::parse_segment_file_stem("00000000000000070000").unwrap());
+        assert_eq!(12, utils::parse_segment_file_stem("00012").unwrap());
+        assert!(utils::parse_segment_file_stem("abc").is_err());
     }
-}
\ No newline at end of file
+}
@@ -1,138 +1,112 @@
-use std::{
-    fs::OpenOptions,
-    io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write},
-};
+use std::sync::Arc;
 
-use crate::{index::Entry, nolan_errors::IndexError};
+use crate::{
+    index::{IndexEntries, IndexLookup},
+    nolan_errors::IndexError,
+    storage::{StorageBackend, StorageFile},
+};
 use log::error;
 
 /// A virtual index is a struct that holds all of the meta data about the segment. 
-/// Most importantly it stores entry information for fast lookups.
+/// Most importantly it stores entry information for fast lookups. Entries are kept in memory and
+/// appended to the index file as they are added.
 pub struct VirtualIndex {
-    contents: Cursor<Vec<u8>>,
-    entries: Vec<Entry>,
-    full_index_file_path: String,
+    entries: IndexEntries,
+    index_file_name: String,
+    index_file: Box<dyn StorageFile>,
+    backend: Arc<dyn StorageBackend>,
 }
 
 impl VirtualIndex {
-    /// new creates a new virtual index. This is memory implementation where the data is stored in a cursur
-    pub fn new(index_path: String) -> VirtualIndex {
-        VirtualIndex {
-            contents: Cursor::new(Vec::new()),
-            entries: Vec::new(),
-            full_index_file_path: index_path,
-        }
-    }
-
-    /// Add a new entry to the index
-    pub fn add_entry(&mut self, start_position: u32, total_bytes: u32) -> Result<bool, IndexError> {
-        let entry = Entry {
-            start: start_position,
-            total: total_bytes,
-        };
-        let encoded_entry: Vec<u8> = bincode::serialize(&entry).map_err(|e| {
+    /// new creates a new virtual index, opening the index file or creating it if it doesn't exist.
+    /// The index is sparse if an interval is given, an index file that already has entries keeps its own format.
+    pub fn new(
+        backend: Arc<dyn StorageBackend>,
+        index_file_name: String,
+        index_interval_bytes: Option<u32>,
+    ) -> Result<VirtualIndex, IndexError> {
+        let mut index_file = backend.open(&index_file_name, false).map_err(|e| {
             error!("{}", e);
-            IndexError::new("Unable to serialize entry")
+            IndexError::new("Unable to create and open index file")
         })?;
+        let entries = IndexEntries::new(index_interval_bytes);
+        let index_contents = backend.read(&index_file_name).map_err(|e| {
+            error!("{}", e);
+            IndexError::new("Unable to read index file")
+        })?;
+        if index_contents.is_empty() {
+            index_file.append(&entries.header()).map_err(|e| {
+                error!("{}", e);
+                IndexError::new("Unable to write index header")
+            })?;
+        }
+        Ok(VirtualIndex {
+            entries,
+            index_file_name,
+            index_file,
+            backend,
+        })
+    }
 
-        self.entries.push(entry);
-
-        let entry_bytes: &[u8] = &encoded_entry[..];
-        self.contents.write(entry_bytes).map_err(|e| {
+    /// Add a batch of records, given as the relative offset, start position and total bytes of each record, to the
+    /// index with a single write to the index file.
+    pub fn add_entries(&mut self, records: &[(u64, u32, u32)]) -> Result<bool, IndexError> {
+        let mut encoded_entries = Vec::new();
+        for (offset, start_position, total_bytes) in records {
+            encoded_entries.extend(self.entries.push(*offset, *start_position, *total_bytes)?);
+        }
+        if encoded_entries.is_empty() {
+            return Ok(false);
+        }
+        self.index_file.append(&encoded_entries).map_err(|e| {
             error!("{}", e);
             IndexError::new("Unable to write entry to index file")
         })?;
         Ok(true)
     }
 
-    /// Given an offset, return the entry in the index starting position and total bytes.
-    pub fn return_entry_details_by_offset(
-        &self,
-        offset: usize,
-    ) -> Result<(u64, usize), IndexError> {
-        if self.entries.len() <= offset {
-            return Err(IndexError::new("offset requested is greater than the entries legnth"))
-        }
-        let entry = self.entries[offset];
-        let start_offset: u64 = entry.start.into();
-        let total_bytes: usize = usize::try_from(entry.total).map_err(|e| {
+    /// Drop every entry after the first len entries, from memory and from the index file.
+    pub fn truncate(&mut self, len: usize) -> Result<(), IndexError> {
+        self.entries.truncate(len);
+        self.index_file.truncate(self.entries.encoded_len() as u64).map_err(|e| {
             error!("{}", e);
-            IndexError::new("unable to convert from u32 to usize")
-        })?;
-        Ok((start_offset, total_bytes))
+            IndexError::new("Unable to truncate index file")
+        })
     }
 
-    /// Writes the buffered data to disk.
-    pub fn flush(&self) -> Result<(), IndexError> {
-        let mut index_file = OpenOptions::new()
-            .create(true)
-            .read(false)
-            .write(true)
-            .append(false)
-            .open(self.full_index_file_path.clone()).map_err(|e| {
-                error!("{}", e);
-                IndexError::new("Unable to create and open index file")
-            })?;
+    /// Return the amount of entries in the index.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
 
-        index_file
-            .write_all(self.contents.get_ref()).map_err(|e| {
-                error!("{}", e);
-                IndexError::new("Unable to flush contents to index file")
-            })?;
-            Ok(())
+    /// Given an offset relative to the start of the segment, find where its record is in the log.
+    pub fn lookup(&self, offset: u64) -> Result<IndexLookup, IndexError> {
+        self.entries.lookup(offset).map_err(|e| {
+            error!("{}", e);
+            IndexError::new("offset requested is greater than the entries legnth")
+        })
     }
 
-    /// Load the index data from disk into memory, returing the total amount of entries in the index
-    pub fn load_index(&mut self) -> Result<u16, IndexError> {
-        let mut index_file = OpenOptions::new()
-            .create(false)
-            .read(true)
-            .write(false)
-            .append(false)
-            .open(self.full_index_file_path.clone()).map_err(|e| {
-                error!("{}", e);
-                IndexError::new("Unable to create and open index file")
-            })?;
+    /// Sync the entries written to the index file to disk.
+    pub fn sync(&self) -> Result<(), IndexError> {
+        self.index_file.sync().map_err(|e| {
+            error!("{}: {}", self.index_file_name, e);
+            IndexError::new("Unable to sync index file")
+        })
+    }
 
-        index_file.seek(SeekFrom::Start(0)).map_err(|e| {
+    /// Load the index data from storage into memory, returing the total amount of entries in the index
+    pub fn load_index(&mut self) -> Result<u64, IndexError> {
+        let index_contents = self.backend.read(&self.index_file_name).map_err(|e| {
             error!("{}", e);
-            IndexError::new("unable seek to begining of the index")
+            IndexError::new("unable seek to read from index file")
         })?;
-        let mut circut_break: bool = false;
-        loop {
-            let mut buffer = [0; 8];
-            match index_file.read_exact(&mut buffer) {
-                Ok(_) => {},
-                Err(error) => {
-                    if error.kind() == ErrorKind::UnexpectedEof {
-                        circut_break = true;
-                    } else {
-                        error!{"{}", error}
-                        return Err(IndexError::new("unable seek to read from index file"));
-                    }
-                },
-            }
-            if circut_break {
-                break;
-            }
-            //First write bytes to our virtual buffer
-            self.contents
-                .write(&buffer)
-                .map_err(|e| {
-                    error!("{}", e);
-                    IndexError::new("unable to write to buffer")
-                })?;
-
-            //Decode the entry and add it to our entry vector
-            let decoded_entry: Entry = bincode::deserialize(&buffer).map_err(|e| {
-                error!("{}", e);
-                IndexError::new("unable to deserialize entry")
-            })?;
-            self.entries.push(decoded_entry);
+        if !index_contents.is_empty() {
+            self.entries = IndexEntries::decode(&index_contents)?;
         }
-        let total_entries = u16::try_from(self.entries.len()).map_err(|e| {
+        let total_entries = u64::try_from(self.entries.len()).map_err(|e| {
             error!("{}", e);
-            IndexError::new("unable to convert usize to u16 for entries length")
+            IndexError::new("unable to convert usize to u64 for entries length")
         })?;
         Ok(total_entries)
     }
@@ -140,44 +114,68 @@ impl VirtualIndex {
 
 #[cfg(test)]
 mod virtual_index_tests {
-    use std::path::Path;
+    use std::sync::Arc;
 
     use tempdir::TempDir;
 
+    use crate::index::{Entry, IndexEntries, IndexLookup};
+    use crate::storage::{FilesystemStorage, StorageBackend};
     use crate::virtual_index::VirtualIndex;
     use crate::nolan_errors::IndexError;
+
+    fn dense_entries(test_index: &VirtualIndex) -> &Vec<Entry> {
+        match &test_index.entries {
+            IndexEntries::Dense(entries) => entries,
+            IndexEntries::Sparse { .. } => panic!("Expected a dense index"),
+        }
+    }
+
+    fn create_test_backend(tmp_dir: &TempDir) -> Arc<dyn StorageBackend> {
+        let test_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to convert path to string");
+        Arc::new(FilesystemStorage::new(test_dir_path).expect("Unable to create storage"))
+    }
+
+    fn create_test_index(tmp_dir: &TempDir) -> VirtualIndex {
+        VirtualIndex::new(create_test_backend(tmp_dir), String::from("test.index"), None).expect("Unable to create index")
+    }
+
     #[test]
-    fn test_add_entry() {
-        let mut test_index = VirtualIndex::new(String::from("test_dir/test.index"));
+    fn test_add_entries() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let mut test_index = create_test_index(&tmp_dir);
 
         let start_position = 0;
         let total_bytes = 10;
         test_index
-            .add_entry(start_position, total_bytes)
+            .add_entries(&[(0, start_position, total_bytes)])
             .expect("Unable to add entry");
 
-        let retrieved_entry = test_index.entries.get(0).expect("Got entry");
+        let retrieved_entry = dense_entries(&test_index).first().expect("Got entry");
         assert_eq!(start_position, retrieved_entry.start);
         assert_eq!(total_bytes, retrieved_entry.total);
     }
 
     #[test]
     fn test_add_multiple_entry() {
-        let mut test_index = VirtualIndex::new(String::from("test_dir/test.index"));
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let mut test_index = create_test_index(&tmp_dir);
 
         let mut start_position = 0;
         let total_bytes = 10;
 
-        for _ in 0..9 {
+        for i in 0..9 {
             test_index
-                .add_entry(start_position, total_bytes)
+                .add_entries(&[(i, start_position, total_bytes)])
                 .expect("Unable to add entry");
             start_position += total_bytes
         }
 
         start_position = 0;
         for i in 0..9 {
-            let retrieved_entry = test_index.entries.get(i).expect("Got entry");
+            let retrieved_entry = dense_entries(&test_index).get(i).expect("Got entry");
             assert_eq!(start_position, retrieved_entry.start);
             assert_eq!(total_bytes, retrieved_entry.total);
             start_position += total_bytes
@@ -186,77 +184,87 @@ mod virtual_index_tests {
 
     #[test]
     fn test_retrieve_entry() {
-        let mut test_index = VirtualIndex::new(String::from("test_dir/test.index"));
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let mut test_index = create_test_index(&tmp_dir);
 
         let start_position = 0;
         let total_bytes = 10;
         test_index
-            .add_entry(start_position, total_bytes)
+            .add_entries(&[(0, start_position, total_bytes)])
             .expect("Unable to add entry");
 
-        let (retrieved_position, retrieved_total_bytes) = test_index.return_entry_details_by_offset(0).expect("Unable to return entry");
-        assert_eq!(u64::from(start_position), retrieved_position);
-        assert_eq!(usize::try_from(total_bytes).unwrap(), retrieved_total_bytes);
+        let lookup = test_index.lookup(0).expect("Unable to return entry");
+        let wanted_lookup = IndexLookup::Record {
+            start: u64::from(start_position),
+            total: usize::try_from(total_bytes).unwrap(),
+        };
+        assert_eq!(wanted_lookup, lookup);
     }
 
     #[test]
     fn test_retrieve_entry_offset_greater() {
-        let mut test_index = VirtualIndex::new(String::from("test_dir/test.index"));
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let mut test_index = create_test_index(&tmp_dir);
 
         let start_position = 0;
         let total_bytes = 10;
         test_index
-            .add_entry(start_position, total_bytes)
+            .add_entries(&[(0, start_position, total_bytes)])
             .expect("Unable to add entry");
 
-        let index_error = test_index.return_entry_details_by_offset(1).unwrap_err();
+        let index_error = test_index.lookup(1).unwrap_err();
         let wanted_error: IndexError = IndexError::new("offset requested is greater than the entries legnth");
         assert_eq!(wanted_error, index_error);
     }
 
     #[test]
-    fn test_flush() {
+    fn test_entries_written_to_file() {
         let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
-        let test_dir_path = tmp_dir
-            .path()
-            .to_str()
-            .expect("Unable to convert path to string");
-        
-        let index_file_name = format!("{}/test.index",test_dir_path);
-
-        let mut test_index = VirtualIndex::new(index_file_name);
+        let mut test_index = create_test_index(&tmp_dir);
 
         let start_position = 0;
         let total_bytes = 10;
         test_index
-            .add_entry(start_position, total_bytes)
+            .add_entries(&[(0, start_position, total_bytes)])
             .expect("Unable to add entry");
-        test_index.flush().expect("Unable to flush index");
+        test_index.sync().expect("Unable to sync index");
 
-        assert!(Path::new(&test_index.full_index_file_path).exists());
+        assert!(test_index.backend.exists(&test_index.index_file_name));
+        let mut loaded_index = VirtualIndex::new(test_index.backend.clone(), test_index.index_file_name.clone(), None)
+            .expect("Unable to open index");
+        assert_eq!(1, loaded_index.load_index().expect("Unable to load index"));
+        assert_eq!(test_index.entries, loaded_index.entries);
     }
 
     #[test]
-    fn test_flush_dir_dne() {
-        let mut test_index = VirtualIndex::new(String::from("test_dir/test.index"));
-
-        let start_position = 0;
-        let total_bytes = 10;
-        test_index
-            .add_entry(start_position, total_bytes)
-            .expect("Unable to add entry");
-        
-        let index_error = test_index.flush().unwrap_err();
+    fn test_new_dir_dne() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let backend = create_test_backend(&tmp_dir);
+        let index_error = VirtualIndex::new(backend, String::from("test_dir/test.index"), None).err().unwrap();
         let wanted_error: IndexError = IndexError::new("Unable to create and open index file");
         assert_eq!(wanted_error, index_error);
-
     }
 
     #[test]
-    fn test_load_dir_dne() {
-        let mut test_index = VirtualIndex::new(String::from("test_dir/test.index"));
-        let index_error = test_index.load_index().unwrap_err();
-        let wanted_error: IndexError = IndexError::new("Unable to create and open index file");
-        assert_eq!(wanted_error, index_error);
+    fn test_sparse_entries_written_to_file() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let backend = create_test_backend(&tmp_dir);
+        let index_file_name = String::from("test.index");
+        let mut test_index = VirtualIndex::new(backend.clone(), index_file_name.clone(), Some(100))
+            .expect("Unable to create index");
+        let mut start_position = 8;
+        for i in 0..10 {
+            test_index
+                .add_entries(&[(i, start_position, 40)])
+                .expect("Unable to add entry");
+            start_position += 40;
+        }
+        assert_eq!(3, test_index.entries.len());
+
+        // The loaded index is sparse because of the header, even without an interval being given
+        let mut loaded_index = VirtualIndex::new(backend, index_file_name, None).expect("Unable to open index");
+        assert_eq!(3, loaded_index.load_index().expect("Unable to load index"));
+        assert_eq!(test_index.entries, loaded_index.entries);
+        assert_eq!(IndexLookup::ScanFrom(128), loaded_index.lookup(4).unwrap());
     }
 }
@@ -1,116 +1,191 @@
+use crate::compression::Compression;
+use crate::config::FsyncPolicy;
+use crate::encryption::{self, Keyring};
+use crate::record::{self, Record};
+use crate::storage::{StorageBackend, StorageFile};
+use crate::time_index::TimeIndex;
 use crate::utils;
 use crate::{nolan_errors::SegmentError, virtual_index::VirtualIndex};
 use log::{error, info};
-use std::{
-    fs::OpenOptions,
-    io::{Cursor, Read, Seek, SeekFrom, Write},
-};
+use std::{sync::Arc, time::Instant};
 
-/// A virtual segment is a data type that holds all of the sement data only in memory.
-/// This is intended to use only for the current segment, that is to allow for fast access for data in that segment.
+/// A batch encoded for the log file, along with the index entries of its records given as the relative offset, start
+/// position and total bytes of each record.
+type EncodedBatch = (Vec<u8>, Vec<(u64, u32, u32)>);
+
+/// A virtual segment is a data type that holds all of the sement data in memory, while also appending every
+/// write to the segment files in storage. This is intended to use only for the current segment, that is to allow for
+/// fast access for data in that segment.
 pub struct VirtualSegment {
-    contents: Cursor<Vec<u8>>,
+    /// The log file contents, starting with the log header
+    contents: Vec<u8>,
     /// The last position in the log file to allow for easy writes
     position: u32,
     max_bytes: u64,
     /// The starting offset of the segment relative to the whole commitlog
-    pub starting_offset: u16,
+    pub starting_offset: u64,
     /// The next offset that was written to relative to the current segment
-    pub next_offset: u16,
+    pub next_offset: u64,
     index: VirtualIndex,
-    pub full_log_path: String,
+    time_index: TimeIndex,
+    pub log_file_name: String,
+    /// File ref to the log file, records are appended to it as they are written
+    log_file: Box<dyn StorageFile>,
+    fsync_policy: FsyncPolicy,
+    /// Codec that batches written to the segment are compressed with
+    compression: Compression,
+    /// Keys that written records are encrypted with
+    keyring: Option<Arc<Keyring>>,
+    /// Records written since the segment files were last synced
+    unsynced_records: u64,
+    last_sync: Instant,
 }
 
 impl VirtualSegment {
-    /// Create a virtual segment with the provided starting offset and max segment bytes.
-    pub fn new(base_directory: &str, max_segment_bytes: u64, offset: u16) -> VirtualSegment {
+    /// Create a virtual segment with the provided starting offset and max segment bytes. Any files left over
+    /// at the same starting offset are replaced, a new segment always starts out empty. The index is sparse if
+    /// an index interval is given, written batches are compressed with the given codec and encrypted if a keyring
+    /// is given.
+    pub fn new(
+        backend: Arc<dyn StorageBackend>,
+        max_segment_bytes: u64,
+        offset: u64,
+        fsync_policy: FsyncPolicy,
+        index_interval_bytes: Option<u32>,
+        compression: Compression,
+        keyring: Option<Arc<Keyring>>,
+    ) -> Result<VirtualSegment, SegmentError> {
         info!("Creating a new virtual segment");
-        let log_file_path =
-            utils::create_segment_file_name(&base_directory, offset, utils::LOG_SUFFIX)
-                .expect("Unable to create log file");
-        let index_file_name =
-            utils::create_segment_file_name(&base_directory, offset, utils::INDEX_SUFFIX)
-                .expect("Unable to create index file");
-        let new_virtual_index = VirtualIndex::new(index_file_name);
-        VirtualSegment {
-            contents: Cursor::new(Vec::new()),
-            position: 0,
+        let log_file_name = utils::segment_file_name(offset, utils::LOG_SUFFIX);
+        let index_file_name = utils::segment_file_name(offset, utils::INDEX_SUFFIX);
+        let time_index_file_name = utils::segment_file_name(offset, utils::TIME_INDEX_SUFFIX);
+        let contents = record::log_header().to_vec();
+        let mut log_file = backend.open(&log_file_name, true).map_err(|e| {
+            error!("{}", e);
+            SegmentError::new("unable to create log file")
+        })?;
+        log_file.append(&contents).map_err(|e| {
+            error!("{}", e);
+            SegmentError::new("unable to write log header")
+        })?;
+        backend.open(&index_file_name, true).map_err(|e| {
+            error!("{}", e);
+            SegmentError::new("unable to create index file")
+        })?;
+        backend.open(&time_index_file_name, true).map_err(|e| {
+            error!("{}", e);
+            SegmentError::new("unable to create time index file")
+        })?;
+        let new_time_index = TimeIndex::new(backend.clone(), &time_index_file_name).map_err(|e| {
+            error!("{}", e);
+            SegmentError::new("unable to create time index file")
+        })?;
+        let new_virtual_index = VirtualIndex::new(backend, index_file_name, index_interval_bytes).map_err(|e| {
+            error!("{}", e);
+            SegmentError::new("unable to create index file")
+        })?;
+        let mut segment = VirtualSegment {
+            contents,
+            position: record::LOG_HEADER_SIZE as u32,
             max_bytes: max_segment_bytes,
             starting_offset: offset,
             next_offset: 0,
             index: new_virtual_index,
-            full_log_path: log_file_path,
+            time_index: new_time_index,
+            log_file_name,
+            log_file,
+            fsync_policy,
+            compression,
+            keyring,
+            unsynced_records: 0,
+            last_sync: Instant::now(),
+        };
+        // Make sure the segment exists in storage before any records are acknowledged in it
+        if fsync_policy != FsyncPolicy::Never {
+            segment.flush()?;
         }
+        Ok(segment)
     }
 
-    /// Given a directory and the base name of the log and index file, load a new segment into memory.
+    /// Given the starting offset of a segment in storage, load it into memory.
     pub fn load_segment(
-        base_directory: &str,
-        segment_offset: u16,
+        backend: Arc<dyn StorageBackend>,
+        segment_offset: u64,
         max_segment_bytes: u64,
+        fsync_policy: FsyncPolicy,
+        index_interval_bytes: Option<u32>,
+        compression: Compression,
+        keyring: Option<Arc<Keyring>>,
     ) -> Result<VirtualSegment, SegmentError> {
-        //let segment_error_str = format!("unable to parse base string {segment_offset} into u16");
-        // let segment_offset = segment_base.parse::<u16>().map_err(|e| {
-        //     error!("{}", e);
-        //     SegmentError::new(&segment_error_str)
-        // })?;
-        let log_file_name =
-            utils::create_segment_file_name(base_directory, segment_offset, utils::LOG_SUFFIX)
-                .map_err(|e| {
-                    error!("{}", e);
-                    SegmentError::new("unable to create log file")
-                })?;
-
-        let mut log_file = OpenOptions::new()
-            .create(false)
-            .read(true)
-            .write(false)
-            .append(false)
-            .open(log_file_name.clone())
-            .map_err(|e| {
-                error!("{}", e);
-                SegmentError::new("unable to open log file")
-            })?;
-
-        //Create our buffer
-        let mut buffer = Vec::new();
+        let log_file_name = utils::segment_file_name(segment_offset, utils::LOG_SUFFIX);
+        if !backend.exists(&log_file_name) {
+            return Err(SegmentError::new("unable to open log file"));
+        }
         // Read contents from the file into the buffer
-        let total_bytes_read = log_file.read_to_end(&mut buffer).map_err(|e| {
+        let mut buffer = backend.read(&log_file_name).map_err(|e| {
             error!("{}", e);
             SegmentError::new("unable to read file to buffer")
         })?;
+        let mut log_file = backend.open(&log_file_name, false).map_err(|e| {
+            error!("{}", e);
+            SegmentError::new("unable to open log file")
+        })?;
+        if buffer.is_empty() {
+            buffer.extend_from_slice(&record::log_header());
+            log_file.append(&buffer).map_err(|e| {
+                error!("{}", e);
+                SegmentError::new("unable to write log header")
+            })?;
+        } else if !record::has_log_header(&buffer)? {
+            return Err(SegmentError::new("log file is missing the log header"));
+        }
 
         // This would be unnesseary if we used u64 for the position
-        let current_segment_postion: u32 = u32::try_from(total_bytes_read).map_err(|e| {
+        let current_segment_postion: u32 = u32::try_from(buffer.len()).map_err(|e| {
             error!("{}", e);
             SegmentError::new("unable to convert from u64 to u32")
         })?;
 
-        let index_file_name = utils::create_segment_file_name(
-            base_directory.clone(),
-            segment_offset,
-            utils::INDEX_SUFFIX,
-        )
-        .map_err(|e| {
+        let index_file_name = utils::segment_file_name(segment_offset, utils::INDEX_SUFFIX);
+        let mut loaded_index = VirtualIndex::new(backend.clone(), index_file_name, index_interval_bytes).map_err(|e| {
             error!("{}", e);
-            SegmentError::new("unable to create index file")
+            SegmentError::new("unable to open index file")
         })?;
-        let mut loaded_index = VirtualIndex::new(index_file_name);
 
-        let mut total_entries = loaded_index.load_index().map_err(|e| {
+        loaded_index.load_index().map_err(|e| {
             error!("{}", e);
             SegmentError::new("unable to load index")
         })?;
-        total_entries += segment_offset;
+        let time_index_file_name = utils::segment_file_name(segment_offset, utils::TIME_INDEX_SUFFIX);
+        let mut loaded_time_index = TimeIndex::new(backend, &time_index_file_name).map_err(|e| {
+            error!("{}", e);
+            SegmentError::new("unable to open time index file")
+        })?;
+        loaded_time_index.load_index().map_err(|e| {
+            error!("{}", e);
+            SegmentError::new("unable to load time index")
+        })?;
+        // The next offset of a virtual segment is relative to its starting offset
+        let next_offset = match record::last_offset_and_max_timestamp(&buffer)? {
+            Some((last_offset, _max_timestamp)) => last_offset + 1 - segment_offset,
+            None => 0,
+        };
 
         let segment = VirtualSegment {
-            contents: Cursor::new(buffer),
+            contents: buffer,
             position: current_segment_postion,
             max_bytes: max_segment_bytes,
             starting_offset: segment_offset,
-            next_offset: total_entries,
+            next_offset,
             index: loaded_index,
-            full_log_path: log_file_name,
+            time_index: loaded_time_index,
+            log_file_name,
+            log_file,
+            fsync_policy,
+            compression,
+            keyring,
+            unsynced_records: 0,
+            last_sync: Instant::now(),
         };
 
         Ok(segment)
@@ -118,11 +193,27 @@ impl VirtualSegment {
 
     /// Given a byte array, write that data to the corresponding log and index.
     /// Return the offset in the segment that was written to.
-    pub fn write(&mut self, data: &[u8]) -> Result<u16, SegmentError> {
-        if data.len() > self.max_bytes.try_into().unwrap() {
-            return Err(SegmentError::new("Data to write is greater than the allowed max segment size"));
+    pub fn write(&mut self, data: &[u8]) -> Result<u64, SegmentError> {
+        self.write_batch(&[data])
+    }
+
+    /// Write a batch of records to the log and index, either the whole batch is written or none of it. The records
+    /// are written to the log file with a single write and every record except the last is flagged as part of the
+    /// batch, so recovery drops a batch that was only partly written. If the segment has a compression codec the
+    /// batch is written as a single compressed record instead, unless compressing doesn't make it any smaller. The
+    /// space left in the segment is always checked against the uncompressed size of the batch. Return the offset in
+    /// the segment of the first record of the batch.
+    pub fn write_batch(&mut self, batch: &[&[u8]]) -> Result<u64, SegmentError> {
+        let mut batch_size = 0;
+        for data in batch {
+            let record_size = self.record_size(data.len());
+            // A record has to fit in an empty segment, otherwise splitting would never make room for it
+            if (record::LOG_HEADER_SIZE + record_size) as u64 > self.max_bytes {
+                return Err(SegmentError::new("Data to write is greater than the allowed max segment size"));
+            }
+            batch_size += record_size;
         }
-        let computed_size_bytes = u64::try_from(self.contents.get_ref().len() + data.len())
+        let computed_size_bytes = u64::try_from(self.contents.len() + batch_size)
             .map_err(|e| {
                 error!("{}", e);
                 SegmentError::new("unable to convert from usize to u64")
@@ -132,113 +223,399 @@ impl VirtualSegment {
                 "Write not possible. Segment log would be greater than max bytes",
             ));
         }
-        let u_bytes = self.contents.write(data).map_err(|e| {
+        let timestamp = utils::current_time_millis();
+        let (encoded_batch, index_entries) = match self.encode_compressed_batch(batch, batch_size, timestamp)? {
+            Some(compressed_batch) => compressed_batch,
+            None => self.encode_batch(batch, batch_size, timestamp)?,
+        };
+        let written_bytes = u32::try_from(encoded_batch.len()).map_err(|e| {
             error!("{}", e);
-            SegmentError::new("unable to write to log file")
+            SegmentError::new("unable to convert from usize to u32")
         })?;
-        let written_bytes: u32 = u32::try_from(u_bytes).map_err(|e| {
+        let index_len = self.index.len();
+        let time_index_len = self.time_index.len();
+        if let Err(e) = self.append_to_files(&encoded_batch, &index_entries, timestamp) {
+            // Don't leave part of the batch behind in the segment files
+            self.truncate_files(index_len, time_index_len);
+            return Err(e);
+        }
+        self.contents.extend_from_slice(&encoded_batch);
+        self.position += written_bytes;
+        let offset_written = self.next_offset;
+        self.next_offset += batch.len() as u64;
+        self.unsynced_records += batch.len() as u64;
+        if self.sync_required() {
+            self.flush()?;
+        }
+        Ok(offset_written)
+    }
+
+    /// Encode the batch as a single compressed record if the segment has a compression codec, as long as compressing
+    /// makes the batch smaller than its uncompressed size. Returns the encoded batch along with its index entries, every
+    /// offset of the batch points at the compressed record holding it.
+    fn encode_compressed_batch(
+        &self,
+        batch: &[&[u8]],
+        batch_size: usize,
+        timestamp: u64,
+    ) -> Result<Option<EncodedBatch>, SegmentError> {
+        if self.compression == Compression::None || batch.is_empty() {
+            return Ok(None);
+        }
+        let mut compressed_batch = Vec::new();
+        record::encode_compressed_batch(
+            &mut compressed_batch,
+            self.compression,
+            self.starting_offset + self.next_offset,
+            timestamp,
+            batch,
+            self.keyring.as_deref(),
+        )?;
+        if compressed_batch.len() >= batch_size {
+            return Ok(None);
+        }
+        let written_bytes = u32::try_from(compressed_batch.len()).map_err(|e| {
             error!("{}", e);
             SegmentError::new("unable to convert from usize to u32")
         })?;
-        self.index
-            .add_entry(self.position, written_bytes)
-            .map_err(|e| {
+        let index_entries = (0..batch.len() as u64)
+            .map(|i| (self.next_offset + i, self.position, written_bytes))
+            .collect();
+        Ok(Some((compressed_batch, index_entries)))
+    }
+
+    /// Encode every record of the batch on its own, every record except the last is flagged as part of the batch.
+    /// Returns the encoded batch along with its index entries.
+    fn encode_batch(
+        &self,
+        batch: &[&[u8]],
+        batch_size: usize,
+        timestamp: u64,
+    ) -> Result<EncodedBatch, SegmentError> {
+        let mut encoded_batch = Vec::with_capacity(batch_size);
+        let mut index_entries = Vec::with_capacity(batch.len());
+        for (i, data) in batch.iter().enumerate() {
+            let start = encoded_batch.len();
+            let attributes = if i + 1 < batch.len() { record::BATCH_CONTINUES } else { 0 };
+            let relative_offset = self.next_offset + i as u64;
+            record::encode_sealed_record(
+                &mut encoded_batch,
+                attributes,
+                self.starting_offset + relative_offset,
+                timestamp,
+                data,
+                self.keyring.as_deref(),
+            )?;
+            let (start, written_bytes) = match (u32::try_from(start), u32::try_from(encoded_batch.len() - start)) {
+                (Ok(start), Ok(written_bytes)) => (start, written_bytes),
+                _ => return Err(SegmentError::new("unable to convert from usize to u32")),
+            };
+            index_entries.push((relative_offset, self.position + start, written_bytes));
+        }
+        Ok((encoded_batch, index_entries))
+    }
+
+    /// Append an encoded batch to the log file and its entries to the index files. Records are written to the log
+    /// file first, if the index writes don't make it recovery rebuilds the indexes from the log.
+    fn append_to_files(
+        &mut self,
+        encoded_batch: &[u8],
+        index_entries: &[(u64, u32, u32)],
+        timestamp: u64,
+    ) -> Result<(), SegmentError> {
+        self.log_file.append(encoded_batch).map_err(|e| {
+            error!("{}", e);
+            SegmentError::new("unable to write to log file")
+        })?;
+        self.index.add_entries(index_entries).map_err(|e| {
+            error!("{}", e);
+            SegmentError::new("unable to add entry to index")
+        })?;
+        if !index_entries.is_empty() {
+            self.time_index.add_record(self.next_offset, timestamp).map_err(|e| {
                 error!("{}", e);
-                SegmentError::new("unable to add entry to index")
+                SegmentError::new("unable to add entry to time index")
             })?;
-        self.position += written_bytes;
-        let offset_written = self.next_offset;
-        self.next_offset += 1;
-        Ok(offset_written)
+        }
+        Ok(())
+    }
+
+    /// Cut the segment files back to the records in contents and the given amount of index entries, undoing a
+    /// batch that was only partly appended.
+    fn truncate_files(&mut self, index_len: usize, time_index_len: usize) {
+        if let Err(e) = self.log_file.truncate(self.contents.len() as u64) {
+            error!("{}", e);
+        }
+        if let Err(e) = self.index.truncate(index_len) {
+            error!("{}", e);
+        }
+        if let Err(e) = self.time_index.truncate(time_index_len) {
+            error!("{}", e);
+        }
+    }
+
+    /// Return the amount of bytes a record with the given payload size takes in the segment, before compression.
+    pub fn record_size(&self, payload_len: usize) -> usize {
+        match self.keyring {
+            Some(_) => record::encoded_len(payload_len + encryption::ENVELOPE_SIZE),
+            None => record::encoded_len(payload_len),
+        }
+    }
+
+    /// Return how many records from the start of the batch fit in the space left in the segment.
+    pub fn records_that_fit(&self, batch: &[&[u8]]) -> usize {
+        let mut remaining_bytes = (self.max_bytes as usize).saturating_sub(self.contents.len());
+        batch
+            .iter()
+            .take_while(|data| {
+                let record_size = self.record_size(data.len());
+                if record_size > remaining_bytes {
+                    return false;
+                }
+                remaining_bytes -= record_size;
+                true
+            })
+            .count()
+    }
+
+    /// Sync the records written since the last sync if the fsync policy says they're due. Writes sync as well, this is
+    /// for a segment that isn't written to for a while. Returns if the segment was synced.
+    pub fn sync_if_due(&mut self) -> Result<bool, SegmentError> {
+        if self.unsynced_records == 0 || !self.sync_required() {
+            return Ok(false);
+        }
+        self.flush()?;
+        Ok(true)
+    }
+
+    /// Check if no records have been written to the segment yet.
+    pub fn is_empty(&self) -> bool {
+        self.next_offset == 0
+    }
+
+    /// Check the fsync policy to see if the segment files should be synced after a write.
+    fn sync_required(&self) -> bool {
+        match self.fsync_policy {
+            FsyncPolicy::Always => true,
+            FsyncPolicy::EveryRecords(records) => self.unsynced_records >= records,
+            FsyncPolicy::EveryMillis(millis) => self.last_sync.elapsed().as_millis() >= u128::from(millis),
+            FsyncPolicy::Never => false,
+        }
     }
 
     /// Given an offset, find the entry in the index and get the bytes fromt he log
-    pub fn read_at(&mut self, offset: usize) -> Result<Vec<u8>, SegmentError> {
-        // This condition is only applied when we're dealing with segment 0, can this be combined below??
-        if (self.starting_offset == 0 && offset >= usize::from(self.next_offset))
-            || (offset >= usize::from(self.next_offset + self.starting_offset))
-        {
+    pub fn read_at(&self, offset: usize) -> Result<Vec<u8>, SegmentError> {
+        if offset as u64 >= self.next_offset {
             return Err(SegmentError::new("offset is out of bounds"));
         }
-        let (start, total) = self
+        let lookup = self
             .index
-            .return_entry_details_by_offset(offset)
+            .lookup(offset as u64)
             .map_err(|e| {
                 error!("{}", e);
                 SegmentError::new("unable to get entry details from index")
             })?;
-        // Let's create our buffer
-        let mut buffer = vec![0; total];
-        // Seek to entries start position
-        self.contents.seek(SeekFrom::Start(start)).map_err(|e| {
+        let record = record::read_indexed_record(
+            &self.contents,
+            lookup,
+            self.starting_offset + offset as u64,
+            self.keyring.as_deref(),
+        )?;
+        Ok(record.payload)
+    }
+
+    /// Copy the records of the log starting at the record holding the offset, stopping once max records have been
+    /// copied or the payloads copied reach max bytes. Records are copied out so readers don't hold on to the segment
+    /// while it's appended to, at least one record is copied if the offset is in the segment.
+    pub fn copy_log_from(&self, offset: u64, max_records: usize, max_bytes: usize) -> Result<Vec<u8>, SegmentError> {
+        if offset >= self.next_offset {
+            return Ok(Vec::new());
+        }
+        let lookup = self.index.lookup(offset).map_err(|e| {
             error!("{}", e);
-            SegmentError::new("unable to seek to offset in the log file")
+            SegmentError::new("unable to get entry details from index")
         })?;
-        // Read log file bytes into the buffer
-        self.contents.read_exact(&mut buffer).map_err(|e| {
-            error!("{}", e);
-            SegmentError::new("unable to read into buffer")
+        let start = record::seek_record(&self.contents, lookup, self.starting_offset + offset)?;
+        let mut position = start;
+        let mut copied_records = 0;
+        let mut copied_bytes = 0;
+        while position < self.contents.len() && copied_records < max_records && copied_bytes < max_bytes {
+            let remaining = &self.contents[position..];
+            let total = record::unchecked_record_len(remaining)?;
+            // A compressed batch holds every record up to its last offset
+            let first_offset = (self.starting_offset + offset).max(record::record_offset(remaining));
+            copied_records += (record::record_last_offset(remaining)? + 1).saturating_sub(first_offset) as usize;
+            copied_bytes += total.saturating_sub(record::RECORD_HEADER_SIZE);
+            position += total;
+        }
+        Ok(self.contents[start..position.min(self.contents.len())].to_vec())
+    }
+
+    /// Find the first offset in the segment, relative to the starting offset, with a record appended at or after
+    /// the timestamp.
+    pub fn offset_for_timestamp(&self, timestamp: u64) -> Option<u64> {
+        self.time_index.lookup(timestamp)
+    }
+
+    /// Returns when the first record of the segment was appended, or None if nothing has been appended to it yet.
+    pub fn first_timestamp(&self) -> Option<u64> {
+        self.time_index.first_timestamp()
+    }
+
+    /// Hand every record stored in the segment to the visitor, in offset order.
+    pub fn for_each_record(&self, visit: impl FnMut(Record)) -> Result<(), SegmentError> {
+        let (entries, _valid_bytes) = record::scan_records(&self.contents, self.starting_offset);
+        record::for_each_log_record(&self.contents, &entries, self.keyring.as_deref(), visit)
+    }
+
+    /// Syncs everything written to the segment files to storage. Writes go to the files as they happen, so this
+    /// only has work to do when the fsync policy let records go unsynced.
+    pub fn flush(&mut self) -> Result<(), SegmentError> {
+        self.log_file.sync().map_err(|e| {
+            error!("{}: {}", self.log_file_name, e);
+            SegmentError::new("unable to sync log file")
         })?;
-        Ok(buffer)
-    }
-
-    /// Flushes the virtual segment to disk, where it will become a regular segment. This involves 2 write
-    /// operations, writing the log file and writing the index file.
-    pub fn flush(&self) -> Result<(), SegmentError> {
-        let mut log_file = OpenOptions::new()
-            .create(true)
-            .read(false)
-            .write(true)
-            .append(false)
-            .open(self.full_log_path.clone())
-            .map_err(|e| {
-                error!("{}", e);
-                SegmentError::new("unable to open log file for flushing")
-            })?;
 
-        self.index.flush().map_err(|e| {
+        self.index.sync().map_err(|e| {
             error!("{}", e);
-            SegmentError::new("unable to flush index contents to file")
+            SegmentError::new("unable to sync index file")
         })?;
 
-        log_file.write_all(self.contents.get_ref()).map_err(|e| {
+        self.time_index.sync().map_err(|e| {
             error!("{}", e);
-            SegmentError::new("unable to flush log contents to file")
+            SegmentError::new("unable to sync time index file")
         })?;
-
+        self.unsynced_records = 0;
+        self.last_sync = Instant::now();
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod virtual_segment_tests {
+    use crate::compression::Compression;
+    use crate::config::FsyncPolicy;
     use crate::nolan_errors::SegmentError;
+    use crate::record;
+    use crate::nolan_errors::StorageError;
+    use crate::storage::{FileContents, FilesystemStorage, MemoryStorage, StorageBackend, StorageFile};
+    use crate::utils;
     use crate::virtual_segment::VirtualSegment;
-    use std::path::Path;
+    use std::fs;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, SystemTime};
     use tempdir::TempDir;
 
+    /// Storage that fails appends to files with the given suffix while failing is set.
+    struct FailingStorage {
+        inner: MemoryStorage,
+        suffix: &'static str,
+        failing: Arc<AtomicBool>,
+    }
+
+    struct FailingFile {
+        inner: Box<dyn StorageFile>,
+        failing: Option<Arc<AtomicBool>>,
+    }
+
+    impl StorageFile for FailingFile {
+        fn append(&mut self, data: &[u8]) -> Result<(), StorageError> {
+            if self.failing.as_ref().is_some_and(|failing| failing.load(Ordering::SeqCst)) {
+                return Err(StorageError::new("injected append failure"));
+            }
+            self.inner.append(data)
+        }
+
+        fn truncate(&mut self, len: u64) -> Result<(), StorageError> {
+            self.inner.truncate(len)
+        }
+
+        fn sync(&self) -> Result<(), StorageError> {
+            self.inner.sync()
+        }
+    }
+
+    impl StorageBackend for FailingStorage {
+        fn open(&self, file_name: &str, truncate: bool) -> Result<Box<dyn StorageFile>, StorageError> {
+            Ok(Box::new(FailingFile {
+                inner: self.inner.open(file_name, truncate)?,
+                failing: file_name.ends_with(self.suffix).then(|| self.failing.clone()),
+            }))
+        }
+
+        fn read(&self, file_name: &str) -> Result<Vec<u8>, StorageError> {
+            self.inner.read(file_name)
+        }
+
+        fn map(&self, file_name: &str) -> Result<FileContents, StorageError> {
+            self.inner.map(file_name)
+        }
+
+        fn write(&self, file_name: &str, contents: &[u8]) -> Result<(), StorageError> {
+            self.inner.write(file_name, contents)
+        }
+
+        fn list(&self) -> Result<Vec<String>, StorageError> {
+            self.inner.list()
+        }
+
+        fn rename(&self, file_name: &str, new_file_name: &str) -> Result<(), StorageError> {
+            self.inner.rename(file_name, new_file_name)
+        }
+
+        fn delete(&self, file_name: &str) -> Result<(), StorageError> {
+            self.inner.delete(file_name)
+        }
+
+        fn exists(&self, file_name: &str) -> bool {
+            self.inner.exists(file_name)
+        }
+
+        fn modified(&self, file_name: &str) -> Result<SystemTime, StorageError> {
+            self.inner.modified(file_name)
+        }
+    }
+
+    fn create_test_backend(tmp_dir: &TempDir) -> Arc<dyn StorageBackend> {
+        let test_dir_path = tmp_dir
+            .path()
+            .to_str()
+            .expect("Unable to convert path to string");
+        Arc::new(FilesystemStorage::new(test_dir_path).expect("Unable to create storage"))
+    }
+
+    fn create_test_segment(tmp_dir: &TempDir, max_segment_bytes: u64) -> VirtualSegment {
+        let backend = create_test_backend(tmp_dir);
+        VirtualSegment::new(backend, max_segment_bytes, 0, FsyncPolicy::Always, None, Compression::None, None)
+            .expect("Unable to create virtual segment")
+    }
+
     #[test]
     fn test_one_message() {
-        let mut vs = VirtualSegment::new("test_dir", 100, 0);
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let mut vs = create_test_segment(&tmp_dir, 100);
         let data = "hellos".as_bytes();
         let offset = vs
             .write(data)
             .expect("unable to write data to virtual segment");
-        assert_eq!(0, offset.into());
+        assert_eq!(0, offset);
         let retrieve_data = vs
-            .read_at(offset.into())
+            .read_at(offset as usize)
             .expect("Failed to get message from virtual segment");
         assert_eq!(data, &*retrieve_data);
     }
 
     #[test]
     fn test_multiple_message() {
-        let mut vs = VirtualSegment::new("test_dir", 100, 0);
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let mut vs = create_test_segment(&tmp_dir, 1000);
         let messages = ["hello", "world", "im", "here"];
         for (i, message) in messages.iter().enumerate() {
             let data = message.as_bytes();
             let offset = vs.write(data).expect("unable to write to virtual seg");
-            assert_eq!(i, offset.into());
+            assert_eq!(i as u64, offset);
         }
 
         for (i, message) in messages.iter().enumerate() {
@@ -251,7 +628,8 @@ mod virtual_segment_tests {
 
     #[test]
     fn test_message_greater_than_segment() {
-        let mut vs = VirtualSegment::new("test_dir", 10, 0);
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let mut vs = create_test_segment(&tmp_dir, 10);
         let bytes: [u8; 11] = [0; 11];
         let segment_error = vs.write(&bytes).unwrap_err();
         let want =
@@ -261,7 +639,9 @@ mod virtual_segment_tests {
 
     #[test]
     fn test_multi_messages_greater_than_segment() {
-        let mut vs = VirtualSegment::new("test_dir", 10, 0);
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let max_bytes = (record::LOG_HEADER_SIZE + record::encoded_len(6) + record::encoded_len(4)) as u64;
+        let mut vs = create_test_segment(&tmp_dir, max_bytes);
         let bytes: [u8; 6] = [0; 6];
         vs.write(&bytes).unwrap();
         let segment_error = vs.write(&bytes).unwrap_err();
@@ -270,9 +650,10 @@ mod virtual_segment_tests {
         assert_eq!(want, segment_error);
     }
 
-     #[test]
+    #[test]
     fn test_offset_greater_than_segment() {
-        let mut vs = VirtualSegment::new("test_dir", 100, 0);
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let mut vs = create_test_segment(&tmp_dir, 100);
         let bytes: [u8; 10] = [0; 10];
         vs.write(&bytes).unwrap();
 
@@ -282,43 +663,140 @@ mod virtual_segment_tests {
         assert_eq!(wanted_error, segment_error);
     }
 
-    // #[test]
-    // fn test_offset_less_than_segment() {
-    //     let mut vs = VirtualSegment::new("test_dir", 100, 1);
-    //     let bytes: [u8; 10] = [0; 10];
-    //     vs.write(&bytes).unwrap();
-
-    //     let segment_error = vs.read_at(0).unwrap_err();
-    //     let wanted_error =
-    //         SegmentError::new("offset is out of bounds");
-    //     assert_eq!(wanted_error, segment_error);
-    // }
-
     #[test]
     fn test_flush() {
         let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
-        let test_dir_path = tmp_dir
-            .path()
-            .to_str()
-            .expect("Unable to convert path to string");
-        let mut vs = VirtualSegment::new(test_dir_path, 100, 0);
+        let mut vs = create_test_segment(&tmp_dir, 100);
         let data = "hellos".as_bytes();
         vs.write(data)
             .expect("unable to write data to virtual segment");
         vs.flush().expect("Unable to flush");
 
-        assert!(Path::new(&vs.full_log_path).exists());
+        assert!(tmp_dir.path().join(&vs.log_file_name).exists());
     }
 
     #[test]
-    fn test_flush_dir_dne() {
-        let mut vs = VirtualSegment::new("test", 100, 0);
-        let data = "hellos".as_bytes();
-        vs.write(data)
+    fn test_writes_persisted_without_flush() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let backend = create_test_backend(&tmp_dir);
+        let mut vs = VirtualSegment::new(backend.clone(), 1000, 10, FsyncPolicy::Never, None, Compression::None, None)
+            .expect("Unable to create virtual segment");
+        vs.write("hello".as_bytes())
             .expect("unable to write data to virtual segment");
-        let segment_error = vs.flush().unwrap_err();
+
+        let mut loaded_vs = VirtualSegment::load_segment(
+            backend,
+            10,
+            1000,
+            FsyncPolicy::Never,
+            None,
+            Compression::None,
+            None,
+        )
+        .expect("Unable to load segment");
+        assert_eq!(1, loaded_vs.next_offset);
+        let offset = loaded_vs.write("world".as_bytes()).expect("unable to write to loaded segment");
+        assert_eq!(1, offset);
+        assert_eq!("hello".as_bytes(), &*loaded_vs.read_at(0).unwrap());
+        assert_eq!("world".as_bytes(), &*loaded_vs.read_at(1).unwrap());
+    }
+
+    #[test]
+    fn test_fsync_every_records() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let mut vs = VirtualSegment::new(
+            create_test_backend(&tmp_dir),
+            1000,
+            0,
+            FsyncPolicy::EveryRecords(3),
+            None,
+            Compression::None,
+            None,
+        )
+        .expect("Unable to create virtual segment");
+        for expected_unsynced in [1, 2, 0, 1] {
+            vs.write("hello".as_bytes())
+                .expect("unable to write data to virtual segment");
+            assert_eq!(expected_unsynced, vs.unsynced_records);
+        }
+    }
+
+    #[test]
+    fn test_fsync_every_millis() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let mut vs = VirtualSegment::new(
+            create_test_backend(&tmp_dir),
+            1000,
+            0,
+            FsyncPolicy::EveryMillis(50),
+            None,
+            Compression::None,
+            None,
+        )
+        .expect("Unable to create virtual segment");
+        vs.write("hello".as_bytes())
+            .expect("unable to write data to virtual segment");
+        assert_eq!(1, vs.unsynced_records);
+        // Records are synced once they're due, even without another write
+        thread::sleep(Duration::from_millis(60));
+        assert!(vs.sync_if_due().expect("Unable to sync segment"));
+        assert_eq!(0, vs.unsynced_records);
+        assert!(!vs.sync_if_due().expect("Unable to sync segment"));
+    }
+
+    #[test]
+    fn test_new_dir_dne() {
+        let tmp_dir = TempDir::new("test").expect("Unable to create temp directory");
+        let backend = create_test_backend(&tmp_dir);
+        fs::remove_dir(tmp_dir.path()).expect("Unable to remove directory");
+        let segment_error = VirtualSegment::new(
+            backend,
+            100,
+            0,
+            FsyncPolicy::Always,
+            None,
+            Compression::None,
+            None,
+        ).err().unwrap();
         let wanted_error =
-            SegmentError::new("unable to open log file for flushing");
+            SegmentError::new("unable to create log file");
         assert_eq!(wanted_error, segment_error);
     }
+
+    #[test]
+    fn test_index_failure_leaves_segment_intact() {
+        for suffix in [utils::INDEX_SUFFIX, utils::TIME_INDEX_SUFFIX] {
+            let failing = Arc::new(AtomicBool::new(false));
+            let backend: Arc<dyn StorageBackend> = Arc::new(FailingStorage {
+                inner: MemoryStorage::new(),
+                suffix,
+                failing: failing.clone(),
+            });
+            let mut vs =
+                VirtualSegment::new(backend.clone(), 1000, 0, FsyncPolicy::Always, None, Compression::None, None)
+                    .expect("Unable to create virtual segment");
+            vs.write("hello".as_bytes()).expect("unable to write data to virtual segment");
+
+            // The time index only gets an entry for a batch newer than every record before it
+            thread::sleep(Duration::from_millis(2));
+            failing.store(true, Ordering::SeqCst);
+            vs.write_batch(&["lost".as_bytes(), "batch".as_bytes()])
+                .expect_err("write should fail when the index can't be written");
+            failing.store(false, Ordering::SeqCst);
+
+            let offset = vs.write("world".as_bytes()).expect("unable to write data to virtual segment");
+            assert_eq!(1, offset);
+            assert_eq!("hello".as_bytes(), &*vs.read_at(0).unwrap());
+            assert_eq!("world".as_bytes(), &*vs.read_at(1).unwrap());
+
+            // Nothing of the failed batch is left behind in the segment files either
+            let loaded_vs =
+                VirtualSegment::load_segment(backend, 0, 1000, FsyncPolicy::Always, None, Compression::None, None)
+                    .expect("Unable to load segment");
+            assert_eq!(2, loaded_vs.next_offset);
+            assert_eq!("hello".as_bytes(), &*loaded_vs.read_at(0).unwrap());
+            assert_eq!("world".as_bytes(), &*loaded_vs.read_at(1).unwrap());
+        }
+    }
+
 }